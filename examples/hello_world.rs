@@ -13,12 +13,16 @@
 // limitations under the License.
 
 use anyhow::Result;
-use proxy_wasm_test_framework::tester;
 use proxy_wasm_test_framework::types::*;
+use proxy_wasm_test_framework::{runner, tester};
 use structopt::StructOpt;
 
 fn main() -> Result<()> {
     let args = tester::MockSettings::from_args();
+    runner::run(args, hello_world)
+}
+
+fn hello_world(args: tester::MockSettings) -> Result<()> {
     let mut hello_world_test = tester::mock(args)?;
 
     hello_world_test
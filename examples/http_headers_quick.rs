@@ -45,8 +45,11 @@ fn main() -> Result<()> {
         )
         .execute_and_expect_n(vec![ReturnType::Action(Action::Pause)])?;
 
+    // http_request skips callbacks after a local response, but the plugin logs the headers of
+    // the local response it sent
     let http_context = 2;
     http_headers_test
+        .skip_after_local_response(false)
         .call_proxy_on_response_headers(http_context, 0, false)
         .expect_get_header_map_pairs(Some(MapType::HttpResponseHeaders))
         .returning(Some(vec![(":status", "200"), ("Powered-By", "proxy-wasm")]))
@@ -0,0 +1,30 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::Result;
+use proxy_wasm_test_framework::{scaffold, tester};
+use structopt::StructOpt;
+
+fn main() -> Result<()> {
+    let args = tester::MockSettings::from_args();
+    let request_headers = vec![
+        (":method", "GET"),
+        (":path", "/"),
+        (":authority", "example.com"),
+        ("user-agent", "proxy-wasm-test-framework"),
+    ];
+    let test_source = scaffold::scaffold_http_request(args, request_headers, None)?;
+    println!("{}", test_source);
+    return Ok(());
+}
@@ -0,0 +1,94 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Generators for hostile inputs to be served to proxy-wasm modules in negative tests
+
+pub use crate::hostcalls::serial_utils::serialize_map_bytes;
+use crate::types::*;
+
+use std::convert::TryFrom;
+
+// Byte sequences that are not valid UTF-8
+pub fn invalid_utf8_values() -> Vec<Bytes> {
+    vec![
+        vec![0xff],                   // byte that never appears in UTF-8
+        vec![0xc0, 0xaf],             // overlong encoding of '/'
+        vec![0xe2, 0x82],             // truncated multi-byte sequence
+        vec![0xed, 0xa0, 0x80],       // UTF-16 surrogate half
+        vec![0xf4, 0x90, 0x80, 0x80], // code point above U+10FFFF
+        vec![b'o', b'k', 0x80],       // stray continuation byte after valid data
+    ]
+}
+
+// Declared sizes that do not match any real allocation (negative once reinterpreted as i32)
+pub fn enormous_sizes() -> Vec<i32> {
+    vec![
+        i32::MAX,
+        i32::MAX - 7,
+        u32::MAX as i32,
+        i32::MIN,
+        (1u32 << 31) as i32,
+        -1,
+    ]
+}
+
+pub fn empty_header_map() -> Vec<(&'static str, &'static str)> {
+    vec![]
+}
+
+pub fn duplicate_key_header_map() -> Vec<(&'static str, &'static str)> {
+    vec![
+        (":method", "GET"),
+        (":path", "/"),
+        ("x-duplicate", "first"),
+        ("X-Duplicate", "second"),
+        ("x-duplicate", "third"),
+    ]
+}
+
+pub fn oversized_header_map(value_size: usize) -> Vec<(String, String)> {
+    vec![
+        (":method".to_string(), "GET".to_string()),
+        ("x-oversized".to_string(), "a".repeat(value_size)),
+    ]
+}
+
+// Serialized header map whose values are each one of the invalid UTF-8 sequences
+pub fn invalid_utf8_header_map() -> Bytes {
+    let values = invalid_utf8_values();
+    let keys: Vec<String> = (0..values.len())
+        .map(|index| format!("x-invalid-{}", index))
+        .collect();
+    serialize_map_bytes(
+        keys.iter()
+            .zip(values.iter())
+            .map(|(key, value)| (key.as_bytes(), &value[..]))
+            .collect(),
+    )
+}
+
+// Serialized header map declaring more pairs than it contains
+pub fn truncated_header_map() -> Bytes {
+    let mut bytes = serialize_map_bytes(vec![(&b"x-truncated"[..], &b"value"[..])]);
+    bytes[0..4].copy_from_slice(&16u32.to_le_bytes());
+    bytes
+}
+
+// Serialized header map whose key size points past the end of the data
+pub fn overflowing_header_map() -> Bytes {
+    let mut bytes = serialize_map_bytes(vec![(&b"x-overflow"[..], &b"value"[..])]);
+    let key_size = u32::from_le_bytes(<[u8; 4]>::try_from(&bytes[4..8]).unwrap());
+    bytes[4..8].copy_from_slice(&(key_size + 1024).to_le_bytes());
+    bytes
+}
@@ -0,0 +1,138 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::types::*;
+
+// Context IDs created on a Tester along with their parents (0 for root contexts) and lifecycle
+// states, so that new contexts are never given the ID of an existing one
+#[derive(Debug, Default, Clone)]
+pub struct ContextManager {
+    contexts: Vec<(i32, i32, ContextState)>,
+}
+
+impl ContextManager {
+    pub fn new() -> ContextManager {
+        ContextManager {
+            contexts: Vec::new(),
+        }
+    }
+
+    // Next unused ID, above every context created so far
+    pub fn allocate(&self) -> i32 {
+        self.contexts
+            .iter()
+            .map(|(context_id, _, _)| *context_id)
+            .max()
+            .unwrap_or(0)
+            + 1
+    }
+
+    pub fn register(&mut self, context_id: i32, parent_context_id: i32) {
+        if let Some((_, parent, state)) = self
+            .contexts
+            .iter_mut()
+            .find(|(id, _, _)| *id == context_id)
+        {
+            if *state != ContextState::Deleted {
+                println!(
+                    "Warning: context {} is created again with parent {} before it was deleted",
+                    context_id, parent_context_id
+                );
+            }
+            *parent = parent_context_id;
+            *state = ContextState::Created;
+            return;
+        }
+        self.contexts
+            .push((context_id, parent_context_id, ContextState::Created));
+    }
+
+    // Moves a context to the given state, returning the state it was in when the host is not
+    // allowed to make that transition (e.g. proxy_on_log before proxy_on_done)
+    pub fn transition(&mut self, context_id: i32, next: ContextState) -> Result<(), ContextState> {
+        let state = match self
+            .contexts
+            .iter_mut()
+            .find(|(id, _, _)| *id == context_id)
+        {
+            Some((_, _, state)) => state,
+            None => return Ok(()),
+        };
+        let allowed = match next {
+            ContextState::Created => true,
+            ContextState::Active | ContextState::Done => {
+                *state == ContextState::Created || *state == ContextState::Active
+            }
+            ContextState::Logged => *state == ContextState::Done,
+            ContextState::Deleted => *state == ContextState::Done || *state == ContextState::Logged,
+        };
+        let previous = *state;
+        *state = next;
+        if allowed {
+            Ok(())
+        } else {
+            Err(previous)
+        }
+    }
+
+    pub fn get_state(&self, context_id: i32) -> Option<ContextState> {
+        self.contexts
+            .iter()
+            .find(|(id, _, _)| *id == context_id)
+            .map(|(_, _, state)| *state)
+    }
+
+    pub fn contains(&self, context_id: i32) -> bool {
+        self.contexts.iter().any(|(id, _, _)| *id == context_id)
+    }
+
+    pub fn get_parent(&self, context_id: i32) -> Option<i32> {
+        self.contexts
+            .iter()
+            .find(|(id, _, _)| *id == context_id)
+            .map(|(_, parent, _)| *parent)
+    }
+
+    pub fn is_root(&self, context_id: i32) -> bool {
+        self.get_parent(context_id) == Some(0)
+    }
+
+    pub fn get_root_contexts(&self) -> Vec<i32> {
+        self.get_children(0)
+    }
+
+    pub fn get_children(&self, parent_context_id: i32) -> Vec<i32> {
+        self.contexts
+            .iter()
+            .filter(|(_, parent, _)| *parent == parent_context_id)
+            .map(|(context_id, _, _)| *context_id)
+            .collect()
+    }
+
+    // Contexts that were created but never received proxy_on_delete
+    pub fn get_leaked_contexts(&self) -> Vec<i32> {
+        self.contexts
+            .iter()
+            .filter(|(_, _, state)| *state != ContextState::Deleted)
+            .map(|(context_id, _, _)| *context_id)
+            .collect()
+    }
+
+    pub fn get_contexts(&self) -> Vec<i32> {
+        self.contexts
+            .iter()
+            .map(|(context_id, _, _)| *context_id)
+            .collect()
+    }
+}
@@ -12,7 +12,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::expectations::Responder;
+use crate::hostcalls::serial_utils::serialize_map;
 use crate::tester::Tester;
+use crate::types::*;
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 // As of now, the following expectations do not require "fn returning()" implementations and hence
 // no structure is provided for them. Setting of these expectations are built directly into tester.rs:
@@ -20,26 +26,122 @@ use crate::tester::Tester;
 // proxy_replace_header_map_value(), proxy_remove_header_map_value(), proxy_add_header_map_value(),
 // proxy_send_local_response(), etc.
 
+#[must_use = "expectations are only staged by returning(..)"]
 pub struct ExpectGetCurrentTimeNanos<'a> {
     tester: &'a mut Tester,
+    staged: bool,
 }
 
 impl<'a> ExpectGetCurrentTimeNanos<'a> {
     pub fn expecting(tester: &'a mut Tester) -> ExpectGetCurrentTimeNanos {
-        ExpectGetCurrentTimeNanos { tester: tester }
+        ExpectGetCurrentTimeNanos {
+            tester: tester,
+            staged: false,
+        }
     }
 
+    #[track_caller]
     pub fn returning(&mut self, current_time_nanos: Option<u64>) -> &mut Tester {
+        self.returning_time(
+            current_time_nanos.map(|time_nanos| UNIX_EPOCH + Duration::from_nanos(time_nanos)),
+        )
+    }
+
+    #[track_caller]
+    pub fn returning_time(&mut self, current_time: Option<SystemTime>) -> &mut Tester {
+        self.staged = true;
+        self.tester
+            .get_expect_handle()
+            .staged
+            .set_expect_get_current_time(current_time);
+        self.tester
+    }
+
+    // Stages one expectation per value, answered in order by successive calls of the host function
+    // (a cardinality set afterwards applies to the last value only)
+    #[track_caller]
+    pub fn returning_seq(&mut self, values: Vec<u64>) -> &mut Tester {
+        assert!(
+            !values.is_empty(),
+            "Error: returning_seq needs at least one value"
+        );
+        for value in values {
+            self.returning(Some(value));
+        }
+        self.tester
+    }
+
+    // Computes the time on each call, e.g. to have it move forward by a step of its own
+    #[track_caller]
+    pub fn returning_with<F>(&mut self, responder: F) -> &mut Tester
+    where
+        F: Fn() -> Option<u64> + Send + Sync + 'static,
+    {
+        self.staged = true;
+        self.tester
+            .get_expect_handle()
+            .staged
+            .set_expect_get_current_time_with(Responder(Arc::new(move || {
+                responder().map(|time_nanos| UNIX_EPOCH + Duration::from_nanos(time_nanos))
+            })));
+        self.tester
+    }
+}
+
+#[must_use = "expectations are only staged by returning(..)"]
+pub struct ExpectGetStatus<'a> {
+    tester: &'a mut Tester,
+    staged: bool,
+}
+
+impl<'a> ExpectGetStatus<'a> {
+    pub fn expecting(tester: &'a mut Tester) -> ExpectGetStatus {
+        ExpectGetStatus {
+            tester: tester,
+            staged: false,
+        }
+    }
+
+    #[track_caller]
+    pub fn returning(&mut self, status_code: Option<u32>, message: Option<&str>) -> &mut Tester {
+        self.staged = true;
+        self.tester
+            .get_expect_handle()
+            .staged
+            .set_expect_get_status(status_code, message);
+        self.tester
+    }
+
+    #[track_caller]
+    pub fn returning_seq(&mut self, values: Vec<(u32, &str)>) -> &mut Tester {
+        assert!(
+            !values.is_empty(),
+            "Error: returning_seq needs at least one value"
+        );
+        for value in values {
+            self.returning(Some(value.0), Some(value.1));
+        }
+        self.tester
+    }
+
+    #[track_caller]
+    pub fn returning_with<F>(&mut self, responder: F) -> &mut Tester
+    where
+        F: Fn() -> (Option<u32>, Option<String>) + Send + Sync + 'static,
+    {
+        self.staged = true;
         self.tester
             .get_expect_handle()
             .staged
-            .set_expect_get_current_time_nanos(current_time_nanos);
+            .set_expect_get_status_with(Responder(Arc::new(responder)));
         self.tester
     }
 }
 
+#[must_use = "expectations are only staged by returning(..)"]
 pub struct ExpectGetBufferBytes<'a> {
     tester: &'a mut Tester,
+    staged: bool,
     buffer_type: Option<i32>,
 }
 
@@ -47,21 +149,70 @@ impl<'a> ExpectGetBufferBytes<'a> {
     pub fn expecting(tester: &'a mut Tester, buffer_type: Option<i32>) -> ExpectGetBufferBytes {
         ExpectGetBufferBytes {
             tester: tester,
+            staged: false,
             buffer_type: buffer_type,
         }
     }
 
+    #[track_caller]
     pub fn returning(&mut self, buffer_data: Option<&str>) -> &mut Tester {
+        self.returning_bytes(buffer_data.map(|data| data.as_bytes()))
+    }
+
+    // Serves binary payloads (compressed, protobuf...) as they are
+    #[track_caller]
+    pub fn returning_bytes(&mut self, buffer_data: Option<&[u8]>) -> &mut Tester {
+        self.staged = true;
         self.tester
             .get_expect_handle()
             .staged
             .set_expect_get_buffer_bytes(self.buffer_type, buffer_data);
         self.tester
     }
+
+    #[track_caller]
+    pub fn returning_seq(&mut self, values: Vec<&str>) -> &mut Tester {
+        assert!(
+            !values.is_empty(),
+            "Error: returning_seq needs at least one value"
+        );
+        for value in values {
+            self.returning(Some(value));
+        }
+        self.tester
+    }
+
+    #[track_caller]
+    pub fn returning_bytes_seq(&mut self, values: Vec<&[u8]>) -> &mut Tester {
+        assert!(
+            !values.is_empty(),
+            "Error: returning_bytes_seq needs at least one value"
+        );
+        for value in values {
+            self.returning_bytes(Some(value));
+        }
+        self.tester
+    }
+
+    // Computes the data from the buffer type requested by the plugin
+    #[track_caller]
+    pub fn returning_with<F>(&mut self, responder: F) -> &mut Tester
+    where
+        F: Fn(i32) -> Option<Bytes> + Send + Sync + 'static,
+    {
+        self.staged = true;
+        self.tester
+            .get_expect_handle()
+            .staged
+            .set_expect_get_buffer_bytes_with(self.buffer_type, Responder(Arc::new(responder)));
+        self.tester
+    }
 }
 
+#[must_use = "expectations are only staged by returning(..)"]
 pub struct ExpectGetHeaderMapPairs<'a> {
     tester: &'a mut Tester,
+    staged: bool,
     map_type: Option<i32>,
 }
 
@@ -69,84 +220,985 @@ impl<'a> ExpectGetHeaderMapPairs<'a> {
     pub fn expecting(tester: &'a mut Tester, map_type: Option<i32>) -> ExpectGetHeaderMapPairs {
         ExpectGetHeaderMapPairs {
             tester: tester,
+            staged: false,
             map_type: map_type,
         }
     }
 
+    #[track_caller]
     pub fn returning(&mut self, header_map_pairs: Option<Vec<(&str, &str)>>) -> &mut Tester {
+        self.staged = true;
         self.tester
             .get_expect_handle()
             .staged
             .set_expect_get_header_map_pairs(self.map_type, header_map_pairs);
         self.tester
     }
+
+    #[track_caller]
+    pub fn returning_seq(&mut self, values: Vec<Vec<(&str, &str)>>) -> &mut Tester {
+        assert!(
+            !values.is_empty(),
+            "Error: returning_seq needs at least one value"
+        );
+        for value in values {
+            self.returning(Some(value));
+        }
+        self.tester
+    }
+
+    // Responds with an already serialized (possibly malformed) header map
+    #[track_caller]
+    pub fn returning_serialized(&mut self, serialized_map: Option<Bytes>) -> &mut Tester {
+        self.staged = true;
+        self.tester
+            .get_expect_handle()
+            .staged
+            .set_expect_get_header_map_pairs_serialized(self.map_type, serialized_map);
+        self.tester
+    }
+
+    // Computes the pairs from the map type requested by the plugin
+    #[track_caller]
+    pub fn returning_with<F>(&mut self, responder: F) -> &mut Tester
+    where
+        F: Fn(i32) -> Option<Vec<(String, String)>> + Send + Sync + 'static,
+    {
+        self.staged = true;
+        self.tester
+            .get_expect_handle()
+            .staged
+            .set_expect_get_header_map_pairs_with(
+                self.map_type,
+                Responder(Arc::new(move |map_type: i32| {
+                    responder(map_type).map(|pairs| serialize_map(to_borrowed_pairs(&pairs)))
+                })),
+            );
+        self.tester
+    }
 }
 
+#[must_use = "expectations are only staged by returning(..)"]
 pub struct ExpectGetHeaderMapValue<'a> {
     tester: &'a mut Tester,
+    staged: bool,
     map_type: Option<i32>,
-    header_map_key: Option<&'static str>,
+    header_map_key: Option<String>,
 }
 
 impl<'a> ExpectGetHeaderMapValue<'a> {
     pub fn expecting(
         tester: &'a mut Tester,
         map_type: Option<i32>,
-        header_map_key: Option<&'static str>,
+        header_map_key: Option<&str>,
     ) -> ExpectGetHeaderMapValue<'a> {
         ExpectGetHeaderMapValue {
             tester: tester,
+            staged: false,
             map_type: map_type,
-            header_map_key: header_map_key,
+            header_map_key: header_map_key.map(|key| key.to_string()),
         }
     }
 
+    #[track_caller]
     pub fn returning(&mut self, header_map_value: Option<&str>) -> &mut Tester {
+        self.returning_bytes(header_map_value.map(|value| value.as_bytes()))
+    }
+
+    // Serves values that are not valid utf-8 as they are
+    #[track_caller]
+    pub fn returning_bytes(&mut self, header_map_value: Option<&[u8]>) -> &mut Tester {
+        self.staged = true;
         self.tester
             .get_expect_handle()
             .staged
-            .set_expect_get_header_map_value(self.map_type, self.header_map_key, header_map_value);
+            .set_expect_get_header_map_value(
+                self.map_type,
+                self.header_map_key.as_deref(),
+                header_map_value,
+            );
         self.tester
     }
+
+    #[track_caller]
+    pub fn returning_seq(&mut self, values: Vec<&str>) -> &mut Tester {
+        assert!(
+            !values.is_empty(),
+            "Error: returning_seq needs at least one value"
+        );
+        for value in values {
+            self.returning(Some(value));
+        }
+        self.tester
+    }
+
+    #[track_caller]
+    pub fn returning_bytes_seq(&mut self, values: Vec<&[u8]>) -> &mut Tester {
+        assert!(
+            !values.is_empty(),
+            "Error: returning_bytes_seq needs at least one value"
+        );
+        for value in values {
+            self.returning_bytes(Some(value));
+        }
+        self.tester
+    }
+
+    // Computes the value from the key requested by the plugin, e.g. |key| Some(key.to_string())
+    #[track_caller]
+    pub fn returning_with<F>(&mut self, responder: F) -> &mut Tester
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        self.staged = true;
+        self.tester
+            .get_expect_handle()
+            .staged
+            .set_expect_get_header_map_value_with(
+                self.map_type,
+                self.header_map_key.as_deref(),
+                Responder(Arc::new(move |key: &str| {
+                    responder(key).map(|value| value.into_bytes())
+                })),
+            );
+        self.tester
+    }
+}
+
+#[must_use = "expectations are only staged by returning(..)"]
+pub struct ExpectGetSharedData<'a> {
+    tester: &'a mut Tester,
+    staged: bool,
+    key: Option<String>,
 }
 
+impl<'a> ExpectGetSharedData<'a> {
+    pub fn expecting(tester: &'a mut Tester, key: Option<&str>) -> ExpectGetSharedData<'a> {
+        ExpectGetSharedData {
+            tester: tester,
+            staged: false,
+            key: key.map(|data| data.to_string()),
+        }
+    }
+
+    #[track_caller]
+    pub fn returning(&mut self, value: Option<&str>, cas: Option<u32>) -> &mut Tester {
+        self.staged = true;
+        self.tester
+            .get_expect_handle()
+            .staged
+            .set_expect_get_shared_data(self.key.as_deref(), value, cas);
+        self.tester
+    }
+
+    #[track_caller]
+    pub fn returning_seq(&mut self, values: Vec<(&str, Option<u32>)>) -> &mut Tester {
+        assert!(
+            !values.is_empty(),
+            "Error: returning_seq needs at least one value"
+        );
+        for value in values {
+            self.returning(Some(value.0), value.1);
+        }
+        self.tester
+    }
+
+    #[track_caller]
+    pub fn returning_with<F>(&mut self, responder: F) -> &mut Tester
+    where
+        F: Fn(&str) -> (Option<String>, Option<u32>) + Send + Sync + 'static,
+    {
+        self.staged = true;
+        self.tester
+            .get_expect_handle()
+            .staged
+            .set_expect_get_shared_data_with(
+                self.key.as_deref(),
+                Responder(Arc::new(move |key: &str| {
+                    let (value, cas) = responder(key);
+                    (value.map(|data| data.into_bytes()), cas)
+                })),
+            );
+        self.tester
+    }
+}
+
+#[must_use = "expectations are only staged by returning(..)"]
+pub struct ExpectResolveSharedQueue<'a> {
+    tester: &'a mut Tester,
+    staged: bool,
+    vm_id: Option<String>,
+    name: Option<String>,
+}
+
+impl<'a> ExpectResolveSharedQueue<'a> {
+    pub fn expecting(
+        tester: &'a mut Tester,
+        vm_id: Option<&str>,
+        name: Option<&str>,
+    ) -> ExpectResolveSharedQueue<'a> {
+        ExpectResolveSharedQueue {
+            tester: tester,
+            staged: false,
+            vm_id: vm_id.map(|data| data.to_string()),
+            name: name.map(|data| data.to_string()),
+        }
+    }
+
+    #[track_caller]
+    pub fn returning(&mut self, queue_id: Option<u32>) -> &mut Tester {
+        self.staged = true;
+        self.tester
+            .get_expect_handle()
+            .staged
+            .set_expect_resolve_shared_queue(self.vm_id.as_deref(), self.name.as_deref(), queue_id);
+        self.tester
+    }
+
+    #[track_caller]
+    pub fn returning_seq(&mut self, values: Vec<u32>) -> &mut Tester {
+        assert!(
+            !values.is_empty(),
+            "Error: returning_seq needs at least one value"
+        );
+        for value in values {
+            self.returning(Some(value));
+        }
+        self.tester
+    }
+
+    // Computes the queue id from the vm id and queue name requested by the plugin
+    #[track_caller]
+    pub fn returning_with<F>(&mut self, responder: F) -> &mut Tester
+    where
+        F: Fn(&str, &str) -> Option<u32> + Send + Sync + 'static,
+    {
+        self.staged = true;
+        self.tester
+            .get_expect_handle()
+            .staged
+            .set_expect_resolve_shared_queue_with(
+                self.vm_id.as_deref(),
+                self.name.as_deref(),
+                Responder(Arc::new(responder)),
+            );
+        self.tester
+    }
+}
+
+#[must_use = "expectations are only staged by returning(..)"]
+pub struct ExpectDequeueSharedQueue<'a> {
+    tester: &'a mut Tester,
+    staged: bool,
+    queue_id: Option<u32>,
+}
+
+impl<'a> ExpectDequeueSharedQueue<'a> {
+    pub fn expecting(
+        tester: &'a mut Tester,
+        queue_id: Option<u32>,
+    ) -> ExpectDequeueSharedQueue<'a> {
+        ExpectDequeueSharedQueue {
+            tester: tester,
+            staged: false,
+            queue_id: queue_id,
+        }
+    }
+
+    #[track_caller]
+    pub fn returning(&mut self, data: Option<&str>) -> &mut Tester {
+        self.staged = true;
+        self.tester
+            .get_expect_handle()
+            .staged
+            .set_expect_dequeue_shared_queue(self.queue_id, data);
+        self.tester
+    }
+
+    #[track_caller]
+    pub fn returning_seq(&mut self, values: Vec<&str>) -> &mut Tester {
+        assert!(
+            !values.is_empty(),
+            "Error: returning_seq needs at least one value"
+        );
+        for value in values {
+            self.returning(Some(value));
+        }
+        self.tester
+    }
+
+    #[track_caller]
+    pub fn returning_with<F>(&mut self, responder: F) -> &mut Tester
+    where
+        F: Fn(u32) -> Option<String> + Send + Sync + 'static,
+    {
+        self.staged = true;
+        self.tester
+            .get_expect_handle()
+            .staged
+            .set_expect_dequeue_shared_queue_with(
+                self.queue_id,
+                Responder(Arc::new(move |queue_id: u32| {
+                    responder(queue_id).map(|data| data.into_bytes())
+                })),
+            );
+        self.tester
+    }
+}
+
+#[must_use = "expectations are only staged by returning(..)"]
+pub struct ExpectGetProperty<'a> {
+    tester: &'a mut Tester,
+    staged: bool,
+    path: Option<Vec<String>>,
+}
+
+impl<'a> ExpectGetProperty<'a> {
+    pub fn expecting(tester: &'a mut Tester, path: Option<Vec<&str>>) -> ExpectGetProperty<'a> {
+        ExpectGetProperty {
+            tester: tester,
+            staged: false,
+            path: path.map(|data| data.iter().map(|part| part.to_string()).collect()),
+        }
+    }
+
+    #[track_caller]
+    pub fn returning(&mut self, value: Option<&[u8]>) -> &mut Tester {
+        let path = self
+            .path
+            .as_ref()
+            .map(|data| data.iter().map(|part| part.as_str()).collect());
+        self.staged = true;
+        self.tester
+            .get_expect_handle()
+            .staged
+            .set_expect_get_property(path, value);
+        self.tester
+    }
+
+    #[track_caller]
+    pub fn returning_seq(&mut self, values: Vec<&[u8]>) -> &mut Tester {
+        assert!(
+            !values.is_empty(),
+            "Error: returning_seq needs at least one value"
+        );
+        for value in values {
+            self.returning(Some(value));
+        }
+        self.tester
+    }
+
+    #[track_caller]
+    pub fn returning_value<T: Into<PropertyValue>>(&mut self, value: T) -> &mut Tester {
+        let value = value.into().encode();
+        self.returning(Some(&value))
+    }
+
+    // Computes the value from the path requested by the plugin, e.g. to serve a whole subtree
+    #[track_caller]
+    pub fn returning_with<F>(&mut self, responder: F) -> &mut Tester
+    where
+        F: Fn(&[&str]) -> Option<Bytes> + Send + Sync + 'static,
+    {
+        let path = self
+            .path
+            .as_ref()
+            .map(|data| data.iter().map(|part| part.as_str()).collect());
+        self.staged = true;
+        self.tester
+            .get_expect_handle()
+            .staged
+            .set_expect_get_property_with(
+                path,
+                Responder(Arc::new(move |path: &[u8]| {
+                    let path = String::from_utf8_lossy(path);
+                    let parts: Vec<&str> = match path.is_empty() {
+                        true => Vec::new(),
+                        false => path.split('\0').collect(),
+                    };
+                    responder(&parts)
+                })),
+            );
+        self.tester
+    }
+}
+
+#[must_use = "expectations are only staged by returning(..)"]
+pub struct ExpectDefineMetric<'a> {
+    tester: &'a mut Tester,
+    staged: bool,
+    metric_type: Option<i32>,
+    name: Option<String>,
+}
+
+impl<'a> ExpectDefineMetric<'a> {
+    pub fn expecting(
+        tester: &'a mut Tester,
+        metric_type: Option<i32>,
+        name: Option<&str>,
+    ) -> ExpectDefineMetric<'a> {
+        ExpectDefineMetric {
+            tester: tester,
+            staged: false,
+            metric_type: metric_type,
+            name: name.map(|data| data.to_string()),
+        }
+    }
+
+    #[track_caller]
+    pub fn returning(&mut self, metric_id: Option<u32>) -> &mut Tester {
+        self.staged = true;
+        self.tester
+            .get_expect_handle()
+            .staged
+            .set_expect_define_metric(self.metric_type, self.name.as_deref(), metric_id);
+        self.tester
+    }
+
+    #[track_caller]
+    pub fn returning_seq(&mut self, values: Vec<u32>) -> &mut Tester {
+        assert!(
+            !values.is_empty(),
+            "Error: returning_seq needs at least one value"
+        );
+        for value in values {
+            self.returning(Some(value));
+        }
+        self.tester
+    }
+
+    // Computes the metric id from the type and name the plugin defines
+    #[track_caller]
+    pub fn returning_with<F>(&mut self, responder: F) -> &mut Tester
+    where
+        F: Fn(i32, &str) -> Option<u32> + Send + Sync + 'static,
+    {
+        self.staged = true;
+        self.tester
+            .get_expect_handle()
+            .staged
+            .set_expect_define_metric_with(
+                self.metric_type,
+                self.name.as_deref(),
+                Responder(Arc::new(responder)),
+            );
+        self.tester
+    }
+}
+
+#[must_use = "expectations are only staged by returning(..)"]
+pub struct ExpectGetMetric<'a> {
+    tester: &'a mut Tester,
+    staged: bool,
+    metric_id: Option<u32>,
+}
+
+impl<'a> ExpectGetMetric<'a> {
+    pub fn expecting(tester: &'a mut Tester, metric_id: Option<u32>) -> ExpectGetMetric<'a> {
+        ExpectGetMetric {
+            tester: tester,
+            staged: false,
+            metric_id: metric_id,
+        }
+    }
+
+    #[track_caller]
+    pub fn returning(&mut self, value: Option<u64>) -> &mut Tester {
+        self.staged = true;
+        self.tester
+            .get_expect_handle()
+            .staged
+            .set_expect_get_metric(self.metric_id, value);
+        self.tester
+    }
+
+    #[track_caller]
+    pub fn returning_seq(&mut self, values: Vec<u64>) -> &mut Tester {
+        assert!(
+            !values.is_empty(),
+            "Error: returning_seq needs at least one value"
+        );
+        for value in values {
+            self.returning(Some(value));
+        }
+        self.tester
+    }
+
+    #[track_caller]
+    pub fn returning_with<F>(&mut self, responder: F) -> &mut Tester
+    where
+        F: Fn(u32) -> Option<u64> + Send + Sync + 'static,
+    {
+        self.staged = true;
+        self.tester
+            .get_expect_handle()
+            .staged
+            .set_expect_get_metric_with(self.metric_id, Responder(Arc::new(responder)));
+        self.tester
+    }
+}
+
+#[must_use = "expectations are only staged by returning(..)"]
 pub struct ExpectHttpCall<'a> {
     tester: &'a mut Tester,
-    upstream: Option<&'a str>,
-    headers: Option<Option<Vec<(&'a str, &'a str)>>>,
-    body: Option<&'a str>,
-    trailers: Option<Option<Vec<(&'a str, &'a str)>>>,
-    timeout: Option<u64>,
+    staged: bool,
+    upstream: Option<String>,
+    headers: Option<Vec<(String, String)>>,
+    body: Option<String>,
+    trailers: Option<Vec<(String, String)>>,
+    timeout: Option<Duration>,
 }
 
 impl<'a> ExpectHttpCall<'a> {
     pub fn expecting(
         tester: &'a mut Tester,
-        upstream: Option<&'a str>,
-        headers: Option<Vec<(&'a str, &'a str)>>,
-        body: Option<&'a str>,
-        trailers: Option<Vec<(&'a str, &'a str)>>,
+        upstream: Option<&str>,
+        headers: Option<Vec<(&str, &str)>>,
+        body: Option<&str>,
+        trailers: Option<Vec<(&str, &str)>>,
         timeout: Option<u64>,
     ) -> ExpectHttpCall<'a> {
         ExpectHttpCall {
             tester: tester,
-            upstream: upstream,
-            headers: Some(headers),
-            body: body,
-            trailers: Some(trailers),
-            timeout: timeout,
+            staged: false,
+            upstream: upstream.map(|data| data.to_string()),
+            headers: headers.map(|data| to_owned_pairs(data)),
+            body: body.map(|data| data.to_string()),
+            trailers: trailers.map(|data| to_owned_pairs(data)),
+            timeout: timeout.map(Duration::from_millis),
         }
     }
 
+    // Overrides the timeout given in milliseconds to expecting()
+    pub fn with_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    #[track_caller]
     pub fn returning(&mut self, token_id: Option<u32>) -> &mut Tester {
+        self.staged = true;
         self.tester.get_expect_handle().staged.set_expect_http_call(
-            self.upstream,
-            self.headers.take().unwrap(),
-            self.body,
-            self.trailers.take().unwrap(),
+            self.upstream.as_deref(),
+            self.headers.as_ref().map(|data| to_borrowed_pairs(data)),
+            self.body.as_deref(),
+            self.trailers.as_ref().map(|data| to_borrowed_pairs(data)),
+            self.timeout,
+            token_id,
+        );
+        self.tester
+    }
+
+    #[track_caller]
+    pub fn returning_seq(&mut self, values: Vec<u32>) -> &mut Tester {
+        assert!(
+            !values.is_empty(),
+            "Error: returning_seq needs at least one value"
+        );
+        for value in values {
+            self.returning(Some(value));
+        }
+        self.tester
+    }
+
+    // Computes the token from the body of the callout, e.g. to tell apart concurrent callouts
+    #[track_caller]
+    pub fn returning_with<F>(&mut self, responder: F) -> &mut Tester
+    where
+        F: Fn(Option<&str>) -> Option<u32> + Send + Sync + 'static,
+    {
+        self.staged = true;
+        self.tester
+            .get_expect_handle()
+            .staged
+            .set_expect_http_call_with(
+                self.upstream.as_deref(),
+                self.headers.as_ref().map(|data| to_borrowed_pairs(data)),
+                self.body.as_deref(),
+                self.trailers.as_ref().map(|data| to_borrowed_pairs(data)),
+                self.timeout,
+                Responder(Arc::new(responder)),
+            );
+        self.tester
+    }
+
+    // Satisfies the callout with a token of its own and delivers the response to the calling
+    // context through proxy_on_http_call_response once the current callback returns
+    #[track_caller]
+    pub fn returning_response(
+        &mut self,
+        status_code: u32,
+        headers: Vec<(&str, &str)>,
+        body: Option<&str>,
+    ) -> &mut Tester {
+        self.returning_callout_response(
+            HttpCalloutResponse::new(status_code)
+                .with_headers(headers)
+                .with_body(body.unwrap_or("").as_bytes()),
+        )
+    }
+
+    // Same as returning_response with trailers or a binary body
+    #[track_caller]
+    pub fn returning_callout_response(&mut self, response: HttpCalloutResponse) -> &mut Tester {
+        let token_id = self
+            .tester
+            .get_settings_handle()
+            .staged
+            .set_http_call_response(response);
+        self.returning(Some(token_id))
+    }
+}
+
+#[must_use = "expectations are only staged by returning(..)"]
+pub struct ExpectCallForeignFunction<'a> {
+    tester: &'a mut Tester,
+    staged: bool,
+    name: Option<String>,
+    args: Option<Bytes>,
+}
+
+impl<'a> ExpectCallForeignFunction<'a> {
+    pub fn expecting(
+        tester: &'a mut Tester,
+        name: Option<&str>,
+        args: Option<&[u8]>,
+    ) -> ExpectCallForeignFunction<'a> {
+        ExpectCallForeignFunction {
+            tester: tester,
+            staged: false,
+            name: name.map(|data| data.to_string()),
+            args: args.map(|data| data.to_vec()),
+        }
+    }
+
+    #[track_caller]
+    pub fn returning(&mut self, results: Option<&[u8]>) -> &mut Tester {
+        self.staged = true;
+        self.tester
+            .get_expect_handle()
+            .staged
+            .set_expect_call_foreign_function(self.name.as_deref(), self.args.as_deref(), results);
+        self.tester
+    }
+
+    #[track_caller]
+    pub fn returning_seq(&mut self, values: Vec<&[u8]>) -> &mut Tester {
+        assert!(
+            !values.is_empty(),
+            "Error: returning_seq needs at least one value"
+        );
+        for value in values {
+            self.returning(Some(value));
+        }
+        self.tester
+    }
+
+    // Computes the results from the arguments the plugin passed to the foreign function
+    #[track_caller]
+    pub fn returning_with<F>(&mut self, responder: F) -> &mut Tester
+    where
+        F: Fn(&[u8]) -> Bytes + Send + Sync + 'static,
+    {
+        self.staged = true;
+        self.tester
+            .get_expect_handle()
+            .staged
+            .set_expect_call_foreign_function_with(
+                self.name.as_deref(),
+                self.args.as_deref(),
+                Responder(Arc::new(responder)),
+            );
+        self.tester
+    }
+}
+
+#[must_use = "expectations are only staged by returning(..)"]
+pub struct ExpectGrpcCall<'a> {
+    tester: &'a mut Tester,
+    staged: bool,
+    upstream: Option<String>,
+    service: Option<String>,
+    method: Option<String>,
+    initial_metadata: Option<Vec<(String, String)>>,
+    message: Option<Bytes>,
+    timeout: Option<Duration>,
+}
+
+impl<'a> ExpectGrpcCall<'a> {
+    pub fn expecting(
+        tester: &'a mut Tester,
+        upstream: Option<&str>,
+        service: Option<&str>,
+        method: Option<&str>,
+        initial_metadata: Option<Vec<(&str, &str)>>,
+        message: Option<&[u8]>,
+        timeout: Option<u64>,
+    ) -> ExpectGrpcCall<'a> {
+        ExpectGrpcCall {
+            tester: tester,
+            staged: false,
+            upstream: upstream.map(|data| data.to_string()),
+            service: service.map(|data| data.to_string()),
+            method: method.map(|data| data.to_string()),
+            initial_metadata: initial_metadata.map(|data| to_owned_pairs(data)),
+            message: message.map(|data| data.to_vec()),
+            timeout: timeout.map(Duration::from_millis),
+        }
+    }
+
+    // Overrides the timeout given in milliseconds to expecting()
+    pub fn with_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    #[track_caller]
+    pub fn returning(&mut self, token_id: Option<u32>) -> &mut Tester {
+        self.staged = true;
+        self.tester.get_expect_handle().staged.set_expect_grpc_call(
+            self.upstream.as_deref(),
+            self.service.as_deref(),
+            self.method.as_deref(),
+            self.initial_metadata
+                .as_ref()
+                .map(|data| to_borrowed_pairs(data)),
+            self.message.as_deref(),
             self.timeout,
             token_id,
         );
         self.tester
     }
+
+    #[track_caller]
+    pub fn returning_seq(&mut self, values: Vec<u32>) -> &mut Tester {
+        assert!(
+            !values.is_empty(),
+            "Error: returning_seq needs at least one value"
+        );
+        for value in values {
+            self.returning(Some(value));
+        }
+        self.tester
+    }
+
+    // Computes the token from the message of the call
+    #[track_caller]
+    pub fn returning_with<F>(&mut self, responder: F) -> &mut Tester
+    where
+        F: Fn(&[u8]) -> Option<u32> + Send + Sync + 'static,
+    {
+        self.staged = true;
+        self.tester
+            .get_expect_handle()
+            .staged
+            .set_expect_grpc_call_with(
+                self.upstream.as_deref(),
+                self.service.as_deref(),
+                self.method.as_deref(),
+                self.initial_metadata
+                    .as_ref()
+                    .map(|data| to_borrowed_pairs(data)),
+                self.message.as_deref(),
+                self.timeout,
+                Responder(Arc::new(responder)),
+            );
+        self.tester
+    }
+}
+
+#[must_use = "expectations are only staged by returning(..)"]
+pub struct ExpectGrpcStream<'a> {
+    tester: &'a mut Tester,
+    staged: bool,
+    upstream: Option<String>,
+    service: Option<String>,
+    method: Option<String>,
+    initial_metadata: Option<Vec<(String, String)>>,
+}
+
+impl<'a> ExpectGrpcStream<'a> {
+    pub fn expecting(
+        tester: &'a mut Tester,
+        upstream: Option<&str>,
+        service: Option<&str>,
+        method: Option<&str>,
+        initial_metadata: Option<Vec<(&str, &str)>>,
+    ) -> ExpectGrpcStream<'a> {
+        ExpectGrpcStream {
+            tester: tester,
+            staged: false,
+            upstream: upstream.map(|data| data.to_string()),
+            service: service.map(|data| data.to_string()),
+            method: method.map(|data| data.to_string()),
+            initial_metadata: initial_metadata.map(|data| to_owned_pairs(data)),
+        }
+    }
+
+    #[track_caller]
+    pub fn returning(&mut self, token_id: Option<u32>) -> &mut Tester {
+        self.staged = true;
+        self.tester
+            .get_expect_handle()
+            .staged
+            .set_expect_grpc_stream(
+                self.upstream.as_deref(),
+                self.service.as_deref(),
+                self.method.as_deref(),
+                self.initial_metadata
+                    .as_ref()
+                    .map(|data| to_borrowed_pairs(data)),
+                token_id,
+            );
+        self.tester
+    }
+
+    #[track_caller]
+    pub fn returning_seq(&mut self, values: Vec<u32>) -> &mut Tester {
+        assert!(
+            !values.is_empty(),
+            "Error: returning_seq needs at least one value"
+        );
+        for value in values {
+            self.returning(Some(value));
+        }
+        self.tester
+    }
+
+    // Computes the token from the method the stream is opened on
+    #[track_caller]
+    pub fn returning_with<F>(&mut self, responder: F) -> &mut Tester
+    where
+        F: Fn(&str) -> Option<u32> + Send + Sync + 'static,
+    {
+        self.staged = true;
+        self.tester
+            .get_expect_handle()
+            .staged
+            .set_expect_grpc_stream_with(
+                self.upstream.as_deref(),
+                self.service.as_deref(),
+                self.method.as_deref(),
+                self.initial_metadata
+                    .as_ref()
+                    .map(|data| to_borrowed_pairs(data)),
+                Responder(Arc::new(responder)),
+            );
+        self.tester
+    }
+}
+
+impl<'a> Drop for ExpectGetCurrentTimeNanos<'a> {
+    fn drop(&mut self) {
+        check_staged(self.staged, "expect_get_current_time_nanos");
+    }
+}
+
+impl<'a> Drop for ExpectGetStatus<'a> {
+    fn drop(&mut self) {
+        check_staged(self.staged, "expect_get_status");
+    }
+}
+
+impl<'a> Drop for ExpectGetBufferBytes<'a> {
+    fn drop(&mut self) {
+        check_staged(self.staged, "expect_get_buffer_bytes");
+    }
+}
+
+impl<'a> Drop for ExpectGetHeaderMapPairs<'a> {
+    fn drop(&mut self) {
+        check_staged(self.staged, "expect_get_header_map_pairs");
+    }
+}
+
+impl<'a> Drop for ExpectGetHeaderMapValue<'a> {
+    fn drop(&mut self) {
+        check_staged(self.staged, "expect_get_header_map_value");
+    }
+}
+
+impl<'a> Drop for ExpectGetSharedData<'a> {
+    fn drop(&mut self) {
+        check_staged(self.staged, "expect_get_shared_data");
+    }
+}
+
+impl<'a> Drop for ExpectResolveSharedQueue<'a> {
+    fn drop(&mut self) {
+        check_staged(self.staged, "expect_resolve_shared_queue");
+    }
+}
+
+impl<'a> Drop for ExpectDequeueSharedQueue<'a> {
+    fn drop(&mut self) {
+        check_staged(self.staged, "expect_dequeue_shared_queue");
+    }
+}
+
+impl<'a> Drop for ExpectGetProperty<'a> {
+    fn drop(&mut self) {
+        check_staged(self.staged, "expect_get_property");
+    }
+}
+
+impl<'a> Drop for ExpectDefineMetric<'a> {
+    fn drop(&mut self) {
+        check_staged(self.staged, "expect_define_metric");
+    }
+}
+
+impl<'a> Drop for ExpectGetMetric<'a> {
+    fn drop(&mut self) {
+        check_staged(self.staged, "expect_get_metric");
+    }
+}
+
+impl<'a> Drop for ExpectHttpCall<'a> {
+    fn drop(&mut self) {
+        check_staged(self.staged, "expect_http_call");
+    }
+}
+
+impl<'a> Drop for ExpectCallForeignFunction<'a> {
+    fn drop(&mut self) {
+        check_staged(self.staged, "expect_call_foreign_function");
+    }
+}
+
+impl<'a> Drop for ExpectGrpcCall<'a> {
+    fn drop(&mut self) {
+        check_staged(self.staged, "expect_grpc_call");
+    }
+}
+
+impl<'a> Drop for ExpectGrpcStream<'a> {
+    fn drop(&mut self) {
+        check_staged(self.staged, "expect_grpc_stream");
+    }
+}
+
+// Builders dropped without returning(..) would silently stage nothing, leaving the test to fail
+// later on an unexpected host call
+fn check_staged(staged: bool, expectation: &str) {
+    if !staged && !std::thread::panicking() {
+        panic!(
+            "Error: {}(..) was never completed with returning(..) and staged nothing",
+            expectation
+        );
+    }
+}
+
+fn to_owned_pairs(pairs: Vec<(&str, &str)>) -> Vec<(String, String)> {
+    pairs
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+fn to_borrowed_pairs(pairs: &[(String, String)]) -> Vec<(&str, &str)> {
+    pairs
+        .iter()
+        .map(|(key, value)| (key as &str, value as &str))
+        .collect()
 }
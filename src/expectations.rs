@@ -12,46 +12,184 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::hostcalls::{serial_utils::serialize_map, set_status};
+use crate::hostcalls::serial_utils::{
+    deserialize_map, serialize_map, serialize_map_bytes, serialize_property_path,
+};
+use crate::scaffold;
 use crate::types::*;
 
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use anyhow::Result;
+use regex::Regex;
+use serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+use std::panic::Location;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
-fn set_expect_status(checks: bool) {
-    if checks {
-        set_status(ExpectStatus::Expected)
-    } else {
-        set_status(ExpectStatus::Failed);
+// Compiles a regex pattern once, when it is staged or imported, so matching host calls against it
+// neither recompiles it nor fails mid-test
+fn check_pattern(string_match: &mut Option<StringMatch>) -> Result<()> {
+    if let Some(StringMatch::Regex(pattern, compiled)) = string_match {
+        if compiled.is_none() {
+            *compiled = Some(Regex::new(pattern).map_err(|error| {
+                anyhow::format_err!("Error: invalid pattern {:?}: {}", pattern, error)
+            })?);
+        }
+    }
+    Ok(())
+}
+
+// Closure computing the answer of a staged expectation from the arguments of the host call, which
+// unlike the rest of the stage cannot be exported to another process
+pub struct Responder<F: ?Sized>(pub Arc<F>);
+
+pub type HeaderMapValueResponder = Responder<dyn Fn(&str) -> Option<Bytes> + Send + Sync>;
+pub type SharedDataResponder =
+    Responder<dyn Fn(&str) -> (Option<Bytes>, Option<u32>) + Send + Sync>;
+pub type ForeignFunctionResponder = Responder<dyn Fn(&[u8]) -> Bytes + Send + Sync>;
+pub type CurrentTimeResponder = Responder<dyn Fn() -> Option<SystemTime> + Send + Sync>;
+pub type StatusResponder = Responder<dyn Fn() -> (Option<u32>, Option<String>) + Send + Sync>;
+pub type BufferBytesResponder = Responder<dyn Fn(i32) -> Option<Bytes> + Send + Sync>;
+pub type HeaderMapPairsResponder = Responder<dyn Fn(i32) -> Option<Bytes> + Send + Sync>;
+pub type ResolveSharedQueueResponder = Responder<dyn Fn(&str, &str) -> Option<u32> + Send + Sync>;
+pub type DequeueSharedQueueResponder = Responder<dyn Fn(u32) -> Option<Bytes> + Send + Sync>;
+pub type PropertyResponder = Responder<dyn Fn(&[u8]) -> Option<Bytes> + Send + Sync>;
+pub type DefineMetricResponder = Responder<dyn Fn(i32, &str) -> Option<u32> + Send + Sync>;
+pub type MetricResponder = Responder<dyn Fn(u32) -> Option<u64> + Send + Sync>;
+pub type HttpCallResponder = Responder<dyn Fn(Option<&str>) -> Option<u32> + Send + Sync>;
+pub type GrpcCallResponder = Responder<dyn Fn(&[u8]) -> Option<u32> + Send + Sync>;
+pub type GrpcStreamResponder = Responder<dyn Fn(&str) -> Option<u32> + Send + Sync>;
+
+impl<F: ?Sized> Clone for Responder<F> {
+    fn clone(&self) -> Self {
+        Responder(self.0.clone())
+    }
+}
+
+impl<F: ?Sized> fmt::Debug for Responder<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Responder")
+    }
+}
+
+impl<F: ?Sized> Serialize for Responder<F> {
+    fn serialize<S: Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+        Err(ser::Error::custom(
+            "returning_with closures cannot be exported",
+        ))
+    }
+}
+
+impl<'de, F: ?Sized> Deserialize<'de> for Responder<F> {
+    fn deserialize<D: Deserializer<'de>>(_: D) -> Result<Self, D::Error> {
+        Err(de::Error::custom(
+            "returning_with closures cannot be imported",
+        ))
+    }
+}
+
+// Handle on the arguments a plugin passed to a host function, filled in when the expectation it was
+// staged with matches so that the test can inspect data it cannot predict (signatures, compressed
+// bodies...) after the execution
+#[derive(Clone, Default)]
+pub struct Capture(Arc<Mutex<Vec<Bytes>>>);
+
+impl Capture {
+    pub fn new() -> Capture {
+        Capture::default()
+    }
+
+    fn push(&self, data: &[u8]) {
+        self.0.lock().unwrap().push(data.to_vec());
+    }
+
+    // Data of the last matching call, if any
+    pub fn get(&self) -> Option<Bytes> {
+        self.0.lock().unwrap().last().cloned()
+    }
+
+    pub fn get_string(&self) -> Option<String> {
+        self.get()
+            .map(|data| String::from_utf8_lossy(&data).to_string())
+    }
+
+    // Last header map captured, deserialized into its pairs
+    pub fn get_pairs(&self) -> Option<Vec<(String, String)>> {
+        self.get().map(|data| deserialize_map(&data))
+    }
+
+    pub fn get_all(&self) -> Vec<Bytes> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl fmt::Debug for Capture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Capture({} calls)", self.0.lock().unwrap().len())
+    }
+}
+
+impl Serialize for Capture {
+    fn serialize<S: Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+        Err(ser::Error::custom("captures cannot be exported"))
+    }
+}
+
+impl<'de> Deserialize<'de> for Capture {
+    fn deserialize<D: Deserializer<'de>>(_: D) -> Result<Self, D::Error> {
+        Err(de::Error::custom("captures cannot be imported"))
     }
 }
 
 // Global structure for handling low-level expectation structure (staged)
 pub struct ExpectHandle {
     pub staged: Expect,
+    warnings: Vec<String>,
 }
 
 impl ExpectHandle {
     pub fn new() -> ExpectHandle {
         ExpectHandle {
             staged: Expect::new(false),
+            warnings: Vec::new(),
         }
     }
 
     pub fn update_stage(&mut self, allow_unexpected: bool) {
-        self.staged = Expect::new(allow_unexpected);
+        if self.staged.allow_unexpected {
+            self.warnings.extend(self.staged.unexpected_calls.drain(..));
+        }
+        self.staged.next_stage(allow_unexpected);
+    }
+
+    // Unexpected host calls let through in lenient mode over all previous stages
+    pub fn get_warnings(&self) -> &[String] {
+        &self.warnings
     }
 
     pub fn assert_stage(&self) {
         if self.staged.expect_count > 0 {
             panic!(
-                "Error: failed to consume all expectations - total remaining: {}",
-                self.staged.expect_count
+                "Error: failed to consume all expectations - total remaining: {}\n{}",
+                self.staged.expect_count,
+                self.staged
+                    .get_unmet_expectations()
+                    .iter()
+                    .map(|unmet| format!("  - {}\n", unmet))
+                    .collect::<String>()
             );
         } else if self.staged.expect_count < 0 {
             panic!(
                 "Error: expectations failed to account for all host calls by {} \n\
-            if this is intended, please use --allow-unexpected (-a) mode",
-                -1 * self.staged.expect_count
+            if this is intended, please use --allow-unexpected (-a) mode or ExpectMode::Lenient\n\
+            unexpected host calls:\n{}",
+                -1 * self.staged.expect_count,
+                self.staged
+                    .unexpected_calls
+                    .iter()
+                    .map(|call| format!("  + {}\n", call))
+                    .collect::<String>()
             );
         }
     }
@@ -59,24 +197,192 @@ impl ExpectHandle {
     pub fn print_staged(&self) {
         println!("{:?}", self.staged);
     }
+
+    pub fn export_stage(&self) -> Result<String> {
+        Ok(serde_json::to_string(&self.staged)?)
+    }
+
+    pub fn import_stage(&mut self, serialized_stage: &str) -> Result<()> {
+        let mut staged: Expect = serde_json::from_str(serialized_stage)?;
+        // the receiving process keeps its own policy on unexpected and ignored host calls
+        staged.allow_unexpected = self.staged.allow_unexpected;
+        staged.ignored = std::mem::take(&mut self.staged.ignored);
+        staged.colored_diffs = self.staged.colored_diffs;
+        staged.status = self.staged.status.clone();
+        for (_, string_match) in staged.log_message.iter_mut() {
+            check_pattern(string_match)?;
+        }
+        for (_, _, value_match) in staged
+            .replace_header_map_value
+            .iter_mut()
+            .chain(staged.add_header_map_value.iter_mut())
+        {
+            check_pattern(value_match)?;
+        }
+        self.staged = staged;
+        Ok(())
+    }
+}
+
+// Number of host calls a staged expectation accounts for, at least min and at most max (unbounded
+// when None), along with the calls it accounted for so far and where the test staged it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Cardinality {
+    min: u32,
+    max: Option<u32>,
+    calls: u32,
+    location: String,
+    #[serde(default)]
+    name: Option<String>,
+    persistent: bool,
+}
+
+impl Cardinality {
+    fn once(location: String) -> Cardinality {
+        Cardinality {
+            min: 1,
+            max: Some(1),
+            calls: 0,
+            location: location,
+            name: None,
+            persistent: false,
+        }
+    }
+
+    // Where the expectation comes from in failure output, led by its name if the test gave one
+    fn origin(&self) -> String {
+        match &self.name {
+            Some(name) => format!("\"{}\" staged at {}", name, self.location),
+            None => format!("staged at {}", self.location),
+        }
+    }
+}
+
+// How a staged expectation compares a string argument of a host call (log messages, header map
+// values), predicates cover values the test cannot predict such as UUIDs, hashes or timestamps
+#[derive(Clone, Serialize, Deserialize)]
+pub enum StringMatch {
+    Exact(String),
+    // header map values are not necessarily valid utf-8
+    Bytes(Bytes),
+    Contains(String),
+    Regex(String, #[serde(skip)] Option<Regex>),
+    #[serde(skip)]
+    Predicate(Arc<dyn Fn(&str) -> bool + Send + Sync>),
+}
+
+impl StringMatch {
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            StringMatch::Exact(expected) => value == expected,
+            StringMatch::Bytes(expected) => value.as_bytes() == &expected[..],
+            StringMatch::Contains(substring) => value.contains(substring.as_str()),
+            // compiled by check_pattern when staged or imported
+            StringMatch::Regex(_, compiled) => compiled
+                .as_ref()
+                .map_or(false, |compiled| compiled.is_match(value)),
+            StringMatch::Predicate(predicate) => predicate(value),
+        }
+    }
+
+    // Exact matches compare the bytes as they are, the others the value decoded as utf-8
+    fn matches_bytes(&self, value: &[u8]) -> bool {
+        match self {
+            StringMatch::Exact(expected) => value == expected.as_bytes(),
+            StringMatch::Bytes(expected) => value == &expected[..],
+            _ => self.matches(&String::from_utf8_lossy(value)),
+        }
+    }
+}
+
+impl fmt::Debug for StringMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StringMatch::Exact(expected) => write!(f, "{:?}", expected),
+            StringMatch::Bytes(expected) => match std::str::from_utf8(expected) {
+                Ok(expected) => write!(f, "{:?}", expected),
+                Err(_) => write!(f, "{:?}", expected),
+            },
+            StringMatch::Contains(substring) => write!(f, "Contains({:?})", substring),
+            StringMatch::Regex(pattern, _) => write!(f, "Regex({:?})", pattern),
+            StringMatch::Predicate(_) => write!(f, "Predicate"),
+        }
+    }
+}
+
+// Keeps the staged expectations whose cardinality is persistent, see Expect::next_stage
+fn retain_persistent<T>(staged: &mut Vec<T>, cardinality: Option<&Vec<Cardinality>>) {
+    let mut index = 0;
+    staged.retain(|_| {
+        index += 1;
+        cardinality
+            .and_then(|cardinality| cardinality.get(index - 1))
+            .map_or(false, |cardinality| cardinality.persistent)
+    });
+}
+
+fn count_persistent(cardinality: Option<&Vec<Cardinality>>) -> u32 {
+    cardinality.map_or(0, |cardinality| {
+        cardinality
+            .iter()
+            .filter(|cardinality| cardinality.persistent)
+            .count() as u32
+    })
+}
+
+fn new_status() -> Arc<Mutex<ExpectStatus>> {
+    Arc::new(Mutex::new(ExpectStatus::Unexpected))
 }
 
 // Structure for setting low-level expectations over specific host functions
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Expect {
+    // outcome of the host call being handled, shared with the host functions of the tester
+    #[serde(skip, default = "new_status")]
+    status: Arc<Mutex<ExpectStatus>>,
+    #[serde(skip)]
     allow_unexpected: bool,
+    #[serde(skip)]
+    recording: Option<Vec<String>>,
+    #[serde(skip)]
+    last_staged: Option<String>,
+    #[serde(skip)]
+    unexpected_calls: Vec<String>,
+    #[serde(skip)]
+    ignored: Vec<HostcallKind>,
+    #[serde(skip)]
+    matched_calls: Vec<String>,
+    #[serde(skip)]
+    last_taken: String,
+    #[serde(skip)]
+    last_taken_origin: String,
+    #[serde(skip)]
+    colored_diffs: bool,
     pub expect_count: i32,
-    log_message: Vec<(Option<i32>, Option<String>)>,
-    tick_period_millis: Vec<Option<Duration>>,
-    current_time_nanos: Vec<Option<SystemTime>>,
-    get_buffer_bytes: Vec<(Option<i32>, Option<Bytes>)>,
-    set_buffer_bytes: Vec<(Option<i32>, Option<Bytes>)>,
-    get_header_map_pairs: Vec<(Option<i32>, Option<Bytes>)>,
-    set_header_map_pairs: Vec<(Option<i32>, Option<Bytes>)>,
-    get_header_map_value: Vec<(Option<i32>, Option<String>, Option<String>)>,
-    replace_header_map_value: Vec<(Option<i32>, Option<String>, Option<String>)>,
+    forbidden: Vec<String>,
+    cardinality: HashMap<String, Vec<Cardinality>>,
+    log_message: Vec<(Option<i32>, Option<StringMatch>)>,
+    get_status: Vec<(Option<u32>, Option<String>, Option<StatusResponder>)>,
+    tick_period: Vec<Option<Duration>>,
+    current_time: Vec<(Option<SystemTime>, Option<CurrentTimeResponder>)>,
+    get_buffer_bytes: Vec<(Option<i32>, Option<Bytes>, Option<BufferBytesResponder>)>,
+    set_buffer_bytes: Vec<(Option<i32>, Option<Bytes>, Option<Capture>)>,
+    get_header_map_pairs: Vec<(Option<i32>, Option<Bytes>, Option<HeaderMapPairsResponder>)>,
+    set_header_map_pairs: Vec<(
+        Option<i32>,
+        Option<Bytes>,
+        Option<Vec<(String, String)>>,
+        Option<Capture>,
+    )>,
+    get_header_map_value: Vec<(
+        Option<i32>,
+        Option<String>,
+        Option<Bytes>,
+        Option<HeaderMapValueResponder>,
+    )>,
+    replace_header_map_value: Vec<(Option<i32>, Option<String>, Option<StringMatch>)>,
     remove_header_map_value: Vec<(Option<i32>, Option<String>)>,
-    add_header_map_value: Vec<(Option<i32>, Option<String>, Option<String>)>,
+    add_header_map_value: Vec<(Option<i32>, Option<String>, Option<StringMatch>)>,
     send_local_response: Vec<(Option<i32>, Option<String>, Option<Bytes>, Option<i32>)>,
     http_call: Vec<(
         Option<String>,
@@ -85,17 +391,97 @@ pub struct Expect {
         Option<Bytes>,
         Option<Duration>,
         Option<u32>,
+        Option<HttpCallResponder>,
+    )>,
+    grpc_call: Vec<(
+        Option<String>,
+        Option<String>,
+        Option<String>,
+        Option<Bytes>,
+        Option<Bytes>,
+        Option<Duration>,
+        Option<u32>,
+        Option<GrpcCallResponder>,
+    )>,
+    grpc_stream: Vec<(
+        Option<String>,
+        Option<String>,
+        Option<String>,
+        Option<Bytes>,
+        Option<u32>,
+        Option<GrpcStreamResponder>,
     )>,
+    grpc_send: Vec<(Option<u32>, Option<Bytes>, Option<bool>)>,
+    grpc_cancel: Vec<Option<u32>>,
+    grpc_close: Vec<Option<u32>>,
+    set_effective_context: Vec<Option<i32>>,
+    call_foreign_function: Vec<(
+        Option<String>,
+        Option<Bytes>,
+        Option<Bytes>,
+        Option<ForeignFunctionResponder>,
+    )>,
+    proxy_done: Vec<Option<i32>>,
+    clear_route_cache: u32,
+    continue_request: u32,
+    continue_response: u32,
+    resume_downstream: u32,
+    resume_upstream: u32,
+    continue_stream: Vec<Option<i32>>,
+    close_stream: Vec<Option<i32>>,
+    get_shared_data: Vec<(
+        Option<String>,
+        Option<Bytes>,
+        Option<u32>,
+        Option<SharedDataResponder>,
+    )>,
+    set_shared_data: Vec<(Option<String>, Option<Bytes>, Option<u32>)>,
+    register_shared_queue: Vec<Option<String>>,
+    resolve_shared_queue: Vec<(
+        Option<String>,
+        Option<String>,
+        Option<u32>,
+        Option<ResolveSharedQueueResponder>,
+    )>,
+    enqueue_shared_queue: Vec<(Option<u32>, Option<Bytes>)>,
+    dequeue_shared_queue: Vec<(
+        Option<u32>,
+        Option<Bytes>,
+        Option<DequeueSharedQueueResponder>,
+    )>,
+    define_metric: Vec<(
+        Option<i32>,
+        Option<String>,
+        Option<u32>,
+        Option<DefineMetricResponder>,
+    )>,
+    increment_metric: Vec<(Option<u32>, Option<i64>)>,
+    record_metric: Vec<(Option<u32>, Option<u64>)>,
+    get_metric: Vec<(Option<u32>, Option<u64>, Option<MetricResponder>)>,
+    get_property: Vec<(Option<Bytes>, Option<Bytes>, Option<PropertyResponder>)>,
+    set_property: Vec<(Option<Bytes>, Option<Bytes>)>,
 }
 
 impl Expect {
     pub fn new(allow_unexpected: bool) -> Expect {
         Expect {
+            status: new_status(),
             allow_unexpected: allow_unexpected,
+            recording: None,
+            last_staged: None,
+            unexpected_calls: Vec::new(),
+            ignored: Vec::new(),
+            matched_calls: Vec::new(),
+            last_taken: String::new(),
+            last_taken_origin: String::new(),
+            colored_diffs: false,
             expect_count: 0,
+            forbidden: Vec::new(),
+            cardinality: HashMap::new(),
             log_message: vec![],
-            tick_period_millis: vec![],
-            current_time_nanos: vec![],
+            get_status: vec![],
+            tick_period: vec![],
+            current_time: vec![],
             get_buffer_bytes: vec![],
             set_buffer_bytes: vec![],
             get_header_map_pairs: vec![],
@@ -106,222 +492,801 @@ impl Expect {
             add_header_map_value: vec![],
             send_local_response: vec![],
             http_call: vec![],
+            grpc_call: vec![],
+            grpc_stream: vec![],
+            grpc_send: vec![],
+            grpc_cancel: vec![],
+            grpc_close: vec![],
+            set_effective_context: vec![],
+            call_foreign_function: vec![],
+            proxy_done: vec![],
+            clear_route_cache: 0,
+            continue_request: 0,
+            continue_response: 0,
+            resume_downstream: 0,
+            resume_upstream: 0,
+            continue_stream: vec![],
+            close_stream: vec![],
+            get_shared_data: vec![],
+            set_shared_data: vec![],
+            register_shared_queue: vec![],
+            resolve_shared_queue: vec![],
+            enqueue_shared_queue: vec![],
+            dequeue_shared_queue: vec![],
+            define_metric: vec![],
+            increment_metric: vec![],
+            record_metric: vec![],
+            get_metric: vec![],
+            get_property: vec![],
+            set_property: vec![],
         }
     }
 
-    pub fn set_expect_log(&mut self, log_level: Option<i32>, log_string: Option<&str>) {
+    // Records every hostcall as the expectation that would match it, see scaffold.rs
+    pub fn start_recording(&mut self) {
+        self.recording = Some(Vec::new());
+    }
+
+    pub fn stop_recording(&mut self) -> Vec<String> {
+        self.recording.take().unwrap_or_default()
+    }
+
+    fn record<F: Fn() -> String>(&mut self, call: &F) {
+        if let Some(recording) = self.recording.as_mut() {
+            recording.push(call());
+        }
+    }
+
+    // Host calls without a staged expectation fall back to the host defaults, failing the stage in
+    // strict mode and only warning in lenient mode (see allow_unexpected)
+    fn unexpected<F: Fn() -> String>(&mut self, kind: &str, call: &F) {
+        if self.forbidden.iter().any(|forbidden| forbidden == kind) {
+            let call = call();
+            println!(
+                "Error: host call expected not to happen, matched by {}",
+                call
+            );
+            self.unexpected_calls.push(call);
+            self.set_status(ExpectStatus::Failed);
+            return;
+        }
+        self.set_status(ExpectStatus::Unexpected);
+        if self
+            .ignored
+            .iter()
+            .any(|ignored| ignored.staged_name() == kind)
+        {
+            return;
+        }
+        let call = call();
+        if self.allow_unexpected {
+            println!("Warning: unexpected host call, matched by {}", call);
+        } else {
+            self.expect_count -= 1;
+        }
+        self.unexpected_calls.push(call);
+    }
+
+    // Fails the stage on any call of the host function, which must have nothing staged for it
+    pub fn set_forbidden(&mut self, kind: HostcallKind) {
+        let kind = kind.staged_name();
+        assert!(
+            self.cardinality
+                .get(kind)
+                .map_or(true, |staged| staged.is_empty()),
+            "Error: {} cannot be both expected and expected not to happen",
+            kind
+        );
+        if !self.forbidden.iter().any(|forbidden| forbidden == kind) {
+            self.forbidden.push(kind.to_string());
+        }
+    }
+
+    // Ignored host functions fall back to the host defaults when nothing is staged for them, without
+    // failing the stage or warning (expectations staged for them are still checked)
+    pub fn set_ignored(&mut self, ignored: Vec<HostcallKind>) {
+        self.ignored = ignored;
+    }
+
+    pub fn get_ignored(&self) -> &[HostcallKind] {
+        &self.ignored
+    }
+
+    pub fn get_status_handle(&self) -> Arc<Mutex<ExpectStatus>> {
+        self.status.clone()
+    }
+
+    fn set_status(&self, expect_status: ExpectStatus) {
+        *self.status.lock().unwrap() = expect_status;
+    }
+
+    // Staged expectations that accounted for fewer host calls than their cardinality requires
+    pub fn get_unmet_expectations(&self) -> Vec<String> {
+        let mut kinds: Vec<&String> = self.cardinality.keys().collect();
+        kinds.sort();
+        let mut report = Vec::new();
+        for kind in kinds {
+            for cardinality in &self.cardinality[kind] {
+                if cardinality.calls < cardinality.min {
+                    report.push(format!(
+                        "{} {}: called {} of {} times",
+                        kind,
+                        cardinality.origin(),
+                        cardinality.calls,
+                        cardinality.min
+                    ));
+                }
+            }
+        }
+        report
+    }
+
+    pub fn set_allow_unexpected(&mut self, allow_unexpected: bool) {
+        self.allow_unexpected = allow_unexpected;
+    }
+
+    pub fn get_allow_unexpected(&self) -> bool {
+        self.allow_unexpected
+    }
+
+    pub fn get_unexpected_calls(&self) -> &[String] {
+        &self.unexpected_calls
+    }
+
+    #[track_caller]
+    fn stage(&mut self, kind: &str) {
+        assert!(
+            !self.forbidden.iter().any(|forbidden| forbidden == kind),
+            "Error: {} cannot be both expected and expected not to happen",
+            kind
+        );
         self.expect_count += 1;
-        self.log_message
-            .push((log_level, log_string.map(|s| s.to_string())));
+        self.cardinality
+            .entry(kind.to_string())
+            .or_default()
+            .push(Cardinality::once(Location::caller().to_string()));
+        self.last_staged = Some(kind.to_string());
+    }
+
+    // Lets the last staged expectation account for between min and max host calls rather than one,
+    // expectations of the same host function are still consumed in the order they were staged
+    pub fn set_cardinality(&mut self, min: u32, max: Option<u32>) {
+        let kind = match self.last_staged.take() {
+            Some(kind) => kind,
+            None => panic!("Error: cardinality can only be set right after staging an expectation"),
+        };
+        if let Some(max) = max {
+            assert!(
+                max > 0 && max >= min,
+                "Error: cannot expect between {} and {} calls of {}",
+                min,
+                max,
+                kind
+            );
+        }
+        let cardinality = self
+            .cardinality
+            .get_mut(&kind)
+            .and_then(|staged| staged.last_mut())
+            .unwrap();
+        cardinality.min = min;
+        cardinality.max = max;
+        self.expect_count += min as i32 - 1;
+    }
+
+    // Names the last staged expectation in failure output, cardinality can still be set after it
+    pub fn set_name(&mut self, name: &str) {
+        let cardinality = match &self.last_staged {
+            Some(kind) => self
+                .cardinality
+                .get_mut(kind)
+                .and_then(|staged| staged.last_mut())
+                .unwrap(),
+            None => panic!("Error: names can only be given right after staging an expectation"),
+        };
+        cardinality.name = Some(name.to_string());
+    }
+
+    // Keeps the last staged expectation across stages, accounting for any number of host calls
+    // that no expectation of the current stage accounts for
+    pub fn set_persistent(&mut self) {
+        let kind = self.last_staged.clone();
+        self.set_cardinality(0, None);
+        if let Some(cardinality) = kind
+            .and_then(|kind| self.cardinality.get_mut(&kind))
+            .and_then(|staged| staged.last_mut())
+        {
+            cardinality.persistent = true;
+        }
+    }
+
+    // Counts a host call against the first expectation of a kind (persistent ones only when no
+    // other is staged), which stays staged and is returned again until it accounted for its maximum
+    // number of calls
+    fn take<T: Clone + fmt::Debug>(
+        &mut self,
+        kind: &str,
+        staged: fn(&mut Expect) -> &mut Vec<T>,
+    ) -> T {
+        let (index, exhausted) = self.consume(kind);
+        let expectation = if exhausted {
+            staged(self).remove(index)
+        } else {
+            staged(self)[index].clone()
+        };
+        self.last_taken = format!("{} {:?}", kind, expectation);
+        expectation
+    }
+
+    fn take_count(&mut self, kind: &str, staged: fn(&mut Expect) -> &mut u32) {
+        if self.consume(kind).1 {
+            *staged(self) -= 1;
+        }
+        self.last_taken = kind.to_string();
+    }
+
+    // Checks a host call against the expectation just taken for it, printing a diff between the two
+    // along with the host calls matched so far in the stage when they differ
+    fn set_expect_status<F: Fn() -> String>(&mut self, call: &F, checks: bool) {
+        if checks {
+            self.matched_calls.push(call());
+            self.set_status(ExpectStatus::Expected);
+            return;
+        }
+        let (red, green, reset) = if self.colored_diffs {
+            ("\x1b[31m", "\x1b[32m", "\x1b[0m")
+        } else {
+            ("", "", "")
+        };
+        println!(
+            "Error: host call does not match the expectation {}\n\
+            {}  - expected: {}{}\n\
+            {}  + received: {}{}\n\
+            host calls matched before in this stage:{}",
+            self.last_taken_origin,
+            red,
+            self.last_taken,
+            reset,
+            green,
+            call(),
+            reset,
+            self.matched_calls
+                .iter()
+                .map(|matched| format!("\n    {}", matched))
+                .collect::<String>()
+        );
+        self.set_status(ExpectStatus::Failed);
+    }
+
+    pub fn set_colored_diffs(&mut self, colored_diffs: bool) {
+        self.colored_diffs = colored_diffs;
+    }
+
+    fn consume(&mut self, kind: &str) -> (usize, bool) {
+        let staged = self.cardinality.entry(kind.to_string()).or_default();
+        if staged.is_empty() {
+            staged.push(Cardinality::once("an unknown location".to_string()));
+        }
+        let index = staged
+            .iter()
+            .position(|cardinality| !cardinality.persistent)
+            .unwrap_or(0);
+        let cardinality = &mut staged[index];
+        cardinality.calls += 1;
+        self.last_taken_origin = cardinality.origin();
+        if cardinality.calls <= cardinality.min {
+            self.expect_count -= 1;
+        }
+        if Some(cardinality.calls) == cardinality.max {
+            staged.remove(index);
+            (index, true)
+        } else {
+            (index, false)
+        }
+    }
+
+    // Starts the next stage in place, keeping only the persistent expectations
+    fn next_stage(&mut self, allow_unexpected: bool) {
+        self.allow_unexpected = allow_unexpected;
+        self.recording = None;
+        self.last_staged = None;
+        self.unexpected_calls.clear();
+        self.matched_calls.clear();
+        self.expect_count = 0;
+        self.forbidden.clear();
+        retain_persistent(&mut self.log_message, self.cardinality.get("log_message"));
+        retain_persistent(&mut self.get_status, self.cardinality.get("get_status"));
+        retain_persistent(&mut self.tick_period, self.cardinality.get("tick_period"));
+        retain_persistent(&mut self.current_time, self.cardinality.get("current_time"));
+        retain_persistent(
+            &mut self.get_buffer_bytes,
+            self.cardinality.get("get_buffer_bytes"),
+        );
+        retain_persistent(
+            &mut self.set_buffer_bytes,
+            self.cardinality.get("set_buffer_bytes"),
+        );
+        retain_persistent(
+            &mut self.get_header_map_pairs,
+            self.cardinality.get("get_header_map_pairs"),
+        );
+        retain_persistent(
+            &mut self.set_header_map_pairs,
+            self.cardinality.get("set_header_map_pairs"),
+        );
+        retain_persistent(
+            &mut self.get_header_map_value,
+            self.cardinality.get("get_header_map_value"),
+        );
+        retain_persistent(
+            &mut self.replace_header_map_value,
+            self.cardinality.get("replace_header_map_value"),
+        );
+        retain_persistent(
+            &mut self.remove_header_map_value,
+            self.cardinality.get("remove_header_map_value"),
+        );
+        retain_persistent(
+            &mut self.add_header_map_value,
+            self.cardinality.get("add_header_map_value"),
+        );
+        retain_persistent(
+            &mut self.send_local_response,
+            self.cardinality.get("send_local_response"),
+        );
+        retain_persistent(&mut self.http_call, self.cardinality.get("http_call"));
+        retain_persistent(&mut self.grpc_call, self.cardinality.get("grpc_call"));
+        retain_persistent(&mut self.grpc_stream, self.cardinality.get("grpc_stream"));
+        retain_persistent(&mut self.grpc_send, self.cardinality.get("grpc_send"));
+        retain_persistent(&mut self.grpc_cancel, self.cardinality.get("grpc_cancel"));
+        retain_persistent(&mut self.grpc_close, self.cardinality.get("grpc_close"));
+        retain_persistent(
+            &mut self.set_effective_context,
+            self.cardinality.get("set_effective_context"),
+        );
+        retain_persistent(
+            &mut self.call_foreign_function,
+            self.cardinality.get("call_foreign_function"),
+        );
+        retain_persistent(&mut self.proxy_done, self.cardinality.get("proxy_done"));
+        self.clear_route_cache = count_persistent(self.cardinality.get("clear_route_cache"));
+        self.continue_request = count_persistent(self.cardinality.get("continue_request"));
+        self.continue_response = count_persistent(self.cardinality.get("continue_response"));
+        self.resume_downstream = count_persistent(self.cardinality.get("resume_downstream"));
+        self.resume_upstream = count_persistent(self.cardinality.get("resume_upstream"));
+        retain_persistent(
+            &mut self.continue_stream,
+            self.cardinality.get("continue_stream"),
+        );
+        retain_persistent(&mut self.close_stream, self.cardinality.get("close_stream"));
+        retain_persistent(
+            &mut self.get_shared_data,
+            self.cardinality.get("get_shared_data"),
+        );
+        retain_persistent(
+            &mut self.set_shared_data,
+            self.cardinality.get("set_shared_data"),
+        );
+        retain_persistent(
+            &mut self.register_shared_queue,
+            self.cardinality.get("register_shared_queue"),
+        );
+        retain_persistent(
+            &mut self.resolve_shared_queue,
+            self.cardinality.get("resolve_shared_queue"),
+        );
+        retain_persistent(
+            &mut self.enqueue_shared_queue,
+            self.cardinality.get("enqueue_shared_queue"),
+        );
+        retain_persistent(
+            &mut self.dequeue_shared_queue,
+            self.cardinality.get("dequeue_shared_queue"),
+        );
+        retain_persistent(
+            &mut self.define_metric,
+            self.cardinality.get("define_metric"),
+        );
+        retain_persistent(
+            &mut self.increment_metric,
+            self.cardinality.get("increment_metric"),
+        );
+        retain_persistent(
+            &mut self.record_metric,
+            self.cardinality.get("record_metric"),
+        );
+        retain_persistent(&mut self.get_metric, self.cardinality.get("get_metric"));
+        retain_persistent(&mut self.get_property, self.cardinality.get("get_property"));
+        retain_persistent(&mut self.set_property, self.cardinality.get("set_property"));
+        for staged in self.cardinality.values_mut() {
+            staged.retain(|cardinality| cardinality.persistent);
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_log(&mut self, log_level: Option<i32>, log_string: Option<&str>) {
+        self.set_expect_log_match(
+            log_level,
+            log_string.map(|s| StringMatch::Exact(s.to_string())),
+        );
+    }
+
+    #[track_caller]
+    pub fn set_expect_log_match(
+        &mut self,
+        log_level: Option<i32>,
+        mut string_match: Option<StringMatch>,
+    ) {
+        if let Err(error) = check_pattern(&mut string_match) {
+            panic!("{}", error);
+        }
+        self.stage("log_message");
+        self.log_message.push((log_level, string_match));
     }
 
     pub fn get_expect_log(&mut self, log_level: i32, log_string: &str) {
+        let call = || scaffold::expect_log(log_level, log_string);
+        self.record(&call);
         match self.log_message.len() {
             0 => {
-                if !self.allow_unexpected {
-                    self.expect_count -= 1;
-                }
-                set_status(ExpectStatus::Unexpected);
+                self.unexpected("log_message", &call);
             }
             _ => {
-                self.expect_count -= 1;
-                let log_tuple = self.log_message.remove(0);
+                let log_tuple = self.take("log_message", |expect| &mut expect.log_message);
                 let mut expect_status = log_level == log_tuple.0.unwrap_or(log_level);
-                expect_status =
-                    expect_status && log_string == log_tuple.1.unwrap_or(log_string.to_string());
-                set_expect_status(expect_status);
+                expect_status = expect_status
+                    && log_tuple
+                        .1
+                        .map_or(true, |string_match| string_match.matches(log_string));
+                self.set_expect_status(&call, expect_status);
             }
         }
     }
 
-    pub fn set_expect_set_tick_period_millis(&mut self, tick_period_millis: Option<u64>) {
-        self.expect_count += 1;
-        self.tick_period_millis
-            .push(tick_period_millis.map(|period| Duration::from_millis(period)));
+    #[track_caller]
+    pub fn set_expect_get_status(&mut self, status_code: Option<u32>, message: Option<&str>) {
+        self.stage("get_status");
+        self.get_status
+            .push((status_code, message.map(|data| data.to_string()), None));
+    }
+
+    #[track_caller]
+    pub fn set_expect_get_status_with(&mut self, responder: StatusResponder) {
+        self.stage("get_status");
+        self.get_status.push((None, None, Some(responder)));
     }
 
-    pub fn get_expect_set_tick_period_millis(&mut self, tick_period_millis: u128) {
-        match self.tick_period_millis.len() {
+    pub fn get_expect_get_status(&mut self) -> (Option<u32>, Option<String>) {
+        let call = || scaffold::expect_get_status();
+        self.record(&call);
+        match self.get_status.len() {
             0 => {
-                if !self.allow_unexpected {
-                    self.expect_count -= 1;
+                self.unexpected("get_status", &call);
+                (None, None)
+            }
+            _ => {
+                let status_tuple = self.take("get_status", |expect| &mut expect.get_status);
+                self.set_expect_status(&call, true);
+                match status_tuple.2 {
+                    Some(responder) => (responder.0)(),
+                    None => (status_tuple.0, status_tuple.1),
                 }
-                set_status(ExpectStatus::Unexpected);
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_set_tick_period(&mut self, tick_period: Option<Duration>) {
+        self.stage("tick_period");
+        self.tick_period.push(tick_period);
+    }
+
+    pub fn get_expect_set_tick_period(&mut self, tick_period: Duration) {
+        let call = || scaffold::expect_set_tick_period(tick_period);
+        self.record(&call);
+        match self.tick_period.len() {
+            0 => {
+                self.unexpected("tick_period", &call);
             }
             _ => {
-                self.expect_count -= 1;
-                let expect_status = tick_period_millis
+                let expect_status = tick_period
                     == self
-                        .tick_period_millis
-                        .remove(0)
-                        .map(|period| period.as_millis())
-                        .unwrap_or(tick_period_millis);
-                set_expect_status(expect_status);
+                        .take("tick_period", |expect| &mut expect.tick_period)
+                        .unwrap_or(tick_period);
+                self.set_expect_status(&call, expect_status);
             }
         }
     }
 
-    pub fn set_expect_get_current_time_nanos(&mut self, current_time_nanos: Option<u64>) {
-        self.expect_count += 1;
-        self.current_time_nanos.push(
-            current_time_nanos.map(|time_nanos| UNIX_EPOCH + Duration::from_nanos(time_nanos)),
-        );
+    #[track_caller]
+    pub fn set_expect_get_current_time(&mut self, current_time: Option<SystemTime>) {
+        self.stage("current_time");
+        self.current_time.push((current_time, None));
     }
 
-    pub fn get_expect_get_current_time_nanos(&mut self) -> Option<u128> {
-        match self.current_time_nanos.len() {
+    #[track_caller]
+    pub fn set_expect_get_current_time_with(&mut self, responder: CurrentTimeResponder) {
+        self.stage("current_time");
+        self.current_time.push((None, Some(responder)));
+    }
+
+    pub fn get_expect_get_current_time(&mut self) -> Option<SystemTime> {
+        let call = || scaffold::expect_get_current_time();
+        self.record(&call);
+        match self.current_time.len() {
             0 => {
-                if !self.allow_unexpected {
-                    self.expect_count -= 1;
-                }
-                set_status(ExpectStatus::Unexpected);
+                self.unexpected("current_time", &call);
                 None
             }
             _ => {
-                self.expect_count -= 1;
-                set_status(ExpectStatus::Expected);
-                self.current_time_nanos
-                    .remove(0)
-                    .map(|time_nanos| time_nanos.duration_since(UNIX_EPOCH).unwrap().as_nanos())
+                let time_tuple = self.take("current_time", |expect| &mut expect.current_time);
+                self.set_expect_status(&call, true);
+                match time_tuple.1 {
+                    Some(responder) => (responder.0)(),
+                    None => time_tuple.0,
+                }
             }
         }
     }
 
+    #[track_caller]
     pub fn set_expect_get_buffer_bytes(
         &mut self,
         buffer_type: Option<i32>,
-        buffer_data: Option<&str>,
+        buffer_data: Option<&[u8]>,
     ) {
-        self.expect_count += 1;
-        self.get_buffer_bytes.push((
-            buffer_type,
-            buffer_data.map(|data| data.as_bytes().to_vec()),
-        ));
+        self.stage("get_buffer_bytes");
+        self.get_buffer_bytes
+            .push((buffer_type, buffer_data.map(|data| data.to_vec()), None));
+    }
+
+    #[track_caller]
+    pub fn set_expect_get_buffer_bytes_with(
+        &mut self,
+        buffer_type: Option<i32>,
+        responder: BufferBytesResponder,
+    ) {
+        self.stage("get_buffer_bytes");
+        self.get_buffer_bytes
+            .push((buffer_type, None, Some(responder)));
     }
 
     pub fn get_expect_get_buffer_bytes(&mut self, buffer_type: i32) -> Option<Bytes> {
+        let call = || scaffold::expect_get_buffer_bytes(buffer_type);
+        self.record(&call);
         match self.get_buffer_bytes.len() {
             0 => {
-                if !self.allow_unexpected {
-                    self.expect_count -= 1;
-                }
-                set_status(ExpectStatus::Unexpected);
+                self.unexpected("get_buffer_bytes", &call);
                 None
             }
             _ => {
-                self.expect_count -= 1;
-                let expect_status =
-                    buffer_type == self.get_buffer_bytes[0].0.unwrap_or(buffer_type);
-                set_expect_status(expect_status);
-                self.get_buffer_bytes.remove(0).1
+                let buffer_tuple =
+                    self.take("get_buffer_bytes", |expect| &mut expect.get_buffer_bytes);
+                self.set_expect_status(&call, buffer_type == buffer_tuple.0.unwrap_or(buffer_type));
+                match buffer_tuple.2 {
+                    Some(responder) => (responder.0)(buffer_type),
+                    None => buffer_tuple.1,
+                }
             }
         }
     }
 
+    #[track_caller]
     pub fn set_expect_set_buffer_bytes(
         &mut self,
         buffer_type: Option<i32>,
-        buffer_data: Option<&str>,
+        buffer_data: Option<&[u8]>,
     ) {
-        self.expect_count += 1;
-        self.set_buffer_bytes.push((
-            buffer_type,
-            buffer_data.map(|data| data.as_bytes().to_vec()),
-        ));
+        self.stage("set_buffer_bytes");
+        self.set_buffer_bytes
+            .push((buffer_type, buffer_data.map(|data| data.to_vec()), None));
+    }
+
+    #[track_caller]
+    pub fn set_capture_set_buffer_bytes(&mut self, buffer_type: Option<i32>, capture: Capture) {
+        self.stage("set_buffer_bytes");
+        self.set_buffer_bytes
+            .push((buffer_type, None, Some(capture)));
     }
 
     pub fn get_expect_set_buffer_bytes(&mut self, buffer_type: i32, buffer_data: &[u8]) {
+        let call = || scaffold::expect_set_buffer_bytes(buffer_type, buffer_data);
+        self.record(&call);
         match self.set_buffer_bytes.len() {
             0 => {
-                if !self.allow_unexpected {
-                    self.expect_count -= 1;
-                }
-                set_status(ExpectStatus::Unexpected);
+                self.unexpected("set_buffer_bytes", &call);
             }
             _ => {
-                self.expect_count -= 1;
-                let expect_buffer = self.set_buffer_bytes.remove(0);
+                let expect_buffer =
+                    self.take("set_buffer_bytes", |expect| &mut expect.set_buffer_bytes);
                 let mut expect_status = buffer_type == expect_buffer.0.unwrap_or(buffer_type);
                 expect_status = expect_status
                     && &buffer_data == &&expect_buffer.1.unwrap_or(buffer_data.to_vec())[..];
-                set_expect_status(expect_status);
+                if let (true, Some(capture)) = (expect_status, expect_buffer.2) {
+                    capture.push(buffer_data);
+                }
+                self.set_expect_status(&call, expect_status);
             }
         }
     }
 
+    #[track_caller]
     pub fn set_expect_get_header_map_pairs(
         &mut self,
         map_type: Option<i32>,
         header_map_pairs: Option<Vec<(&str, &str)>>,
     ) {
-        self.expect_count += 1;
+        self.stage("get_header_map_pairs");
+        self.get_header_map_pairs.push((
+            map_type,
+            header_map_pairs.map(|map| serialize_map(map)),
+            None,
+        ));
+    }
+
+    #[track_caller]
+    pub fn set_expect_get_header_map_pairs_serialized(
+        &mut self,
+        map_type: Option<i32>,
+        serialized_map: Option<Bytes>,
+    ) {
+        self.stage("get_header_map_pairs");
         self.get_header_map_pairs
-            .push((map_type, header_map_pairs.map(|map| serialize_map(map))));
+            .push((map_type, serialized_map, None));
     }
 
-    pub fn get_expect_get_header_map_pairs(&mut self, map_type: i32) -> Option<Bytes> {
+    #[track_caller]
+    pub fn set_expect_get_header_map_pairs_with(
+        &mut self,
+        map_type: Option<i32>,
+        responder: HeaderMapPairsResponder,
+    ) {
+        self.stage("get_header_map_pairs");
+        self.get_header_map_pairs
+            .push((map_type, None, Some(responder)));
+    }
+
+    pub fn get_expect_get_header_map_pairs(&mut self, map_type: i32) -> Option<Bytes> {
+        let call = || scaffold::expect_get_header_map_pairs(map_type);
+        self.record(&call);
         match self.get_header_map_pairs.len() {
             0 => {
-                if !self.allow_unexpected {
-                    self.expect_count -= 1;
-                }
-                set_status(ExpectStatus::Unexpected);
+                self.unexpected("get_header_map_pairs", &call);
                 None
             }
             _ => {
-                self.expect_count -= 1;
-                let expect_status = map_type == self.get_header_map_pairs[0].0.unwrap_or(map_type);
-                set_expect_status(expect_status);
-                self.get_header_map_pairs.remove(0).1
+                let header_map_tuple = self.take("get_header_map_pairs", |expect| {
+                    &mut expect.get_header_map_pairs
+                });
+                self.set_expect_status(&call, map_type == header_map_tuple.0.unwrap_or(map_type));
+                match header_map_tuple.2 {
+                    Some(responder) => (responder.0)(map_type),
+                    None => header_map_tuple.1,
+                }
             }
         }
     }
 
+    #[track_caller]
     pub fn set_expect_set_header_map_pairs(
         &mut self,
         map_type: Option<i32>,
         header_map_pairs: Option<Vec<(&str, &str)>>,
     ) {
-        self.expect_count += 1;
+        self.stage("set_header_map_pairs");
+        self.set_header_map_pairs.push((
+            map_type,
+            header_map_pairs.map(|map| serialize_map(map)),
+            None,
+            None,
+        ));
+    }
+
+    #[track_caller]
+    pub fn set_expect_set_header_map_data(
+        &mut self,
+        map_type: Option<i32>,
+        header_map_pairs: Option<Vec<(&str, &[u8])>>,
+    ) {
+        self.stage("set_header_map_pairs");
+        self.set_header_map_pairs.push((
+            map_type,
+            header_map_pairs.map(|map| {
+                serialize_map_bytes(
+                    map.into_iter()
+                        .map(|(key, value)| (key.as_bytes(), value))
+                        .collect(),
+                )
+            }),
+            None,
+            None,
+        ));
+    }
+
+    #[track_caller]
+    pub fn set_capture_set_header_map_pairs(&mut self, map_type: Option<i32>, capture: Capture) {
+        self.stage("set_header_map_pairs");
         self.set_header_map_pairs
-            .push((map_type, header_map_pairs.map(|map| serialize_map(map))));
+            .push((map_type, None, None, Some(capture)));
+    }
+
+    // Lets the set_header_map_pairs expectation staged last match any map holding the given pairs,
+    // instead of exactly the map it was staged with
+    pub fn set_containing(&mut self, header_map_pairs: Vec<(&str, &str)>) {
+        assert_eq!(
+            self.last_staged.as_deref(),
+            Some("set_header_map_pairs"),
+            "Error: containing can only follow expect_set_header_map_pairs"
+        );
+        let header_map_tuple = self.set_header_map_pairs.last_mut().unwrap();
+        header_map_tuple.1 = None;
+        header_map_tuple.2 = Some(
+            header_map_pairs
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        );
     }
 
     pub fn get_expect_set_header_map_pairs(&mut self, map_type: i32, header_map_pairs: &[u8]) {
+        let call = || scaffold::expect_set_header_map_pairs(map_type, header_map_pairs);
+        self.record(&call);
         match self.set_header_map_pairs.len() {
             0 => {
-                if !self.allow_unexpected {
-                    self.expect_count -= 1;
-                }
-                set_status(ExpectStatus::Unexpected);
+                self.unexpected("set_header_map_pairs", &call);
             }
             _ => {
-                self.expect_count -= 1;
-                let mut expect_status =
-                    map_type == self.set_header_map_pairs[0].0.unwrap_or(map_type);
+                let header_map_tuple = self.take("set_header_map_pairs", |expect| {
+                    &mut expect.set_header_map_pairs
+                });
+                let mut expect_status = map_type == header_map_tuple.0.unwrap_or(map_type);
                 expect_status = expect_status
                     && &header_map_pairs
-                        == &&self
-                            .set_header_map_pairs
-                            .remove(0)
-                            .1
-                            .unwrap_or(header_map_pairs.to_vec())[..];
-                set_expect_status(expect_status);
+                        == &&header_map_tuple.1.unwrap_or(header_map_pairs.to_vec())[..];
+                if let Some(contained_pairs) = header_map_tuple.2 {
+                    let received_pairs = deserialize_map(header_map_pairs);
+                    expect_status = expect_status
+                        && contained_pairs
+                            .iter()
+                            .all(|pair| received_pairs.contains(pair));
+                }
+                if let (true, Some(capture)) = (expect_status, header_map_tuple.3) {
+                    capture.push(header_map_pairs);
+                }
+                self.set_expect_status(&call, expect_status);
             }
         }
     }
 
+    #[track_caller]
     pub fn set_expect_get_header_map_value(
         &mut self,
         map_type: Option<i32>,
         header_map_key: Option<&str>,
-        header_map_value: Option<&str>,
+        header_map_value: Option<&[u8]>,
     ) {
-        self.expect_count += 1;
+        self.stage("get_header_map_value");
+        self.get_header_map_value.push((
+            map_type,
+            header_map_key.map(|key| key.to_string()),
+            header_map_value.map(|value| value.to_vec()),
+            None,
+        ));
+    }
+
+    #[track_caller]
+    pub fn set_expect_get_header_map_value_with(
+        &mut self,
+        map_type: Option<i32>,
+        header_map_key: Option<&str>,
+        responder: HeaderMapValueResponder,
+    ) {
+        self.stage("get_header_map_value");
         self.get_header_map_value.push((
             map_type,
             header_map_key.map(|key| key.to_string()),
-            header_map_value.map(|value| value.to_string()),
+            None,
+            Some(responder),
         ));
     }
 
@@ -329,38 +1294,59 @@ impl Expect {
         &mut self,
         map_type: i32,
         header_map_key: &str,
-    ) -> Option<String> {
+    ) -> Option<Bytes> {
+        let call = || scaffold::expect_get_header_map_value(map_type, header_map_key);
+        self.record(&call);
         match self.get_header_map_value.len() {
             0 => {
-                if !self.allow_unexpected {
-                    self.expect_count -= 1;
-                }
-                set_status(ExpectStatus::Unexpected);
+                self.unexpected("get_header_map_value", &call);
                 None
             }
             _ => {
-                self.expect_count -= 1;
-                let header_map_tuple = self.get_header_map_value.remove(0);
+                let header_map_tuple = self.take("get_header_map_value", |expect| {
+                    &mut expect.get_header_map_value
+                });
                 let mut expect_status = map_type == header_map_tuple.0.unwrap_or(map_type);
                 expect_status = expect_status
                     && header_map_key == &header_map_tuple.1.unwrap_or(header_map_key.to_string());
-                set_expect_status(expect_status);
-                header_map_tuple.2
+                self.set_expect_status(&call, expect_status);
+                match header_map_tuple.3 {
+                    Some(responder) => (responder.0)(header_map_key),
+                    None => header_map_tuple.2,
+                }
             }
         }
     }
 
+    #[track_caller]
     pub fn set_expect_replace_header_map_value(
         &mut self,
         map_type: Option<i32>,
         header_map_key: Option<&str>,
-        header_map_value: Option<&str>,
+        header_map_value: Option<&[u8]>,
     ) {
-        self.expect_count += 1;
+        self.set_expect_replace_header_map_value_match(
+            map_type,
+            header_map_key,
+            header_map_value.map(|value| StringMatch::Bytes(value.to_vec())),
+        );
+    }
+
+    #[track_caller]
+    pub fn set_expect_replace_header_map_value_match(
+        &mut self,
+        map_type: Option<i32>,
+        header_map_key: Option<&str>,
+        mut header_map_value: Option<StringMatch>,
+    ) {
+        if let Err(error) = check_pattern(&mut header_map_value) {
+            panic!("{}", error);
+        }
+        self.stage("replace_header_map_value");
         self.replace_header_map_value.push((
             map_type,
             header_map_key.map(|key| key.to_string()),
-            header_map_value.map(|value| value.to_string()),
+            header_map_value,
         ));
     }
 
@@ -368,69 +1354,91 @@ impl Expect {
         &mut self,
         map_type: i32,
         header_map_key: &str,
-        header_map_value: &str,
+        header_map_value: &[u8],
     ) {
+        let call = || {
+            scaffold::expect_replace_header_map_value(map_type, header_map_key, header_map_value)
+        };
+        self.record(&call);
         match self.replace_header_map_value.len() {
             0 => {
-                if !self.allow_unexpected {
-                    self.expect_count -= 1;
-                }
-                set_status(ExpectStatus::Unexpected);
+                self.unexpected("replace_header_map_value", &call);
             }
             _ => {
-                self.expect_count -= 1;
-                let header_map_tuple = self.replace_header_map_value.remove(0);
+                let header_map_tuple = self.take("replace_header_map_value", |expect| {
+                    &mut expect.replace_header_map_value
+                });
                 let mut expect_status = map_type == header_map_tuple.0.unwrap_or(map_type);
                 expect_status = expect_status
                     && header_map_key == &header_map_tuple.1.unwrap_or(header_map_key.to_string());
                 expect_status = expect_status
-                    && header_map_value
-                        == &header_map_tuple.2.unwrap_or(header_map_value.to_string());
-                set_expect_status(expect_status);
+                    && header_map_tuple.2.map_or(true, |value_match| {
+                        value_match.matches_bytes(header_map_value)
+                    });
+                self.set_expect_status(&call, expect_status);
             }
         }
     }
 
+    #[track_caller]
     pub fn set_expect_remove_header_map_value(
         &mut self,
         map_type: Option<i32>,
         header_map_key: Option<&str>,
     ) {
-        self.expect_count += 1;
+        self.stage("remove_header_map_value");
         self.remove_header_map_value
             .push((map_type, header_map_key.map(|key| key.to_string())));
     }
 
     pub fn get_expect_remove_header_map_value(&mut self, map_type: i32, header_map_key: &str) {
+        let call = || scaffold::expect_remove_header_map_value(map_type, header_map_key);
+        self.record(&call);
         match self.remove_header_map_value.len() {
             0 => {
-                if !self.allow_unexpected {
-                    self.expect_count -= 1;
-                }
-                set_status(ExpectStatus::Unexpected);
+                self.unexpected("remove_header_map_value", &call);
             }
             _ => {
-                self.expect_count -= 1;
-                let header_map_tuple = self.remove_header_map_value.remove(0);
+                let header_map_tuple = self.take("remove_header_map_value", |expect| {
+                    &mut expect.remove_header_map_value
+                });
                 let mut expect_status = map_type == header_map_tuple.0.unwrap_or(map_type);
                 expect_status = expect_status
                     && header_map_key == &header_map_tuple.1.unwrap_or(header_map_key.to_string());
-                set_expect_status(expect_status);
+                self.set_expect_status(&call, expect_status);
             }
         }
     }
 
+    #[track_caller]
     pub fn set_expect_add_header_map_value(
         &mut self,
         map_type: Option<i32>,
         header_map_key: Option<&str>,
-        header_map_value: Option<&str>,
+        header_map_value: Option<&[u8]>,
     ) {
-        self.expect_count += 1;
+        self.set_expect_add_header_map_value_match(
+            map_type,
+            header_map_key,
+            header_map_value.map(|value| StringMatch::Bytes(value.to_vec())),
+        );
+    }
+
+    #[track_caller]
+    pub fn set_expect_add_header_map_value_match(
+        &mut self,
+        map_type: Option<i32>,
+        header_map_key: Option<&str>,
+        mut header_map_value: Option<StringMatch>,
+    ) {
+        if let Err(error) = check_pattern(&mut header_map_value) {
+            panic!("{}", error);
+        }
+        self.stage("add_header_map_value");
         self.add_header_map_value.push((
             map_type,
             header_map_key.map(|key| key.to_string()),
-            header_map_value.map(|value| value.to_string()),
+            header_map_value,
         ));
     }
 
@@ -438,29 +1446,32 @@ impl Expect {
         &mut self,
         map_type: i32,
         header_map_key: &str,
-        header_map_value: &str,
+        header_map_value: &[u8],
     ) {
+        let call =
+            || scaffold::expect_add_header_map_value(map_type, header_map_key, header_map_value);
+        self.record(&call);
         match self.add_header_map_value.len() {
             0 => {
-                if !self.allow_unexpected {
-                    self.expect_count -= 1;
-                }
-                set_status(ExpectStatus::Unexpected);
+                self.unexpected("add_header_map_value", &call);
             }
             _ => {
-                self.expect_count -= 1;
-                let header_map_tuple = self.add_header_map_value.remove(0);
+                let header_map_tuple = self.take("add_header_map_value", |expect| {
+                    &mut expect.add_header_map_value
+                });
                 let mut expect_status = map_type == header_map_tuple.0.unwrap_or(map_type);
                 expect_status = expect_status
                     && header_map_key == &header_map_tuple.1.unwrap_or(header_map_key.to_string());
                 expect_status = expect_status
-                    && header_map_value
-                        == &header_map_tuple.2.unwrap_or(header_map_value.to_string());
-                set_expect_status(expect_status);
+                    && header_map_tuple.2.map_or(true, |value_match| {
+                        value_match.matches_bytes(header_map_value)
+                    });
+                self.set_expect_status(&call, expect_status);
             }
         }
     }
 
+    #[track_caller]
     pub fn set_expect_send_local_response(
         &mut self,
         status_code: Option<i32>,
@@ -468,7 +1479,7 @@ impl Expect {
         headers: Option<Vec<(&str, &str)>>,
         grpc_status: Option<i32>,
     ) {
-        self.expect_count += 1;
+        self.stage("send_local_response");
         self.send_local_response.push((
             status_code,
             body.map(|data| data.to_string()),
@@ -484,16 +1495,16 @@ impl Expect {
         headers: &[u8],
         grpc_status: i32,
     ) {
+        let call = || scaffold::expect_send_local_response(status_code, body, headers, grpc_status);
+        self.record(&call);
         match self.send_local_response.len() {
             0 => {
-                if !self.allow_unexpected {
-                    self.expect_count -= 1;
-                }
-                set_status(ExpectStatus::Unexpected);
+                self.unexpected("send_local_response", &call);
             }
             _ => {
-                self.expect_count -= 1;
-                let local_response_tuple = self.send_local_response.remove(0);
+                let local_response_tuple = self.take("send_local_response", |expect| {
+                    &mut expect.send_local_response
+                });
                 let mut expect_status =
                     status_code == local_response_tuple.0.unwrap_or(status_code);
                 expect_status = expect_status
@@ -505,28 +1516,52 @@ impl Expect {
                     && &headers == &&local_response_tuple.2.unwrap_or(headers.to_vec())[..];
                 expect_status =
                     expect_status && grpc_status == local_response_tuple.3.unwrap_or(grpc_status);
-                set_expect_status(expect_status);
+                self.set_expect_status(&call, expect_status);
             }
         }
     }
 
+    #[track_caller]
     pub fn set_expect_http_call(
         &mut self,
         upstream: Option<&str>,
         headers: Option<Vec<(&str, &str)>>,
         body: Option<&str>,
         trailers: Option<Vec<(&str, &str)>>,
-        timeout: Option<u64>,
+        timeout: Option<Duration>,
         token_id: Option<u32>,
     ) {
-        self.expect_count += 1;
+        self.stage("http_call");
         self.http_call.push((
             upstream.map(|data| data.to_string()),
             headers.map(|data| serialize_map(data)),
             body.map(|data| data.to_string()),
             trailers.map(|data| serialize_map(data)),
-            timeout.map(|data| Duration::from_millis(data)),
+            timeout,
             token_id,
+            None,
+        ));
+    }
+
+    #[track_caller]
+    pub fn set_expect_http_call_with(
+        &mut self,
+        upstream: Option<&str>,
+        headers: Option<Vec<(&str, &str)>>,
+        body: Option<&str>,
+        trailers: Option<Vec<(&str, &str)>>,
+        timeout: Option<Duration>,
+        responder: HttpCallResponder,
+    ) {
+        self.stage("http_call");
+        self.http_call.push((
+            upstream.map(|data| data.to_string()),
+            headers.map(|data| serialize_map(data)),
+            body.map(|data| data.to_string()),
+            trailers.map(|data| serialize_map(data)),
+            timeout,
+            None,
+            Some(responder),
         ));
     }
 
@@ -536,19 +1571,17 @@ impl Expect {
         headers: &[u8],
         body: Option<&str>,
         trailers: &[u8],
-        timeout: i32,
+        timeout: Duration,
     ) -> Option<u32> {
+        let call = || scaffold::expect_http_call(upstream, headers, body, trailers, timeout);
+        self.record(&call);
         match self.http_call.len() {
             0 => {
-                if !self.allow_unexpected {
-                    self.expect_count -= 1;
-                }
-                set_status(ExpectStatus::Unexpected);
+                self.unexpected("http_call", &call);
                 None
             }
             _ => {
-                self.expect_count -= 1;
-                let http_call_tuple = self.http_call.remove(0);
+                let http_call_tuple = self.take("http_call", |expect| &mut expect.http_call);
                 let mut expect_status =
                     upstream == &http_call_tuple.0.unwrap_or(upstream.to_string());
                 expect_status = expect_status
@@ -560,14 +1593,947 @@ impl Expect {
                             .unwrap_or(body.unwrap_or("default").to_string());
                 expect_status = expect_status
                     && &trailers == &&http_call_tuple.3.unwrap_or(trailers.to_vec())[..];
+                expect_status = expect_status && timeout == http_call_tuple.4.unwrap_or(timeout);
+                self.set_expect_status(&call, expect_status);
+                match http_call_tuple.6 {
+                    Some(responder) => (responder.0)(body),
+                    None => http_call_tuple.5,
+                }
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_grpc_call(
+        &mut self,
+        upstream: Option<&str>,
+        service: Option<&str>,
+        method: Option<&str>,
+        initial_metadata: Option<Vec<(&str, &str)>>,
+        message: Option<&[u8]>,
+        timeout: Option<Duration>,
+        token_id: Option<u32>,
+    ) {
+        self.stage("grpc_call");
+        self.grpc_call.push((
+            upstream.map(|data| data.to_string()),
+            service.map(|data| data.to_string()),
+            method.map(|data| data.to_string()),
+            initial_metadata.map(|data| serialize_map(data)),
+            message.map(|data| data.to_vec()),
+            timeout,
+            token_id,
+            None,
+        ));
+    }
+
+    #[track_caller]
+    pub fn set_expect_grpc_call_with(
+        &mut self,
+        upstream: Option<&str>,
+        service: Option<&str>,
+        method: Option<&str>,
+        initial_metadata: Option<Vec<(&str, &str)>>,
+        message: Option<&[u8]>,
+        timeout: Option<Duration>,
+        responder: GrpcCallResponder,
+    ) {
+        self.stage("grpc_call");
+        self.grpc_call.push((
+            upstream.map(|data| data.to_string()),
+            service.map(|data| data.to_string()),
+            method.map(|data| data.to_string()),
+            initial_metadata.map(|data| serialize_map(data)),
+            message.map(|data| data.to_vec()),
+            timeout,
+            None,
+            Some(responder),
+        ));
+    }
+
+    pub fn get_expect_grpc_call(
+        &mut self,
+        upstream: &str,
+        service: &str,
+        method: &str,
+        initial_metadata: &[u8],
+        message: &[u8],
+        timeout: Duration,
+    ) -> Option<u32> {
+        let call = || {
+            scaffold::expect_grpc_call(
+                upstream,
+                service,
+                method,
+                initial_metadata,
+                message,
+                timeout,
+            )
+        };
+        self.record(&call);
+        match self.grpc_call.len() {
+            0 => {
+                self.unexpected("grpc_call", &call);
+                None
+            }
+            _ => {
+                let grpc_call_tuple = self.take("grpc_call", |expect| &mut expect.grpc_call);
+                let mut expect_status =
+                    upstream == &grpc_call_tuple.0.unwrap_or(upstream.to_string());
+                expect_status =
+                    expect_status && service == &grpc_call_tuple.1.unwrap_or(service.to_string());
+                expect_status =
+                    expect_status && method == &grpc_call_tuple.2.unwrap_or(method.to_string());
                 expect_status = expect_status
-                    && timeout
-                        == http_call_tuple
-                            .4
-                            .map(|data| data.as_millis() as i32)
-                            .unwrap_or(timeout);
-                set_expect_status(expect_status);
-                http_call_tuple.5
+                    && initial_metadata
+                        == &grpc_call_tuple.3.unwrap_or(initial_metadata.to_vec())[..];
+                expect_status =
+                    expect_status && message == &grpc_call_tuple.4.unwrap_or(message.to_vec())[..];
+                expect_status = expect_status && timeout == grpc_call_tuple.5.unwrap_or(timeout);
+                self.set_expect_status(&call, expect_status);
+                match grpc_call_tuple.7 {
+                    Some(responder) => (responder.0)(message),
+                    None => grpc_call_tuple.6,
+                }
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_grpc_stream(
+        &mut self,
+        upstream: Option<&str>,
+        service: Option<&str>,
+        method: Option<&str>,
+        initial_metadata: Option<Vec<(&str, &str)>>,
+        token_id: Option<u32>,
+    ) {
+        self.stage("grpc_stream");
+        self.grpc_stream.push((
+            upstream.map(|data| data.to_string()),
+            service.map(|data| data.to_string()),
+            method.map(|data| data.to_string()),
+            initial_metadata.map(|data| serialize_map(data)),
+            token_id,
+            None,
+        ));
+    }
+
+    #[track_caller]
+    pub fn set_expect_grpc_stream_with(
+        &mut self,
+        upstream: Option<&str>,
+        service: Option<&str>,
+        method: Option<&str>,
+        initial_metadata: Option<Vec<(&str, &str)>>,
+        responder: GrpcStreamResponder,
+    ) {
+        self.stage("grpc_stream");
+        self.grpc_stream.push((
+            upstream.map(|data| data.to_string()),
+            service.map(|data| data.to_string()),
+            method.map(|data| data.to_string()),
+            initial_metadata.map(|data| serialize_map(data)),
+            None,
+            Some(responder),
+        ));
+    }
+
+    pub fn get_expect_grpc_stream(
+        &mut self,
+        upstream: &str,
+        service: &str,
+        method: &str,
+        initial_metadata: &[u8],
+    ) -> Option<u32> {
+        let call = || scaffold::expect_grpc_stream(upstream, service, method, initial_metadata);
+        self.record(&call);
+        match self.grpc_stream.len() {
+            0 => {
+                self.unexpected("grpc_stream", &call);
+                None
+            }
+            _ => {
+                let grpc_stream_tuple = self.take("grpc_stream", |expect| &mut expect.grpc_stream);
+                let mut expect_status =
+                    upstream == &grpc_stream_tuple.0.unwrap_or(upstream.to_string());
+                expect_status =
+                    expect_status && service == &grpc_stream_tuple.1.unwrap_or(service.to_string());
+                expect_status =
+                    expect_status && method == &grpc_stream_tuple.2.unwrap_or(method.to_string());
+                expect_status = expect_status
+                    && initial_metadata
+                        == &grpc_stream_tuple.3.unwrap_or(initial_metadata.to_vec())[..];
+                self.set_expect_status(&call, expect_status);
+                match grpc_stream_tuple.5 {
+                    Some(responder) => (responder.0)(method),
+                    None => grpc_stream_tuple.4,
+                }
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_grpc_send(
+        &mut self,
+        token_id: Option<u32>,
+        message: Option<&[u8]>,
+        end_of_stream: Option<bool>,
+    ) {
+        self.stage("grpc_send");
+        self.grpc_send
+            .push((token_id, message.map(|data| data.to_vec()), end_of_stream));
+    }
+
+    pub fn get_expect_grpc_send(&mut self, token_id: u32, message: &[u8], end_of_stream: bool) {
+        let call = || scaffold::expect_grpc_send(token_id, message, end_of_stream);
+        self.record(&call);
+        match self.grpc_send.len() {
+            0 => {
+                self.unexpected("grpc_send", &call);
+            }
+            _ => {
+                let grpc_send_tuple = self.take("grpc_send", |expect| &mut expect.grpc_send);
+                let mut expect_status = token_id == grpc_send_tuple.0.unwrap_or(token_id);
+                expect_status =
+                    expect_status && message == &grpc_send_tuple.1.unwrap_or(message.to_vec())[..];
+                expect_status =
+                    expect_status && end_of_stream == grpc_send_tuple.2.unwrap_or(end_of_stream);
+                self.set_expect_status(&call, expect_status);
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_grpc_cancel(&mut self, token_id: Option<u32>) {
+        self.stage("grpc_cancel");
+        self.grpc_cancel.push(token_id);
+    }
+
+    pub fn get_expect_grpc_cancel(&mut self, token_id: u32) {
+        let call = || scaffold::expect_grpc_cancel(token_id);
+        self.record(&call);
+        match self.grpc_cancel.len() {
+            0 => {
+                self.unexpected("grpc_cancel", &call);
+            }
+            _ => {
+                let expect_token = self.take("grpc_cancel", |expect| &mut expect.grpc_cancel);
+                self.set_expect_status(&call, token_id == expect_token.unwrap_or(token_id));
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_grpc_close(&mut self, token_id: Option<u32>) {
+        self.stage("grpc_close");
+        self.grpc_close.push(token_id);
+    }
+
+    pub fn get_expect_grpc_close(&mut self, token_id: u32) {
+        let call = || scaffold::expect_grpc_close(token_id);
+        self.record(&call);
+        match self.grpc_close.len() {
+            0 => {
+                self.unexpected("grpc_close", &call);
+            }
+            _ => {
+                let expect_token = self.take("grpc_close", |expect| &mut expect.grpc_close);
+                self.set_expect_status(&call, token_id == expect_token.unwrap_or(token_id));
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_set_effective_context(&mut self, context_id: Option<i32>) {
+        self.stage("set_effective_context");
+        self.set_effective_context.push(context_id);
+    }
+
+    pub fn get_expect_set_effective_context(&mut self, context_id: i32) {
+        let call = || scaffold::expect_set_effective_context(context_id);
+        self.record(&call);
+        match self.set_effective_context.len() {
+            0 => {
+                self.unexpected("set_effective_context", &call);
+            }
+            _ => {
+                let expect_context_id = self.take("set_effective_context", |expect| {
+                    &mut expect.set_effective_context
+                });
+                self.set_expect_status(
+                    &call,
+                    context_id == expect_context_id.unwrap_or(context_id),
+                );
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_call_foreign_function(
+        &mut self,
+        name: Option<&str>,
+        args: Option<&[u8]>,
+        results: Option<&[u8]>,
+    ) {
+        self.stage("call_foreign_function");
+        self.call_foreign_function.push((
+            name.map(|data| data.to_string()),
+            args.map(|data| data.to_vec()),
+            results.map(|data| data.to_vec()),
+            None,
+        ));
+    }
+
+    #[track_caller]
+    pub fn set_expect_call_foreign_function_with(
+        &mut self,
+        name: Option<&str>,
+        args: Option<&[u8]>,
+        responder: ForeignFunctionResponder,
+    ) {
+        self.stage("call_foreign_function");
+        self.call_foreign_function.push((
+            name.map(|data| data.to_string()),
+            args.map(|data| data.to_vec()),
+            None,
+            Some(responder),
+        ));
+    }
+
+    // An expected call without staged results responds with empty results, an unexpected one with
+    // None so that the host reports the function as not found
+    pub fn get_expect_call_foreign_function(&mut self, name: &str, args: &[u8]) -> Option<Bytes> {
+        let call = || scaffold::expect_call_foreign_function(name, args);
+        self.record(&call);
+        match self.call_foreign_function.len() {
+            0 => {
+                self.unexpected("call_foreign_function", &call);
+                None
+            }
+            _ => {
+                let foreign_function_tuple = self.take("call_foreign_function", |expect| {
+                    &mut expect.call_foreign_function
+                });
+                let mut expect_status =
+                    name == &foreign_function_tuple.0.unwrap_or(name.to_string());
+                expect_status =
+                    expect_status && args == &foreign_function_tuple.1.unwrap_or(args.to_vec())[..];
+                self.set_expect_status(&call, expect_status);
+                match foreign_function_tuple.3 {
+                    Some(responder) => Some((responder.0)(args)),
+                    None => Some(foreign_function_tuple.2.unwrap_or_default()),
+                }
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_proxy_done(&mut self, context_id: Option<i32>) {
+        self.stage("proxy_done");
+        self.proxy_done.push(context_id);
+    }
+
+    pub fn get_expect_proxy_done(&mut self, context_id: i32) {
+        let call = || scaffold::expect_proxy_done(context_id);
+        self.record(&call);
+        match self.proxy_done.len() {
+            0 => {
+                self.unexpected("proxy_done", &call);
+            }
+            _ => {
+                let expect_status = context_id
+                    == self
+                        .take("proxy_done", |expect| &mut expect.proxy_done)
+                        .unwrap_or(context_id);
+                self.set_expect_status(&call, expect_status);
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_clear_route_cache(&mut self) {
+        self.stage("clear_route_cache");
+        self.clear_route_cache += 1;
+    }
+
+    pub fn get_expect_clear_route_cache(&mut self) {
+        let call = || scaffold::expect_clear_route_cache();
+        self.record(&call);
+        match self.clear_route_cache {
+            0 => {
+                self.unexpected("clear_route_cache", &call);
+            }
+            _ => {
+                self.take_count("clear_route_cache", |expect| &mut expect.clear_route_cache);
+                self.set_expect_status(&call, true);
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_continue_request(&mut self) {
+        self.stage("continue_request");
+        self.continue_request += 1;
+    }
+
+    pub fn get_expect_continue_request(&mut self) {
+        let call = || scaffold::expect_continue_request();
+        self.record(&call);
+        match self.continue_request {
+            0 => {
+                self.unexpected("continue_request", &call);
+            }
+            _ => {
+                self.take_count("continue_request", |expect| &mut expect.continue_request);
+                self.set_expect_status(&call, true);
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_continue_response(&mut self) {
+        self.stage("continue_response");
+        self.continue_response += 1;
+    }
+
+    pub fn get_expect_continue_response(&mut self) {
+        let call = || scaffold::expect_continue_response();
+        self.record(&call);
+        match self.continue_response {
+            0 => {
+                self.unexpected("continue_response", &call);
+            }
+            _ => {
+                self.take_count("continue_response", |expect| &mut expect.continue_response);
+                self.set_expect_status(&call, true);
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_resume_downstream(&mut self) {
+        self.stage("resume_downstream");
+        self.resume_downstream += 1;
+    }
+
+    pub fn get_expect_resume_downstream(&mut self) {
+        let call = || scaffold::expect_resume_downstream();
+        self.record(&call);
+        match self.resume_downstream {
+            0 => {
+                self.unexpected("resume_downstream", &call);
+            }
+            _ => {
+                self.take_count("resume_downstream", |expect| &mut expect.resume_downstream);
+                self.set_expect_status(&call, true);
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_resume_upstream(&mut self) {
+        self.stage("resume_upstream");
+        self.resume_upstream += 1;
+    }
+
+    pub fn get_expect_resume_upstream(&mut self) {
+        let call = || scaffold::expect_resume_upstream();
+        self.record(&call);
+        match self.resume_upstream {
+            0 => {
+                self.unexpected("resume_upstream", &call);
+            }
+            _ => {
+                self.take_count("resume_upstream", |expect| &mut expect.resume_upstream);
+                self.set_expect_status(&call, true);
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_continue_stream(&mut self, stream_type: Option<i32>) {
+        self.stage("continue_stream");
+        self.continue_stream.push(stream_type);
+    }
+
+    pub fn get_expect_continue_stream(&mut self, stream_type: i32) {
+        let call = || scaffold::expect_continue_stream(stream_type);
+        self.record(&call);
+        match self.continue_stream.len() {
+            0 => {
+                self.unexpected("continue_stream", &call);
+            }
+            _ => {
+                let expect_stream_type =
+                    self.take("continue_stream", |expect| &mut expect.continue_stream);
+                self.set_expect_status(
+                    &call,
+                    stream_type == expect_stream_type.unwrap_or(stream_type),
+                );
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_close_stream(&mut self, stream_type: Option<i32>) {
+        self.stage("close_stream");
+        self.close_stream.push(stream_type);
+    }
+
+    pub fn get_expect_close_stream(&mut self, stream_type: i32) {
+        let call = || scaffold::expect_close_stream(stream_type);
+        self.record(&call);
+        match self.close_stream.len() {
+            0 => {
+                self.unexpected("close_stream", &call);
+            }
+            _ => {
+                let expect_stream_type =
+                    self.take("close_stream", |expect| &mut expect.close_stream);
+                self.set_expect_status(
+                    &call,
+                    stream_type == expect_stream_type.unwrap_or(stream_type),
+                );
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_get_shared_data(
+        &mut self,
+        key: Option<&str>,
+        value: Option<&str>,
+        cas: Option<u32>,
+    ) {
+        self.stage("get_shared_data");
+        self.get_shared_data.push((
+            key.map(|data| data.to_string()),
+            value.map(|data| data.as_bytes().to_vec()),
+            cas,
+            None,
+        ));
+    }
+
+    #[track_caller]
+    pub fn set_expect_get_shared_data_with(
+        &mut self,
+        key: Option<&str>,
+        responder: SharedDataResponder,
+    ) {
+        self.stage("get_shared_data");
+        self.get_shared_data.push((
+            key.map(|data| data.to_string()),
+            None,
+            None,
+            Some(responder),
+        ));
+    }
+
+    pub fn get_expect_get_shared_data(&mut self, key: &str) -> (Option<Bytes>, Option<u32>) {
+        let call = || scaffold::expect_get_shared_data(key);
+        self.record(&call);
+        match self.get_shared_data.len() {
+            0 => {
+                self.unexpected("get_shared_data", &call);
+                (None, None)
+            }
+            _ => {
+                let shared_data_tuple =
+                    self.take("get_shared_data", |expect| &mut expect.get_shared_data);
+                let expect_status = key == &shared_data_tuple.0.unwrap_or(key.to_string());
+                self.set_expect_status(&call, expect_status);
+                match shared_data_tuple.3 {
+                    Some(responder) => (responder.0)(key),
+                    None => (shared_data_tuple.1, shared_data_tuple.2),
+                }
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_set_shared_data(
+        &mut self,
+        key: Option<&str>,
+        value: Option<&str>,
+        cas: Option<u32>,
+    ) {
+        self.stage("set_shared_data");
+        self.set_shared_data.push((
+            key.map(|data| data.to_string()),
+            value.map(|data| data.as_bytes().to_vec()),
+            cas,
+        ));
+    }
+
+    pub fn get_expect_set_shared_data(&mut self, key: &str, value: &[u8], cas: u32) {
+        let call = || scaffold::expect_set_shared_data(key, value, cas);
+        self.record(&call);
+        match self.set_shared_data.len() {
+            0 => {
+                self.unexpected("set_shared_data", &call);
+            }
+            _ => {
+                let shared_data_tuple =
+                    self.take("set_shared_data", |expect| &mut expect.set_shared_data);
+                let mut expect_status = key == &shared_data_tuple.0.unwrap_or(key.to_string());
+                expect_status =
+                    expect_status && value == &shared_data_tuple.1.unwrap_or(value.to_vec())[..];
+                expect_status = expect_status && cas == shared_data_tuple.2.unwrap_or(cas);
+                self.set_expect_status(&call, expect_status);
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_register_shared_queue(&mut self, name: Option<&str>) {
+        self.stage("register_shared_queue");
+        self.register_shared_queue
+            .push(name.map(|data| data.to_string()));
+    }
+
+    pub fn get_expect_register_shared_queue(&mut self, name: &str) {
+        let call = || scaffold::expect_register_shared_queue(name);
+        self.record(&call);
+        match self.register_shared_queue.len() {
+            0 => {
+                self.unexpected("register_shared_queue", &call);
+            }
+            _ => {
+                let expect_status = name
+                    == &self
+                        .take("register_shared_queue", |expect| {
+                            &mut expect.register_shared_queue
+                        })
+                        .unwrap_or(name.to_string());
+                self.set_expect_status(&call, expect_status);
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_resolve_shared_queue(
+        &mut self,
+        vm_id: Option<&str>,
+        name: Option<&str>,
+        queue_id: Option<u32>,
+    ) {
+        self.stage("resolve_shared_queue");
+        self.resolve_shared_queue.push((
+            vm_id.map(|data| data.to_string()),
+            name.map(|data| data.to_string()),
+            queue_id,
+            None,
+        ));
+    }
+
+    #[track_caller]
+    pub fn set_expect_resolve_shared_queue_with(
+        &mut self,
+        vm_id: Option<&str>,
+        name: Option<&str>,
+        responder: ResolveSharedQueueResponder,
+    ) {
+        self.stage("resolve_shared_queue");
+        self.resolve_shared_queue.push((
+            vm_id.map(|data| data.to_string()),
+            name.map(|data| data.to_string()),
+            None,
+            Some(responder),
+        ));
+    }
+
+    pub fn get_expect_resolve_shared_queue(&mut self, vm_id: &str, name: &str) -> Option<u32> {
+        let call = || scaffold::expect_resolve_shared_queue(vm_id, name);
+        self.record(&call);
+        match self.resolve_shared_queue.len() {
+            0 => {
+                self.unexpected("resolve_shared_queue", &call);
+                None
+            }
+            _ => {
+                let shared_queue_tuple = self.take("resolve_shared_queue", |expect| {
+                    &mut expect.resolve_shared_queue
+                });
+                let mut expect_status = vm_id == &shared_queue_tuple.0.unwrap_or(vm_id.to_string());
+                expect_status =
+                    expect_status && name == &shared_queue_tuple.1.unwrap_or(name.to_string());
+                self.set_expect_status(&call, expect_status);
+                match shared_queue_tuple.3 {
+                    Some(responder) => (responder.0)(vm_id, name),
+                    None => shared_queue_tuple.2,
+                }
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_enqueue_shared_queue(&mut self, queue_id: Option<u32>, data: Option<&str>) {
+        self.stage("enqueue_shared_queue");
+        self.enqueue_shared_queue
+            .push((queue_id, data.map(|data| data.as_bytes().to_vec())));
+    }
+
+    pub fn get_expect_enqueue_shared_queue(&mut self, queue_id: u32, data: &[u8]) {
+        let call = || scaffold::expect_enqueue_shared_queue(queue_id, data);
+        self.record(&call);
+        match self.enqueue_shared_queue.len() {
+            0 => {
+                self.unexpected("enqueue_shared_queue", &call);
+            }
+            _ => {
+                let shared_queue_tuple = self.take("enqueue_shared_queue", |expect| {
+                    &mut expect.enqueue_shared_queue
+                });
+                let mut expect_status = queue_id == shared_queue_tuple.0.unwrap_or(queue_id);
+                expect_status =
+                    expect_status && data == &shared_queue_tuple.1.unwrap_or(data.to_vec())[..];
+                self.set_expect_status(&call, expect_status);
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_dequeue_shared_queue(&mut self, queue_id: Option<u32>, data: Option<&str>) {
+        self.stage("dequeue_shared_queue");
+        self.dequeue_shared_queue
+            .push((queue_id, data.map(|data| data.as_bytes().to_vec()), None));
+    }
+
+    #[track_caller]
+    pub fn set_expect_dequeue_shared_queue_with(
+        &mut self,
+        queue_id: Option<u32>,
+        responder: DequeueSharedQueueResponder,
+    ) {
+        self.stage("dequeue_shared_queue");
+        self.dequeue_shared_queue
+            .push((queue_id, None, Some(responder)));
+    }
+
+    pub fn get_expect_dequeue_shared_queue(&mut self, queue_id: u32) -> Option<Bytes> {
+        let call = || scaffold::expect_dequeue_shared_queue(queue_id);
+        self.record(&call);
+        match self.dequeue_shared_queue.len() {
+            0 => {
+                self.unexpected("dequeue_shared_queue", &call);
+                None
+            }
+            _ => {
+                let shared_queue_tuple = self.take("dequeue_shared_queue", |expect| {
+                    &mut expect.dequeue_shared_queue
+                });
+                let expect_status = queue_id == shared_queue_tuple.0.unwrap_or(queue_id);
+                self.set_expect_status(&call, expect_status);
+                match shared_queue_tuple.2 {
+                    Some(responder) => (responder.0)(queue_id),
+                    None => shared_queue_tuple.1,
+                }
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_get_property(&mut self, path: Option<Vec<&str>>, value: Option<&[u8]>) {
+        self.stage("get_property");
+        self.get_property.push((
+            path.map(|data| serialize_property_path(data)),
+            value.map(|data| data.to_vec()),
+            None,
+        ));
+    }
+
+    #[track_caller]
+    pub fn set_expect_get_property_with(
+        &mut self,
+        path: Option<Vec<&str>>,
+        responder: PropertyResponder,
+    ) {
+        self.stage("get_property");
+        self.get_property.push((
+            path.map(|data| serialize_property_path(data)),
+            None,
+            Some(responder),
+        ));
+    }
+
+    pub fn get_expect_get_property(&mut self, path: &[u8]) -> Option<Bytes> {
+        let call = || scaffold::expect_get_property(path);
+        self.record(&call);
+        match self.get_property.len() {
+            0 => {
+                self.unexpected("get_property", &call);
+                None
+            }
+            _ => {
+                let property_tuple = self.take("get_property", |expect| &mut expect.get_property);
+                let expect_status = path == &property_tuple.0.unwrap_or(path.to_vec())[..];
+                self.set_expect_status(&call, expect_status);
+                match property_tuple.2 {
+                    Some(responder) => (responder.0)(path),
+                    None => property_tuple.1,
+                }
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_set_property(&mut self, path: Option<Vec<&str>>, value: Option<&[u8]>) {
+        self.stage("set_property");
+        self.set_property.push((
+            path.map(|data| serialize_property_path(data)),
+            value.map(|data| data.to_vec()),
+        ));
+    }
+
+    pub fn get_expect_set_property(&mut self, path: &[u8], value: &[u8]) {
+        let call = || scaffold::expect_set_property(path, value);
+        self.record(&call);
+        match self.set_property.len() {
+            0 => {
+                self.unexpected("set_property", &call);
+            }
+            _ => {
+                let property_tuple = self.take("set_property", |expect| &mut expect.set_property);
+                let mut expect_status = path == &property_tuple.0.unwrap_or(path.to_vec())[..];
+                expect_status =
+                    expect_status && value == &property_tuple.1.unwrap_or(value.to_vec())[..];
+                self.set_expect_status(&call, expect_status);
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_define_metric(
+        &mut self,
+        metric_type: Option<i32>,
+        name: Option<&str>,
+        metric_id: Option<u32>,
+    ) {
+        self.stage("define_metric");
+        self.define_metric.push((
+            metric_type,
+            name.map(|data| data.to_string()),
+            metric_id,
+            None,
+        ));
+    }
+
+    #[track_caller]
+    pub fn set_expect_define_metric_with(
+        &mut self,
+        metric_type: Option<i32>,
+        name: Option<&str>,
+        responder: DefineMetricResponder,
+    ) {
+        self.stage("define_metric");
+        self.define_metric.push((
+            metric_type,
+            name.map(|data| data.to_string()),
+            None,
+            Some(responder),
+        ));
+    }
+
+    pub fn get_expect_define_metric(&mut self, metric_type: i32, name: &str) -> Option<u32> {
+        let call = || scaffold::expect_define_metric(metric_type, name);
+        self.record(&call);
+        match self.define_metric.len() {
+            0 => {
+                self.unexpected("define_metric", &call);
+                None
+            }
+            _ => {
+                let metric_tuple = self.take("define_metric", |expect| &mut expect.define_metric);
+                let mut expect_status = metric_type == metric_tuple.0.unwrap_or(metric_type);
+                expect_status =
+                    expect_status && name == &metric_tuple.1.unwrap_or(name.to_string());
+                self.set_expect_status(&call, expect_status);
+                match metric_tuple.3 {
+                    Some(responder) => (responder.0)(metric_type, name),
+                    None => metric_tuple.2,
+                }
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_increment_metric(&mut self, metric_id: Option<u32>, offset: Option<i64>) {
+        self.stage("increment_metric");
+        self.increment_metric.push((metric_id, offset));
+    }
+
+    pub fn get_expect_increment_metric(&mut self, metric_id: u32, offset: i64) {
+        let call = || scaffold::expect_increment_metric(metric_id, offset);
+        self.record(&call);
+        match self.increment_metric.len() {
+            0 => {
+                self.unexpected("increment_metric", &call);
+            }
+            _ => {
+                let metric_tuple =
+                    self.take("increment_metric", |expect| &mut expect.increment_metric);
+                let mut expect_status = metric_id == metric_tuple.0.unwrap_or(metric_id);
+                expect_status = expect_status && offset == metric_tuple.1.unwrap_or(offset);
+                self.set_expect_status(&call, expect_status);
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_record_metric(&mut self, metric_id: Option<u32>, value: Option<u64>) {
+        self.stage("record_metric");
+        self.record_metric.push((metric_id, value));
+    }
+
+    pub fn get_expect_record_metric(&mut self, metric_id: u32, value: u64) {
+        let call = || scaffold::expect_record_metric(metric_id, value);
+        self.record(&call);
+        match self.record_metric.len() {
+            0 => {
+                self.unexpected("record_metric", &call);
+            }
+            _ => {
+                let metric_tuple = self.take("record_metric", |expect| &mut expect.record_metric);
+                let mut expect_status = metric_id == metric_tuple.0.unwrap_or(metric_id);
+                expect_status = expect_status && value == metric_tuple.1.unwrap_or(value);
+                self.set_expect_status(&call, expect_status);
+            }
+        }
+    }
+
+    #[track_caller]
+    pub fn set_expect_get_metric(&mut self, metric_id: Option<u32>, value: Option<u64>) {
+        self.stage("get_metric");
+        self.get_metric.push((metric_id, value, None));
+    }
+
+    #[track_caller]
+    pub fn set_expect_get_metric_with(
+        &mut self,
+        metric_id: Option<u32>,
+        responder: MetricResponder,
+    ) {
+        self.stage("get_metric");
+        self.get_metric.push((metric_id, None, Some(responder)));
+    }
+
+    pub fn get_expect_get_metric(&mut self, metric_id: u32) -> Option<u64> {
+        let call = || scaffold::expect_get_metric(metric_id);
+        self.record(&call);
+        match self.get_metric.len() {
+            0 => {
+                self.unexpected("get_metric", &call);
+                None
+            }
+            _ => {
+                let metric_tuple = self.take("get_metric", |expect| &mut expect.get_metric);
+                let expect_status = metric_id == metric_tuple.0.unwrap_or(metric_id);
+                self.set_expect_status(&call, expect_status);
+                match metric_tuple.2 {
+                    Some(responder) => (responder.0)(metric_id),
+                    None => metric_tuple.1,
+                }
             }
         }
     }
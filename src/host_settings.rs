@@ -12,26 +12,46 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::hostcalls::serial_utils::serialize_map;
+use crate::hostcalls::serial_utils::{
+    serialize_grpc_frame, serialize_map_bytes, serialize_property_path,
+};
 use crate::types::*;
 
-use std::collections::HashMap;
-use std::time::Duration;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 // Global structure for handling default host behaviour (and high-level expectation setting)
 pub struct HostHandle {
     pub staged: HostSettings,
+    shared_stores: Option<(Arc<Mutex<HostStores>>, usize)>,
 }
 
 impl HostHandle {
     pub fn new() -> HostHandle {
         HostHandle {
             staged: HostSettings::new(AbiVersion::UnknownAbiVersion, false),
+            shared_stores: None,
         }
     }
 
+    // Settings of one of the workers of mock_workers, backed by the stores of every worker and kept
+    // backed by them across resets
+    pub fn with_shared_stores(stores: Arc<Mutex<HostStores>>, worker_id: usize) -> HostHandle {
+        let mut handle = HostHandle {
+            staged: HostSettings::new(AbiVersion::UnknownAbiVersion, false),
+            shared_stores: Some((stores, worker_id)),
+        };
+        handle.reset(AbiVersion::UnknownAbiVersion, false);
+        handle
+    }
+
     pub fn reset(&mut self, abi_version: AbiVersion, quiet: bool) {
         self.staged = HostSettings::new(abi_version, quiet);
+        if let Some((stores, worker_id)) = &self.shared_stores {
+            self.staged.stores = stores.clone();
+            self.staged.worker_id = *worker_id;
+        }
     }
 
     pub fn print_staged(&self) {
@@ -39,14 +59,80 @@ impl HostHandle {
     }
 }
 
+// Shared data, shared queues and metrics, which workers of the same host see the writes of one
+// another to
+#[derive(Debug)]
+pub struct HostStores {
+    shared_data: HashMap<(String, String), (Bytes, u32)>,
+    shared_queues: HashMap<u32, (String, String, i32, usize)>,
+    next_queue_id: u32,
+    queue_data: HashMap<u32, VecDeque<Bytes>>,
+    queue_ready: Vec<(usize, i32, u32)>,
+    metrics: HashMap<u32, (i32, String, i64)>,
+    metric_ids: HashMap<String, u32>,
+    histogram_samples: HashMap<u32, Vec<u64>>,
+    next_metric_id: u32,
+}
+
+impl HostStores {
+    pub fn new() -> HostStores {
+        HostStores {
+            shared_data: HashMap::new(),
+            shared_queues: HashMap::new(),
+            next_queue_id: 1,
+            queue_data: HashMap::new(),
+            queue_ready: Vec::new(),
+            metrics: HashMap::new(),
+            metric_ids: HashMap::new(),
+            histogram_samples: HashMap::new(),
+            next_metric_id: 1,
+        }
+    }
+}
+
 // Global struct for host environment default settings
 #[derive(Debug)]
 pub struct HostSettings {
     abi_version: AbiVersion,
     quiet: bool,
-    tick_period_millis: Duration,
-    header_map_pairs: HashMap<i32, Vec<(String, String)>>,
+    log_level: i32,
+    status: (u32, String),
+    tick_period: Duration,
+    tick_schedule: HashMap<i32, (Duration, SystemTime)>,
+    current_time: Option<SystemTime>,
+    time_step: Option<Duration>,
+    header_map_pairs: HashMap<i32, Vec<(String, Bytes)>>,
     buffer_bytes: HashMap<i32, Bytes>,
+    modified_buffers: Vec<i32>,
+    queue_delivery: QueueDelivery,
+    map_serialization: MapSerialization,
+    sync_content_length: bool,
+    effective_context_id: i32,
+    contexts: Vec<i32>,
+    context_switches: Vec<i32>,
+    pending_done: Vec<i32>,
+    resumed_streams: Vec<(i32, StreamType)>,
+    closed_streams: Vec<(i32, StreamType)>,
+    local_response_sent: Vec<i32>,
+    local_responses: Vec<LocalResponse>,
+    route_cache_cleared: Vec<i32>,
+    upstream_cluster_header: String,
+    selected_upstream: SelectedUpstream,
+    vm_id: String,
+    root_id: String,
+    plugin_configuration: Bytes,
+    vm_configuration: Bytes,
+    vm_starting: bool,
+    allocation_failure: bool,
+    stores: Arc<Mutex<HostStores>>,
+    worker_id: usize,
+    properties: HashMap<Bytes, Bytes>,
+    grpc_calls: HashMap<u32, bool>,
+    grpc_messages: HashMap<u32, Vec<Bytes>>,
+    next_grpc_token: u32,
+    http_call_responses: HashMap<u32, HttpCalloutResponse>,
+    pending_http_call_responses: Vec<(i32, u32, HttpCalloutResponse)>,
+    next_http_call_token: u32,
 }
 
 impl HostSettings {
@@ -54,9 +140,44 @@ impl HostSettings {
         HostSettings {
             abi_version: abi_version,
             quiet: quiet,
-            tick_period_millis: Duration::new(0, 0),
+            log_level: LogLevel::Trace as i32,
+            status: (0, String::new()),
+            tick_period: Duration::new(0, 0),
+            tick_schedule: HashMap::new(),
+            current_time: None,
+            time_step: None,
             header_map_pairs: default_header_map_pairs(),
             buffer_bytes: default_buffer_bytes(),
+            modified_buffers: Vec::new(),
+            queue_delivery: QueueDelivery::Manual,
+            map_serialization: MapSerialization::Framework,
+            sync_content_length: false,
+            effective_context_id: 0,
+            contexts: Vec::new(),
+            context_switches: Vec::new(),
+            pending_done: Vec::new(),
+            resumed_streams: Vec::new(),
+            closed_streams: Vec::new(),
+            local_response_sent: Vec::new(),
+            local_responses: Vec::new(),
+            route_cache_cleared: Vec::new(),
+            upstream_cluster_header: "x-upstream-cluster".to_string(),
+            selected_upstream: SelectedUpstream::default(),
+            vm_id: String::new(),
+            root_id: String::new(),
+            plugin_configuration: Vec::new(),
+            vm_configuration: Vec::new(),
+            vm_starting: false,
+            allocation_failure: false,
+            stores: Arc::new(Mutex::new(HostStores::new())),
+            worker_id: 0,
+            properties: HashMap::new(),
+            grpc_calls: HashMap::new(),
+            grpc_messages: HashMap::new(),
+            next_grpc_token: 1,
+            http_call_responses: HashMap::new(),
+            pending_http_call_responses: Vec::new(),
+            next_http_call_token: 1,
         }
     }
 
@@ -76,25 +197,171 @@ impl HostSettings {
         self.quiet
     }
 
-    pub fn reset_tick_period_millis(&mut self) {
-        self.tick_period_millis = Duration::from_millis(0u64);
+    pub fn set_log_level(&mut self, log_level: i32) {
+        self.log_level = log_level;
+    }
+
+    pub fn get_log_level(&self) -> i32 {
+        self.log_level
+    }
+
+    // Status code and message reported by proxy_get_status
+    pub fn set_status(&mut self, status_code: u32, message: &str) {
+        self.status = (status_code, message.to_string());
     }
 
-    pub fn set_tick_period_millis(&mut self, tick_period_millis: u64) {
-        self.tick_period_millis = Duration::from_millis(tick_period_millis);
+    pub fn get_status(&self) -> (u32, String) {
+        self.status.clone()
     }
 
-    pub fn get_tick_period_millis(&self) -> u128 {
-        self.tick_period_millis.as_millis()
+    // Virtual clock read by proxy_get_current_time_nanoseconds, following the wall clock until set
+    pub fn set_current_time(&mut self, current_time: SystemTime) {
+        self.current_time = Some(current_time);
+    }
+
+    pub fn advance_current_time(&mut self, duration: Duration) {
+        self.current_time = Some(self.get_current_time() + duration);
+    }
+
+    pub fn get_current_time(&self) -> SystemTime {
+        self.current_time.unwrap_or_else(SystemTime::now)
+    }
+
+    // With a time step every read moves the virtual clock forward, so consecutive reads never
+    // observe the same time
+    pub fn set_time_step(&mut self, time_step: Option<Duration>) {
+        self.time_step = time_step;
+    }
+
+    pub fn read_current_time(&mut self) -> SystemTime {
+        let current_time = self.get_current_time();
+        if let Some(time_step) = self.time_step {
+            self.current_time = Some(current_time + time_step);
+        }
+        current_time
+    }
+
+    pub fn reset_tick_period(&mut self) {
+        self.tick_period = Duration::new(0, 0);
+        self.tick_schedule.clear();
+    }
+
+    pub fn set_tick_period(&mut self, tick_period: Duration) {
+        self.tick_period = tick_period;
+    }
+
+    pub fn get_tick_period(&self) -> Duration {
+        self.tick_period
+    }
+
+    // Schedules proxy_on_tick on the root context every tick_period from now, a zero period
+    // cancels its ticks
+    pub fn schedule_ticks(&mut self, context_id: i32, tick_period: Duration) {
+        if tick_period == Duration::new(0, 0) {
+            self.tick_schedule.remove(&context_id);
+            return;
+        }
+        let next_tick = self.get_current_time() + tick_period;
+        self.tick_schedule
+            .insert(context_id, (tick_period, next_tick));
+    }
+
+    // Earliest tick due no later than the deadline, which is rescheduled one period later
+    pub fn take_next_tick(&mut self, deadline: SystemTime) -> Option<(i32, SystemTime)> {
+        let (context_id, next_tick) = self
+            .tick_schedule
+            .iter()
+            .map(|(context_id, (_, next_tick))| (*context_id, *next_tick))
+            .filter(|(_, next_tick)| *next_tick <= deadline)
+            .min_by_key(|(context_id, next_tick)| (*next_tick, *context_id))?;
+        if let Some((tick_period, scheduled)) = self.tick_schedule.get_mut(&context_id) {
+            *scheduled = next_tick + *tick_period;
+        }
+        Some((context_id, next_tick))
     }
 
     pub fn reset_buffer_bytes(&mut self) {
         self.buffer_bytes = default_buffer_bytes();
+        self.modified_buffers.clear();
+        self.buffer_bytes.insert(
+            BufferType::VmConfiguration as i32,
+            self.vm_configuration.clone(),
+        );
+        self.buffer_bytes.insert(
+            BufferType::PluginConfiguration as i32,
+            self.plugin_configuration.clone(),
+        );
     }
 
     pub fn set_buffer_bytes(&mut self, buffer_type: i32, buffer_data: &str) {
-        self.buffer_bytes
-            .insert(buffer_type, buffer_data.as_bytes().to_vec());
+        self.set_buffer_data(buffer_type, buffer_data.as_bytes());
+    }
+
+    // Binary counterpart of set_buffer_bytes for payloads such as framed grpc messages
+    pub fn set_buffer_data(&mut self, buffer_type: i32, buffer_data: &[u8]) {
+        self.buffer_bytes.insert(buffer_type, buffer_data.to_vec());
+        self.modified_buffers
+            .retain(|modified| *modified != buffer_type);
+        self.update_content_length(buffer_type);
+    }
+
+    pub fn set_sync_content_length(&mut self, sync_content_length: bool) {
+        self.sync_content_length = sync_content_length;
+    }
+
+    // Rewrites the content-length header of the http body, when present, to the size of the body
+    // as Envoy does once the body is replaced
+    fn update_content_length(&mut self, buffer_type: i32) {
+        if !self.sync_content_length {
+            return;
+        }
+        let map_type = if buffer_type == BufferType::HttpRequestBody as i32 {
+            MapType::HttpRequestHeaders
+        } else if buffer_type == BufferType::HttpResponseBody as i32 {
+            MapType::HttpResponseHeaders
+        } else {
+            return;
+        };
+        if self
+            .get_header_map_value(map_type as i32, "content-length")
+            .is_some()
+        {
+            let content_length = self.buffer_bytes[&buffer_type].len().to_string();
+            self.replace_header_map_value(
+                map_type as i32,
+                "content-length",
+                content_length.as_bytes(),
+            );
+        }
+    }
+
+    // Replaces the bytes in [start, start + size) of the buffer with buffer_data, as the module
+    // does through proxy_set_buffer_bytes
+    pub fn splice_buffer_bytes(
+        &mut self,
+        buffer_type: i32,
+        start: usize,
+        size: usize,
+        buffer_data: &[u8],
+    ) -> Status {
+        let buffer = self
+            .buffer_bytes
+            .entry(buffer_type)
+            .or_insert_with(Vec::new);
+        if start > buffer.len() {
+            return Status::BadArgument;
+        }
+        let end = start.saturating_add(size).min(buffer.len());
+        buffer.splice(start..end, buffer_data.iter().cloned());
+        if !self.modified_buffers.contains(&buffer_type) {
+            self.modified_buffers.push(buffer_type);
+        }
+        self.update_content_length(buffer_type);
+        Status::Ok
+    }
+
+    pub fn is_buffer_modified(&self, buffer_type: i32) -> bool {
+        self.modified_buffers.contains(&buffer_type)
     }
 
     pub fn get_buffer_bytes(&self, buffer_type: i32) -> Bytes {
@@ -107,87 +374,647 @@ impl HostSettings {
     }
 
     pub fn set_header_map_pairs(&mut self, map_type: i32, header_map_pairs: Vec<(&str, &str)>) {
+        self.set_header_map_data(
+            map_type,
+            header_map_pairs
+                .into_iter()
+                .map(|(key, value)| (key, value.as_bytes()))
+                .collect(),
+        );
+    }
+
+    // Header values are kept as bytes, so that -bin headers such as grpc-status-details-bin reach
+    // the module unchanged
+    pub fn set_header_map_data(&mut self, map_type: i32, header_map_pairs: Vec<(&str, &[u8])>) {
         let mut header_map = Vec::new();
         for (header_map_key, header_map_value) in header_map_pairs.into_iter() {
-            header_map.push((header_map_key.to_string(), header_map_value.to_string()));
+            header_map.push((header_map_key.to_string(), header_map_value.to_vec()));
         }
         self.header_map_pairs.insert(map_type, header_map);
     }
 
     pub fn get_header_map_pairs(&self, map_type: i32) -> Bytes {
-        let header_map_pairs = self.header_map_pairs.get(&map_type).unwrap();
-        let header_map_pairs = header_map_pairs
-            .iter()
-            .map(|(k, v)| (k as &str, v as &str))
-            .collect();
-        serialize_map(header_map_pairs)
+        let header_map_pairs = self.get_header_map_data(map_type);
+        if header_map_pairs.is_empty() && self.map_serialization == MapSerialization::CppHost {
+            return Vec::new();
+        }
+        serialize_map_bytes(
+            header_map_pairs
+                .iter()
+                .map(|(k, v)| (k.as_bytes(), &v[..]))
+                .collect(),
+        )
     }
 
-    pub fn get_header_map_value(&self, map_type: i32, header_map_key: &str) -> Option<String> {
-        let mut header_map_value: Option<String> = None;
-        let header_map = self.header_map_pairs.get(&map_type).unwrap();
-        for (key, value) in header_map {
-            if key == header_map_key {
-                header_map_value = Some(value.to_string());
-            }
-        }
-        header_map_value
+    // Current contents of the header map, including every mutation made by the module (values that
+    // are not UTF-8 are replaced lossily)
+    pub fn get_header_map(&self, map_type: i32) -> Vec<(String, String)> {
+        self.get_header_map_data(map_type)
+            .into_iter()
+            .map(|(key, value)| (key, String::from_utf8_lossy(&value).to_string()))
+            .collect()
+    }
+
+    pub fn get_header_map_data(&self, map_type: i32) -> Vec<(String, Bytes)> {
+        self.header_map_pairs
+            .get(&map_type)
+            .cloned()
+            .unwrap_or_default()
     }
 
+    // Header names are matched case-insensitively, as the host does for http headers
+    pub fn get_header_map_value(&self, map_type: i32, header_map_key: &str) -> Option<Bytes> {
+        self.header_map_pairs.get(&map_type).and_then(|header_map| {
+            header_map
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(header_map_key))
+                .map(|(_, value)| value.clone())
+        })
+    }
+
+    // Replaces every value of the header, adding it when it is not present yet
     pub fn replace_header_map_value(
         &mut self,
         map_type: i32,
         header_map_key: &str,
-        header_map_value: &str,
+        header_map_value: &[u8],
     ) {
-        let mut new_header_map: Vec<(String, String)> = Vec::new();
-        let header_map = self.header_map_pairs.get(&map_type).unwrap();
-        for (key, value) in header_map {
-            if key != header_map_key {
-                new_header_map.push((key.to_string(), value.to_string()));
-            } else {
-                new_header_map.push((key.to_string(), header_map_value.to_string()));
+        let header_map = self
+            .header_map_pairs
+            .entry(map_type)
+            .or_insert_with(Vec::new);
+        match header_map
+            .iter()
+            .position(|(key, _)| key.eq_ignore_ascii_case(header_map_key))
+        {
+            Some(index) => {
+                header_map.retain(|(key, _)| !key.eq_ignore_ascii_case(header_map_key));
+                header_map.insert(
+                    index,
+                    (header_map_key.to_string(), header_map_value.to_vec()),
+                );
             }
+            None => header_map.push((header_map_key.to_string(), header_map_value.to_vec())),
         }
-        self.header_map_pairs.insert(map_type, new_header_map);
     }
 
     pub fn remove_header_map_value(&mut self, map_type: i32, header_map_key: &str) {
-        let mut new_header_map: Vec<(String, String)> = Vec::new();
-        let header_map = self.header_map_pairs.get(&map_type).unwrap();
-        for (key, value) in header_map {
-            if key != header_map_key {
-                new_header_map.push((key.to_string(), value.to_string()));
-            }
+        if let Some(header_map) = self.header_map_pairs.get_mut(&map_type) {
+            header_map.retain(|(key, _)| !key.eq_ignore_ascii_case(header_map_key));
         }
-        self.header_map_pairs.insert(map_type, new_header_map);
     }
 
+    // Appends the value, keeping any value already present for the header
     pub fn add_header_map_value(
+        &mut self,
+        map_type: i32,
+        header_map_key: &str,
+        header_map_value: &[u8],
+    ) {
+        self.header_map_pairs
+            .entry(map_type)
+            .or_insert_with(Vec::new)
+            .push((header_map_key.to_string(), header_map_value.to_vec()));
+    }
+
+    // Pairs are laid out the same way in both modes: the count, the key and value sizes, then each
+    // key and value followed by a NUL terminator
+    pub fn set_map_serialization(&mut self, map_serialization: MapSerialization) {
+        self.map_serialization = map_serialization;
+    }
+
+    pub fn set_queue_delivery(&mut self, queue_delivery: QueueDelivery) {
+        self.queue_delivery = queue_delivery;
+    }
+
+    pub fn get_queue_delivery(&self) -> QueueDelivery {
+        self.queue_delivery
+    }
+
+    fn get_stores(&self) -> MutexGuard<HostStores> {
+        self.stores.lock().unwrap()
+    }
+
+    // Records that queue_id has data ready for the consumer context_id of the worker that
+    // registered the queue, merging the notification with an undelivered one for the same queue in
+    // coalesced mode
+    fn notify_queue_ready(&self, worker_id: usize, context_id: i32, queue_id: u32) {
+        let mut stores = self.get_stores();
+        if self.queue_delivery == QueueDelivery::Coalesced
+            && stores.queue_ready.iter().any(|(_, _, id)| *id == queue_id)
+        {
+            return;
+        }
+        stores.queue_ready.push((worker_id, context_id, queue_id));
+    }
+
+    // Notifications for the queues registered by this worker, left to the other workers otherwise
+    pub fn take_queue_ready(&mut self) -> Vec<(i32, u32)> {
+        let mut stores = self.get_stores();
+        let (taken, kept): (Vec<_>, Vec<_>) =
+            std::mem::replace(&mut stores.queue_ready, Vec::new())
+                .into_iter()
+                .partition(|(worker_id, _, _)| *worker_id == self.worker_id);
+        stores.queue_ready = kept;
+        taken
+            .into_iter()
+            .map(|(_, context_id, queue_id)| (context_id, queue_id))
+            .collect()
+    }
+
+    pub fn set_effective_context_id(&mut self, context_id: i32) {
+        self.effective_context_id = context_id;
+    }
+
+    pub fn get_effective_context_id(&self) -> i32 {
+        self.effective_context_id
+    }
+
+    pub fn create_context(&mut self, context_id: i32) {
+        if !self.contexts.contains(&context_id) {
+            self.contexts.push(context_id);
+        }
+    }
+
+    // Switches made by the plugin through proxy_set_effective_context, which only accepts contexts
+    // the host has created
+    pub fn switch_effective_context(&mut self, context_id: i32) -> Status {
+        if !self.contexts.contains(&context_id) {
+            return Status::BadArgument;
+        }
+        self.effective_context_id = context_id;
+        self.context_switches.push(context_id);
+        Status::Ok
+    }
+
+    pub fn get_context_switches(&self) -> Vec<i32> {
+        self.context_switches.clone()
+    }
+
+    // Contexts whose proxy_on_done returned false are pending until the plugin calls proxy_done
+    pub fn set_pending_done(&mut self, context_id: i32) {
+        if !self.pending_done.contains(&context_id) {
+            self.pending_done.push(context_id);
+        }
+    }
+
+    pub fn is_pending_done(&self, context_id: i32) -> bool {
+        self.pending_done.contains(&context_id)
+    }
+
+    pub fn set_done(&mut self, context_id: i32) -> bool {
+        let pending = self.is_pending_done(context_id);
+        self.pending_done.retain(|id| *id != context_id);
+        pending
+    }
+
+    pub fn resume_stream(&mut self, context_id: i32, stream_type: StreamType) {
+        self.resumed_streams.push((context_id, stream_type));
+    }
+
+    pub fn take_resumed_streams(&mut self) -> Vec<(i32, StreamType)> {
+        std::mem::replace(&mut self.resumed_streams, Vec::new())
+    }
+
+    pub fn close_stream(&mut self, context_id: i32, stream_type: StreamType) {
+        if !self.closed_streams.contains(&(context_id, stream_type)) {
+            self.closed_streams.push((context_id, stream_type));
+        }
+    }
+
+    pub fn is_stream_closed(&self, context_id: i32, stream_type: StreamType) -> bool {
+        self.closed_streams.contains(&(context_id, stream_type))
+    }
+
+    pub fn set_local_response_sent(&mut self, context_id: i32) {
+        if !self.local_response_sent.contains(&context_id) {
+            self.local_response_sent.push(context_id);
+        }
+    }
+
+    pub fn capture_local_response(&mut self, local_response: LocalResponse) {
+        self.local_responses.push(local_response);
+    }
+
+    pub fn get_local_responses(&self) -> Vec<LocalResponse> {
+        self.local_responses.clone()
+    }
+
+    pub fn has_sent_local_response(&self, context_id: i32) -> bool {
+        self.local_response_sent.contains(&context_id)
+    }
+
+    pub fn clear_route_cache(&mut self, context_id: i32) {
+        self.route_cache_cleared.push(context_id);
+    }
+
+    pub fn get_route_cache_clears(&self, context_id: i32) -> usize {
+        self.route_cache_cleared
+            .iter()
+            .filter(|id| **id == context_id)
+            .count()
+    }
+
+    pub fn set_upstream_cluster_header(&mut self, header_map_key: &str) {
+        self.upstream_cluster_header = header_map_key.to_lowercase();
+    }
+
+    // Records writes to the request headers that select the upstream cluster or rewrite its host
+    pub fn track_upstream_override(
         &mut self,
         map_type: i32,
         header_map_key: &str,
         header_map_value: &str,
     ) {
-        let mut key_found = false;
-        let mut new_header_map: Vec<(String, String)> = Vec::new();
-        let header_map = self.header_map_pairs.get(&map_type).unwrap();
-        for (key, value) in header_map {
-            if key != header_map_key {
-                new_header_map.push((key.to_string(), value.to_string()));
-            } else {
-                key_found = true;
+        if map_type != MapType::HttpRequestHeaders as i32 {
+            return;
+        }
+        let header_map_key = header_map_key.to_lowercase();
+        if header_map_key == self.upstream_cluster_header {
+            self.selected_upstream.cluster = Some(header_map_value.to_string());
+        } else if header_map_key == ":authority" || header_map_key == "host" {
+            self.selected_upstream.host = Some(header_map_value.to_string());
+        }
+    }
+
+    pub fn get_selected_upstream(&self) -> SelectedUpstream {
+        self.selected_upstream.clone()
+    }
+
+    pub fn set_vm_id(&mut self, vm_id: &str) {
+        self.vm_id = vm_id.to_string();
+    }
+
+    pub fn get_vm_id(&self) -> &str {
+        &self.vm_id
+    }
+
+    pub fn set_root_id(&mut self, root_id: &str) {
+        self.root_id = root_id.to_string();
+    }
+
+    pub fn get_root_id(&self) -> &str {
+        &self.root_id
+    }
+
+    // Plugin configuration served by proxy_get_configuration to 0.1.0 modules and as the
+    // PluginConfiguration buffer
+    pub fn set_plugin_configuration(&mut self, plugin_configuration: &[u8]) {
+        self.plugin_configuration = plugin_configuration.to_vec();
+        self.set_buffer_data(BufferType::PluginConfiguration as i32, plugin_configuration);
+    }
+
+    pub fn get_plugin_configuration(&self) -> Bytes {
+        self.plugin_configuration.clone()
+    }
+
+    // VM configuration served as the VmConfiguration buffer, and by proxy_get_configuration to
+    // 0.1.0 modules during proxy_on_vm_start
+    pub fn set_vm_configuration(&mut self, vm_configuration: &[u8]) {
+        self.vm_configuration = vm_configuration.to_vec();
+        self.set_buffer_data(BufferType::VmConfiguration as i32, vm_configuration);
+    }
+
+    pub fn get_vm_configuration(&self) -> Bytes {
+        self.vm_configuration.clone()
+    }
+
+    pub fn set_vm_starting(&mut self, vm_starting: bool) {
+        self.vm_starting = vm_starting;
+    }
+
+    // Configuration of the callback being executed, as returned by proxy_get_configuration
+    pub fn get_configuration(&self) -> Bytes {
+        if self.vm_starting {
+            self.get_vm_configuration()
+        } else {
+            self.get_plugin_configuration()
+        }
+    }
+
+    // While set, every allocation the host makes in the module's memory yields a null pointer
+    pub fn set_allocation_failure(&mut self, allocation_failure: bool) {
+        self.allocation_failure = allocation_failure;
+    }
+
+    pub fn get_allocation_failure(&self) -> bool {
+        self.allocation_failure
+    }
+
+    // Shared data and shared queues are only visible to modules running in the same vm_id
+    pub fn get_shared_namespace(&self, vm_id: Option<&str>) -> String {
+        vm_id.unwrap_or(&self.vm_id).to_string()
+    }
+
+    pub fn get_shared_data(&self, key: &str) -> Option<(Bytes, u32)> {
+        self.get_stores()
+            .shared_data
+            .get(&(self.get_shared_namespace(None), key.to_string()))
+            .cloned()
+    }
+
+    // Stores the value unless cas is non-zero and differs from the cas of the stored value,
+    // every successful write increments the cas of the key
+    pub fn set_shared_data(&mut self, key: &str, value: &[u8], cas: u32) -> Status {
+        let shared_key = (self.get_shared_namespace(None), key.to_string());
+        let mut stores = self.get_stores();
+        let next_cas = match stores.shared_data.get(&shared_key) {
+            Some((_, stored_cas)) if cas != 0 && cas != *stored_cas => return Status::CasMismatch,
+            Some((_, stored_cas)) => stored_cas.wrapping_add(1).max(1),
+            None => 1,
+        };
+        stores
+            .shared_data
+            .insert(shared_key, (value.to_vec(), next_cas));
+        Status::Ok
+    }
+
+    pub fn get_shared_data_keys(&self) -> Vec<String> {
+        let namespace = self.get_shared_namespace(None);
+        let mut keys: Vec<String> = self
+            .get_stores()
+            .shared_data
+            .keys()
+            .filter(|(key_namespace, _)| *key_namespace == namespace)
+            .map(|(_, key)| key.clone())
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    // Registers the queue for the consumer context_id, re-registering a queue keeps its queue_id
+    pub fn register_shared_queue(&mut self, name: &str, context_id: i32) -> u32 {
+        let namespace = self.get_shared_namespace(None);
+        let mut stores = self.get_stores();
+        let registered = stores
+            .shared_queues
+            .iter()
+            .find(|(_, (queue_namespace, queue_name, _, _))| {
+                *queue_namespace == namespace && queue_name == name
+            })
+            .map(|(queue_id, _)| *queue_id);
+        let queue_id = match registered {
+            Some(queue_id) => queue_id,
+            None => {
+                stores.next_queue_id += 1;
+                stores.next_queue_id - 1
+            }
+        };
+        stores.shared_queues.insert(
+            queue_id,
+            (namespace, name.to_string(), context_id, self.worker_id),
+        );
+        queue_id
+    }
+
+    // An empty vm_id resolves queues registered in the vm_id of the plugin
+    pub fn resolve_shared_queue(&self, vm_id: &str, name: &str) -> Option<u32> {
+        let namespace = match vm_id {
+            "" => self.get_shared_namespace(None),
+            vm_id => self.get_shared_namespace(Some(vm_id)),
+        };
+        self.get_stores()
+            .shared_queues
+            .iter()
+            .find(|(_, (queue_namespace, queue_name, _, _))| {
+                *queue_namespace == namespace && queue_name == name
+            })
+            .map(|(queue_id, _)| *queue_id)
+    }
+
+    pub fn get_shared_queue_consumer(&self, queue_id: u32) -> Option<i32> {
+        self.get_stores()
+            .shared_queues
+            .get(&queue_id)
+            .map(|(_, _, context_id, _)| *context_id)
+    }
+
+    // Appends data to the queue and, unless queue delivery is manual, notifies the context that
+    // registered the queue so that its proxy_on_queue_ready is called by the tester (of the worker
+    // that registered it)
+    pub fn enqueue_shared_queue(&mut self, queue_id: u32, data: &[u8]) -> Status {
+        let (consumer_worker_id, consumer_context_id) = {
+            let mut stores = self.get_stores();
+            let consumer = match stores.shared_queues.get(&queue_id) {
+                Some((_, _, context_id, worker_id)) => (*worker_id, *context_id),
+                None => return Status::NotFound,
+            };
+            stores
+                .queue_data
+                .entry(queue_id)
+                .or_insert_with(VecDeque::new)
+                .push_back(data.to_vec());
+            consumer
+        };
+        if self.queue_delivery != QueueDelivery::Manual {
+            self.notify_queue_ready(consumer_worker_id, consumer_context_id, queue_id);
+        }
+        Status::Ok
+    }
+
+    pub fn dequeue_shared_queue(&mut self, queue_id: u32) -> Result<Bytes, Status> {
+        if self.get_shared_queue_consumer(queue_id).is_none() {
+            return Err(Status::NotFound);
+        }
+        self.get_stores()
+            .queue_data
+            .get_mut(&queue_id)
+            .and_then(|queue| queue.pop_front())
+            .ok_or(Status::Empty)
+    }
+
+    pub fn get_shared_queue_len(&self, queue_id: u32) -> usize {
+        self.get_stores()
+            .queue_data
+            .get(&queue_id)
+            .map_or(0, |queue| queue.len())
+    }
+
+    // Redefining a metric under an existing name hands back the same metric_id, unless an
+    // expectation dictates the metric_id to serve
+    pub fn define_metric(&mut self, metric_type: i32, name: &str, metric_id: Option<u32>) -> u32 {
+        let mut stores = self.get_stores();
+        if let (None, Some(metric_id)) = (metric_id, stores.metric_ids.get(name)) {
+            return *metric_id;
+        }
+        let metric_id = metric_id.unwrap_or_else(|| {
+            stores.next_metric_id += 1;
+            stores.next_metric_id - 1
+        });
+        stores.metric_ids.insert(name.to_string(), metric_id);
+        stores
+            .metrics
+            .insert(metric_id, (metric_type, name.to_string(), 0));
+        metric_id
+    }
+
+    // Counters only move forward, gauges move in either direction
+    pub fn increment_metric(&mut self, metric_id: u32, offset: i64) -> Status {
+        match self.get_stores().metrics.get_mut(&metric_id) {
+            None => Status::NotFound,
+            Some((metric_type, _, value)) => {
+                if *metric_type == MetricType::Counter as i32 && offset < 0 {
+                    return Status::BadArgument;
+                }
+                if *metric_type == MetricType::Histogram as i32 {
+                    return Status::BadArgument;
+                }
+                *value += offset;
+                Status::Ok
+            }
+        }
+    }
+
+    // Counters accumulate the recorded value, gauges are overwritten by it and histograms keep every
+    // recorded sample
+    pub fn record_metric(&mut self, metric_id: u32, recorded: u64) -> Status {
+        let mut stores = self.get_stores();
+        let stores = &mut *stores;
+        match stores.metrics.get_mut(&metric_id) {
+            None => Status::NotFound,
+            Some((metric_type, _, value)) => {
+                if *metric_type == MetricType::Counter as i32 {
+                    *value += recorded as i64;
+                } else if *metric_type == MetricType::Gauge as i32 {
+                    *value = recorded as i64;
+                } else if *metric_type == MetricType::Histogram as i32 {
+                    stores
+                        .histogram_samples
+                        .entry(metric_id)
+                        .or_insert_with(Vec::new)
+                        .push(recorded);
+                }
+                Status::Ok
             }
         }
-        if !key_found {
-            new_header_map.push((header_map_key.to_string(), header_map_value.to_string()));
+    }
+
+    pub fn get_metric(&self, metric_id: u32) -> Option<i64> {
+        self.get_stores()
+            .metrics
+            .get(&metric_id)
+            .map(|(_, _, value)| *value)
+    }
+
+    pub fn get_histogram_samples(&self, name: &str) -> Option<Vec<u64>> {
+        let stores = self.get_stores();
+        let metric_id = stores.metric_ids.get(name)?;
+        match stores.metrics.get(metric_id) {
+            Some((metric_type, _, _)) if *metric_type == MetricType::Histogram as i32 => Some(
+                stores
+                    .histogram_samples
+                    .get(metric_id)
+                    .cloned()
+                    .unwrap_or_default(),
+            ),
+            _ => None,
+        }
+    }
+
+    pub fn get_metric_by_name(&self, name: &str) -> Option<i64> {
+        let metric_id = *self.get_stores().metric_ids.get(name)?;
+        self.get_metric(metric_id)
+    }
+
+    // Response delivered with proxy_on_http_call_response once the module makes the callout that is
+    // given token_id, returning the token
+    pub fn set_http_call_response(&mut self, response: HttpCalloutResponse) -> u32 {
+        let token_id = self.next_http_call_token;
+        self.next_http_call_token += 1;
+        self.http_call_responses.insert(token_id, response);
+        token_id
+    }
+
+    // Same as set_http_call_response for a token chosen by the test, e.g. the one returned by an
+    // expect_http_call(..).returning(Some(token_id)) expectation
+    pub fn attach_http_call_response(&mut self, token_id: u32, response: HttpCalloutResponse) {
+        self.http_call_responses.insert(token_id, response);
+    }
+
+    pub fn dispatch_http_call_response(&mut self, token_id: u32) {
+        if let Some(response) = self.http_call_responses.remove(&token_id) {
+            self.pending_http_call_responses
+                .push((self.effective_context_id, token_id, response));
+        }
+    }
+
+    pub fn take_http_call_responses(&mut self) -> Vec<(i32, u32, HttpCalloutResponse)> {
+        std::mem::replace(&mut self.pending_http_call_responses, Vec::new())
+    }
+
+    // Tracks an outstanding grpc call or stream under its token, unless an expectation dictates the
+    // token to serve the next token is allocated
+    pub fn open_grpc_call(&mut self, is_stream: bool, token_id: Option<u32>) -> u32 {
+        let token_id = token_id.unwrap_or_else(|| {
+            self.next_grpc_token += 1;
+            self.next_grpc_token - 1
+        });
+        self.grpc_calls.insert(token_id, is_stream);
+        token_id
+    }
+
+    pub fn send_grpc_message(&mut self, token_id: u32, message: &[u8]) -> Status {
+        match self.grpc_calls.get(&token_id) {
+            Some(true) => {
+                self.grpc_messages
+                    .entry(token_id)
+                    .or_insert_with(Vec::new)
+                    .push(message.to_vec());
+                Status::Ok
+            }
+            Some(false) => Status::BadArgument,
+            None => Status::NotFound,
+        }
+    }
+
+    // Both cancelling and closing stop tracking the token
+    pub fn close_grpc_call(&mut self, token_id: u32) -> Status {
+        match self.grpc_calls.remove(&token_id) {
+            Some(_) => Status::Ok,
+            None => Status::NotFound,
+        }
+    }
+
+    pub fn is_grpc_call_open(&self, token_id: u32) -> bool {
+        self.grpc_calls.contains_key(&token_id)
+    }
+
+    pub fn get_grpc_messages(&self, token_id: u32) -> Vec<Bytes> {
+        self.grpc_messages
+            .get(&token_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    // Properties are keyed by their serialized path, so "request.path" and "request.host" share the
+    // "request" branch of the tree
+    pub fn set_property(&mut self, path: &[u8], value: &[u8]) {
+        self.properties.insert(path.to_vec(), value.to_vec());
+    }
+
+    pub fn set_properties(&mut self, properties: HashMap<Bytes, Bytes>) {
+        self.properties.extend(properties);
+    }
+
+    pub fn get_property(&self, path: &[u8]) -> Option<Bytes> {
+        match self.properties.get(path) {
+            Some(value) => Some(value.clone()),
+            None => self.get_identity_property(path),
+        }
+    }
+
+    // Serves the standard properties describing the identity of the plugin
+    pub fn get_identity_property(&self, path: &[u8]) -> Option<Bytes> {
+        match path {
+            b"plugin_vm_id" => Some(self.vm_id.as_bytes().to_vec()),
+            b"plugin_root_id" => Some(self.root_id.as_bytes().to_vec()),
+            _ => None,
         }
-        self.header_map_pairs.insert(map_type, new_header_map);
     }
 }
 
 // functions to retrieve default values
-pub fn default_header_map_pairs() -> HashMap<i32, Vec<(String, String)>> {
+pub fn default_header_map_pairs() -> HashMap<i32, Vec<(String, Bytes)>> {
     let mut default_header_maps = HashMap::new();
 
     let mut http_on_request_headers = Vec::new();
@@ -260,6 +1087,15 @@ pub fn default_header_map_pairs() -> HashMap<i32, Vec<(String, String)>> {
     );
 
     default_header_maps
+        .into_iter()
+        .map(|(map_type, header_map): (i32, Vec<(String, String)>)| {
+            let header_map = header_map
+                .into_iter()
+                .map(|(key, value)| (key, value.into_bytes()))
+                .collect();
+            (map_type, header_map)
+        })
+        .collect()
 }
 
 pub fn default_buffer_bytes() -> HashMap<i32, Bytes> {
@@ -284,5 +1120,55 @@ pub fn default_buffer_bytes() -> HashMap<i32, Bytes> {
         BufferType::HttpCallResponseBody as i32,
         "default_call_response_body".as_bytes().to_vec(),
     );
+    default_bytes.insert(
+        BufferType::GrpcReceiveBuffer as i32,
+        serialize_grpc_frame(b"default_grpc_receive_message", false),
+    );
     default_bytes
 }
+
+// Typical attributes exposed by Envoy, encoded the way Envoy serializes them
+pub fn envoy_properties() -> HashMap<Bytes, Bytes> {
+    let mut properties = HashMap::new();
+    let mut insert = |path: Vec<&str>, value: PropertyValue| {
+        properties.insert(serialize_property_path(path), value.encode());
+    };
+
+    insert(
+        vec!["request", "path"],
+        "/default/request/headers/path".into(),
+    );
+    insert(
+        vec!["request", "url_path"],
+        "/default/request/headers/path".into(),
+    );
+    insert(vec!["request", "host"], "abi_test_harness".into());
+    insert(vec!["request", "scheme"], "http".into());
+    insert(vec!["request", "method"], "GET".into());
+    insert(vec!["request", "protocol"], "HTTP/1.1".into());
+    insert(
+        vec!["request", "id"],
+        "00000000-0000-0000-0000-000000000000".into(),
+    );
+    insert(vec!["request", "size"], 0i64.into());
+    insert(vec!["request", "time"], UNIX_EPOCH.into());
+    insert(vec!["request", "duration"], Duration::from_millis(0).into());
+
+    insert(vec!["source", "address"], "127.0.0.1:54321".into());
+    insert(vec!["source", "port"], 54321i64.into());
+    insert(vec!["destination", "address"], "127.0.0.1:8080".into());
+    insert(vec!["destination", "port"], 8080i64.into());
+
+    insert(vec!["connection", "id"], 1u64.into());
+    insert(vec!["connection", "mtls"], false.into());
+
+    insert(vec!["node", "id"], "abi_test_harness".into());
+    insert(vec!["node", "cluster"], "abi_test_harness_cluster".into());
+
+    insert(vec!["cluster_name"], "abi_test_harness_upstream".into());
+    insert(vec!["route_name"], "abi_test_harness_route".into());
+    insert(vec!["listener_direction"], 1i64.into());
+    insert(vec!["plugin_name"], "abi_test_harness_plugin".into());
+
+    properties
+}
@@ -13,1569 +13,3338 @@
 // limitations under the License.
 
 use crate::expectations::ExpectHandle;
-use crate::host_settings::HostHandle;
+use crate::host_settings::{HostHandle, HostStores};
+use crate::runtime::{Guest, GuestMemory, WasmRuntime, WasmtimeRuntime};
 use crate::types::*;
 
-use lazy_static::lazy_static;
+use anyhow::{anyhow, Result};
 use more_asserts::*;
 use std::sync::{Arc, Mutex};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, UNIX_EPOCH};
 use wasmtime::*;
 
-lazy_static! {
-    static ref HOST: Arc<Mutex<HostHandle>> = Arc::new(Mutex::new(HostHandle::new()));
-    static ref EXPECT: Arc<Mutex<ExpectHandle>> = Arc::new(Mutex::new(ExpectHandle::new()));
-    pub static ref STATUS: Arc<Mutex<ExpectStatus>> =
-        Arc::new(Mutex::new(ExpectStatus::Unexpected));
+// State behind the host functions linked for a module, owned by its tester so that testers do not
+// share host defaults or expectations (the workers of mock_workers only share the host stores)
+#[derive(Clone)]
+pub struct HostState {
+    pub host: Arc<Mutex<HostHandle>>,
+    pub expect: Arc<Mutex<ExpectHandle>>,
+    status: Arc<Mutex<ExpectStatus>>,
 }
 
-pub fn set_status(expect_status: ExpectStatus) {
-    *STATUS.lock().unwrap() = expect_status;
+impl HostState {
+    pub fn new() -> HostState {
+        HostState::with_host(HostHandle::new())
+    }
+
+    fn with_host(host: HostHandle) -> HostState {
+        let expect = ExpectHandle::new();
+        let status = expect.staged.get_status_handle();
+        HostState {
+            host: Arc::new(Mutex::new(host)),
+            expect: Arc::new(Mutex::new(expect)),
+            status: status,
+        }
+    }
 }
 
-pub fn get_status() -> ExpectStatus {
-    let status = *STATUS.lock().unwrap();
+fn set_status(status: &Mutex<ExpectStatus>, expect_status: ExpectStatus) {
+    *status.lock().unwrap() = expect_status;
+}
+
+fn get_status(status: &Mutex<ExpectStatus>) -> ExpectStatus {
+    let status = *status.lock().unwrap();
     status
 }
 
 pub fn get_abi_version(module: &Module) -> AbiVersion {
+    match detect_abi_version(module) {
+        AbiVersion::UnknownAbiVersion => {
+            panic!("Error: test-framework does not support proxy-wasm modules of this abi version")
+        }
+        abi_version => abi_version,
+    }
+}
+
+pub fn detect_abi_version(module: &Module) -> AbiVersion {
     if module.get_export("proxy_abi_version_0_1_0") != None {
         AbiVersion::ProxyAbiVersion0_1_0
     } else if module.get_export("proxy_abi_version_0_2_0") != None {
         AbiVersion::ProxyAbiVersion0_2_0
     } else {
-        panic!("Error: test-framework does not support proxy-wasm modules of this abi version");
+        AbiVersion::UnknownAbiVersion
+    }
+}
+
+// Exports the host needs from every proxy-wasm module to create contexts and copy data into them
+pub fn get_missing_exports(module: &Module) -> Vec<String> {
+    let mut missing_exports = Vec::new();
+    for name in &["memory", "proxy_on_context_create"] {
+        if module.get_export(name) == None {
+            missing_exports.push(name.to_string());
+        }
+    }
+    if module.get_export("proxy_on_memory_allocate") == None && module.get_export("malloc") == None
+    {
+        missing_exports.push("proxy_on_memory_allocate (or malloc)".to_string());
+    }
+    missing_exports
+}
+
+// Exports and imports whose presence or arity differs between ABI versions:
+// (name, is_export, number of params in 0.1.0, number of params in 0.2.0)
+const ABI_SIGNATURES: &[(&str, bool, Option<usize>, Option<usize>)] = &[
+    ("proxy_abi_version_0_1_0", true, Some(0), None),
+    ("proxy_abi_version_0_2_0", true, None, Some(0)),
+    ("proxy_on_request_headers", true, Some(2), Some(3)),
+    ("proxy_on_response_headers", true, Some(2), Some(3)),
+    ("proxy_on_foreign_function", true, None, Some(3)),
+    ("proxy_get_configuration", false, Some(2), None),
+    ("proxy_continue_request", false, Some(0), None),
+    ("proxy_continue_response", false, Some(0), None),
+    ("proxy_resume_downstream", false, Some(0), None),
+    ("proxy_resume_upstream", false, Some(0), None),
+    ("proxy_continue_stream", false, None, Some(1)),
+    ("proxy_close_stream", false, None, Some(1)),
+    ("proxy_call_foreign_function", false, None, Some(6)),
+];
+
+// Lists the exports and imports of a module that do not belong to the ABI version it declares,
+// as left behind by partial SDK upgrades
+pub fn get_abi_mismatches(module: &Module) -> Vec<AbiMismatch> {
+    find_abi_mismatches(module, detect_abi_version(module))
+}
+
+// Same as get_abi_mismatches for a module interpreted as the given ABI version
+pub fn find_abi_mismatches(module: &Module, declared: AbiVersion) -> Vec<AbiMismatch> {
+    let mut mismatches = Vec::new();
+    for (name, is_export, params_0_1_0, params_0_2_0) in ABI_SIGNATURES {
+        let extern_type = if *is_export {
+            module
+                .exports()
+                .find(|export| export.name() == *name)
+                .map(|export| export.ty())
+        } else {
+            module
+                .imports()
+                .find(|import| import.name() == *name)
+                .map(|import| import.ty())
+        };
+        let num_params = match extern_type {
+            Some(ExternType::Func(func_type)) => func_type.params().len(),
+            _ => continue,
+        };
+        let expected = match declared {
+            AbiVersion::ProxyAbiVersion0_1_0 => *params_0_1_0,
+            AbiVersion::ProxyAbiVersion0_2_0 => *params_0_2_0,
+            AbiVersion::UnknownAbiVersion => None,
+        };
+        if expected == Some(num_params) {
+            continue;
+        }
+        let matching = if *params_0_1_0 == Some(num_params) {
+            AbiVersion::ProxyAbiVersion0_1_0
+        } else if *params_0_2_0 == Some(num_params) {
+            AbiVersion::ProxyAbiVersion0_2_0
+        } else {
+            AbiVersion::UnknownAbiVersion
+        };
+        mismatches.push(AbiMismatch {
+            name: name.to_string(),
+            is_export: *is_export,
+            num_params: num_params,
+            declared: declared,
+            matching: matching,
+        });
+    }
+    mismatches
+}
+
+// Checks every import of a module against the host functions of the test-framework, returning the
+// names of imports that are not provided and descriptions of imports whose signatures differ
+pub fn check_imports(engine: &Engine, module: &Module) -> (Vec<String>, Vec<String>) {
+    let mut runtime = WasmtimeRuntime::new(engine, module, HostState::new());
+    let mut unsupported_imports = Vec::new();
+    let mut signature_mismatches = Vec::new();
+    for import in module.imports() {
+        let name = format!("{}.{}", import.module(), import.name());
+        let func_type = match import.ty() {
+            ExternType::Func(func_type) => func_type,
+            _ => {
+                unsupported_imports.push(name);
+                continue;
+            }
+        };
+        match runtime.define_host_function(import.module(), import.name()) {
+            Ok(true) => {}
+            _ => {
+                unsupported_imports.push(name);
+                continue;
+            }
+        }
+        let host_type = match runtime.get_host_function_type(import.module(), import.name()) {
+            Some(host_type) => host_type,
+            None => {
+                unsupported_imports.push(name);
+                continue;
+            }
+        };
+        if host_type != func_type {
+            signature_mismatches.push(format!(
+                "import \"{}\" is declared as {:?} but the host provides {:?}",
+                name, func_type, host_type
+            ));
+        }
     }
+    (unsupported_imports, signature_mismatches)
 }
 
-pub fn generate_import_list(
-    store: &Store,
+// State for the host functions linked for a module of the given ABI version, warning about the
+// exports and imports of the module that belong to another ABI version
+pub fn create_host_state(module: &Module, abi_version: AbiVersion) -> HostState {
+    let state = HostState::new();
+    state
+        .host
+        .lock()
+        .unwrap()
+        .staged
+        .set_abi_version(abi_version);
+    print_abi_mismatches(module, abi_version);
+    state
+}
+
+// One host state per worker, each with host settings and expectations of its own backed by the same
+// shared data, shared queues and metrics
+pub fn create_worker_host_states(
     module: &Module,
-    func_vec: Arc<Mutex<Vec<Extern>>>,
-) -> (Arc<Mutex<HostHandle>>, Arc<Mutex<ExpectHandle>>) {
-    let abi_version = get_abi_version(module);
-    HOST.lock().unwrap().staged.set_abi_version(abi_version);
-    let imports = module.imports();
-    for import in imports {
-        match get_hostfunc(&store, abi_version, &import) {
-            Some(func) => (*func_vec).lock().unwrap().push(func.into()),
-            None => panic!("Error: failed to acquire \"{}\"", import.name()),
+    abi_version: AbiVersion,
+    num_workers: usize,
+) -> Vec<HostState> {
+    let stores = Arc::new(Mutex::new(HostStores::new()));
+    let states: Vec<HostState> = (0..num_workers)
+        .map(|worker_id| {
+            let mut host = HostHandle::with_shared_stores(stores.clone(), worker_id);
+            host.staged.set_abi_version(abi_version);
+            HostState::with_host(host)
+        })
+        .collect();
+    print_abi_mismatches(module, abi_version);
+    states
+}
+
+fn print_abi_mismatches(module: &Module, abi_version: AbiVersion) {
+    for mismatch in find_abi_mismatches(module, abi_version) {
+        println!(
+            "Warning: module declares {:?} but {} \"{}\" with {} params matches {:?}",
+            mismatch.declared,
+            if mismatch.is_export {
+                "export"
+            } else {
+                "import"
+            },
+            mismatch.name,
+            mismatch.num_params,
+            mismatch.matching
+        );
+    }
+}
+
+// Defines a host function for every import of the module in the runtime the module is then
+// instantiated by
+pub fn link_host_functions(module: &Module, runtime: &mut dyn WasmRuntime) {
+    for import in module.imports() {
+        match runtime.define_host_function(import.module(), import.name()) {
+            Ok(true) => {}
+            Ok(false) => panic!("Error: failed to acquire \"{}\"", import.name()),
+            Err(error) => panic!("Error: failed to link \"{}\": {}", import.name(), error),
         }
     }
-    (HOST.clone(), EXPECT.clone())
-}
-
-fn get_hostfunc(store: &Store, _abi_version: AbiVersion, import: &ImportType) -> Option<Func> {
-    match import.name() {
-        /* ---------------------------------- Configuration and Status ---------------------------------- */
-        "proxy_get_configuration" => {
-            Some(Func::wrap(
-                &store,
-                |_caller: Caller<'_>, _return_buffer_data: i32, _return_buffer_size: i32| -> i32 {
-                    // Default Function:
-                    // Expectation:
-                    assert_eq!(
-                        HOST.lock().unwrap().staged.get_abi_version(),
-                        AbiVersion::ProxyAbiVersion0_1_0
-                    );
-                    println!(
-                        "[vm->host] proxy_get_configuration() -> (...) status: {:?}",
-                        get_status()
-                    );
-                    println!("[vm<-host] proxy_get_configuration() -> (return_buffer_data, return_buffer_size) return: {:?}", Status::InternalFailure);
-                    return Status::InternalFailure as i32;
-                },
-            ))
-        }
-
-        "proxy_get_status" => {
-            Some(Func::wrap(
-                &store,
-                |_caller: Caller<'_>,
-                 _status_code_ptr: i32,
-                 _message_ptr: i32,
-                 _message_size: i32|
-                 -> i32 {
-                    // Default Function:
-                    // Expectation:
-                    println!(
-                        "[vm->host] proxy_get_status() -> (...) status: {:?}",
-                        get_status()
-                    );
-                    println!(
-                        "[vm<-host] proxy_get_status() -> (..) return: {:?}",
-                        Status::InternalFailure
-                    );
-                    return Status::InternalFailure as i32;
-                },
-            ))
-        }
-
-        /* ---------------------------------- Logging ---------------------------------- */
-        "proxy_log" => {
-            Some(Func::wrap(
-                &store,
-                |caller: Caller<'_>, level: i32, message_data: i32, message_size: i32| -> i32 {
-                    // Default Function: retrieve and display log message from proxy-wasm module
-                    // Expectation: ensure the log level and the message data are as expected
-                    let mem = match caller.get_export("memory") {
-                        Some(Extern::Memory(mem)) => mem,
-                        _ => {
-                            println!("Error: proxy_log cannot get_export \"memory\"");
-                            println!(
-                                "[vm<-host] proxy_log(...) return: {:?}",
-                                Status::InternalFailure
-                            );
-                            return Status::InternalFailure as i32;
-                        }
-                    };
-
-                    unsafe {
-                        let data = mem
-                            .data_unchecked()
-                            .get(message_data as u32 as usize..)
-                            .and_then(|arr| arr.get(..message_size as u32 as usize));
-
-                        let string_msg =
-                            data.map(|string_msg| std::str::from_utf8(string_msg).unwrap());
-                        let string_msg = match string_msg {
-                            Some(s) => s,
-                            _ => "invalid utf-8 slice",
-                        };
-
-                        EXPECT
-                            .lock()
-                            .unwrap()
-                            .staged
-                            .get_expect_log(level, string_msg);
-                        println!(
-                            "[vm->host] proxy_log(level={}, message_data=\"{}\") status: {:?}",
-                            level,
-                            string_msg,
-                            get_status()
-                        );
-                        // println!("[vm<-host] proxy_log(...) return: {:?}", Status::Ok)
-                    }
-                    assert_ne!(get_status(), ExpectStatus::Failed);
-                    set_status(ExpectStatus::Unexpected);
-                    return Status::Ok as i32;
-                },
-            ))
-        }
-
-        "proxy_get_log_level" => {
-            Some(Func::wrap(
-                &store,
-                |_caller: Caller<'_>, _level: i32| -> i32 {
-                    // Default Function:
-                    // Expectation:
-                    println!(
-                        "[vm->host] proxy_get_log_level() -> (...) status: {:?}",
-                        get_status()
-                    );
-                    println!(
-                        "[vm<-host] proxy_get_log_level() -> (..) return: {:?}",
-                        Status::InternalFailure
-                    );
-                    return Status::InternalFailure as i32;
-                },
-            ))
-        }
-
-        /* ---------------------------------- Timer ---------------------------------- */
-        "proxy_set_tick_period_milliseconds" => {
-            Some(Func::wrap(
-                &store,
-                |_caller: Caller<'_>, period: i32| -> i32 {
-                    // Default Function: receive and store tick period from proxy-wasm module
-                    // Expectation: assert received tick period is equal to expected
-                    HOST.lock()
-                        .unwrap()
-                        .staged
-                        .set_tick_period_millis(period as u64);
-                    EXPECT
-                        .lock()
-                        .unwrap()
-                        .staged
-                        .get_expect_set_tick_period_millis(period as u128);
+}
 
-                    println!(
-                        "[vm->host] proxy_set_tick_period_milliseconds(period={}) status: {:?}",
-                        period,
-                        get_status()
-                    );
-                    println!(
-                        "[vm<-host] proxy_set_tick_period_milliseconds(...) return: {:?}",
-                        Status::Ok
-                    );
-                    assert_ne!(get_status(), ExpectStatus::Failed);
-                    set_status(ExpectStatus::Unexpected);
-                    return Status::Ok as i32;
-                },
-            ))
-        }
-
-        /* ---------------------------------- Time ---------------------------------- */
-        "proxy_get_current_time_nanoseconds" => {
-            Some(Func::wrap(
-                &store,
-                |caller: Caller<'_>, return_time: i32| -> i32 {
-                    // Default Function: respond to proxy-wasm module with the current time
-                    // Expectation: respond with a pre-set expected time
-                    let mem = match caller.get_export("memory") {
-                        Some(Extern::Memory(mem)) => mem,
-                        _ => {
-                            println!("Error: proxy_get_current_time_nanoseconds cannot get export \"memory\"");
-                            println!("[vm<-host] proxy_get_current_time_nanoseconds(...) -> (return_time) return: {:?}", Status::InternalFailure);
-                            return Status::InternalFailure as i32;
-                        }
-                    };
-
-                    let time = match EXPECT
-                        .lock()
-                        .unwrap()
-                        .staged
-                        .get_expect_get_current_time_nanos()
-                    {
-                        Some(current_time_nanos) => current_time_nanos as u64,
-                        None => SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .unwrap()
-                            .as_nanos() as u64,
-                    };
-
-                    unsafe {
-                        let data = mem.data_unchecked_mut().get_unchecked_mut(
-                            return_time as u32 as usize..return_time as u32 as usize + 8,
-                        );
+// Lists the host functions with their params (all of them return a status as i32), for the
+// runtime to wrap each of them into a function the module can import
+macro_rules! for_each_host_function {
+    ($link:ident) => {
+        $link! {
+            proxy_get_configuration(return_buffer_data: i32, return_buffer_size: i32);
+            proxy_get_status(status_code_ptr: i32, message_ptr: i32, message_size: i32);
+            proxy_log(level: i32, message_data: i32, message_size: i32);
+            proxy_get_log_level(return_level: i32);
+            proxy_set_tick_period_milliseconds(period: i32);
+            proxy_get_current_time_nanoseconds(return_time: i32);
+            proxy_get_property(
+                path_data: i32,
+                path_size: i32,
+                return_value_data: i32,
+                return_value_size: i32
+            );
+            proxy_set_property(path_data: i32, path_size: i32, value_data: i32, value_size: i32);
+            proxy_continue_stream(stream_type: i32);
+            proxy_close_stream(stream_type: i32);
+            proxy_continue_request();
+            proxy_continue_response();
+            proxy_resume_downstream();
+            proxy_resume_upstream();
+            proxy_send_local_response(
+                status_code: i32,
+                status_code_details_data: i32,
+                status_code_details_size: i32,
+                body_data: i32,
+                body_size: i32,
+                headers_data: i32,
+                headers_size: i32,
+                grpc_status: i32
+            );
+            proxy_clear_route_cache();
+            proxy_get_shared_data(
+                key_data: i32,
+                key_size: i32,
+                return_value_data: i32,
+                return_value_size: i32,
+                return_cas: i32
+            );
+            proxy_set_shared_data(
+                key_data: i32,
+                key_size: i32,
+                value_data: i32,
+                value_size: i32,
+                cas: i32
+            );
+            proxy_register_shared_queue(name_data: i32, name_size: i32, return_id: i32);
+            proxy_resolve_shared_queue(
+                vm_id_data: i32,
+                vm_id_size: i32,
+                name_data: i32,
+                name_size: i32,
+                return_id: i32
+            );
+            proxy_dequeue_shared_queue(queue_id: i32, payload_data: i32, payload_size: i32);
+            proxy_enqueue_shared_queue(queue_id: i32, value_data: i32, value_size: i32);
+            proxy_get_header_map_size(_map_type: i32, _map_size: i32);
+            proxy_get_header_map_pairs(map_type: i32, return_map_data: i32, return_map_size: i32);
+            proxy_set_header_map_pairs(map_type: i32, map_data: i32, map_size: i32);
+            proxy_get_header_map_value(
+                map_type: i32,
+                key_data: i32,
+                key_size: i32,
+                return_value_data: i32,
+                return_value_size: i32
+            );
+            proxy_replace_header_map_value(
+                map_type: i32,
+                key_data: i32,
+                key_size: i32,
+                value_data: i32,
+                value_size: i32
+            );
+            proxy_remove_header_map_value(map_type: i32, key_data: i32, key_size: i32);
+            proxy_add_header_map_value(
+                map_type: i32,
+                key_data: i32,
+                key_size: i32,
+                value_data: i32,
+                value_size: i32
+            );
+            proxy_get_buffer_status(_buffer_type: i32, _length_ptr: i32, _flags_ptr: i32);
+            proxy_get_buffer_bytes(
+                buffer_type: i32,
+                start: i32,
+                max_size: i32,
+                return_buffer_data: i32,
+                return_buffer_size: i32
+            );
+            proxy_set_buffer_bytes(
+                buffer_type: i32,
+                start: i32,
+                size: i32,
+                buffer_data: i32,
+                buffer_size: i32
+            );
+            proxy_http_call(
+                upstream_data: i32,
+                upstream_size: i32,
+                headers_data: i32,
+                headers_size: i32,
+                body_data: i32,
+                body_size: i32,
+                trailers_data: i32,
+                trailers_size: i32,
+                timeout: i32,
+                return_token: i32
+            );
+            proxy_grpc_call(
+                service_ptr: i32,
+                service_size: i32,
+                service_name_ptr: i32,
+                service_name_size: i32,
+                method_name_ptr: i32,
+                method_name_size: i32,
+                initial_metadata_ptr: i32,
+                initial_metadata_size: i32,
+                request_ptr: i32,
+                request_size: i32,
+                timeout_milliseconds: i32,
+                token_ptr: i32
+            );
+            proxy_grpc_stream(
+                service_ptr: i32,
+                service_size: i32,
+                service_name_ptr: i32,
+                service_name_size: i32,
+                method_name_ptr: i32,
+                method_name_size: i32,
+                initial_metadata_ptr: i32,
+                initial_metadata_size: i32,
+                token_ptr: i32
+            );
+            proxy_grpc_cancel(token: i32);
+            proxy_grpc_close(token: i32);
+            proxy_grpc_send(token: i32, message_ptr: i32, message_size: i32, end_of_stream: i32);
+            proxy_define_metric(metric_type: i32, name_data: i32, name_size: i32, return_id: i32);
+            proxy_increment_metric(metric_id: i32, offset: i64);
+            proxy_record_metric(metric_id: i32, value: i64);
+            proxy_get_metric(metric_id: i32, return_value: i32);
+            proxy_set_effective_context(context_id: i32);
+            proxy_done();
+            proxy_call_foreign_function(
+                function_name: i32,
+                function_name_size: i32,
+                arguments: i32,
+                arguments_size: i32,
+                return_results: i32,
+                return_results_size: i32
+            );
+        }
+    };
+}
 
-                        data.copy_from_slice(&time.to_le_bytes());
-                    }
-                    println!(
-                        "[vm->host] proxy_get_current_time_nanoseconds() -> (...) status: {:?}",
-                        get_status()
-                    );
-                    println!(
-                        "[vm<-host] proxy_get_current_time_nanoseconds() -> (return_time) return: {:?}",
-                        Status::Ok
-                    );
-                    assert_ne!(get_status(), ExpectStatus::Failed);
-                    set_status(ExpectStatus::Unexpected);
-                    return Status::Ok as i32;
-                },
-            ))
-        }
-
-        /* ---------------------------------- State Accessors ---------------------------------- */
-        "proxy_get_property" => {
-            Some(Func::wrap(
-                &store,
-                |_caller: Caller<'_>,
-                 _path_data: i32,
-                 _path_size: i32,
-                 _return_value_data: i32,
-                 _return_value_size: i32|
-                 -> i32 {
-                    // Default Function:
-                    // Expectation:
-                    println!(
-                        "[vm->host] proxy_get_property(path_data, path_size) -> (...) status: {:?}",
-                        get_status()
-                    );
-                    println!("[vm<-host] proxy_get_property(...) -> (return_value_data, return_value_size) return: {:?}", Status::InternalFailure);
-                    return Status::InternalFailure as i32;
-                },
-            ))
-        }
-
-        "proxy_set_property" => {
-            Some(Func::wrap(
-                &store,
-                |_caller: Caller<'_>,
-                 _path_data: i32,
-                 _path_size: i32,
-                 _value_data: i32,
-                 _value_size: i32|
-                 -> i32 {
-                    // Default Function:
-                    // Expectation:
-                    println!("[vm->host] proxy_set_property(path_data, path_size, value_data, value_size) status: {:?}", get_status());
-                    println!(
-                        "[vm<-host] proxy_set_property(...) return: {:?}",
-                        Status::InternalFailure
-                    );
-                    return Status::InternalFailure as i32;
-                },
-            ))
-        }
-
-        /* ---------------------------------- Continue/Close/Reply/Route ---------------------------------- */
-        "proxy_continue_stream" => {
-            Some(Func::wrap(&store, |_caller: Caller<'_>| -> i32 {
-                // Default Function:
-                // Expectation:
-                assert_eq!(
-                    HOST.lock().unwrap().staged.get_abi_version(),
-                    AbiVersion::ProxyAbiVersion0_2_0
-                );
-                println!(
-                    "[vm->host] proxy_continue_stream() status: {:?}",
-                    get_status()
-                );
-                println!(
-                    "[vm<-host] proxy_continue_stream() return: {:?}",
-                    Status::Ok
-                );
-                assert_ne!(get_status(), ExpectStatus::Failed);
-                set_status(ExpectStatus::Unexpected);
-                return Status::Ok as i32;
-            }))
-        }
-
-        "proxy_close_stream" => {
-            Some(Func::wrap(&store, |_caller: Caller<'_>| -> i32 {
-                // Default Function:
-                // Expectation:
-                assert_eq!(
-                    HOST.lock().unwrap().staged.get_abi_version(),
-                    AbiVersion::ProxyAbiVersion0_2_0
-                );
-                println!("[vm->host] proxy_close_stream() status: {:?}", get_status());
-                println!("[vm<-host] proxy_close_stream() return: {:?}", Status::Ok);
-                assert_ne!(get_status(), ExpectStatus::Failed);
-                set_status(ExpectStatus::Unexpected);
-                return Status::Ok as i32;
-            }))
-        }
-
-        "proxy_continue_request" => {
-            Some(Func::wrap(&store, |_caller: Caller<'_>| -> i32 {
-                // Default Function:
-                // Expectation:
-                assert_eq!(
-                    HOST.lock().unwrap().staged.get_abi_version(),
-                    AbiVersion::ProxyAbiVersion0_1_0
-                );
-                println!(
-                    "[vm->host] proxy_continue_request() status: {:?}",
-                    get_status()
-                );
-                println!(
-                    "[vm<-host] proxy_continue_request() return: {:?}",
-                    Status::Ok
-                );
-                assert_ne!(get_status(), ExpectStatus::Failed);
-                set_status(ExpectStatus::Unexpected);
-                return Status::Ok as i32;
-            }))
-        }
-
-        "proxy_continue_response" => {
-            Some(Func::wrap(&store, |_caller: Caller<'_>| -> i32 {
-                // Default Function:
-                // Expectation:
-                assert_eq!(
-                    HOST.lock().unwrap().staged.get_abi_version(),
-                    AbiVersion::ProxyAbiVersion0_1_0
-                );
-                println!(
-                    "[vm->host] proxy_continue_response() status: {:?}",
-                    get_status()
-                );
-                println!(
-                    "[vm<-host] proxy_continue_response() return: {:?}",
-                    Status::Ok
-                );
-                assert_ne!(get_status(), ExpectStatus::Failed);
-                set_status(ExpectStatus::Unexpected);
-                return Status::Ok as i32;
-            }))
-        }
-
-        "proxy_send_local_response" => {
-            Some(Func::wrap(
-                &store,
-                |caller: Caller<'_>,
-                 status_code: i32,
-                 _status_code_details_data: i32,
-                 _status_code_details_size: i32,
-                 body_data: i32,
-                 body_size: i32,
-                 headers_data: i32,
-                 headers_size: i32,
-                 grpc_status: i32|
-                 -> i32 {
-                    // Default Function: receive and display local response
-                    // Expectation: assert equal the received local response with the expected one
-                    let mem = match caller.get_export("memory") {
-                        Some(Extern::Memory(mem)) => mem,
-                        _ => {
-                            println!(
-                                "Error: proxy_send_local_response cannot get export \"memory\""
-                            );
-                            println!(
-                                "[vm<-host] proxy_send_local_response(...) return: {:?}",
-                                Status::InternalFailure
-                            );
-                            return Status::InternalFailure as i32;
-                        }
-                    };
-
-                    unsafe {
-                        let mut string_body: Option<&str> = None;
-                        if body_size > 0 {
-                            let body_data_ptr = mem
-                                .data_unchecked()
-                                .get(body_data as u32 as usize..)
-                                .and_then(|arr| arr.get(..body_size as u32 as usize));
-                            string_body = body_data_ptr
-                                .map(|string_msg| std::str::from_utf8(string_msg).unwrap());
-                        }
-
-                        let header_data_ptr = mem.data_unchecked().get_unchecked(
-                            headers_data as u32 as usize
-                                ..headers_data as u32 as usize + headers_size as u32 as usize,
-                        );
-                        let deserialized_header = serial_utils::deserialize_map(header_data_ptr);
-
-                        EXPECT
-                            .lock()
-                            .unwrap()
-                            .staged
-                            .get_expect_send_local_response(
-                                status_code,
-                                string_body,
-                                &header_data_ptr,
-                                grpc_status,
-                            );
-
-                        println!("[vm->host] proxy_send_local_response(status_code={}, status_code_details_data, status_code_details_size", status_code);
-                        println!(
-                            "                                     body_data={}, body_size={}",
-                            string_body.unwrap_or("None"),
-                            body_size
-                        );
-                        println!("                                     headers_data={:?}, headers_size={}) status: {:?}", deserialized_header, headers_size, get_status());
-                    }
-                    println!(
-                        "[vm<-host] proxy_send_local_response(...) return: {:?}",
-                        Status::Ok
-                    );
-                    assert_ne!(get_status(), ExpectStatus::Failed);
-                    set_status(ExpectStatus::Unexpected);
-                    return Status::Ok as i32;
-                },
-            ))
+pub(crate) use for_each_host_function;
+
+/* ---------------------------------- Configuration and Status ---------------------------------- */
+
+pub(crate) fn proxy_get_configuration(
+    caller: &mut dyn Guest,
+    return_buffer_data: i32,
+    return_buffer_size: i32,
+) -> i32 {
+    // Default Function: return the vm configuration during proxy_on_vm_start and the
+    // plugin configuration otherwise, or NotFound if there is none
+    // Expectation:
+    let HostState {
+        host: host_handle,
+        status: status_handle,
+        ..
+    } = caller.state().clone();
+    assert_eq!(
+        host_handle.lock().unwrap().staged.get_abi_version(),
+        AbiVersion::ProxyAbiVersion0_1_0
+    );
+
+    let mut mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_get_configuration cannot get export \"memory\"");
+            println!("[vm<-host] proxy_get_configuration() -> (return_buffer_data, return_buffer_size) return: {:?}", Status::InternalFailure);
+            return Status::InternalFailure as i32;
         }
+    };
 
-        "proxy_clear_route_cache" => {
-            Some(Func::wrap(&store, |_caller: Caller<'_>| -> i32 {
-                // Default Function:
-                // Expectation:
-                println!(
-                    "[vm->host] proxy_clear_route_cache() status: {:?}",
-                    get_status()
-                );
+    if !caller.has_allocator() {
+        println!("Error: proxy_get_configuration cannot get an allocator export");
+        println!("[vm<-host] proxy_get_configuration() -> (return_buffer_data, return_buffer_size) return: {:?}", Status::InternalFailure);
+        return Status::InternalFailure as i32;
+    }
+
+    let configuration = host_handle.lock().unwrap().staged.get_configuration();
+    println!(
+        "[vm->host] proxy_get_configuration() -> (...) status: {:?}",
+        get_status(&status_handle)
+    );
+    if configuration.is_empty() {
+        println!("[vm<-host] proxy_get_configuration() -> (return_buffer_data, return_buffer_size) return: {:?}", Status::NotFound);
+        return Status::NotFound as i32;
+    }
+
+    // allocate memory and store configuration
+    let buffer_data_add =
+        allocate(caller, &mut mem, configuration.len() as i32).unwrap() as u32 as usize;
+    if buffer_data_add == 0 {
+        println!(
+            "Error: proxy_get_configuration failed to allocate {} bytes",
+            configuration.len()
+        );
+        println!("[vm<-host] proxy_get_configuration() -> (return_buffer_data, return_buffer_size) return: {:?}", Status::InternalFailure);
+        return Status::InternalFailure as i32;
+    }
+    unsafe {
+        let return_buffer_size_ptr = mem.data_unchecked_mut().get_unchecked_mut(
+            return_buffer_size as u32 as usize..return_buffer_size as u32 as usize + 4,
+        );
+
+        let return_buffer_data_ptr = mem.data_unchecked_mut().get_unchecked_mut(
+            return_buffer_data as u32 as usize..return_buffer_data as u32 as usize + 4,
+        );
+
+        let buffer_data_ptr = mem
+            .data_unchecked_mut()
+            .get_unchecked_mut(buffer_data_add..buffer_data_add + configuration.len());
+        buffer_data_ptr.copy_from_slice(&configuration);
+
+        return_buffer_size_ptr.copy_from_slice(&(configuration.len() as u32).to_le_bytes());
+        return_buffer_data_ptr.copy_from_slice(&(buffer_data_add as u32).to_le_bytes());
+    }
+
+    println!(
+        "[vm<-host] proxy_get_configuration() -> (configuration_data={:?}) return: {:?}",
+        String::from_utf8_lossy(&configuration),
+        Status::Ok
+    );
+    return Status::Ok as i32;
+}
+
+pub(crate) fn proxy_get_status(
+    caller: &mut dyn Guest,
+    status_code_ptr: i32,
+    message_ptr: i32,
+    message_size: i32,
+) -> i32 {
+    // Default Function: respond with the host status code and message
+    // Expectation: respond with the expected status code and message
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let mut mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_get_status cannot get export \"memory\"");
+            println!("[vm<-host] proxy_get_status() -> (status_code_ptr, message_ptr, message_size) return: {:?}", Status::InternalFailure);
+            return Status::InternalFailure as i32;
+        }
+    };
+
+    if !caller.has_allocator() {
+        println!("Error: proxy_get_status cannot get an allocator export");
+        println!("[vm<-host] proxy_get_status() -> (status_code_ptr, message_ptr, message_size) return: {:?}", Status::InternalFailure);
+        return Status::InternalFailure as i32;
+    }
+
+    let (expect_status_code, expect_message) =
+        expect_handle.lock().unwrap().staged.get_expect_get_status();
+    let (host_status_code, host_message) = host_handle.lock().unwrap().staged.get_status();
+    let status_code = expect_status_code.unwrap_or(host_status_code);
+    let message = expect_message.unwrap_or(host_message);
+
+    unsafe {
+        let message_data_add =
+            allocate(caller, &mut mem, message.len() as i32).unwrap() as u32 as usize;
+        if message_data_add == 0 {
+            println!(
+                "Error: proxy_get_status failed to allocate {} bytes",
+                message.len()
+            );
+            println!("[vm<-host] proxy_get_status() -> (status_code_ptr, message_ptr, message_size) return: {:?}", Status::InternalFailure);
+            return Status::InternalFailure as i32;
+        }
+        let message_data_ptr = mem
+            .data_unchecked_mut()
+            .get_unchecked_mut(message_data_add..message_data_add + message.len());
+        message_data_ptr.copy_from_slice(message.as_bytes());
+
+        let status_code_add = mem.data_unchecked_mut().get_unchecked_mut(
+            status_code_ptr as u32 as usize..status_code_ptr as u32 as usize + 4,
+        );
+        status_code_add.copy_from_slice(&status_code.to_le_bytes());
+
+        let message_ptr_add = mem
+            .data_unchecked_mut()
+            .get_unchecked_mut(message_ptr as u32 as usize..message_ptr as u32 as usize + 4);
+        message_ptr_add.copy_from_slice(&(message_data_add as u32).to_le_bytes());
+
+        let message_size_add = mem
+            .data_unchecked_mut()
+            .get_unchecked_mut(message_size as u32 as usize..message_size as u32 as usize + 4);
+        message_size_add.copy_from_slice(&(message.len() as u32).to_le_bytes());
+    }
+    println!(
+        "[vm->host] proxy_get_status() -> (...) status: {:?}",
+        get_status(&status_handle)
+    );
+    println!(
+        "[vm<-host] proxy_get_status() -> (status_code={}, message={:?}) return: {:?}",
+        status_code,
+        message,
+        Status::Ok
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
+
+/* ---------------------------------- Logging ---------------------------------- */
+
+pub(crate) fn proxy_log(
+    caller: &mut dyn Guest,
+    level: i32,
+    message_data: i32,
+    message_size: i32,
+) -> i32 {
+    // Default Function: retrieve and display log message from proxy-wasm module
+    // Expectation: ensure the log level and the message data are as expected
+    let HostState {
+        expect: expect_handle,
+        status: status_handle,
+        ..
+    } = caller.state().clone();
+    let mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_log cannot get_export \"memory\"");
+            println!(
+                "[vm<-host] proxy_log(...) return: {:?}",
+                Status::InternalFailure
+            );
+            return Status::InternalFailure as i32;
+        }
+    };
+
+    unsafe {
+        let data = mem
+            .data_unchecked()
+            .get(message_data as u32 as usize..)
+            .and_then(|arr| arr.get(..message_size as u32 as usize));
+
+        let string_msg = data.map(|string_msg| std::str::from_utf8(string_msg).unwrap());
+        let string_msg = match string_msg {
+            Some(s) => s,
+            _ => "invalid utf-8 slice",
+        };
+
+        expect_handle
+            .lock()
+            .unwrap()
+            .staged
+            .get_expect_log(level, string_msg);
+        println!(
+            "[vm->host] proxy_log(level={}, message_data=\"{}\") status: {:?}",
+            level,
+            string_msg,
+            get_status(&status_handle)
+        );
+        // println!("[vm<-host] proxy_log(...) return: {:?}", Status::Ok)
+    }
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
+
+pub(crate) fn proxy_get_log_level(caller: &mut dyn Guest, return_level: i32) -> i32 {
+    // Default Function: respond with the host log level
+    // Expectation:
+    let HostState {
+        host: host_handle,
+        status: status_handle,
+        ..
+    } = caller.state().clone();
+    let mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_get_log_level cannot get export \"memory\"");
+            println!(
+                "[vm<-host] proxy_get_log_level() -> (return_level) return: {:?}",
+                Status::InternalFailure
+            );
+            return Status::InternalFailure as i32;
+        }
+    };
+
+    let level = host_handle.lock().unwrap().staged.get_log_level();
+    unsafe {
+        let return_level_ptr = mem
+            .data_unchecked_mut()
+            .get_unchecked_mut(return_level as u32 as usize..return_level as u32 as usize + 4);
+        return_level_ptr.copy_from_slice(&(level as u32).to_le_bytes());
+    }
+    println!(
+        "[vm->host] proxy_get_log_level() -> (...) status: {:?}",
+        get_status(&status_handle)
+    );
+    println!(
+        "[vm<-host] proxy_get_log_level() -> (return_level={}) return: {:?}",
+        level,
+        Status::Ok
+    );
+    return Status::Ok as i32;
+}
+
+/* ---------------------------------- Timer ---------------------------------- */
+
+pub(crate) fn proxy_set_tick_period_milliseconds(caller: &mut dyn Guest, period: i32) -> i32 {
+    // Default Function: receive and store tick period from proxy-wasm module
+    // Expectation: assert received tick period is equal to expected
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let tick_period = Duration::from_millis(period as u32 as u64);
+    {
+        let mut host = host_handle.lock().unwrap();
+        let context_id = host.staged.get_effective_context_id();
+        host.staged.set_tick_period(tick_period);
+        host.staged.schedule_ticks(context_id, tick_period);
+    }
+    expect_handle
+        .lock()
+        .unwrap()
+        .staged
+        .get_expect_set_tick_period(tick_period);
+
+    println!(
+        "[vm->host] proxy_set_tick_period_milliseconds(period={}) status: {:?}",
+        period,
+        get_status(&status_handle)
+    );
+    println!(
+        "[vm<-host] proxy_set_tick_period_milliseconds(...) return: {:?}",
+        Status::Ok
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
+
+/* ---------------------------------- Time ---------------------------------- */
+
+pub(crate) fn proxy_get_current_time_nanoseconds(caller: &mut dyn Guest, return_time: i32) -> i32 {
+    // Default Function: respond to proxy-wasm module with the time of the host clock
+    // Expectation: respond with a pre-set expected time
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_get_current_time_nanoseconds cannot get export \"memory\"");
+            println!(
+                "[vm<-host] proxy_get_current_time_nanoseconds(...) -> (return_time) return: {:?}",
+                Status::InternalFailure
+            );
+            return Status::InternalFailure as i32;
+        }
+    };
+
+    let current_time = expect_handle
+        .lock()
+        .unwrap()
+        .staged
+        .get_expect_get_current_time()
+        .unwrap_or_else(|| host_handle.lock().unwrap().staged.read_current_time());
+    let time = current_time.duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64;
+
+    unsafe {
+        let data = mem
+            .data_unchecked_mut()
+            .get_unchecked_mut(return_time as u32 as usize..return_time as u32 as usize + 8);
+
+        data.copy_from_slice(&time.to_le_bytes());
+    }
+    println!(
+        "[vm->host] proxy_get_current_time_nanoseconds() -> (...) status: {:?}",
+        get_status(&status_handle)
+    );
+    println!(
+        "[vm<-host] proxy_get_current_time_nanoseconds() -> (return_time) return: {:?}",
+        Status::Ok
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
+
+/* ---------------------------------- State Accessors ---------------------------------- */
+
+pub(crate) fn proxy_get_property(
+    caller: &mut dyn Guest,
+    path_data: i32,
+    path_size: i32,
+    return_value_data: i32,
+    return_value_size: i32,
+) -> i32 {
+    // Default Function: respond from the property store, falling back on the plugin identity properties (plugin_vm_id, plugin_root_id)
+    // Expectation: assert that the received property path is as expected and respond with the expected value
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let mut mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_get_property cannot get export \"memory\"");
+            println!("[vm<-host] proxy_get_property(...) -> (return_value_data, return_value_size) return: {:?}", Status::InternalFailure);
+            return Status::InternalFailure as i32;
+        }
+    };
+
+    if !caller.has_allocator() {
+        println!("Error: proxy_get_property cannot get an allocator export");
+        println!("[vm<-host] proxy_get_property(...) -> (return_value_data, return_value_size) return: {:?}", Status::InternalFailure);
+        return Status::InternalFailure as i32;
+    }
+
+    let path_range = match serial_utils::guest_range(path_data, path_size, mem.data_size()) {
+        Some(path_range) => path_range,
+        None => {
+            println!("[vm<-host] proxy_get_property(...) -> (return_value_data, return_value_size) return: {:?}", Status::BadArgument);
+            return Status::BadArgument as i32;
+        }
+    };
+
+    unsafe {
+        let path = mem.data_unchecked().get_unchecked(path_range).to_vec();
+        let value = match expect_handle
+            .lock()
+            .unwrap()
+            .staged
+            .get_expect_get_property(&path)
+        {
+            Some(expect_value) => Some(expect_value),
+            None => host_handle.lock().unwrap().staged.get_property(&path),
+        };
+        println!(
+            "[vm->host] proxy_get_property(path_data={:?}, path_size={}) -> (...) status: {:?}",
+            String::from_utf8_lossy(&path).replace('\0', "."),
+            path_size,
+            get_status(&status_handle)
+        );
+        let value = match value {
+            Some(value) => value,
+            None => {
+                println!("[vm<-host] proxy_get_property(...) -> (return_value_data, return_value_size) return: {:?}", Status::NotFound);
+                assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+                set_status(&status_handle, ExpectStatus::Unexpected);
+                return Status::NotFound as i32;
+            }
+        };
+
+        let value_data_add =
+            allocate(caller, &mut mem, value.len() as i32).unwrap() as u32 as usize;
+        if value_data_add == 0 {
+            println!(
+                "Error: proxy_get_property failed to allocate {} bytes",
+                value.len()
+            );
+            println!("[vm<-host] proxy_get_property(...) -> (return_value_data, return_value_size) return: {:?}", Status::InternalFailure);
+            return Status::InternalFailure as i32;
+        }
+        let value_data_ptr = mem
+            .data_unchecked_mut()
+            .get_unchecked_mut(value_data_add..value_data_add + value.len());
+        value_data_ptr.copy_from_slice(&value);
+
+        let return_value_data_ptr = mem.data_unchecked_mut().get_unchecked_mut(
+            return_value_data as u32 as usize..return_value_data as u32 as usize + 4,
+        );
+        return_value_data_ptr.copy_from_slice(&(value_data_add as u32).to_le_bytes());
+
+        let return_value_size_ptr = mem.data_unchecked_mut().get_unchecked_mut(
+            return_value_size as u32 as usize..return_value_size as u32 as usize + 4,
+        );
+        return_value_size_ptr.copy_from_slice(&(value.len() as u32).to_le_bytes());
+
+        println!("[vm<-host] proxy_get_property(...) -> (return_value_data={}, return_value_size={}) return: {:?}",
+            String::from_utf8_lossy(&value), value.len(), Status::Ok
+        );
+    }
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
+
+pub(crate) fn proxy_set_property(
+    caller: &mut dyn Guest,
+    path_data: i32,
+    path_size: i32,
+    value_data: i32,
+    value_size: i32,
+) -> i32 {
+    // Default Function: write the property value into the property store
+    // Expectation: assert that the received property path and value are as expected
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_set_property cannot get export \"memory\"");
+            println!(
+                "[vm<-host] proxy_set_property(...) return: {:?}",
+                Status::InternalFailure
+            );
+            return Status::InternalFailure as i32;
+        }
+    };
+
+    let (path_range, value_range) = match (
+        serial_utils::guest_range(path_data, path_size, mem.data_size()),
+        serial_utils::guest_range(value_data, value_size, mem.data_size()),
+    ) {
+        (Some(path_range), Some(value_range)) => (path_range, value_range),
+        _ => {
+            println!(
+                "[vm<-host] proxy_set_property(...) return: {:?}",
+                Status::BadArgument
+            );
+            return Status::BadArgument as i32;
+        }
+    };
+
+    unsafe {
+        let path = mem.data_unchecked().get_unchecked(path_range).to_vec();
+        let value = mem.data_unchecked().get_unchecked(value_range).to_vec();
+        expect_handle
+            .lock()
+            .unwrap()
+            .staged
+            .get_expect_set_property(&path, &value);
+        host_handle
+            .lock()
+            .unwrap()
+            .staged
+            .set_property(&path, &value);
+        println!(
+            "[vm->host] proxy_set_property(path_data={:?}, path_size={}, value_data={}, value_size={}) status: {:?}",
+            String::from_utf8_lossy(&path).replace('\0', "."),
+            path_size,
+            String::from_utf8_lossy(&value),
+            value_size,
+            get_status(&status_handle)
+        );
+    }
+    println!(
+        "[vm<-host] proxy_set_property(...) return: {:?}",
+        Status::Ok
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
+
+/* ---------------------------------- Continue/Close/Reply/Route ---------------------------------- */
+
+pub(crate) fn proxy_continue_stream(caller: &mut dyn Guest, stream_type: i32) -> i32 {
+    // Default Function: resume the effective context's stream, delivering any held body
+    // Expectation: assert that the resumed stream type is the expected one
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    assert_eq!(
+        host_handle.lock().unwrap().staged.get_abi_version(),
+        AbiVersion::ProxyAbiVersion0_2_0
+    );
+    expect_handle
+        .lock()
+        .unwrap()
+        .staged
+        .get_expect_continue_stream(stream_type);
+    let return_status = match get_stream_type(stream_type) {
+        Some(stream) => {
+            let mut host = host_handle.lock().unwrap();
+            let context_id = host.staged.get_effective_context_id();
+            host.staged.resume_stream(context_id, stream);
+            Status::Ok
+        }
+        None => Status::BadArgument,
+    };
+    println!(
+        "[vm->host] proxy_continue_stream(stream_type={}) status: {:?}",
+        stream_type,
+        get_status(&status_handle)
+    );
+    println!(
+        "[vm<-host] proxy_continue_stream(...) return: {:?}",
+        return_status
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return return_status as i32;
+}
+
+pub(crate) fn proxy_close_stream(caller: &mut dyn Guest, stream_type: i32) -> i32 {
+    // Default Function: record that the effective context closed the stream
+    // Expectation: assert that the closed stream type is the expected one
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    assert_eq!(
+        host_handle.lock().unwrap().staged.get_abi_version(),
+        AbiVersion::ProxyAbiVersion0_2_0
+    );
+    expect_handle
+        .lock()
+        .unwrap()
+        .staged
+        .get_expect_close_stream(stream_type);
+    let return_status = match get_stream_type(stream_type) {
+        Some(stream) => {
+            let mut host = host_handle.lock().unwrap();
+            let context_id = host.staged.get_effective_context_id();
+            host.staged.close_stream(context_id, stream);
+            Status::Ok
+        }
+        None => Status::BadArgument,
+    };
+    println!(
+        "[vm->host] proxy_close_stream(stream_type={}) status: {:?}",
+        stream_type,
+        get_status(&status_handle)
+    );
+    println!(
+        "[vm<-host] proxy_close_stream(...) return: {:?}",
+        return_status
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return return_status as i32;
+}
+
+pub(crate) fn proxy_continue_request(caller: &mut dyn Guest) -> i32 {
+    // Default Function: resume the effective context's stream, delivering any held body
+    // Expectation: assert that the request was expected to be resumed
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    assert_eq!(
+        host_handle.lock().unwrap().staged.get_abi_version(),
+        AbiVersion::ProxyAbiVersion0_1_0
+    );
+    {
+        let mut host = host_handle.lock().unwrap();
+        let context_id = host.staged.get_effective_context_id();
+        host.staged
+            .resume_stream(context_id, StreamType::HttpRequest);
+    }
+    expect_handle
+        .lock()
+        .unwrap()
+        .staged
+        .get_expect_continue_request();
+    println!(
+        "[vm->host] proxy_continue_request() status: {:?}",
+        get_status(&status_handle)
+    );
+    println!(
+        "[vm<-host] proxy_continue_request() return: {:?}",
+        Status::Ok
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
+
+pub(crate) fn proxy_continue_response(caller: &mut dyn Guest) -> i32 {
+    // Default Function: resume the effective context's stream, delivering any held body
+    // Expectation: assert that the response was expected to be resumed
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    assert_eq!(
+        host_handle.lock().unwrap().staged.get_abi_version(),
+        AbiVersion::ProxyAbiVersion0_1_0
+    );
+    {
+        let mut host = host_handle.lock().unwrap();
+        let context_id = host.staged.get_effective_context_id();
+        host.staged
+            .resume_stream(context_id, StreamType::HttpResponse);
+    }
+    expect_handle
+        .lock()
+        .unwrap()
+        .staged
+        .get_expect_continue_response();
+    println!(
+        "[vm->host] proxy_continue_response() status: {:?}",
+        get_status(&status_handle)
+    );
+    println!(
+        "[vm<-host] proxy_continue_response() return: {:?}",
+        Status::Ok
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
+
+pub(crate) fn proxy_resume_downstream(caller: &mut dyn Guest) -> i32 {
+    // Default Function: resume the effective context's connection, delivering any held downstream data
+    // Expectation: assert that the downstream was expected to be resumed
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    assert_eq!(
+        host_handle.lock().unwrap().staged.get_abi_version(),
+        AbiVersion::ProxyAbiVersion0_1_0
+    );
+    {
+        let mut host = host_handle.lock().unwrap();
+        let context_id = host.staged.get_effective_context_id();
+        host.staged
+            .resume_stream(context_id, StreamType::Downstream);
+    }
+    expect_handle
+        .lock()
+        .unwrap()
+        .staged
+        .get_expect_resume_downstream();
+    println!(
+        "[vm->host] proxy_resume_downstream() status: {:?}",
+        get_status(&status_handle)
+    );
+    println!(
+        "[vm<-host] proxy_resume_downstream() return: {:?}",
+        Status::Ok
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
+
+pub(crate) fn proxy_resume_upstream(caller: &mut dyn Guest) -> i32 {
+    // Default Function: resume the effective context's connection, delivering any held upstream data
+    // Expectation: assert that the upstream was expected to be resumed
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    assert_eq!(
+        host_handle.lock().unwrap().staged.get_abi_version(),
+        AbiVersion::ProxyAbiVersion0_1_0
+    );
+    {
+        let mut host = host_handle.lock().unwrap();
+        let context_id = host.staged.get_effective_context_id();
+        host.staged.resume_stream(context_id, StreamType::Upstream);
+    }
+    expect_handle
+        .lock()
+        .unwrap()
+        .staged
+        .get_expect_resume_upstream();
+    println!(
+        "[vm->host] proxy_resume_upstream() status: {:?}",
+        get_status(&status_handle)
+    );
+    println!(
+        "[vm<-host] proxy_resume_upstream() return: {:?}",
+        Status::Ok
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
+
+pub(crate) fn proxy_send_local_response(
+    caller: &mut dyn Guest,
+    status_code: i32,
+    status_code_details_data: i32,
+    status_code_details_size: i32,
+    body_data: i32,
+    body_size: i32,
+    headers_data: i32,
+    headers_size: i32,
+    grpc_status: i32,
+) -> i32 {
+    // Default Function: receive and display local response
+    // Expectation: assert equal the received local response with the expected one
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_send_local_response cannot get export \"memory\"");
+            println!(
+                "[vm<-host] proxy_send_local_response(...) return: {:?}",
+                Status::InternalFailure
+            );
+            return Status::InternalFailure as i32;
+        }
+    };
+
+    unsafe {
+        let mut string_body: Option<&str> = None;
+        if body_size > 0 {
+            let body_data_ptr = mem
+                .data_unchecked()
+                .get(body_data as u32 as usize..)
+                .and_then(|arr| arr.get(..body_size as u32 as usize));
+            string_body = body_data_ptr.map(|string_msg| std::str::from_utf8(string_msg).unwrap());
+        }
+
+        let header_data_ptr = mem.data_unchecked().get_unchecked(
+            headers_data as u32 as usize
+                ..headers_data as u32 as usize + headers_size as u32 as usize,
+        );
+        let deserialized_header = serial_utils::deserialize_map(header_data_ptr);
+
+        let status_code_details = serial_utils::guest_range(
+            status_code_details_data,
+            status_code_details_size,
+            mem.data_size(),
+        )
+        .map(|range| String::from_utf8_lossy(mem.data_unchecked().get_unchecked(range)).to_string())
+        .unwrap_or_default();
+
+        {
+            let mut host = host_handle.lock().unwrap();
+            let context_id = host.staged.get_effective_context_id();
+            host.staged.set_local_response_sent(context_id);
+            host.staged.capture_local_response(LocalResponse {
+                context_id: context_id,
+                status_code: status_code as u32,
+                status_code_details: status_code_details,
+                body: string_body.unwrap_or("").as_bytes().to_vec(),
+                headers: deserialized_header.clone(),
+                grpc_status: grpc_status,
+            });
+        }
+        expect_handle
+            .lock()
+            .unwrap()
+            .staged
+            .get_expect_send_local_response(
+                status_code,
+                string_body,
+                &header_data_ptr,
+                grpc_status,
+            );
+
+        println!("[vm->host] proxy_send_local_response(status_code={}, status_code_details_data, status_code_details_size", status_code);
+        println!(
+            "                                     body_data={}, body_size={}",
+            string_body.unwrap_or("None"),
+            body_size
+        );
+        println!(
+            "                                     headers_data={:?}, headers_size={}) status: {:?}",
+            deserialized_header,
+            headers_size,
+            get_status(&status_handle)
+        );
+    }
+    println!(
+        "[vm<-host] proxy_send_local_response(...) return: {:?}",
+        Status::Ok
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
+
+pub(crate) fn proxy_clear_route_cache(caller: &mut dyn Guest) -> i32 {
+    // Default Function: record that the effective context invalidated its route
+    // Expectation: assert that the route cache was expected to be cleared
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    clear_route_cache(&host_handle, &expect_handle);
+    println!(
+        "[vm->host] proxy_clear_route_cache() status: {:?}",
+        get_status(&status_handle)
+    );
+    println!(
+        "[vm<-host] proxy_clear_route_cache() return: {:?}",
+        Status::Ok
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
+
+/* ---------------------------------- SharedData ---------------------------------- */
+
+pub(crate) fn proxy_get_shared_data(
+    caller: &mut dyn Guest,
+    key_data: i32,
+    key_size: i32,
+    return_value_data: i32,
+    return_value_size: i32,
+    return_cas: i32,
+) -> i32 {
+    // Default Function: respond with the value and cas stored for the key (if exists)
+    // Expectation: respond with the expected value and cas for the given key
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let mut mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_get_shared_data cannot get export \"memory\"");
+            println!("[vm<-host] proxy_get_shared_data(...) -> (return_value_data, return_value_size, return_cas) return: {:?}", Status::InternalFailure);
+            return Status::InternalFailure as i32;
+        }
+    };
+
+    if !caller.has_allocator() {
+        println!("Error: proxy_get_shared_data cannot get an allocator export");
+        println!("[vm<-host] proxy_get_shared_data(...) -> (return_value_data, return_value_size, return_cas) return: {:?}", Status::InternalFailure);
+        return Status::InternalFailure as i32;
+    }
+
+    unsafe {
+        let key_data_ptr = mem
+            .data_unchecked()
+            .get(key_data as u32 as usize..)
+            .and_then(|arr| arr.get(..key_size as u32 as usize));
+        let string_key = key_data_ptr
+            .map(|string_msg| std::str::from_utf8(string_msg).unwrap())
+            .unwrap();
+
+        let (value, cas) = expect_handle
+            .lock()
+            .unwrap()
+            .staged
+            .get_expect_get_shared_data(string_key);
+        println!(
+            "[vm->host] proxy_get_shared_data(key_data={}, key_size={}) -> (...) status: {:?}",
+            string_key,
+            key_size,
+            get_status(&status_handle)
+        );
+        let stored = host_handle
+            .lock()
+            .unwrap()
+            .staged
+            .get_shared_data(string_key);
+        let (value, cas) = match (value, stored) {
+            (Some(value), stored) => (
+                value,
+                cas.or(stored.map(|(_, stored_cas)| stored_cas))
+                    .unwrap_or(0),
+            ),
+            (None, Some((stored_value, stored_cas))) => (stored_value, cas.unwrap_or(stored_cas)),
+            (None, None) => {
+                println!("[vm<-host] proxy_get_shared_data(...) -> (return_value_data, return_value_size, return_cas) return: {:?}", Status::NotFound);
+                assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+                set_status(&status_handle, ExpectStatus::Unexpected);
+                return Status::NotFound as i32;
+            }
+        };
+
+        let value_data_add =
+            allocate(caller, &mut mem, value.len() as i32).unwrap() as u32 as usize;
+        if value_data_add == 0 {
+            println!(
+                "Error: proxy_get_shared_data failed to allocate {} bytes",
+                value.len()
+            );
+            println!("[vm<-host] proxy_get_shared_data(...) -> (return_value_data, return_value_size, return_cas) return: {:?}", Status::InternalFailure);
+            return Status::InternalFailure as i32;
+        }
+        let value_data_ptr = mem
+            .data_unchecked_mut()
+            .get_unchecked_mut(value_data_add..value_data_add + value.len());
+        value_data_ptr.copy_from_slice(&value);
+
+        let return_value_data_ptr = mem.data_unchecked_mut().get_unchecked_mut(
+            return_value_data as u32 as usize..return_value_data as u32 as usize + 4,
+        );
+        return_value_data_ptr.copy_from_slice(&(value_data_add as u32).to_le_bytes());
+
+        let return_value_size_ptr = mem.data_unchecked_mut().get_unchecked_mut(
+            return_value_size as u32 as usize..return_value_size as u32 as usize + 4,
+        );
+        return_value_size_ptr.copy_from_slice(&(value.len() as u32).to_le_bytes());
+
+        let return_cas_ptr = mem
+            .data_unchecked_mut()
+            .get_unchecked_mut(return_cas as u32 as usize..return_cas as u32 as usize + 4);
+        return_cas_ptr.copy_from_slice(&cas.to_le_bytes());
+
+        println!("[vm<-host] proxy_get_shared_data(...) -> (return_value_data={}, return_value_size={}, return_cas={}) return: {:?}",
+            String::from_utf8_lossy(&value), value.len(), cas, Status::Ok
+        );
+    }
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
+
+pub(crate) fn proxy_set_shared_data(
+    caller: &mut dyn Guest,
+    key_data: i32,
+    key_size: i32,
+    value_data: i32,
+    value_size: i32,
+    cas: i32,
+) -> i32 {
+    // Default Function: store the key-value pair, unless the cas does not match the stored one
+    // Expectation: assert that the received key, value and cas are as expected
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_set_shared_data cannot get export \"memory\"");
+            println!(
+                "[vm<-host] proxy_set_shared_data(...) return: {:?}",
+                Status::InternalFailure
+            );
+            return Status::InternalFailure as i32;
+        }
+    };
+
+    let status;
+    unsafe {
+        let key_data_ptr = mem
+            .data_unchecked()
+            .get(key_data as u32 as usize..)
+            .and_then(|arr| arr.get(..key_size as u32 as usize));
+        let string_key = key_data_ptr
+            .map(|string_msg| std::str::from_utf8(string_msg).unwrap())
+            .unwrap();
+
+        let value_data_ptr = mem
+            .data_unchecked()
+            .get(value_data as u32 as usize..)
+            .and_then(|arr| arr.get(..value_size as u32 as usize))
+            .unwrap();
+
+        expect_handle
+            .lock()
+            .unwrap()
+            .staged
+            .get_expect_set_shared_data(string_key, value_data_ptr, cas as u32);
+        println!("[vm->host] proxy_set_shared_data(key_data={}, key_size={}, value_data={}, value_size={}, cas={}) status: {:?}",
+            string_key, key_size, String::from_utf8_lossy(value_data_ptr), value_size, cas as u32, get_status(&status_handle)
+        );
+        status = host_handle.lock().unwrap().staged.set_shared_data(
+            string_key,
+            value_data_ptr,
+            cas as u32,
+        );
+    }
+    println!("[vm<-host] proxy_set_shared_data(...) return: {:?}", status);
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return status as i32;
+}
+
+/* ---------------------------------- SharedQueue ---------------------------------- */
+
+pub(crate) fn proxy_register_shared_queue(
+    caller: &mut dyn Guest,
+    name_data: i32,
+    name_size: i32,
+    return_id: i32,
+) -> i32 {
+    // Default Function: register the queue for the effective context and respond with its queue_id
+    // Expectation: assert that the received queue name is as expected
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_register_shared_queue cannot get export \"memory\"");
+            println!(
+                "[vm<-host] proxy_register_shared_queue(...) -> (return_id) return: {:?}",
+                Status::InternalFailure
+            );
+            return Status::InternalFailure as i32;
+        }
+    };
+
+    unsafe {
+        let name_data_ptr = mem
+            .data_unchecked()
+            .get(name_data as u32 as usize..)
+            .and_then(|arr| arr.get(..name_size as u32 as usize));
+        let string_name = name_data_ptr
+            .map(|string_msg| std::str::from_utf8(string_msg).unwrap())
+            .unwrap();
+
+        expect_handle
+            .lock()
+            .unwrap()
+            .staged
+            .get_expect_register_shared_queue(string_name);
+        let queue_id = {
+            let mut host = host_handle.lock().unwrap();
+            let context_id = host.staged.get_effective_context_id();
+            host.staged.register_shared_queue(string_name, context_id)
+        };
+
+        let return_id_ptr = mem
+            .data_unchecked_mut()
+            .get_unchecked_mut(return_id as u32 as usize..return_id as u32 as usize + 4);
+        return_id_ptr.copy_from_slice(&queue_id.to_le_bytes());
+
+        println!("[vm->host] proxy_register_shared_queue(name_data={}, name_size={}) -> (...) status: {:?}",
+            string_name, name_size, get_status(&status_handle)
+        );
+        println!(
+            "[vm<-host] proxy_register_shared_queue(...) -> (return_id={}) return: {:?}",
+            queue_id,
+            Status::Ok
+        );
+    }
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
+
+pub(crate) fn proxy_resolve_shared_queue(
+    caller: &mut dyn Guest,
+    vm_id_data: i32,
+    vm_id_size: i32,
+    name_data: i32,
+    name_size: i32,
+    return_id: i32,
+) -> i32 {
+    // Default Function: respond with the queue_id registered under vm_id and name (if exists)
+    // Expectation: respond with the expected queue_id for the given vm_id and name
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_resolve_shared_queue cannot get export \"memory\"");
+            println!(
+                "[vm<-host] proxy_resolve_shared_queue(...) -> (return_id) return: {:?}",
+                Status::InternalFailure
+            );
+            return Status::InternalFailure as i32;
+        }
+    };
+
+    unsafe {
+        let vm_id_data_ptr = mem
+            .data_unchecked()
+            .get(vm_id_data as u32 as usize..)
+            .and_then(|arr| arr.get(..vm_id_size as u32 as usize));
+        let string_vm_id = vm_id_data_ptr
+            .map(|string_msg| std::str::from_utf8(string_msg).unwrap())
+            .unwrap();
+
+        let name_data_ptr = mem
+            .data_unchecked()
+            .get(name_data as u32 as usize..)
+            .and_then(|arr| arr.get(..name_size as u32 as usize));
+        let string_name = name_data_ptr
+            .map(|string_msg| std::str::from_utf8(string_msg).unwrap())
+            .unwrap();
+
+        let queue_id = match expect_handle
+            .lock()
+            .unwrap()
+            .staged
+            .get_expect_resolve_shared_queue(string_vm_id, string_name)
+        {
+            Some(expect_queue_id) => Some(expect_queue_id),
+            None => host_handle
+                .lock()
+                .unwrap()
+                .staged
+                .resolve_shared_queue(string_vm_id, string_name),
+        };
+        println!("[vm->host] proxy_resolve_shared_queue(vm_id_data={}, vm_id_size={}, name_data={}, name_size={}) -> (...) status: {:?}",
+            string_vm_id, vm_id_size, string_name, name_size, get_status(&status_handle)
+        );
+
+        let queue_id = match queue_id {
+            Some(queue_id) => queue_id,
+            None => {
                 println!(
-                    "[vm<-host] proxy_clear_route_cache() return: {:?}",
-                    Status::InternalFailure
+                    "[vm<-host] proxy_resolve_shared_queue(...) -> (return_id) return: {:?}",
+                    Status::NotFound
                 );
-                return Status::InternalFailure as i32;
-            }))
-        }
-
-        /* ---------------------------------- SharedData ---------------------------------- */
-        "proxy_get_shared_data" => {
-            Some(Func::wrap(
-                &store,
-                |_caller: Caller<'_>,
-                 _key_data: i32,
-                 _key_size: i32,
-                 _return_value_data: i32,
-                 _return_value_size: i32,
-                 _return_cas: i32|
-                 -> i32 {
-                    // Default Function:
-                    // Expectation:
-                    println!("[vm->host] proxy_get_shared_data(key_data, key_size) -> (...) status: {:?}", get_status());
-                    println!("[vm<-host] proxy_get_shared_data(...) -> (return_value_data, return_value_size, return_cas) return: {:?}", Status::InternalFailure);
-                    return Status::InternalFailure as i32;
-                },
-            ))
-        }
-
-        "proxy_set_shared_data" => {
-            Some(Func::wrap(
-                &store,
-                |_caller: Caller<'_>,
-                 _key_data: i32,
-                 _key_size: i32,
-                 _value_data: i32,
-                 _value_size: i32,
-                 _cas: i32|
-                 -> i32 {
-                    // Default Function:
-                    // Expectation:
-                    println!("[vm->host] proxy_set_shared_data(key_data, key_size, value_data, value_size, cas) status: {:?}", get_status());
-                    println!(
-                        "[vm<-host] proxy_set_shared_data(...) return: {:?}",
-                        Status::InternalFailure
-                    );
-                    return Status::InternalFailure as i32;
-                },
-            ))
-        }
-
-        /* ---------------------------------- SharedQueue ---------------------------------- */
-        "proxy_register_shared_queue" => {
-            Some(Func::wrap(
-                &store,
-                |_caller: Caller<'_>, _name_data: i32, _name_size: i32, _return_id: i32| -> i32 {
-                    // Default Function:
-                    // Expectation:
-                    println!("[vm->host] proxy_register_shared_queue(name_data, name_size) -> (...) status: {:?}", get_status());
-                    println!(
-                        "[vm<-host] proxy_register_shared_queue(...) -> (return_id) return: {:?}",
-                        Status::InternalFailure
-                    );
-                    return Status::InternalFailure as i32;
-                },
-            ))
-        }
-
-        "proxy_resolve_shared_queue" => {
-            Some(Func::wrap(
-                &store,
-                |_caller: Caller<'_>,
-                 _vm_id_data: i32,
-                 _vm_id_size: i32,
-                 _name_data: i32,
-                 _name_size: i32,
-                 _return_id: i32|
-                 -> i32 {
-                    // Default Function:
-                    // Expectation:
-                    println!("[vm->host] proxy_resolve_shared_queue(vm_id_data, vm_id_size, name_data, name_size) -> (...) status: {:?}", get_status());
-                    println!(
-                        "[vm<-host] proxy_resolve_shared_queue(...) -> (return_id) return: {:?}",
-                        Status::InternalFailure
-                    );
-                    return Status::InternalFailure as i32;
-                },
-            ))
-        }
-
-        "proxy_dequeue_shared_queue" => {
-            Some(Func::wrap(
-                &store,
-                |_caller: Caller<'_>,
-                 _queue_id: i32,
-                 _payload_data: i32,
-                 _payload_size: i32|
-                 -> i32 {
-                    // Default Function:
-                    // Expectation:
-                    println!("[vm->host] proxy_dequeue_shared_queue(queue_id, payload_data, payload_size) status: {:?}", get_status());
-                    println!(
-                        "[vm<-host] proxy_dequeue_shared_queue(...) return: {:?}",
-                        Status::InternalFailure
-                    );
-                    return Status::InternalFailure as i32;
-                },
-            ))
+                assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+                set_status(&status_handle, ExpectStatus::Unexpected);
+                return Status::NotFound as i32;
+            }
+        };
+
+        let return_id_ptr = mem
+            .data_unchecked_mut()
+            .get_unchecked_mut(return_id as u32 as usize..return_id as u32 as usize + 4);
+        return_id_ptr.copy_from_slice(&queue_id.to_le_bytes());
+        println!(
+            "[vm<-host] proxy_resolve_shared_queue(...) -> (return_id={}) return: {:?}",
+            queue_id,
+            Status::Ok
+        );
+    }
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
+
+pub(crate) fn proxy_dequeue_shared_queue(
+    caller: &mut dyn Guest,
+    queue_id: i32,
+    payload_data: i32,
+    payload_size: i32,
+) -> i32 {
+    // Default Function: respond with the oldest data enqueued on the queue (if any)
+    // Expectation: respond with the expected data for the given queue_id
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let mut mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_dequeue_shared_queue cannot get export \"memory\"");
+            println!("[vm<-host] proxy_dequeue_shared_queue(...) -> (payload_data, payload_size) return: {:?}", Status::InternalFailure);
+            return Status::InternalFailure as i32;
         }
+    };
 
-        "proxy_enqueue_shared_queue" => {
-            Some(Func::wrap(
-                &store,
-                |_caller: Caller<'_>, _queue_id: i32, _value_data: i32, _value_size: i32| -> i32 {
-                    // Default Function:
-                    // Expectation:
-                    println!("[vm->host] proxy_enqueue_shared_queue(queue_id, value_data, value_size) status: {:?}", get_status());
-                    println!(
-                        "[vm<-host] proxy_enqueue_shared_queue(...) return: {:?}",
-                        Status::InternalFailure
-                    );
-                    return Status::InternalFailure as i32;
-                },
-            ))
+    if !caller.has_allocator() {
+        println!("Error: proxy_dequeue_shared_queue cannot get an allocator export");
+        println!("[vm<-host] proxy_dequeue_shared_queue(...) -> (payload_data, payload_size) return: {:?}", Status::InternalFailure);
+        return Status::InternalFailure as i32;
+    }
+
+    let queue_id = queue_id as u32;
+    let data = match expect_handle
+        .lock()
+        .unwrap()
+        .staged
+        .get_expect_dequeue_shared_queue(queue_id)
+    {
+        Some(expect_data) => Ok(expect_data),
+        None => host_handle
+            .lock()
+            .unwrap()
+            .staged
+            .dequeue_shared_queue(queue_id),
+    };
+    println!(
+        "[vm->host] proxy_dequeue_shared_queue(queue_id={}) -> (...) status: {:?}",
+        queue_id,
+        get_status(&status_handle)
+    );
+
+    let data = match data {
+        Ok(data) => data,
+        Err(status) => {
+            println!("[vm<-host] proxy_dequeue_shared_queue(...) -> (payload_data, payload_size) return: {:?}", status);
+            assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+            set_status(&status_handle, ExpectStatus::Unexpected);
+            return status as i32;
         }
+    };
+
+    unsafe {
+        let data_add = allocate(caller, &mut mem, data.len() as i32).unwrap() as u32 as usize;
+        if data_add == 0 {
+            println!(
+                "Error: proxy_dequeue_shared_queue failed to allocate {} bytes",
+                data.len()
+            );
+            println!("[vm<-host] proxy_dequeue_shared_queue(...) -> (payload_data, payload_size) return: {:?}", Status::InternalFailure);
+            return Status::InternalFailure as i32;
+        }
+        let data_ptr = mem
+            .data_unchecked_mut()
+            .get_unchecked_mut(data_add..data_add + data.len());
+        data_ptr.copy_from_slice(&data);
+
+        let payload_data_ptr = mem
+            .data_unchecked_mut()
+            .get_unchecked_mut(payload_data as u32 as usize..payload_data as u32 as usize + 4);
+        payload_data_ptr.copy_from_slice(&(data_add as u32).to_le_bytes());
+
+        let payload_size_ptr = mem
+            .data_unchecked_mut()
+            .get_unchecked_mut(payload_size as u32 as usize..payload_size as u32 as usize + 4);
+        payload_size_ptr.copy_from_slice(&(data.len() as u32).to_le_bytes());
+    }
+    println!("[vm<-host] proxy_dequeue_shared_queue(...) -> (payload_data={}, payload_size={}) return: {:?}",
+        String::from_utf8_lossy(&data), data.len(), Status::Ok
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
 
-        /* ---------------------------------- Headers/Trailers/Metadata Maps ---------------------------------- */
-        "proxy_get_header_map_size" => {
-            Some(Func::wrap(
-                &store,
-                |_caller: Caller<'_>, _map_type: i32, _map_size: i32| -> i32 {
-                    // Default Function:
-                    // Expectation:
-                    println!(
-                        "[vm->host] proxy_get_header_map_size() -> (...) status: {:?}",
-                        get_status()
-                    );
-                    println!(
-                        "[vm<-host] proxy_get_header_map_size() -> (..) return: {:?}",
-                        Status::InternalFailure
-                    );
-                    return Status::InternalFailure as i32;
-                },
-            ))
-        }
-
-        "proxy_get_header_map_pairs" => {
-            Some(Func::wrap(
-                &store,
-                |caller: Caller<'_>,
-                 map_type: i32,
-                 return_map_data: i32,
-                 return_map_size: i32|
-                 -> i32 {
-                    // Default Function: respond with default header map pairs depending on map_type
-                    // Expectation: respond with set expected header map pairs
-                    let mem = match caller.get_export("memory") {
-                        Some(Extern::Memory(mem)) => mem,
-                        _ => {
-                            println!(
-                                "Error: proxy_get_header_map_pairs cannot get export \"memory\""
-                            );
-                            println!("[vm<-host] proxy_get_header_map_pairs(...) -> (return_map_data, return_map_size) return: {:?}", Status::InternalFailure);
-                            return Status::InternalFailure as i32;
-                        }
-                    };
-
-                    let malloc = match caller.get_export("malloc") {
-                        Some(Extern::Func(func)) => func.get1::<i32, i32>().unwrap(),
-                        _ => {
-                            println!(
-                                "Error: proxy_get_header_map_pairs cannot get export \"malloc\""
-                            );
-                            println!("[vm<-host] proxy_get_header_map_pairs(...) -> (return_map_data, return_map_size) return: {:?}", Status::InternalFailure);
-                            return Status::InternalFailure as i32;
-                        }
-                    };
-
-                    let serial_map = match EXPECT
-                        .lock()
-                        .unwrap()
-                        .staged
-                        .get_expect_get_header_map_pairs(map_type)
-                    {
-                        Some(header_map_pairs) => header_map_pairs,
-                        None => HOST.lock().unwrap().staged.get_header_map_pairs(map_type),
-                    };
-                    let serial_map_size = serial_map.len();
-
-                    unsafe {
-                        let return_map_size_ptr = mem.data_unchecked_mut().get_unchecked_mut(
-                            return_map_size as u32 as usize..return_map_size as u32 as usize + 4,
-                        );
+pub(crate) fn proxy_enqueue_shared_queue(
+    caller: &mut dyn Guest,
+    queue_id: i32,
+    value_data: i32,
+    value_size: i32,
+) -> i32 {
+    // Default Function: append the data to the queue (if registered)
+    // Expectation: assert that the received queue_id and data are as expected
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_enqueue_shared_queue cannot get export \"memory\"");
+            println!(
+                "[vm<-host] proxy_enqueue_shared_queue(...) return: {:?}",
+                Status::InternalFailure
+            );
+            return Status::InternalFailure as i32;
+        }
+    };
+
+    let queue_id = queue_id as u32;
+    let status;
+    unsafe {
+        let value_data_ptr = mem
+            .data_unchecked()
+            .get(value_data as u32 as usize..)
+            .and_then(|arr| arr.get(..value_size as u32 as usize))
+            .unwrap();
+
+        expect_handle
+            .lock()
+            .unwrap()
+            .staged
+            .get_expect_enqueue_shared_queue(queue_id, value_data_ptr);
+        println!("[vm->host] proxy_enqueue_shared_queue(queue_id={}, value_data={}, value_size={}) status: {:?}",
+            queue_id, String::from_utf8_lossy(value_data_ptr), value_size, get_status(&status_handle)
+        );
+        status = host_handle
+            .lock()
+            .unwrap()
+            .staged
+            .enqueue_shared_queue(queue_id, value_data_ptr);
+    }
+    println!(
+        "[vm<-host] proxy_enqueue_shared_queue(...) return: {:?}",
+        status
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return status as i32;
+}
 
-                        let return_map_data_ptr = mem.data_unchecked_mut().get_unchecked_mut(
-                            return_map_data as u32 as usize..return_map_data as u32 as usize + 4,
-                        );
+/* ---------------------------------- Headers/Trailers/Metadata Maps ---------------------------------- */
+
+pub(crate) fn proxy_get_header_map_size(
+    caller: &mut dyn Guest,
+    _map_type: i32,
+    _map_size: i32,
+) -> i32 {
+    // Default Function:
+    // Expectation:
+    let HostState {
+        status: status_handle,
+        ..
+    } = caller.state().clone();
+    println!(
+        "[vm->host] proxy_get_header_map_size() -> (...) status: {:?}",
+        get_status(&status_handle)
+    );
+    println!(
+        "[vm<-host] proxy_get_header_map_size() -> (..) return: {:?}",
+        Status::InternalFailure
+    );
+    return Status::InternalFailure as i32;
+}
 
-                        let map_data_add = malloc(serial_map_size as i32).unwrap() as u32 as usize;
-                        let map_data_ptr = mem
-                            .data_unchecked_mut()
-                            .get_unchecked_mut(map_data_add..map_data_add + serial_map_size);
-                        map_data_ptr.copy_from_slice(&serial_map);
+pub(crate) fn proxy_get_header_map_pairs(
+    caller: &mut dyn Guest,
+    map_type: i32,
+    return_map_data: i32,
+    return_map_size: i32,
+) -> i32 {
+    // Default Function: respond with default header map pairs depending on map_type
+    // Expectation: respond with set expected header map pairs
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let mut mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_get_header_map_pairs cannot get export \"memory\"");
+            println!("[vm<-host] proxy_get_header_map_pairs(...) -> (return_map_data, return_map_size) return: {:?}", Status::InternalFailure);
+            return Status::InternalFailure as i32;
+        }
+    };
 
-                        return_map_data_ptr.copy_from_slice(&(map_data_add as u32).to_le_bytes());
-                        return_map_size_ptr
-                            .copy_from_slice(&(serial_map_size as u32).to_le_bytes());
-                    }
-                    println!(
-                        "[vm->host] proxy_get_header_map_pairs(map_type={}) -> (...) status: {:?}",
-                        map_type,
-                        get_status()
-                    );
-                    println!("[vm<-host] proxy_get_header_map_pairs(...) -> (return_map_data, return_map_size) return: {:?}", Status::Ok);
-                    assert_ne!(get_status(), ExpectStatus::Failed);
-                    set_status(ExpectStatus::Unexpected);
-                    return Status::Ok as i32;
-                },
-            ))
-        }
-
-        "proxy_set_header_map_pairs" => {
-            Some(Func::wrap(
-                &store,
-                |caller: Caller<'_>, map_type: i32, map_data: i32, map_size: i32| -> i32 {
-                    // Default Function: Reads and sets the according header map as the simulator default for the given map type
-                    // Expectation: asserts that the received header map and header map type corresponds to the expected one
-                    let mem = match caller.get_export("memory") {
-                        Some(Extern::Memory(mem)) => mem,
-                        _ => {
-                            println!(
-                                "[vm<-host] proxy_set_header_map_pairs(...) return: {:?}",
-                                Status::InternalFailure
-                            );
-                            println!(
-                                "Error: proxy_set_header_map_pairs cannot get export \"memory\""
-                            );
-                            return Status::InternalFailure as i32;
-                        }
-                    };
-
-                    unsafe {
-                        let header_map_ptr = mem.data_unchecked().get_unchecked(
-                            map_data as u32 as usize..(map_data + map_size) as u32 as usize,
-                        );
+    if !caller.has_allocator() {
+        println!("Error: proxy_get_header_map_pairs cannot get an allocator export");
+        println!("[vm<-host] proxy_get_header_map_pairs(...) -> (return_map_data, return_map_size) return: {:?}", Status::InternalFailure);
+        return Status::InternalFailure as i32;
+    }
 
-                        HOST.lock().unwrap().staged.set_header_map_pairs(
-                            map_type,
-                            serial_utils::deserialize_map(header_map_ptr)
-                                .iter()
-                                .map(|(k, v)| (k as &str, v as &str))
-                                .collect(),
-                        );
-                        EXPECT
-                            .lock()
-                            .unwrap()
-                            .staged
-                            .get_expect_set_header_map_pairs(map_type, header_map_ptr);
-                    }
-                    println!("[vm->host] proxy_set_header_map_pairs(map_type={}, map_data, map_size) status: {:?}", 
-                        map_type, get_status()
-                    );
-                    println!(
-                        "[vm<-host] proxy_set_header_map_pairs(...) return: {:?}",
-                        Status::Ok
-                    );
-                    assert_ne!(get_status(), ExpectStatus::Failed);
-                    set_status(ExpectStatus::Unexpected);
-                    return Status::Ok as i32;
-                },
-            ))
-        }
-
-        "proxy_get_header_map_value" => {
-            Some(Func::wrap(
-                &store,
-                |caller: Caller<'_>,
-                 map_type: i32,
-                 key_data: i32,
-                 key_size: i32,
-                 return_value_data: i32,
-                 return_value_size: i32|
-                 -> i32 {
-                    // Default Function: respond with a default header map value corresponding to map_type (if exists)
-                    // Expectation: respond with set expected header map value for the given key and map_type
-                    // Panics if there is no header map value in expectation or host simulator for the provided map_type and key
-                    let mem = match caller.get_export("memory") {
-                        Some(Extern::Memory(mem)) => mem,
-                        _ => {
-                            println!(
-                                "Error: proxy_get_header_map_value cannot get export \"memory\""
-                            );
-                            println!("[vm<-host] proxy_get_header_map_value(...) -> (return_value_data, return_value_size) return: {:?}", Status::InternalFailure);
-                            return Status::InternalFailure as i32;
-                        }
-                    };
-
-                    let malloc = match caller.get_export("malloc") {
-                        Some(Extern::Func(func)) => func.get1::<i32, i32>().unwrap(),
-                        _ => {
-                            println!(
-                                "Error: proxy_get_header_map_value cannot get export \"malloc\""
-                            );
-                            println!("[vm<-host] proxy_get_header_map_value(...) -> (return_value_data, return_value_size) return: {:?}", Status::InternalFailure);
-                            return Status::InternalFailure as i32;
-                        }
-                    };
-
-                    unsafe {
-                        let key_data_ptr = mem
-                            .data_unchecked()
-                            .get(key_data as u32 as usize..)
-                            .and_then(|arr| arr.get(..key_size as u32 as usize));
-                        let string_key = key_data_ptr
-                            .map(|string_msg| std::str::from_utf8(string_msg).unwrap())
-                            .unwrap();
-
-                        let string_value = match EXPECT
-                            .lock()
-                            .unwrap()
-                            .staged
-                            .get_expect_get_header_map_value(map_type, string_key)
-                        {
-                            Some(expect_string_value) => expect_string_value,
-                            None => {
-                                match HOST.lock().unwrap().staged.get_header_map_value(map_type, &string_key) {
-                                Some(host_string_value) => host_string_value,
-                                None => panic!("Error: proxy_get_header_map_value | no header map value for key {}", string_key)}
-                            }
-                        };
-
-                        let value_data_add =
-                            malloc(string_value.len() as i32).unwrap() as u32 as usize;
-                        let value_data_ptr = mem
-                            .data_unchecked_mut()
-                            .get_unchecked_mut(value_data_add..value_data_add + string_value.len());
-                        value_data_ptr.copy_from_slice((&string_value).as_bytes());
-
-                        let return_value_data_ptr = mem.data_unchecked_mut().get_unchecked_mut(
-                            return_value_data as u32 as usize
-                                ..return_value_data as u32 as usize + 4,
-                        );
+    let serial_map = match expect_handle
+        .lock()
+        .unwrap()
+        .staged
+        .get_expect_get_header_map_pairs(map_type)
+    {
+        Some(header_map_pairs) => header_map_pairs,
+        None => host_handle
+            .lock()
+            .unwrap()
+            .staged
+            .get_header_map_pairs(map_type),
+    };
+    let serial_map_size = serial_map.len();
+
+    unsafe {
+        let map_data_add = match serial_map_size {
+            0 => 0,
+            _ => allocate(caller, &mut mem, serial_map_size as i32).unwrap() as u32 as usize,
+        };
+        if map_data_add == 0 && serial_map_size != 0 {
+            println!(
+                "Error: proxy_get_header_map_pairs failed to allocate {} bytes",
+                serial_map_size
+            );
+            println!("[vm<-host] proxy_get_header_map_pairs(...) -> (return_map_data, return_map_size) return: {:?}", Status::InternalFailure);
+            return Status::InternalFailure as i32;
+        }
+        let map_data_ptr = mem
+            .data_unchecked_mut()
+            .get_unchecked_mut(map_data_add..map_data_add + serial_map_size);
+        map_data_ptr.copy_from_slice(&serial_map);
 
-                        let return_value_size_ptr = mem.data_unchecked_mut().get_unchecked_mut(
-                            return_value_size as u32 as usize
-                                ..return_value_size as u32 as usize + 4,
-                        );
+        let return_map_size_ptr = mem.data_unchecked_mut().get_unchecked_mut(
+            return_map_size as u32 as usize..return_map_size as u32 as usize + 4,
+        );
 
-                        return_value_data_ptr
-                            .copy_from_slice(&(value_data_add as u32).to_le_bytes());
-                        return_value_size_ptr
-                            .copy_from_slice(&(string_value.len() as u32).to_le_bytes());
+        let return_map_data_ptr = mem.data_unchecked_mut().get_unchecked_mut(
+            return_map_data as u32 as usize..return_map_data as u32 as usize + 4,
+        );
 
-                        println!("[vm->host] proxy_get_header_map_value(map_type={}, key_data={}, key_size={}) -> (...) status: {:?}", 
-                            map_type, string_key, key_size, get_status()
-                        );
-                        println!("[vm<-host] proxy_get_header_map_value(...) -> (return_value_data={}, return_value_size={}) return: {:?}", 
-                            string_value, string_value.len(), Status::Ok
-                        );
-                    }
-                    assert_ne!(get_status(), ExpectStatus::Failed);
-                    set_status(ExpectStatus::Unexpected);
-                    return Status::Ok as i32;
-                },
-            ))
-        }
-
-        "proxy_replace_header_map_value" => {
-            Some(Func::wrap(
-                &store,
-                |caller: Caller<'_>,
-                 map_type: i32,
-                 key_data: i32,
-                 key_size: i32,
-                 value_data: i32,
-                 value_size: i32|
-                 -> i32 {
-                    // Default Function: replace the specified key-value pair in the default host environment if it exists
-                    // Expectation: assert that the received key-value pair are as expected
-                    let mem = match caller.get_export("memory") {
-                        Some(Extern::Memory(mem)) => mem,
-                        _ => {
-                            println!("Error: proxy_replace_header_map_value cannot get export \"memory\"");
-                            println!(
-                                "[vm<-host] proxy_replace_header_map_value(...) return: {:?}",
-                                Status::InternalFailure
-                            );
-                            return Status::InternalFailure as i32;
-                        }
-                    };
-
-                    unsafe {
-                        let key_data_ptr = mem
-                            .data_unchecked()
-                            .get(key_data as u32 as usize..)
-                            .and_then(|arr| arr.get(..key_size as u32 as usize));
-                        let string_key = key_data_ptr
-                            .map(|string_msg| std::str::from_utf8(string_msg).unwrap())
-                            .unwrap();
-
-                        let value_data_ptr = mem
-                            .data_unchecked()
-                            .get(value_data as u32 as usize..)
-                            .and_then(|arr| arr.get(..value_size as u32 as usize));
-                        let string_value = value_data_ptr
-                            .map(|string_msg| std::str::from_utf8(string_msg).unwrap())
-                            .unwrap();
-
-                        EXPECT
-                            .lock()
-                            .unwrap()
-                            .staged
-                            .get_expect_replace_header_map_value(
-                                map_type,
-                                string_key,
-                                string_value,
-                            );
-                        HOST.lock().unwrap().staged.replace_header_map_value(
-                            map_type,
-                            string_key,
-                            string_value,
-                        );
-                        println!("[vm->host] proxy_replace_header_map_value(map_type={}, key_data={}, key_size={}, value_data={}, value_size={}) status: {:?}", 
-                            map_type, string_key, string_key.len(), string_value, string_value.len(), get_status()
-                        );
-                    }
-                    println!(
-                        "[vm<-host] proxy_replace_header_map_value(...) return: {:?}",
-                        Status::Ok
-                    );
-                    assert_ne!(get_status(), ExpectStatus::Failed);
-                    set_status(ExpectStatus::Unexpected);
-                    return Status::Ok as i32;
-                },
-            ))
-        }
-
-        "proxy_remove_header_map_value" => {
-            Some(Func::wrap(
-                &store,
-                |caller: Caller<'_>, map_type: i32, key_data: i32, key_size: i32| -> i32 {
-                    // Default Function: remove the specified key-value pair in the default host environment if it exists
-                    // Expectation: assert that the received key is as expected
-                    let mem = match caller.get_export("memory") {
-                        Some(Extern::Memory(mem)) => mem,
-                        _ => {
-                            println!(
-                                "Error: proxy_remove_header_map_value cannot get export \"memory\""
-                            );
-                            println!(
-                                "[vm<-host] proxy_remove_header_map_value(...) return: {:?}",
-                                Status::InternalFailure
-                            );
-                            return Status::InternalFailure as i32;
-                        }
-                    };
-
-                    unsafe {
-                        let key_data_ptr = mem
-                            .data_unchecked()
-                            .get(key_data as u32 as usize..)
-                            .and_then(|arr| arr.get(..key_size as u32 as usize));
-                        let string_key = key_data_ptr
-                            .map(|string_msg| std::str::from_utf8(string_msg).unwrap())
-                            .unwrap();
-
-                        EXPECT
-                            .lock()
-                            .unwrap()
-                            .staged
-                            .get_expect_remove_header_map_value(map_type, string_key);
-                        HOST.lock()
-                            .unwrap()
-                            .staged
-                            .remove_header_map_value(map_type, string_key);
-                        println!("[vm->host] proxy_remove_header_map_value(map_type={}, key_data={}, key_size={}) status: {:?}", 
-                            map_type, string_key, string_key.len(), get_status()
-                        );
-                    }
-                    println!(
-                        "[vm<-host] proxy_remove_header_map_value(...) return: {:?}",
-                        Status::Ok
-                    );
-                    assert_ne!(get_status(), ExpectStatus::Failed);
-                    set_status(ExpectStatus::Unexpected);
-                    return Status::Ok as i32;
-                },
-            ))
-        }
-
-        "proxy_add_header_map_value" => {
-            Some(Func::wrap(
-                &store,
-                |caller: Caller<'_>,
-                 map_type: i32,
-                 key_data: i32,
-                 key_size: i32,
-                 value_data: i32,
-                 value_size: i32|
-                 -> i32 {
-                    // Default Function: add the specified key-value pair in the default host environment if it exists
-                    // Expectation: assert that the received key-value pair are as expected
-                    let mem = match caller.get_export("memory") {
-                        Some(Extern::Memory(mem)) => mem,
-                        _ => {
-                            println!(
-                                "Error: proxy_add_header_map_value cannot get export \"memory\""
-                            );
-                            println!(
-                                "[vm<-host] proxy_add_header_map_value(...) return: {:?}",
-                                Status::InternalFailure
-                            );
-                            return Status::InternalFailure as i32;
-                        }
-                    };
-
-                    unsafe {
-                        let key_data_ptr = mem
-                            .data_unchecked()
-                            .get(key_data as u32 as usize..)
-                            .and_then(|arr| arr.get(..key_size as u32 as usize));
-                        let string_key = key_data_ptr
-                            .map(|string_msg| std::str::from_utf8(string_msg).unwrap())
-                            .unwrap();
-
-                        let value_data_ptr = mem
-                            .data_unchecked()
-                            .get(value_data as u32 as usize..)
-                            .and_then(|arr| arr.get(..value_size as u32 as usize));
-                        let string_value = value_data_ptr
-                            .map(|string_msg| std::str::from_utf8(string_msg).unwrap())
-                            .unwrap();
-
-                        EXPECT
-                            .lock()
-                            .unwrap()
-                            .staged
-                            .get_expect_add_header_map_value(map_type, string_key, string_value);
-                        HOST.lock().unwrap().staged.add_header_map_value(
-                            map_type,
-                            string_key,
-                            string_value,
-                        );
-                        println!("[vm->host] proxy_add_header_map_value(map_type={}, key_data={}, key_size={}, value_data={}, value_size={}) status: {:?}", 
-                            map_type, string_key, string_key.len(), string_value, string_value.len(), get_status()
-                        );
-                    }
-                    println!(
-                        "[vm<-host] proxy_add_header_map_value(...) return: {:?}",
-                        Status::Ok
-                    );
-                    assert_ne!(get_status(), ExpectStatus::Failed);
-                    set_status(ExpectStatus::Unexpected);
-                    return Status::Ok as i32;
-                },
-            ))
-        }
-
-        /* ---------------------------------- Buffer ---------------------------------- */
-        "proxy_get_buffer_status" => {
-            Some(Func::wrap(
-                &store,
-                |_caller: Caller<'_>,
-                 _buffer_type: i32,
-                 _length_ptr: i32,
-                 _flags_ptr: i32|
-                 -> i32 {
-                    // Default Function:
-                    // Expectation:
-                    println!(
-                        "[vm->host] proxy_get_buffer_status() -> (...) status: {:?}",
-                        get_status()
-                    );
-                    println!(
-                        "[vm<-host] proxy_get_buffer_status() -> (..) return: {:?}",
-                        Status::InternalFailure
-                    );
-                    return Status::InternalFailure as i32;
-                },
-            ))
-        }
-
-        "proxy_get_buffer_bytes" => {
-            Some(Func::wrap(
-                &store,
-                |caller: Caller<'_>,
-                 buffer_type: i32,
-                 start: i32,
-                 max_size: i32,
-                 return_buffer_data: i32,
-                 return_buffer_size: i32|
-                 -> i32 {
-                    // Default Function: generate and return random buffer_bytes of length max_size - start
-                    // Expectation: return buffer bytes set in expectation
-                    let mem = match caller.get_export("memory") {
-                        Some(Extern::Memory(mem)) => mem,
-                        _ => {
-                            println!("Error: proxy_get_buffer_bytes cannot get export \"memory\"");
-                            println!("[vm<-host] proxy_get_buffer_bytes(...) -> (return_buffer_data, return_buffer_size) return: {:?}", Status::InternalFailure);
-                            return Status::InternalFailure as i32;
-                        }
-                    };
-
-                    let malloc = match caller.get_export("malloc") {
-                        Some(Extern::Func(func)) => func.get1::<i32, i32>().unwrap(),
-                        _ => {
-                            println!("Error: proxy_get_buffer_bytes cannot get export \"malloc\"");
-                            println!("[vm<-host] proxy_get_buffer_bytes(...) -> (return_buffer_data, return_buffer_size) return: {:?}", Status::InternalFailure);
-                            return Status::InternalFailure as i32;
-                        }
-                    };
-
-                    let response_body = match EXPECT
-                        .lock()
-                        .unwrap()
-                        .staged
-                        .get_expect_get_buffer_bytes(buffer_type)
-                    {
-                        Some(expect_buffer_bytes) => {
-                            assert_le!(expect_buffer_bytes.len(), (max_size - start) as usize);
-                            expect_buffer_bytes
-                        }
-                        None => {
-                            let buffer_bytes: Bytes;
-                            let host_buffer_bytes =
-                                HOST.lock().unwrap().staged.get_buffer_bytes(buffer_type);
-                            if host_buffer_bytes.len() == (max_size - start) as usize {
-                                buffer_bytes = host_buffer_bytes;
-                            } else {
-                                buffer_bytes = serial_utils::generate_random_string(
-                                    (max_size - start) as usize,
-                                )
-                                .as_bytes()
-                                .to_vec();
-                            }
-                            buffer_bytes
-                        }
-                    };
-
-                    unsafe {
-                        let return_buffer_size_ptr = mem.data_unchecked_mut().get_unchecked_mut(
-                            return_buffer_size as u32 as usize
-                                ..return_buffer_size as u32 as usize + 4,
-                        );
+        return_map_data_ptr.copy_from_slice(&(map_data_add as u32).to_le_bytes());
+        return_map_size_ptr.copy_from_slice(&(serial_map_size as u32).to_le_bytes());
+    }
+    println!(
+        "[vm->host] proxy_get_header_map_pairs(map_type={}) -> (...) status: {:?}",
+        map_type,
+        get_status(&status_handle)
+    );
+    println!("[vm<-host] proxy_get_header_map_pairs(...) -> (return_map_data, return_map_size) return: {:?}", Status::Ok);
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
 
-                        let return_buffer_data_ptr = mem.data_unchecked_mut().get_unchecked_mut(
-                            return_buffer_data as u32 as usize
-                                ..return_buffer_data as u32 as usize + 4,
-                        );
+pub(crate) fn proxy_set_header_map_pairs(
+    caller: &mut dyn Guest,
+    map_type: i32,
+    map_data: i32,
+    map_size: i32,
+) -> i32 {
+    // Default Function: Reads and sets the according header map as the simulator default for the given map type
+    // Expectation: asserts that the received header map and header map type corresponds to the expected one
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!(
+                "[vm<-host] proxy_set_header_map_pairs(...) return: {:?}",
+                Status::InternalFailure
+            );
+            println!("Error: proxy_set_header_map_pairs cannot get export \"memory\"");
+            return Status::InternalFailure as i32;
+        }
+    };
+
+    let header_map_range = match serial_utils::guest_range(map_data, map_size, mem.data_size()) {
+        Some(header_map_range) => header_map_range,
+        None => {
+            println!(
+                "Error: proxy_set_header_map_pairs map_data={} map_size={} is out of bounds",
+                map_data, map_size as u32
+            );
+            println!(
+                "[vm<-host] proxy_set_header_map_pairs(...) return: {:?}",
+                Status::BadArgument
+            );
+            return Status::BadArgument as i32;
+        }
+    };
+
+    unsafe {
+        let header_map_ptr = mem.data_unchecked().get_unchecked(header_map_range);
+
+        host_handle.lock().unwrap().staged.set_header_map_data(
+            map_type,
+            serial_utils::deserialize_map_bytes(header_map_ptr)
+                .iter()
+                .map(|(k, v)| (k as &str, &v[..]))
+                .collect(),
+        );
+        expect_handle
+            .lock()
+            .unwrap()
+            .staged
+            .get_expect_set_header_map_pairs(map_type, header_map_ptr);
+    }
+    println!(
+        "[vm->host] proxy_set_header_map_pairs(map_type={}, map_data, map_size) status: {:?}",
+        map_type,
+        get_status(&status_handle)
+    );
+    println!(
+        "[vm<-host] proxy_set_header_map_pairs(...) return: {:?}",
+        Status::Ok
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
 
-                        // allocate memory and store buffer bytes
-                        let buffer_data_add =
-                            malloc(response_body.len() as i32).unwrap() as u32 as usize;
-                        let buffer_data_ptr = mem.data_unchecked_mut().get_unchecked_mut(
-                            buffer_data_add..buffer_data_add + response_body.len(),
-                        );
-                        buffer_data_ptr.copy_from_slice(&response_body);
+pub(crate) fn proxy_get_header_map_value(
+    caller: &mut dyn Guest,
+    map_type: i32,
+    key_data: i32,
+    key_size: i32,
+    return_value_data: i32,
+    return_value_size: i32,
+) -> i32 {
+    // Default Function: respond with a default header map value corresponding to map_type (if exists)
+    // Expectation: respond with set expected header map value for the given key and map_type
+    // Panics if there is no header map value in expectation or host simulator for the provided map_type and key
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let mut mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_get_header_map_value cannot get export \"memory\"");
+            println!("[vm<-host] proxy_get_header_map_value(...) -> (return_value_data, return_value_size) return: {:?}", Status::InternalFailure);
+            return Status::InternalFailure as i32;
+        }
+    };
 
-                        return_buffer_size_ptr
-                            .copy_from_slice(&(response_body.len() as u32).to_le_bytes());
-                        return_buffer_data_ptr
-                            .copy_from_slice(&(buffer_data_add as u32).to_le_bytes());
-                    }
-                    println!(
-                        "[vm->host] proxy_get_buffer_bytes(buffer_type={}, start={}, max_size={}) -> (...) status: {:?}",
-                        buffer_type, start, max_size, get_status()
-                    );
-                    println!(
-                        "[vm<-host] proxy_get_buffer_bytes(...) -> (return_buffer_data, return_buffer_size) return: {:?}", Status::Ok
-                    );
-                    assert_ne!(get_status(), ExpectStatus::Failed);
-                    set_status(ExpectStatus::Unexpected);
-                    return Status::Ok as i32;
-                },
-            ))
-        }
-
-        "proxy_set_buffer_bytes" => {
-            Some(Func::wrap(
-                &store,
-                |caller: Caller<'_>,
-                 buffer_type: i32,
-                 start: i32,
-                 size: i32,
-                 buffer_data: i32,
-                 buffer_size: i32|
-                 -> i32 {
-                    // Default Function: set received buffer data as default
-                    // Expectation: assert that the received buffer bytes is as expected
-                    let mem = match caller.get_export("memory") {
-                        Some(Extern::Memory(mem)) => mem,
-                        _ => {
-                            println!("Error: proxy_set_buffer_bytes cannot get export \"memory\"");
-                            println!(
-                                "[vm<-host] proxy_set_buffer_bytes(...) return: {:?}",
-                                Status::InternalFailure
-                            );
-                            return Status::InternalFailure as i32;
-                        }
-                    };
-
-                    unsafe {
-                        let buffer_data_ptr = mem.data_unchecked().get_unchecked(
-                            buffer_data as u32 as usize
-                                ..(buffer_data + buffer_size) as u32 as usize,
-                        );
-                        assert_ge!(buffer_data_ptr.len(), (start + size) as usize);
+    if !caller.has_allocator() {
+        println!("Error: proxy_get_header_map_value cannot get an allocator export");
+        println!("[vm<-host] proxy_get_header_map_value(...) -> (return_value_data, return_value_size) return: {:?}", Status::InternalFailure);
+        return Status::InternalFailure as i32;
+    }
 
-                        EXPECT.lock().unwrap().staged.get_expect_set_buffer_bytes(
-                            buffer_type,
-                            &buffer_data_ptr[start as usize..(start + size) as usize],
-                        );
-                        HOST.lock().unwrap().staged.set_buffer_bytes(
-                            buffer_type,
-                            std::str::from_utf8(
-                                &buffer_data_ptr[start as usize..(start + size) as usize],
-                            )
-                            .unwrap(),
+    unsafe {
+        let key_data_ptr = mem
+            .data_unchecked()
+            .get(key_data as u32 as usize..)
+            .and_then(|arr| arr.get(..key_size as u32 as usize));
+        let string_key = key_data_ptr
+            .map(|string_msg| std::str::from_utf8(string_msg).unwrap())
+            .unwrap();
+
+        let string_value = match expect_handle
+            .lock()
+            .unwrap()
+            .staged
+            .get_expect_get_header_map_value(map_type, string_key)
+        {
+            Some(expect_value) => expect_value,
+            None => {
+                match host_handle
+                    .lock()
+                    .unwrap()
+                    .staged
+                    .get_header_map_value(map_type, &string_key)
+                {
+                    Some(host_string_value) => host_string_value,
+                    None => {
+                        println!("[vm->host] proxy_get_header_map_value(map_type={}, key_data={}, key_size={}) -> (...) status: {:?}",
+                            map_type, string_key, key_size, get_status(&status_handle)
                         );
+                        println!("[vm<-host] proxy_get_header_map_value(...) -> (return_value_data, return_value_size) return: {:?}", Status::NotFound);
+                        assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+                        set_status(&status_handle, ExpectStatus::Unexpected);
+                        return Status::NotFound as i32;
                     }
-                    println!(
-                        "[vm<-host] proxy_set_buffer_bytes(buffer_type={},
-                            start={},
-                            size={},
-                            buffer_data,
-                            buffer_size) status: {:?}",
-                        buffer_type,
-                        start,
-                        size,
-                        get_status()
-                    );
-                    println!(
-                        "[vm<-host] proxy_set_buffer_bytes(...) return: {:?}",
-                        Status::Ok
-                    );
-                    assert_ne!(get_status(), ExpectStatus::Failed);
-                    set_status(ExpectStatus::Unexpected);
-                    return Status::Ok as i32;
-                },
-            ))
-        }
-
-        /* ---------------------------------- HTTP ---------------------------------- */
-        "proxy_http_call" => {
-            Some(Func::wrap(
-                &store,
-                |caller: Caller<'_>,
-                 upstream_data: i32,
-                 upstream_size: i32,
-                 headers_data: i32,
-                 headers_size: i32,
-                 body_data: i32,
-                 body_size: i32,
-                 trailers_data: i32,
-                 trailers_size: i32,
-                 timeout: i32,
-                 return_token: i32|
-                 -> i32 {
-                    // Default Function: receives and displays http call from proxy-wasm module
-                    // Expectation: asserts equal the receieved http call with the expected one
-                    let mem = match caller.get_export("memory") {
-                        Some(Extern::Memory(mem)) => mem,
-                        _ => {
-                            println!("Error: proxy_http_call cannot get export \"memory\"");
-                            println!(
-                                "[vm<-host] proxy_http_call(...) -> (return_token) return: {:?}",
-                                Status::InternalFailure
-                            );
-                            return Status::InternalFailure as i32;
-                        }
-                    };
-
-                    // expectation description not implemented yet
-                    unsafe {
-                        let upstream_data_ptr = mem
-                            .data_unchecked()
-                            .get(upstream_data as u32 as usize..)
-                            .and_then(|arr| arr.get(..upstream_size as u32 as usize));
-                        let string_upstream = upstream_data_ptr
-                            .map(|string_msg| std::str::from_utf8(string_msg).unwrap())
-                            .unwrap();
-
-                        let mut string_body: Option<&str> = None;
-                        if body_size > 0 {
-                            let body_data_ptr = mem
-                                .data_unchecked()
-                                .get(body_data as u32 as usize..)
-                                .and_then(|arr| arr.get(..body_size as u32 as usize));
-                            string_body = body_data_ptr
-                                .map(|string_msg| std::str::from_utf8(string_msg).unwrap());
-                        }
-
-                        let header_data_ptr = mem.data_unchecked().get_unchecked(
-                            headers_data as u32 as usize
-                                ..headers_data as u32 as usize + headers_size as u32 as usize,
-                        );
-                        let deserialized_header = serial_utils::deserialize_map(header_data_ptr);
+                }
+            }
+        };
+
+        let value_data_add =
+            allocate(caller, &mut mem, string_value.len() as i32).unwrap() as u32 as usize;
+        if value_data_add == 0 {
+            println!(
+                "Error: proxy_get_header_map_value failed to allocate {} bytes",
+                string_value.len()
+            );
+            println!("[vm<-host] proxy_get_header_map_value(...) -> (return_value_data, return_value_size) return: {:?}", Status::InternalFailure);
+            return Status::InternalFailure as i32;
+        }
+        let value_data_ptr = mem
+            .data_unchecked_mut()
+            .get_unchecked_mut(value_data_add..value_data_add + string_value.len());
+        value_data_ptr.copy_from_slice(&string_value);
+
+        let return_value_data_ptr = mem.data_unchecked_mut().get_unchecked_mut(
+            return_value_data as u32 as usize..return_value_data as u32 as usize + 4,
+        );
+
+        let return_value_size_ptr = mem.data_unchecked_mut().get_unchecked_mut(
+            return_value_size as u32 as usize..return_value_size as u32 as usize + 4,
+        );
+
+        return_value_data_ptr.copy_from_slice(&(value_data_add as u32).to_le_bytes());
+        return_value_size_ptr.copy_from_slice(&(string_value.len() as u32).to_le_bytes());
+
+        println!("[vm->host] proxy_get_header_map_value(map_type={}, key_data={}, key_size={}) -> (...) status: {:?}", 
+            map_type, string_key, key_size, get_status(&status_handle)
+        );
+        println!("[vm<-host] proxy_get_header_map_value(...) -> (return_value_data={}, return_value_size={}) return: {:?}", 
+            String::from_utf8_lossy(&string_value), string_value.len(), Status::Ok
+        );
+    }
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
 
-                        let trailer_data_ptr = mem.data_unchecked().get_unchecked(
-                            trailers_data as u32 as usize
-                                ..trailers_data as u32 as usize + trailers_size as u32 as usize,
-                        );
-                        let deserialized_trailer = serial_utils::deserialize_map(trailer_data_ptr);
-
-                        let token_id = match EXPECT.lock().unwrap().staged.get_expect_http_call(
-                            string_upstream,
-                            header_data_ptr,
-                            string_body,
-                            trailer_data_ptr,
-                            timeout,
-                        ) {
-                            Some(expect_token) => expect_token,
-                            None => 0,
-                        };
-
-                        let return_token_add = mem.data_unchecked_mut().get_unchecked_mut(
-                            return_token as u32 as usize..return_token as u32 as usize + 4,
-                        );
-                        return_token_add.copy_from_slice(&token_id.to_le_bytes());
-                        println!(
-                            "[vm->host] proxy_http_call(upstream_data={:?}, upstream_size={}",
-                            string_upstream,
-                            string_upstream.len()
-                        );
-                        println!(
-                            "                           headers_data={:?}, headers_size={}",
-                            deserialized_header, headers_size
-                        );
-                        println!(
-                            "                           body_data={}, body_size={}",
-                            string_body.unwrap_or("None"),
-                            string_body.map_or(0, |data| data.len())
-                        );
-                        println!(
-                            "                           trailers_data={:?}, trailers_size={}",
-                            deserialized_trailer, trailers_size
-                        );
-                        println!(
-                            "                           timeout) -> (...) status: {:?}",
-                            get_status()
-                        );
-                        println!(
-                            "[vm<-host] proxy_http_call(...) -> (return_token={}) return: {:?}",
-                            token_id,
-                            Status::Ok
-                        );
-                    }
-                    assert_ne!(get_status(), ExpectStatus::Failed);
-                    set_status(ExpectStatus::Unexpected);
-                    return Status::Ok as i32;
-                },
-            ))
-        }
-
-        /* ---------------------------------- gRPC ---------------------------------- */
-        "proxy_grpc_call" => {
-            Some(Func::wrap(
-                &store,
-                |_caller: Caller<'_>,
-                 _service_ptr: i32,
-                 _service_size: i32,
-                 _service_name_ptr: i32,
-                 _service_name_size: i32,
-                 _method_name_ptr: i32,
-                 _method_name_size: i32,
-                 _initial_metadata_ptr: i32,
-                 _initial_metadata_size: i32,
-                 _request_ptr: i32,
-                 _request_size: i32,
-                 _timeout_milliseconds: i32,
-                 _token_ptr: i32|
-                 -> i32 {
-                    // Default Function:
-                    // Expectation:
-                    println!(
-                        "[vm->host] proxy_grpc_call() -> (...) status: {:?}",
-                        get_status()
-                    );
-                    println!(
-                        "[vm<-host] proxy_grpc_call() -> (..) return: {:?}",
-                        Status::InternalFailure
-                    );
-                    return Status::InternalFailure as i32;
-                },
-            ))
-        }
-
-        "proxy_grpc_stream" => {
-            Some(Func::wrap(
-                &store,
-                |_caller: Caller<'_>,
-                 _service_ptr: i32,
-                 _service_size: i32,
-                 _service_name_ptr: i32,
-                 _service_name_size: i32,
-                 _method_name_ptr: i32,
-                 _method_name_size: i32,
-                 _initial_metadata: i32,
-                 _initial_metadata_size: i32,
-                 _token_ptr: i32|
-                 -> i32 {
-                    // Default Function:
-                    // Expectation:
-                    println!(
-                        "[vm->host] proxy_grpc_stream() -> (...) status: {:?}",
-                        get_status()
-                    );
-                    println!(
-                        "[vm<-host] proxy_grpc_stream() -> (..) return: {:?}",
-                        Status::InternalFailure
-                    );
-                    return Status::InternalFailure as i32;
-                },
-            ))
+pub(crate) fn proxy_replace_header_map_value(
+    caller: &mut dyn Guest,
+    map_type: i32,
+    key_data: i32,
+    key_size: i32,
+    value_data: i32,
+    value_size: i32,
+) -> i32 {
+    // Default Function: replace the specified key-value pair in the default host environment if it exists
+    // Expectation: assert that the received key-value pair are as expected
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_replace_header_map_value cannot get export \"memory\"");
+            println!(
+                "[vm<-host] proxy_replace_header_map_value(...) return: {:?}",
+                Status::InternalFailure
+            );
+            return Status::InternalFailure as i32;
         }
+    };
+
+    let status;
+    unsafe {
+        let key_data_ptr = mem
+            .data_unchecked()
+            .get(key_data as u32 as usize..)
+            .and_then(|arr| arr.get(..key_size as u32 as usize));
+        let string_key = key_data_ptr
+            .map(|string_msg| std::str::from_utf8(string_msg).unwrap())
+            .unwrap();
+
+        let value_data_ptr = mem
+            .data_unchecked()
+            .get(value_data as u32 as usize..)
+            .and_then(|arr| arr.get(..value_size as u32 as usize));
+        let value_data_ptr = value_data_ptr.unwrap();
+        let string_value = &String::from_utf8_lossy(value_data_ptr);
+
+        expect_handle
+            .lock()
+            .unwrap()
+            .staged
+            .get_expect_replace_header_map_value(map_type, string_key, value_data_ptr);
+        host_handle.lock().unwrap().staged.replace_header_map_value(
+            map_type,
+            string_key,
+            value_data_ptr,
+        );
+        host_handle.lock().unwrap().staged.track_upstream_override(
+            map_type,
+            string_key,
+            string_value,
+        );
+        println!("[vm->host] proxy_replace_header_map_value(map_type={}, key_data={}, key_size={}, value_data={}, value_size={}) status: {:?}", 
+            map_type, string_key, string_key.len(), string_value, value_data_ptr.len(), get_status(&status_handle)
+        );
+    }
+    println!(
+        "[vm<-host] proxy_replace_header_map_value(...) return: {:?}",
+        Status::Ok
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
 
-        "proxy_grpc_cancel" => {
-            Some(Func::wrap(
-                &store,
-                |_caller: Caller<'_>, _token: i32| -> i32 {
-                    // Default Function:
-                    // Expectation:
-                    println!(
-                        "[vm->host] proxy_grpc_cancel() -> (...) status: {:?}",
-                        get_status()
-                    );
-                    println!(
-                        "[vm<-host] proxy_grpc_cancel() -> (..) return: {:?}",
-                        Status::InternalFailure
-                    );
-                    return Status::InternalFailure as i32;
-                },
-            ))
+pub(crate) fn proxy_remove_header_map_value(
+    caller: &mut dyn Guest,
+    map_type: i32,
+    key_data: i32,
+    key_size: i32,
+) -> i32 {
+    // Default Function: remove the specified key-value pair in the default host environment if it exists
+    // Expectation: assert that the received key is as expected
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_remove_header_map_value cannot get export \"memory\"");
+            println!(
+                "[vm<-host] proxy_remove_header_map_value(...) return: {:?}",
+                Status::InternalFailure
+            );
+            return Status::InternalFailure as i32;
         }
+    };
+
+    unsafe {
+        let key_data_ptr = mem
+            .data_unchecked()
+            .get(key_data as u32 as usize..)
+            .and_then(|arr| arr.get(..key_size as u32 as usize));
+        let string_key = key_data_ptr
+            .map(|string_msg| std::str::from_utf8(string_msg).unwrap())
+            .unwrap();
+
+        expect_handle
+            .lock()
+            .unwrap()
+            .staged
+            .get_expect_remove_header_map_value(map_type, string_key);
+        host_handle
+            .lock()
+            .unwrap()
+            .staged
+            .remove_header_map_value(map_type, string_key);
+        println!("[vm->host] proxy_remove_header_map_value(map_type={}, key_data={}, key_size={}) status: {:?}", 
+            map_type, string_key, string_key.len(), get_status(&status_handle)
+        );
+    }
+    println!(
+        "[vm<-host] proxy_remove_header_map_value(...) return: {:?}",
+        Status::Ok
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
 
-        "proxy_grpc_close" => {
-            Some(Func::wrap(
-                &store,
-                |_caller: Caller<'_>, _token: i32| -> i32 {
-                    // Default Function:
-                    // Expectation:
-                    println!(
-                        "[vm->host] proxy_grpc_close() -> (...) status: {:?}",
-                        get_status()
-                    );
-                    println!(
-                        "[vm<-host] proxy_grpc_close() -> (..) return: {:?}",
-                        Status::InternalFailure
-                    );
-                    return Status::InternalFailure as i32;
-                },
-            ))
-        }
-
-        "proxy_grpc_send" => {
-            Some(Func::wrap(
-                &store,
-                |_caller: Caller<'_>,
-                 _token: i32,
-                 _message_ptr: i32,
-                 _message_size: i32,
-                 _end_of_stream: i32|
-                 -> i32 {
-                    // Default Function:
-                    // Expectation:
-                    println!(
-                        "[vm->host] proxy_grpc_send() -> (...) status: {:?}",
-                        get_status()
-                    );
-                    println!(
-                        "[vm<-host] proxy_grpc_send() -> (..) return: {:?}",
-                        Status::InternalFailure
-                    );
-                    return Status::InternalFailure as i32;
-                },
-            ))
+pub(crate) fn proxy_add_header_map_value(
+    caller: &mut dyn Guest,
+    map_type: i32,
+    key_data: i32,
+    key_size: i32,
+    value_data: i32,
+    value_size: i32,
+) -> i32 {
+    // Default Function: add the specified key-value pair in the default host environment if it exists
+    // Expectation: assert that the received key-value pair are as expected
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_add_header_map_value cannot get export \"memory\"");
+            println!(
+                "[vm<-host] proxy_add_header_map_value(...) return: {:?}",
+                Status::InternalFailure
+            );
+            return Status::InternalFailure as i32;
         }
+    };
+
+    let status;
+    unsafe {
+        let key_data_ptr = mem
+            .data_unchecked()
+            .get(key_data as u32 as usize..)
+            .and_then(|arr| arr.get(..key_size as u32 as usize));
+        let string_key = key_data_ptr
+            .map(|string_msg| std::str::from_utf8(string_msg).unwrap())
+            .unwrap();
+
+        let value_data_ptr = mem
+            .data_unchecked()
+            .get(value_data as u32 as usize..)
+            .and_then(|arr| arr.get(..value_size as u32 as usize));
+        let value_data_ptr = value_data_ptr.unwrap();
+        let string_value = &String::from_utf8_lossy(value_data_ptr);
+
+        expect_handle
+            .lock()
+            .unwrap()
+            .staged
+            .get_expect_add_header_map_value(map_type, string_key, value_data_ptr);
+        host_handle.lock().unwrap().staged.add_header_map_value(
+            map_type,
+            string_key,
+            value_data_ptr,
+        );
+        host_handle.lock().unwrap().staged.track_upstream_override(
+            map_type,
+            string_key,
+            string_value,
+        );
+        println!("[vm->host] proxy_add_header_map_value(map_type={}, key_data={}, key_size={}, value_data={}, value_size={}) status: {:?}", 
+            map_type, string_key, string_key.len(), string_value, value_data_ptr.len(), get_status(&status_handle)
+        );
+    }
+    println!(
+        "[vm<-host] proxy_add_header_map_value(...) return: {:?}",
+        Status::Ok
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
 
-        /* ---------------------------------- Metrics ---------------------------------- */
-        "proxy_define_metric" => {
-            Some(Func::wrap(&store, |_caller: Caller<'_>| -> i32 {
-                // Default Function:
-                // Expectation:
-                println!(
-                    "[vm->host] proxy_define_metric() -> (...) status: {:?}",
-                    get_status()
-                );
-                println!(
-                    "[vm<-host] proxy_define_metric() -> (..) return: {:?}",
-                    Status::InternalFailure
-                );
-                return Status::InternalFailure as i32;
-            }))
+/* ---------------------------------- Buffer ---------------------------------- */
+
+pub(crate) fn proxy_get_buffer_status(
+    caller: &mut dyn Guest,
+    _buffer_type: i32,
+    _length_ptr: i32,
+    _flags_ptr: i32,
+) -> i32 {
+    // Default Function:
+    // Expectation:
+    let HostState {
+        status: status_handle,
+        ..
+    } = caller.state().clone();
+    println!(
+        "[vm->host] proxy_get_buffer_status() -> (...) status: {:?}",
+        get_status(&status_handle)
+    );
+    println!(
+        "[vm<-host] proxy_get_buffer_status() -> (..) return: {:?}",
+        Status::InternalFailure
+    );
+    return Status::InternalFailure as i32;
+}
+
+pub(crate) fn proxy_get_buffer_bytes(
+    caller: &mut dyn Guest,
+    buffer_type: i32,
+    start: i32,
+    max_size: i32,
+    return_buffer_data: i32,
+    return_buffer_size: i32,
+) -> i32 {
+    // Default Function: return the default buffer_bytes if they are of length max_size - start, the
+    // buffer as modified by the module if it called proxy_set_buffer_bytes, or random buffer_bytes otherwise
+    // Expectation: return buffer bytes set in expectation
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let mut mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_get_buffer_bytes cannot get export \"memory\"");
+            println!("[vm<-host] proxy_get_buffer_bytes(...) -> (return_buffer_data, return_buffer_size) return: {:?}", Status::InternalFailure);
+            return Status::InternalFailure as i32;
+        }
+    };
+
+    if !caller.has_allocator() {
+        println!("Error: proxy_get_buffer_bytes cannot get an allocator export");
+        println!("[vm<-host] proxy_get_buffer_bytes(...) -> (return_buffer_data, return_buffer_size) return: {:?}", Status::InternalFailure);
+        return Status::InternalFailure as i32;
+    }
+
+    // sizes are u32 in the ABI, so a wrapped i32 subtraction would hide oversized requests
+    let requested_size = match (max_size as u32).checked_sub(start as u32) {
+        Some(requested_size) if requested_size as usize <= mem.data_size() => {
+            requested_size as usize
         }
+        _ => {
+            println!(
+                "Error: proxy_get_buffer_bytes start={} max_size={} is out of bounds",
+                start as u32, max_size as u32
+            );
+            println!("[vm<-host] proxy_get_buffer_bytes(...) -> (return_buffer_data, return_buffer_size) return: {:?}", Status::BadArgument);
+            return Status::BadArgument as i32;
+        }
+    };
+
+    let response_body = match expect_handle
+        .lock()
+        .unwrap()
+        .staged
+        .get_expect_get_buffer_bytes(buffer_type)
+    {
+        Some(expect_buffer_bytes) => {
+            assert_le!(expect_buffer_bytes.len(), requested_size);
+            expect_buffer_bytes
+        }
+        None => {
+            let buffer_bytes: Bytes;
+            let host = host_handle.lock().unwrap();
+            let host_buffer_bytes = host.staged.get_buffer_bytes(buffer_type);
+            if host_buffer_bytes.len() == requested_size {
+                buffer_bytes = host_buffer_bytes;
+            } else if host.staged.is_buffer_modified(buffer_type) {
+                buffer_bytes = host_buffer_bytes
+                    .into_iter()
+                    .skip(start as u32 as usize)
+                    .take(max_size as u32 as usize)
+                    .collect();
+            } else {
+                buffer_bytes = serial_utils::generate_random_string(requested_size)
+                    .as_bytes()
+                    .to_vec();
+            }
+            buffer_bytes
+        }
+    };
+
+    unsafe {
+        // allocate memory and store buffer bytes
+        let buffer_data_add =
+            allocate(caller, &mut mem, response_body.len() as i32).unwrap() as u32 as usize;
+        if buffer_data_add == 0 {
+            println!(
+                "Error: proxy_get_buffer_bytes failed to allocate {} bytes",
+                response_body.len()
+            );
+            println!("[vm<-host] proxy_get_buffer_bytes(...) -> (return_buffer_data, return_buffer_size) return: {:?}", Status::InternalFailure);
+            return Status::InternalFailure as i32;
+        }
+        let buffer_data_ptr = mem
+            .data_unchecked_mut()
+            .get_unchecked_mut(buffer_data_add..buffer_data_add + response_body.len());
+        buffer_data_ptr.copy_from_slice(&response_body);
+
+        let return_buffer_size_ptr = mem.data_unchecked_mut().get_unchecked_mut(
+            return_buffer_size as u32 as usize..return_buffer_size as u32 as usize + 4,
+        );
+
+        let return_buffer_data_ptr = mem.data_unchecked_mut().get_unchecked_mut(
+            return_buffer_data as u32 as usize..return_buffer_data as u32 as usize + 4,
+        );
 
-        "proxy_increment_metric" => {
-            Some(Func::wrap(&store, |_caller: Caller<'_>| -> i32 {
-                // Default Function:
-                // Expectation:
+        return_buffer_size_ptr.copy_from_slice(&(response_body.len() as u32).to_le_bytes());
+        return_buffer_data_ptr.copy_from_slice(&(buffer_data_add as u32).to_le_bytes());
+    }
+    println!(
+        "[vm->host] proxy_get_buffer_bytes(buffer_type={}, start={}, max_size={}) -> (...) status: {:?}",
+        buffer_type, start, max_size, get_status(&status_handle)
+    );
+    println!(
+        "[vm<-host] proxy_get_buffer_bytes(...) -> (return_buffer_data, return_buffer_size) return: {:?}", Status::Ok
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
+
+pub(crate) fn proxy_set_buffer_bytes(
+    caller: &mut dyn Guest,
+    buffer_type: i32,
+    start: i32,
+    size: i32,
+    buffer_data: i32,
+    buffer_size: i32,
+) -> i32 {
+    // Default Function: replace [start, start + size) of the buffer with the received buffer data
+    // Expectation: assert that the received buffer bytes is as expected
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_set_buffer_bytes cannot get export \"memory\"");
+            println!(
+                "[vm<-host] proxy_set_buffer_bytes(...) return: {:?}",
+                Status::InternalFailure
+            );
+            return Status::InternalFailure as i32;
+        }
+    };
+
+    let buffer_data_range =
+        match serial_utils::guest_range(buffer_data, buffer_size, mem.data_size()) {
+            Some(buffer_data_range) => buffer_data_range,
+            None => {
                 println!(
-                    "[vm->host] proxy_increment_metric() -> (...) status: {:?}",
-                    get_status()
-                );
+                "Error: proxy_set_buffer_bytes start={} size={} buffer_size={} is out of bounds",
+                start as u32, size as u32, buffer_size as u32
+            );
                 println!(
-                    "[vm<-host] proxy_increment_metric() -> (..) return: {:?}",
-                    Status::InternalFailure
+                    "[vm<-host] proxy_set_buffer_bytes(...) return: {:?}",
+                    Status::BadArgument
                 );
-                return Status::InternalFailure as i32;
-            }))
+                return Status::BadArgument as i32;
+            }
+        };
+
+    let return_status = unsafe {
+        let buffer_data_ptr = mem.data_unchecked().get_unchecked(buffer_data_range);
+
+        expect_handle
+            .lock()
+            .unwrap()
+            .staged
+            .get_expect_set_buffer_bytes(buffer_type, buffer_data_ptr);
+        host_handle.lock().unwrap().staged.splice_buffer_bytes(
+            buffer_type,
+            start as u32 as usize,
+            size as u32 as usize,
+            buffer_data_ptr,
+        )
+    };
+    println!(
+        "[vm<-host] proxy_set_buffer_bytes(buffer_type={},
+            start={},
+            size={},
+            buffer_data,
+            buffer_size) status: {:?}",
+        buffer_type,
+        start,
+        size,
+        get_status(&status_handle)
+    );
+    println!(
+        "[vm<-host] proxy_set_buffer_bytes(...) return: {:?}",
+        return_status
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return return_status as i32;
+}
+
+/* ---------------------------------- HTTP ---------------------------------- */
+
+pub(crate) fn proxy_http_call(
+    caller: &mut dyn Guest,
+    upstream_data: i32,
+    upstream_size: i32,
+    headers_data: i32,
+    headers_size: i32,
+    body_data: i32,
+    body_size: i32,
+    trailers_data: i32,
+    trailers_size: i32,
+    timeout: i32,
+    return_token: i32,
+) -> i32 {
+    // Default Function: receives and displays http call from proxy-wasm module
+    // Expectation: asserts equal the receieved http call with the expected one
+    // and queues proxy_on_http_call_response if it was given a response
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_http_call cannot get export \"memory\"");
+            println!(
+                "[vm<-host] proxy_http_call(...) -> (return_token) return: {:?}",
+                Status::InternalFailure
+            );
+            return Status::InternalFailure as i32;
+        }
+    };
+
+    // expectation description not implemented yet
+    unsafe {
+        let upstream_data_ptr = mem
+            .data_unchecked()
+            .get(upstream_data as u32 as usize..)
+            .and_then(|arr| arr.get(..upstream_size as u32 as usize));
+        let string_upstream = upstream_data_ptr
+            .map(|string_msg| std::str::from_utf8(string_msg).unwrap())
+            .unwrap();
+
+        let mut string_body: Option<&str> = None;
+        if body_size > 0 {
+            let body_data_ptr = mem
+                .data_unchecked()
+                .get(body_data as u32 as usize..)
+                .and_then(|arr| arr.get(..body_size as u32 as usize));
+            string_body = body_data_ptr.map(|string_msg| std::str::from_utf8(string_msg).unwrap());
         }
 
-        "proxy_record_metric" => {
-            Some(Func::wrap(&store, |_caller: Caller<'_>| -> i32 {
-                // Default Function:
-                // Expectation:
-                println!(
-                    "[vm->host] proxy_record_metric() -> (...) status: {:?}",
-                    get_status()
-                );
+        let header_data_ptr = mem.data_unchecked().get_unchecked(
+            headers_data as u32 as usize
+                ..headers_data as u32 as usize + headers_size as u32 as usize,
+        );
+        let deserialized_header = serial_utils::deserialize_map(header_data_ptr);
+
+        let trailer_data_ptr = mem.data_unchecked().get_unchecked(
+            trailers_data as u32 as usize
+                ..trailers_data as u32 as usize + trailers_size as u32 as usize,
+        );
+        let deserialized_trailer = serial_utils::deserialize_map(trailer_data_ptr);
+
+        let token_id = match expect_handle.lock().unwrap().staged.get_expect_http_call(
+            string_upstream,
+            header_data_ptr,
+            string_body,
+            trailer_data_ptr,
+            Duration::from_millis(timeout as u32 as u64),
+        ) {
+            Some(expect_token) => expect_token,
+            None => 0,
+        };
+        host_handle
+            .lock()
+            .unwrap()
+            .staged
+            .dispatch_http_call_response(token_id);
+
+        let return_token_add = mem
+            .data_unchecked_mut()
+            .get_unchecked_mut(return_token as u32 as usize..return_token as u32 as usize + 4);
+        return_token_add.copy_from_slice(&token_id.to_le_bytes());
+        println!(
+            "[vm->host] proxy_http_call(upstream_data={:?}, upstream_size={}",
+            string_upstream,
+            string_upstream.len()
+        );
+        println!(
+            "                           headers_data={:?}, headers_size={}",
+            deserialized_header, headers_size
+        );
+        println!(
+            "                           body_data={}, body_size={}",
+            string_body.unwrap_or("None"),
+            string_body.map_or(0, |data| data.len())
+        );
+        println!(
+            "                           trailers_data={:?}, trailers_size={}",
+            deserialized_trailer, trailers_size
+        );
+        println!(
+            "                           timeout) -> (...) status: {:?}",
+            get_status(&status_handle)
+        );
+        println!(
+            "[vm<-host] proxy_http_call(...) -> (return_token={}) return: {:?}",
+            token_id,
+            Status::Ok
+        );
+    }
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
+
+/* ---------------------------------- gRPC ---------------------------------- */
+
+pub(crate) fn proxy_grpc_call(
+    caller: &mut dyn Guest,
+    service_ptr: i32,
+    service_size: i32,
+    service_name_ptr: i32,
+    service_name_size: i32,
+    method_name_ptr: i32,
+    method_name_size: i32,
+    initial_metadata_ptr: i32,
+    initial_metadata_size: i32,
+    request_ptr: i32,
+    request_size: i32,
+    timeout_milliseconds: i32,
+    token_ptr: i32,
+) -> i32 {
+    // Default Function: receives and displays the grpc call, responding with a new token
+    // Expectation: asserts equal the received grpc call with the expected one
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_grpc_call cannot get export \"memory\"");
+            println!(
+                "[vm<-host] proxy_grpc_call(...) -> (token_ptr) return: {:?}",
+                Status::InternalFailure
+            );
+            return Status::InternalFailure as i32;
+        }
+    };
+
+    let memory_size = mem.data_size();
+    let ranges = (
+        serial_utils::guest_range(service_ptr, service_size, memory_size),
+        serial_utils::guest_range(service_name_ptr, service_name_size, memory_size),
+        serial_utils::guest_range(method_name_ptr, method_name_size, memory_size),
+        serial_utils::guest_range(initial_metadata_ptr, initial_metadata_size, memory_size),
+        serial_utils::guest_range(request_ptr, request_size, memory_size),
+    );
+    let (service_range, service_name_range, method_name_range, metadata_range, request_range) =
+        match ranges {
+            (Some(a), Some(b), Some(c), Some(d), Some(e)) => (a, b, c, d, e),
+            _ => {
                 println!(
-                    "[vm<-host] proxy_record_metric() -> (..) return: {:?}",
-                    Status::InternalFailure
+                    "[vm<-host] proxy_grpc_call(...) -> (token_ptr) return: {:?}",
+                    Status::BadArgument
                 );
-                return Status::InternalFailure as i32;
-            }))
+                return Status::BadArgument as i32;
+            }
+        };
+
+    unsafe {
+        let upstream =
+            String::from_utf8_lossy(mem.data_unchecked().get_unchecked(service_range)).to_string();
+        let service =
+            String::from_utf8_lossy(mem.data_unchecked().get_unchecked(service_name_range))
+                .to_string();
+        let method = String::from_utf8_lossy(mem.data_unchecked().get_unchecked(method_name_range))
+            .to_string();
+        let initial_metadata = mem.data_unchecked().get_unchecked(metadata_range).to_vec();
+        let message = mem.data_unchecked().get_unchecked(request_range).to_vec();
+        let timeout = Duration::from_millis(timeout_milliseconds as u32 as u64);
+
+        let expect_token = expect_handle.lock().unwrap().staged.get_expect_grpc_call(
+            &upstream,
+            &service,
+            &method,
+            &initial_metadata,
+            &message,
+            timeout,
+        );
+        let token_id = host_handle
+            .lock()
+            .unwrap()
+            .staged
+            .open_grpc_call(false, expect_token);
+
+        let token_ptr_add = mem
+            .data_unchecked_mut()
+            .get_unchecked_mut(token_ptr as u32 as usize..token_ptr as u32 as usize + 4);
+        token_ptr_add.copy_from_slice(&token_id.to_le_bytes());
+        println!(
+            "[vm->host] proxy_grpc_call(service={:?}, service_name={:?}, method_name={:?}",
+            upstream, service, method
+        );
+        println!(
+            "                           initial_metadata={:?}, request_size={}",
+            serial_utils::deserialize_map(&initial_metadata),
+            message.len()
+        );
+        println!(
+            "                           timeout_milliseconds={}) -> (...) status: {:?}",
+            timeout.as_millis(),
+            get_status(&status_handle)
+        );
+        println!(
+            "[vm<-host] proxy_grpc_call(...) -> (token_ptr={}) return: {:?}",
+            token_id,
+            Status::Ok
+        );
+    }
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
+
+pub(crate) fn proxy_grpc_stream(
+    caller: &mut dyn Guest,
+    service_ptr: i32,
+    service_size: i32,
+    service_name_ptr: i32,
+    service_name_size: i32,
+    method_name_ptr: i32,
+    method_name_size: i32,
+    initial_metadata_ptr: i32,
+    initial_metadata_size: i32,
+    token_ptr: i32,
+) -> i32 {
+    // Default Function: receives and displays the grpc stream, responding with a new token
+    // Expectation: asserts equal the received grpc stream with the expected one
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_grpc_stream cannot get export \"memory\"");
+            println!(
+                "[vm<-host] proxy_grpc_stream(...) -> (token_ptr) return: {:?}",
+                Status::InternalFailure
+            );
+            return Status::InternalFailure as i32;
+        }
+    };
+
+    let memory_size = mem.data_size();
+    let ranges = (
+        serial_utils::guest_range(service_ptr, service_size, memory_size),
+        serial_utils::guest_range(service_name_ptr, service_name_size, memory_size),
+        serial_utils::guest_range(method_name_ptr, method_name_size, memory_size),
+        serial_utils::guest_range(initial_metadata_ptr, initial_metadata_size, memory_size),
+    );
+    let (service_range, service_name_range, method_name_range, metadata_range) = match ranges {
+        (Some(a), Some(b), Some(c), Some(d)) => (a, b, c, d),
+        _ => {
+            println!(
+                "[vm<-host] proxy_grpc_stream(...) -> (token_ptr) return: {:?}",
+                Status::BadArgument
+            );
+            return Status::BadArgument as i32;
         }
+    };
+
+    unsafe {
+        let upstream =
+            String::from_utf8_lossy(mem.data_unchecked().get_unchecked(service_range)).to_string();
+        let service =
+            String::from_utf8_lossy(mem.data_unchecked().get_unchecked(service_name_range))
+                .to_string();
+        let method = String::from_utf8_lossy(mem.data_unchecked().get_unchecked(method_name_range))
+            .to_string();
+        let initial_metadata = mem.data_unchecked().get_unchecked(metadata_range).to_vec();
+
+        let expect_token = expect_handle.lock().unwrap().staged.get_expect_grpc_stream(
+            &upstream,
+            &service,
+            &method,
+            &initial_metadata,
+        );
+        let token_id = host_handle
+            .lock()
+            .unwrap()
+            .staged
+            .open_grpc_call(true, expect_token);
+
+        let token_ptr_add = mem
+            .data_unchecked_mut()
+            .get_unchecked_mut(token_ptr as u32 as usize..token_ptr as u32 as usize + 4);
+        token_ptr_add.copy_from_slice(&token_id.to_le_bytes());
+        println!(
+            "[vm->host] proxy_grpc_stream(service={:?}, service_name={:?}, method_name={:?}",
+            upstream, service, method
+        );
+        println!(
+            "                             initial_metadata={:?}) -> (...) status: {:?}",
+            serial_utils::deserialize_map(&initial_metadata),
+            get_status(&status_handle)
+        );
+        println!(
+            "[vm<-host] proxy_grpc_stream(...) -> (token_ptr={}) return: {:?}",
+            token_id,
+            Status::Ok
+        );
+    }
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
+
+pub(crate) fn proxy_grpc_cancel(caller: &mut dyn Guest, token: i32) -> i32 {
+    // Default Function: stop tracking the grpc call or stream
+    // Expectation: assert that the cancelled token is the expected one
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    expect_handle
+        .lock()
+        .unwrap()
+        .staged
+        .get_expect_grpc_cancel(token as u32);
+    let return_status = host_handle
+        .lock()
+        .unwrap()
+        .staged
+        .close_grpc_call(token as u32);
+    println!(
+        "[vm->host] proxy_grpc_cancel(token={}) status: {:?}",
+        token as u32,
+        get_status(&status_handle)
+    );
+    println!(
+        "[vm<-host] proxy_grpc_cancel(...) return: {:?}",
+        return_status
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return return_status as i32;
+}
 
-        "proxy_get_metric" => {
-            Some(Func::wrap(&store, |_caller: Caller<'_>| -> i32 {
-                // Default Function:
-                // Expectation:
+pub(crate) fn proxy_grpc_close(caller: &mut dyn Guest, token: i32) -> i32 {
+    // Default Function: stop tracking the grpc call or stream
+    // Expectation: assert that the closed token is the expected one
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    expect_handle
+        .lock()
+        .unwrap()
+        .staged
+        .get_expect_grpc_close(token as u32);
+    let return_status = host_handle
+        .lock()
+        .unwrap()
+        .staged
+        .close_grpc_call(token as u32);
+    println!(
+        "[vm->host] proxy_grpc_close(token={}) status: {:?}",
+        token as u32,
+        get_status(&status_handle)
+    );
+    println!(
+        "[vm<-host] proxy_grpc_close(...) return: {:?}",
+        return_status
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return return_status as i32;
+}
+
+pub(crate) fn proxy_grpc_send(
+    caller: &mut dyn Guest,
+    token: i32,
+    message_ptr: i32,
+    message_size: i32,
+    end_of_stream: i32,
+) -> i32 {
+    // Default Function: record the message sent on the grpc stream
+    // Expectation: assert that the token, message and end_of_stream are as expected
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_grpc_send cannot get export \"memory\"");
+            println!(
+                "[vm<-host] proxy_grpc_send(...) return: {:?}",
+                Status::InternalFailure
+            );
+            return Status::InternalFailure as i32;
+        }
+    };
+
+    let message_range = match serial_utils::guest_range(message_ptr, message_size, mem.data_size())
+    {
+        Some(message_range) => message_range,
+        None => {
+            println!(
+                "[vm<-host] proxy_grpc_send(...) return: {:?}",
+                Status::BadArgument
+            );
+            return Status::BadArgument as i32;
+        }
+    };
+
+    let message = unsafe { mem.data_unchecked().get_unchecked(message_range).to_vec() };
+    expect_handle.lock().unwrap().staged.get_expect_grpc_send(
+        token as u32,
+        &message,
+        end_of_stream != 0,
+    );
+    let return_status = host_handle
+        .lock()
+        .unwrap()
+        .staged
+        .send_grpc_message(token as u32, &message);
+    println!(
+        "[vm->host] proxy_grpc_send(token={}, message_size={}, end_of_stream={}) status: {:?}",
+        token as u32,
+        message_size,
+        end_of_stream != 0,
+        get_status(&status_handle)
+    );
+    println!(
+        "[vm<-host] proxy_grpc_send(...) return: {:?}",
+        return_status
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return return_status as i32;
+}
+
+/* ---------------------------------- Metrics ---------------------------------- */
+
+pub(crate) fn proxy_define_metric(
+    caller: &mut dyn Guest,
+    metric_type: i32,
+    name_data: i32,
+    name_size: i32,
+    return_id: i32,
+) -> i32 {
+    // Default Function: register the metric and respond with its metric_id
+    // Expectation: assert that the received metric type and name are as expected
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_define_metric cannot get export \"memory\"");
+            println!(
+                "[vm<-host] proxy_define_metric(...) -> (return_id) return: {:?}",
+                Status::InternalFailure
+            );
+            return Status::InternalFailure as i32;
+        }
+    };
+
+    unsafe {
+        let name_data_ptr = mem
+            .data_unchecked()
+            .get(name_data as u32 as usize..)
+            .and_then(|arr| arr.get(..name_size as u32 as usize));
+        let string_name = name_data_ptr
+            .map(|string_msg| std::str::from_utf8(string_msg).unwrap())
+            .unwrap();
+
+        let expect_metric_id = expect_handle
+            .lock()
+            .unwrap()
+            .staged
+            .get_expect_define_metric(metric_type, string_name);
+        let metric_id = host_handle.lock().unwrap().staged.define_metric(
+            metric_type,
+            string_name,
+            expect_metric_id,
+        );
+
+        let return_id_ptr = mem
+            .data_unchecked_mut()
+            .get_unchecked_mut(return_id as u32 as usize..return_id as u32 as usize + 4);
+        return_id_ptr.copy_from_slice(&metric_id.to_le_bytes());
+
+        println!("[vm->host] proxy_define_metric(metric_type={}, name_data={}, name_size={}) -> (...) status: {:?}",
+            metric_type, string_name, name_size, get_status(&status_handle)
+        );
+        println!(
+            "[vm<-host] proxy_define_metric(...) -> (return_id={}) return: {:?}",
+            metric_id,
+            Status::Ok
+        );
+    }
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
+
+pub(crate) fn proxy_increment_metric(caller: &mut dyn Guest, metric_id: i32, offset: i64) -> i32 {
+    // Default Function: accumulate the offset into the registered metric
+    // Expectation: assert that the received metric_id and offset are as expected
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    expect_handle
+        .lock()
+        .unwrap()
+        .staged
+        .get_expect_increment_metric(metric_id as u32, offset);
+    let return_status = host_handle
+        .lock()
+        .unwrap()
+        .staged
+        .increment_metric(metric_id as u32, offset);
+    println!(
+        "[vm->host] proxy_increment_metric(metric_id={}, offset={}) status: {:?}",
+        metric_id as u32,
+        offset,
+        get_status(&status_handle)
+    );
+    println!(
+        "[vm<-host] proxy_increment_metric(...) return: {:?}",
+        return_status
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return return_status as i32;
+}
+
+pub(crate) fn proxy_record_metric(caller: &mut dyn Guest, metric_id: i32, value: i64) -> i32 {
+    // Default Function: record the value into the registered metric
+    // Expectation: assert that the received metric_id and value are as expected
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    expect_handle
+        .lock()
+        .unwrap()
+        .staged
+        .get_expect_record_metric(metric_id as u32, value as u64);
+    let return_status = host_handle
+        .lock()
+        .unwrap()
+        .staged
+        .record_metric(metric_id as u32, value as u64);
+    println!(
+        "[vm->host] proxy_record_metric(metric_id={}, value={}) status: {:?}",
+        metric_id as u32,
+        value as u64,
+        get_status(&status_handle)
+    );
+    println!(
+        "[vm<-host] proxy_record_metric(...) return: {:?}",
+        return_status
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return return_status as i32;
+}
+
+pub(crate) fn proxy_get_metric(caller: &mut dyn Guest, metric_id: i32, return_value: i32) -> i32 {
+    // Default Function: respond with the value of the registered metric
+    // Expectation: respond with the expected value for the given metric_id
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_get_metric cannot get export \"memory\"");
+            println!(
+                "[vm<-host] proxy_get_metric(...) -> (return_value) return: {:?}",
+                Status::InternalFailure
+            );
+            return Status::InternalFailure as i32;
+        }
+    };
+
+    let value = match expect_handle
+        .lock()
+        .unwrap()
+        .staged
+        .get_expect_get_metric(metric_id as u32)
+    {
+        Some(expect_value) => expect_value,
+        None => match host_handle
+            .lock()
+            .unwrap()
+            .staged
+            .get_metric(metric_id as u32)
+        {
+            Some(host_value) => host_value as u64,
+            None => {
                 println!(
-                    "[vm->host] proxy_get_metric() -> (...) status: {:?}",
-                    get_status()
+                    "[vm->host] proxy_get_metric(metric_id={}) -> (...) status: {:?}",
+                    metric_id as u32,
+                    get_status(&status_handle)
                 );
                 println!(
-                    "[vm<-host] proxy_get_metric() -> (..) return: {:?}",
-                    Status::InternalFailure
+                    "[vm<-host] proxy_get_metric(...) -> (return_value) return: {:?}",
+                    Status::NotFound
                 );
-                return Status::InternalFailure as i32;
-            }))
+                assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+                set_status(&status_handle, ExpectStatus::Unexpected);
+                return Status::NotFound as i32;
+            }
+        },
+    };
+
+    unsafe {
+        let return_value_ptr = mem
+            .data_unchecked_mut()
+            .get_unchecked_mut(return_value as u32 as usize..return_value as u32 as usize + 8);
+        return_value_ptr.copy_from_slice(&value.to_le_bytes());
+    }
+    println!(
+        "[vm->host] proxy_get_metric(metric_id={}) -> (...) status: {:?}",
+        metric_id as u32,
+        get_status(&status_handle)
+    );
+    println!(
+        "[vm<-host] proxy_get_metric(...) -> (return_value={}) return: {:?}",
+        value,
+        Status::Ok
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return Status::Ok as i32;
+}
+
+/* ---------------------------------- System ---------------------------------- */
+
+pub(crate) fn proxy_set_effective_context(caller: &mut dyn Guest, context_id: i32) -> i32 {
+    // Default Function: switch the effective context if the context has been created
+    // Expectation: assert that the context switched to is the expected one
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    expect_handle
+        .lock()
+        .unwrap()
+        .staged
+        .get_expect_set_effective_context(context_id);
+    let return_status = host_handle
+        .lock()
+        .unwrap()
+        .staged
+        .switch_effective_context(context_id);
+    println!(
+        "[vm->host] proxy_set_effective_context(context_id={}) status: {:?}",
+        context_id,
+        get_status(&status_handle)
+    );
+    println!(
+        "[vm<-host] proxy_set_effective_context(...) return: {:?}",
+        return_status
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return return_status as i32;
+}
+
+pub(crate) fn proxy_done(caller: &mut dyn Guest) -> i32 {
+    // Default Function: mark the effective context (pending after proxy_on_done returned false) as done
+    // Expectation: assert that the context signalling completion is the expected one
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let context_id = host_handle
+        .lock()
+        .unwrap()
+        .staged
+        .get_effective_context_id();
+    expect_handle
+        .lock()
+        .unwrap()
+        .staged
+        .get_expect_proxy_done(context_id);
+    let return_status = if host_handle.lock().unwrap().staged.set_done(context_id) {
+        Status::Ok
+    } else {
+        Status::NotFound
+    };
+    println!(
+        "[vm->host] proxy_done() effective_context_id={} status: {:?}",
+        context_id,
+        get_status(&status_handle)
+    );
+    println!("[vm<-host] proxy_done() return: {:?}", return_status);
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return return_status as i32;
+}
+
+pub(crate) fn proxy_call_foreign_function(
+    caller: &mut dyn Guest,
+    function_name: i32,
+    function_name_size: i32,
+    arguments: i32,
+    arguments_size: i32,
+    return_results: i32,
+    return_results_size: i32,
+) -> i32 {
+    // Default Function: serve the foreign functions Envoy registers (clear_route_cache)
+    // Expectation: assert that the function name and arguments are as expected and respond with the expected results
+    let HostState {
+        host: host_handle,
+        expect: expect_handle,
+        status: status_handle,
+    } = caller.state().clone();
+    let mut mem = match caller.memory() {
+        Some(mem) => mem,
+        _ => {
+            println!("Error: proxy_call_foreign_function cannot get export \"memory\"");
+            println!(
+                "[vm<-host] proxy_call_foreign_function(...) -> (return_results, return_results_size) return: {:?}",
+                Status::InternalFailure
+            );
+            return Status::InternalFailure as i32;
         }
+    };
+
+    if !caller.has_allocator() {
+        println!("Error: proxy_call_foreign_function cannot get an allocator export");
+        println!(
+            "[vm<-host] proxy_call_foreign_function(...) -> (return_results, return_results_size) return: {:?}",
+            Status::InternalFailure
+        );
+        return Status::InternalFailure as i32;
+    }
 
-        /* ---------------------------------- System ---------------------------------- */
-        "proxy_set_effective_context" => {
-            Some(Func::wrap(
-                &store,
-                |_caller: Caller<'_>, context_id: i32| -> i32 {
-                    // Default Function:
-                    // Expectation:
-                    println!(
-                        "[vm->host] proxy_set_effective_context(context_id={}) status: {:?}",
-                        context_id,
-                        get_status()
-                    );
+    let (function_name_range, arguments_range) = match (
+        serial_utils::guest_range(function_name, function_name_size, mem.data_size()),
+        serial_utils::guest_range(arguments, arguments_size, mem.data_size()),
+    ) {
+        (Some(function_name_range), Some(arguments_range)) => {
+            (function_name_range, arguments_range)
+        }
+        _ => {
+            println!(
+                "[vm<-host] proxy_call_foreign_function(...) -> (return_results, return_results_size) return: {:?}",
+                Status::BadArgument
+            );
+            return Status::BadArgument as i32;
+        }
+    };
+
+    let (name, args) = unsafe {
+        (
+            String::from_utf8_lossy(mem.data_unchecked().get_unchecked(function_name_range))
+                .to_string(),
+            mem.data_unchecked().get_unchecked(arguments_range).to_vec(),
+        )
+    };
+    let results = match name.as_str() {
+        // clear_route_cache produces no results
+        "clear_route_cache" => {
+            clear_route_cache(&host_handle, &expect_handle);
+            Some(Vec::new())
+        }
+        _ => expect_handle
+            .lock()
+            .unwrap()
+            .staged
+            .get_expect_call_foreign_function(&name, &args),
+    };
+    let return_status = match results {
+        Some(results) => {
+            unsafe {
+                let results_data_add =
+                    allocate(caller, &mut mem, results.len() as i32).unwrap() as u32 as usize;
+                if results_data_add == 0 {
                     println!(
-                        "[vm->host] proxy_set_effective_context(...) return: {:?}",
-                        Status::Ok
+                        "Error: proxy_call_foreign_function failed to allocate {} bytes",
+                        results.len()
                     );
-                    assert_ne!(get_status(), ExpectStatus::Failed);
-                    set_status(ExpectStatus::Unexpected);
-                    return Status::Ok as i32;
-                },
-            ))
-        }
-
-        "proxy_done" => {
-            Some(Func::wrap(&store, |_caller: Caller<'_>| -> i32 {
-                // Default Function:
-                // Expectation:
-                println!("[vm->host] proxy_done() status: {:?}", get_status());
-                println!(
-                    "[vm->host] proxy_done() return: {:?}",
-                    Status::InternalFailure
+                    println!("[vm<-host] proxy_call_foreign_function(...) -> (return_results, return_results_size) return: {:?}", Status::InternalFailure);
+                    return Status::InternalFailure as i32;
+                }
+                let results_data_ptr = mem
+                    .data_unchecked_mut()
+                    .get_unchecked_mut(results_data_add..results_data_add + results.len());
+                results_data_ptr.copy_from_slice(&results);
+
+                let return_results_ptr = mem.data_unchecked_mut().get_unchecked_mut(
+                    return_results as u32 as usize..return_results as u32 as usize + 4,
                 );
-                return Status::InternalFailure as i32;
-            }))
-        }
-
-        "proxy_call_foreign_function" => Some(Func::wrap(
-            &store,
-            |_caller: Caller<'_>,
-             _function_name: i32,
-             _function_name_size: i32,
-             _arguments: i32,
-             _arguments_size: i32,
-             _results: i32,
-             _size_t: i32|
-             -> i32 {
-                println!(
-                    "[vm->host] proxy_call_foreign_function() status: {:?}",
-                    get_status()
+                return_results_ptr.copy_from_slice(&(results_data_add as u32).to_le_bytes());
+                let return_results_size_ptr = mem.data_unchecked_mut().get_unchecked_mut(
+                    return_results_size as u32 as usize..return_results_size as u32 as usize + 4,
                 );
-                println!(
-                    "[vm->host] proxy_call_foreign_function() return: {:?}",
-                    Status::InternalFailure
-                );
-                return Status::InternalFailure as i32;
-            },
-        )),
+                return_results_size_ptr.copy_from_slice(&(results.len() as u32).to_le_bytes());
+            }
+            Status::Ok
+        }
+        None => Status::NotFound,
+    };
+    println!(
+        "[vm->host] proxy_call_foreign_function(function_name={:?}, arguments_size={}) -> (...) status: {:?}",
+        name,
+        args.len(),
+        get_status(&status_handle)
+    );
+    println!(
+        "[vm<-host] proxy_call_foreign_function(...) -> (return_results, return_results_size) return: {:?}",
+        return_status
+    );
+    assert_ne!(get_status(&status_handle), ExpectStatus::Failed);
+    set_status(&status_handle, ExpectStatus::Unexpected);
+    return return_status as i32;
+}
+
+// Allocates guest memory and takes the view of it again, yielding a null pointer instead while
+// allocation failures are injected by the host settings
+fn allocate(caller: &mut dyn Guest, mem: &mut GuestMemory, size: i32) -> Result<i32> {
+    if caller
+        .state()
+        .host
+        .lock()
+        .unwrap()
+        .staged
+        .get_allocation_failure()
+    {
+        return Ok(0);
+    }
+    let address = caller.call_allocator(size)?;
+    *mem = caller
+        .memory()
+        .ok_or_else(|| anyhow!("module no longer exports \"memory\""))?;
+    Ok(address)
+}
+
+// Shared by proxy_clear_route_cache and the clear_route_cache foreign function
+fn clear_route_cache(host: &Mutex<HostHandle>, expect: &Mutex<ExpectHandle>) {
+    {
+        let mut host = host.lock().unwrap();
+        let context_id = host.staged.get_effective_context_id();
+        host.staged.clear_route_cache(context_id);
+    }
+    expect.lock().unwrap().staged.get_expect_clear_route_cache();
+}
 
+fn get_stream_type(stream_type: i32) -> Option<StreamType> {
+    match stream_type {
+        0 => Some(StreamType::HttpRequest),
+        1 => Some(StreamType::HttpResponse),
+        2 => Some(StreamType::Downstream),
+        3 => Some(StreamType::Upstream),
         _ => None,
     }
 }
@@ -1590,7 +3359,7 @@ pub mod serial_utils {
                             abcdefghijklmnopqrstuvwxyz\
                             0123456789)(*&^%$#@!~";
 
-    pub fn _serialize_property_path(path: Vec<&str>) -> Bytes {
+    pub fn serialize_property_path(path: Vec<&str>) -> Bytes {
         if path.is_empty() {
             return Vec::new();
         }
@@ -1607,7 +3376,26 @@ pub mod serial_utils {
         bytes
     }
 
+    // Wraps a message in the 5-byte grpc length-prefixed frame: a compressed flag followed by the
+    // big-endian message length
+    pub fn serialize_grpc_frame(message: &[u8], compressed: bool) -> Bytes {
+        let mut bytes: Bytes = Vec::with_capacity(5 + message.len());
+        bytes.push(compressed as u8);
+        bytes.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(message);
+        bytes
+    }
+
     pub fn serialize_map(map: Vec<(&str, &str)>) -> Bytes {
+        serialize_map_bytes(
+            map.into_iter()
+                .map(|(name, value)| (name.as_bytes(), value.as_bytes()))
+                .collect(),
+        )
+    }
+
+    // Keys and values of the map may be any bytes, e.g. the values of -bin headers
+    pub fn serialize_map_bytes(map: Vec<(&[u8], &[u8])>) -> Bytes {
         let mut size: usize = 4;
         for (name, value) in &map {
             size += name.len() + value.len() + 10;
@@ -1619,15 +3407,28 @@ pub mod serial_utils {
             bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
         }
         for (name, value) in &map {
-            bytes.extend_from_slice(&name.as_bytes());
+            bytes.extend_from_slice(name);
             bytes.push(0);
-            bytes.extend_from_slice(&value.as_bytes());
+            bytes.extend_from_slice(value);
             bytes.push(0);
         }
         bytes
     }
 
+    // Values that are not UTF-8 are replaced lossily, deserialize_map_bytes keeps them as they are
     pub fn deserialize_map(bytes: &[u8]) -> Vec<(String, String)> {
+        deserialize_map_bytes(bytes)
+            .into_iter()
+            .map(|(key, value)| {
+                (
+                    String::from_utf8_lossy(&key).to_string(),
+                    String::from_utf8_lossy(&value).to_string(),
+                )
+            })
+            .collect()
+    }
+
+    pub fn deserialize_map_bytes(bytes: &[u8]) -> Vec<(String, Bytes)> {
         let mut map = Vec::new();
         if bytes.is_empty() {
             return map;
@@ -1643,14 +3444,22 @@ pub mod serial_utils {
                 u32::from_le_bytes(<[u8; 4]>::try_from(&bytes[s + 4..s + 8]).unwrap()) as usize;
             let value = bytes[p..p + size].to_vec();
             p += size + 1;
-            map.push((
-                String::from_utf8(key).unwrap(),
-                String::from_utf8(value).unwrap(),
-            ));
+            map.push((String::from_utf8_lossy(&key).to_string(), value));
         }
         map
     }
 
+    // Host range of a guest (pointer, size) pair, both u32 in the ABI, or None if the range wraps
+    // around or runs past the end of linear memory
+    pub fn guest_range(ptr: i32, size: i32, memory_size: usize) -> Option<std::ops::Range<usize>> {
+        let start = ptr as u32 as usize;
+        let end = start.checked_add(size as u32 as usize)?;
+        if end > memory_size {
+            return None;
+        }
+        Some(start..end)
+    }
+
     pub fn generate_random_string(string_len: usize) -> String {
         let mut rng = rand::thread_rng();
         let random_string: String = (0..string_len)
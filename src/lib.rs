@@ -15,9 +15,19 @@
 #![crate_type = "lib"]
 #![crate_name = "proxy_wasm_test_framework"]
 
+pub mod adversarial;
+pub mod context_manager;
+pub mod prelude;
+pub mod presets;
+pub mod runner;
+pub mod runtime;
+pub mod scaffold;
 pub mod tester;
 pub mod types;
 pub mod utility;
+#[cfg(feature = "wasmer")]
+pub mod wasmer_runtime;
+pub mod workers;
 
 mod expect_interface;
 mod expectations;
@@ -0,0 +1,47 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Single import for tests: `use proxy_wasm_test_framework::prelude::*;`
+// Items stay available here even when the modules defining them are reorganized.
+
+pub use crate::context_manager::ContextManager;
+pub use crate::expect_interface::{
+    ExpectCallForeignFunction, ExpectDefineMetric, ExpectDequeueSharedQueue, ExpectGetBufferBytes,
+    ExpectGetCurrentTimeNanos, ExpectGetHeaderMapPairs, ExpectGetHeaderMapValue, ExpectGetMetric,
+    ExpectGetProperty, ExpectGetSharedData, ExpectGetStatus, ExpectGrpcCall, ExpectGrpcStream,
+    ExpectHttpCall, ExpectResolveSharedQueue,
+};
+pub use crate::expectations::Capture;
+pub use crate::presets::HostPreset;
+pub use crate::runner::run;
+pub use crate::settings_interface::{DefaultBufferBytes, DefaultHeaderMapPairs};
+pub use crate::tester::{
+    abi_size, check_abi_version, diagnose, mock, mock_precompiled, mock_with_abi, mock_with_config,
+    mock_workers, test_with_abi, MockSettings, TestContext, Tester,
+};
+pub use crate::types::*;
+pub use crate::workers::Workers;
+
+// Runs the module with wasmer instead of wasmtime
+#[cfg(feature = "wasmer")]
+pub use crate::tester::mock_wasmer;
+
+// Provides MockSettings::from_args()
+pub use structopt::StructOpt;
+
+// Params of Tester::call_export
+pub use wasmtime::Val;
+
+// Engine configuration of mock_with_config and TestContext::with_config
+pub use wasmtime::Config;
@@ -0,0 +1,183 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::types::*;
+
+// Default host state resembling what a given proxy hands to plugins, installed on a Tester with
+// set_default_preset
+#[derive(Debug, Default, Clone)]
+pub struct HostPreset {
+    pub header_map_pairs: Vec<(MapType, Vec<(String, String)>)>,
+    pub properties: Vec<(Vec<String>, PropertyValue)>,
+}
+
+impl HostPreset {
+    // Header maps of a GET proxied by Envoy over HTTP/1.1
+    pub fn envoy_http1() -> HostPreset {
+        HostPreset {
+            header_map_pairs: vec![
+                (
+                    MapType::HttpRequestHeaders,
+                    to_owned_pairs(vec![
+                        (":authority", "example.com"),
+                        (":path", "/"),
+                        (":method", "GET"),
+                        (":scheme", "http"),
+                        ("user-agent", "curl/7.68.0"),
+                        ("accept", "*/*"),
+                        ("x-forwarded-proto", "http"),
+                        ("x-request-id", "7b6e9a3c-5f1d-4e2a-9c8b-0d1e2f3a4b5c"),
+                        ("x-envoy-expected-rq-timeout-ms", "15000"),
+                    ]),
+                ),
+                (
+                    MapType::HttpResponseHeaders,
+                    to_owned_pairs(vec![
+                        (":status", "200"),
+                        ("content-type", "text/plain"),
+                        ("content-length", "0"),
+                        ("date", "Wed, 01 Jul 2020 00:00:00 GMT"),
+                        ("server", "envoy"),
+                        ("x-envoy-upstream-service-time", "1"),
+                    ]),
+                ),
+            ],
+            ..HostPreset::default()
+        }
+    }
+
+    // Header maps of a GET proxied by Envoy over HTTP/2, which arrives over TLS
+    pub fn envoy_http2() -> HostPreset {
+        HostPreset {
+            header_map_pairs: vec![
+                (
+                    MapType::HttpRequestHeaders,
+                    to_owned_pairs(vec![
+                        (":method", "GET"),
+                        (":scheme", "https"),
+                        (":authority", "example.com"),
+                        (":path", "/"),
+                        ("user-agent", "curl/7.68.0"),
+                        ("accept", "*/*"),
+                        ("x-forwarded-proto", "https"),
+                        ("x-request-id", "7b6e9a3c-5f1d-4e2a-9c8b-0d1e2f3a4b5c"),
+                        ("x-envoy-expected-rq-timeout-ms", "15000"),
+                    ]),
+                ),
+                (
+                    MapType::HttpResponseHeaders,
+                    to_owned_pairs(vec![
+                        (":status", "200"),
+                        ("content-type", "text/plain"),
+                        ("content-length", "0"),
+                        ("date", "Wed, 01 Jul 2020 00:00:00 GMT"),
+                        ("server", "envoy"),
+                        ("x-envoy-upstream-service-time", "1"),
+                    ]),
+                ),
+            ],
+            ..HostPreset::default()
+        }
+    }
+
+    // Envoy HTTP/1.1 preset as seen by a plugin in the sidecar of productpage calling reviews in an
+    // Istio mesh: node metadata properties, the outbound cluster of the upstream and the peer
+    // metadata exchange headers of both directions
+    pub fn istio_sidecar() -> HostPreset {
+        let mut preset = HostPreset::envoy_http1();
+        for (map_type, header_map_pairs) in preset.header_map_pairs.iter_mut() {
+            let (peer_id, peer_metadata) = match map_type {
+                MapType::HttpRequestHeaders => (ISTIO_NODE_ID, ISTIO_NODE_METADATA),
+                _ => (ISTIO_PEER_ID, ISTIO_PEER_METADATA),
+            };
+            header_map_pairs.extend(to_owned_pairs(vec![
+                ("x-envoy-peer-metadata-id", peer_id),
+                ("x-envoy-peer-metadata", peer_metadata),
+            ]));
+        }
+        header_map_pairs_push(
+            &mut preset,
+            MapType::HttpRequestHeaders,
+            vec![
+                ("x-b3-traceid", "80f198ee56343ba864fe8b2a57d3eff7"),
+                ("x-b3-spanid", "e457b5a2e4d86bd1"),
+                ("x-b3-sampled", "1"),
+            ],
+        );
+
+        let mut insert = |path: Vec<&str>, value: PropertyValue| {
+            preset
+                .properties
+                .push((path.into_iter().map(str::to_string).collect(), value));
+        };
+        insert(vec!["node", "id"], ISTIO_NODE_ID.into());
+        insert(vec!["node", "cluster"], "productpage.default".into());
+        insert(
+            vec!["node", "metadata", "NAME"],
+            "productpage-v1-6b746f74dc-9stvs".into(),
+        );
+        insert(vec!["node", "metadata", "NAMESPACE"], "default".into());
+        insert(vec!["node", "metadata", "CLUSTER_ID"], "Kubernetes".into());
+        insert(vec!["node", "metadata", "MESH_ID"], "cluster.local".into());
+        insert(
+            vec!["node", "metadata", "WORKLOAD_NAME"],
+            "productpage-v1".into(),
+        );
+        insert(
+            vec!["node", "metadata", "SERVICE_ACCOUNT"],
+            "bookinfo-productpage".into(),
+        );
+        insert(vec!["node", "metadata", "ISTIO_VERSION"], "1.6.0".into());
+        insert(
+            vec!["node", "metadata", "LABELS", "app"],
+            "productpage".into(),
+        );
+        insert(vec!["node", "metadata", "LABELS", "version"], "v1".into());
+        insert(vec!["listener_direction"], 2i64.into());
+        insert(
+            vec!["cluster_name"],
+            "outbound|9080||reviews.default.svc.cluster.local".into(),
+        );
+        insert(vec!["upstream", "address"], "10.44.0.12:9080".into());
+        preset
+    }
+}
+
+const ISTIO_NODE_ID: &str =
+    "sidecar~10.44.0.11~productpage-v1-6b746f74dc-9stvs.default~default.svc.cluster.local";
+const ISTIO_PEER_ID: &str =
+    "sidecar~10.44.0.12~reviews-v1-7d8f9c6b5-x2k4p.default~default.svc.cluster.local";
+// base64 serialized google.protobuf.Struct of the metadata of each proxy, as exchanged by Istio
+const ISTIO_NODE_METADATA: &str = "CikKBE5BTUUSIRofcHJvZHVjdHBhZ2UtdjEtNmI3NDZmNzRkYy05c3R2cwoWCglOQU1FU1BBQ0USCRoHZGVmYXVsdAohCg1XT1JLTE9BRF9OQU1FEhAaDnByb2R1Y3RwYWdlLXYxChoKB01FU0hfSUQSDxoNY2x1c3Rlci5sb2NhbA==";
+const ISTIO_PEER_METADATA: &str = "CiQKBE5BTUUSHBoacmV2aWV3cy12MS03ZDhmOWM2YjUteDJrNHAKFgoJTkFNRVNQQUNFEgkaB2RlZmF1bHQKHQoNV09SS0xPQURfTkFNRRIMGgpyZXZpZXdzLXYxChoKB01FU0hfSUQSDxoNY2x1c3Rlci5sb2NhbA==";
+
+fn header_map_pairs_push(preset: &mut HostPreset, map_type: MapType, pairs: Vec<(&str, &str)>) {
+    match preset
+        .header_map_pairs
+        .iter_mut()
+        .find(|(preset_map_type, _)| *preset_map_type == map_type)
+    {
+        Some((_, header_map_pairs)) => header_map_pairs.extend(to_owned_pairs(pairs)),
+        None => preset
+            .header_map_pairs
+            .push((map_type, to_owned_pairs(pairs))),
+    }
+}
+
+fn to_owned_pairs(pairs: Vec<(&str, &str)>) -> Vec<(String, String)> {
+    pairs
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
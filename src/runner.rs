@@ -0,0 +1,74 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::tester::MockSettings;
+
+use anyhow::Result;
+use std::env;
+use std::fs;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+// Set on the child processes spawned in watch mode so that they run the scenario a single time
+const WATCH_CHILD_ENV: &str = "PROXY_WASM_TEST_FRAMEWORK_WATCH_CHILD";
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// Runs the scenario once, or with --watch re-runs it every time the wasm file changes. Each run
+// happens in a fresh process so that a failing scenario neither stops the watcher nor leaves
+// state behind in the simulated host.
+pub fn run<F>(mock_settings: MockSettings, scenario: F) -> Result<()>
+where
+    F: FnOnce(MockSettings) -> Result<()>,
+{
+    if !mock_settings.watch || env::var_os(WATCH_CHILD_ENV).is_some() {
+        return scenario(mock_settings);
+    }
+
+    let mut last_modified = get_modified(&mock_settings.wasm_path);
+    loop {
+        let status = Command::new(env::current_exe()?)
+            .args(env::args_os().skip(1))
+            .env(WATCH_CHILD_ENV, "1")
+            .status()?;
+        if status.success() {
+            println!("[watch] scenario passed");
+        } else {
+            println!("[watch] scenario failed ({})", status);
+        }
+        println!("[watch] waiting for changes to {}", mock_settings.wasm_path);
+        last_modified = wait_for_change(&mock_settings.wasm_path, last_modified);
+    }
+}
+
+fn get_modified(wasm_path: &str) -> Option<SystemTime> {
+    fs::metadata(wasm_path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+// Blocks until the file has been modified and is no longer being written to
+fn wait_for_change(wasm_path: &str, last_modified: Option<SystemTime>) -> Option<SystemTime> {
+    loop {
+        thread::sleep(WATCH_POLL_INTERVAL);
+        let modified = get_modified(wasm_path);
+        if modified.is_none() || modified == last_modified {
+            continue;
+        }
+        thread::sleep(WATCH_POLL_INTERVAL);
+        if get_modified(wasm_path) == modified {
+            return modified;
+        }
+    }
+}
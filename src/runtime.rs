@@ -0,0 +1,266 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::hostcalls::{self, for_each_host_function, HostState};
+
+use anyhow::Result;
+use wasmtime::*;
+
+// Compiles a module from a .wasm binary, or from the text format for .wat fixtures such as
+// hand-written modules probing the host functions
+pub fn load_module(engine: &Engine, wasm_path: &str) -> Result<Module> {
+    if wasm_path.ends_with(".wat") {
+        let binary = wat::parse_file(wasm_path)?;
+        return Module::new(engine, &binary);
+    }
+    Module::from_file(engine, wasm_path)
+}
+
+// Compiles a module ahead of time into an artifact for load_precompiled, e.g. once per CI run
+pub fn precompile(engine: &Engine, wasm_path: &str, cwasm_path: &str) -> Result<()> {
+    let module = load_module(engine, wasm_path)?;
+    std::fs::write(cwasm_path, module.serialize()?)?;
+    Ok(())
+}
+
+// Loads a module compiled by precompile() without compiling it again. Unsafe as the artifact is
+// mapped as machine code without validation: it must come from precompile() with an engine of the
+// same configuration and wasmtime version, never from an untrusted source
+pub unsafe fn load_precompiled(engine: &Engine, cwasm_path: &str) -> Result<Module> {
+    assert!(
+        cwasm_path.ends_with(".cwasm"),
+        "Error: precompiled modules are expected to have the .cwasm extension, got {}",
+        cwasm_path
+    );
+    let artifact = std::fs::read(cwasm_path)?;
+    Module::deserialize(engine, &artifact)
+}
+
+// Execution layer the tester links the host functions and calls the exports of a module through:
+// wasmtime by default, wasmer with the wasmer feature
+pub trait WasmRuntime {
+    // Defines the host function an import of the module resolves to, returning false if the
+    // test-framework does not provide it
+    fn define_host_function(&mut self, module: &str, name: &str) -> Result<bool>;
+
+    // Instantiates the module against the host functions defined so far
+    fn instantiate(&mut self) -> Result<()>;
+
+    // Number of results of the export, or None if the module does not export it
+    fn get_export_results(&mut self, name: &str) -> Option<usize>;
+
+    fn call_export(&mut self, name: &str, params: &[Val]) -> Result<Vec<Val>>;
+
+    // Calls a proxy-wasm callback, all of which take i32 params and return nothing or an i32
+    fn call_void(&mut self, name: &str, params: &[i32]) -> Result<()>;
+
+    fn call_i32(&mut self, name: &str, params: &[i32]) -> Result<i32>;
+}
+
+// View of the memory exported by the module, which is invalidated by anything growing the memory:
+// take it again after calling into the module
+#[derive(Clone, Copy)]
+pub struct GuestMemory {
+    data: *mut u8,
+    size: usize,
+}
+
+impl GuestMemory {
+    pub fn new(data: *mut u8, size: usize) -> GuestMemory {
+        GuestMemory {
+            data: data,
+            size: size,
+        }
+    }
+
+    pub unsafe fn data_unchecked(&self) -> &[u8] {
+        std::slice::from_raw_parts(self.data, self.size)
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn data_unchecked_mut(&self) -> &mut [u8] {
+        std::slice::from_raw_parts_mut(self.data, self.size)
+    }
+
+    pub fn data_size(&self) -> usize {
+        self.size
+    }
+}
+
+// Access the host functions need to the module calling them, provided by each runtime
+pub trait Guest {
+    fn state(&self) -> &HostState;
+    fn memory(&mut self) -> Option<GuestMemory>;
+    fn has_allocator(&mut self) -> bool;
+    fn call_allocator(&mut self, size: i32) -> Result<i32>;
+}
+
+// Runs modules with wasmtime, the engine modules are also loaded and inspected with
+pub struct WasmtimeRuntime {
+    store: Store<HostState>,
+    linker: Linker<HostState>,
+    module: Module,
+    instance: Option<Instance>,
+}
+
+impl WasmtimeRuntime {
+    pub fn new(engine: &Engine, module: &Module, state: HostState) -> WasmtimeRuntime {
+        let mut linker = Linker::new(engine);
+        linker.allow_shadowing(true);
+        WasmtimeRuntime {
+            store: Store::new(engine, state),
+            linker: linker,
+            module: module.clone(),
+            instance: None,
+        }
+    }
+
+    // Signature of a host function defined for the module, to diagnose its imports
+    pub(crate) fn get_host_function_type(&mut self, module: &str, name: &str) -> Option<FuncType> {
+        match self.linker.get(&mut self.store, module, name) {
+            Some(Extern::Func(func)) => Some(func.ty(&self.store)),
+            _ => None,
+        }
+    }
+
+    fn get_func(&mut self, name: &str) -> Result<Func> {
+        let store = &mut self.store;
+        self.instance
+            .and_then(|instance| instance.get_func(store, name))
+            .ok_or(anyhow::format_err!(
+                "Error: failed to find `{}` function export",
+                name
+            ))
+    }
+
+    fn call_typed<R: WasmResults>(&mut self, name: &str, params: &[i32]) -> Result<R> {
+        let func = self.get_func(name)?;
+        let store = &mut self.store;
+        match *params {
+            [] => func.typed::<(), R>(&*store)?.call(store, ()),
+            [a] => func.typed::<i32, R>(&*store)?.call(store, a),
+            [a, b] => func.typed::<(i32, i32), R>(&*store)?.call(store, (a, b)),
+            [a, b, c] => func
+                .typed::<(i32, i32, i32), R>(&*store)?
+                .call(store, (a, b, c)),
+            [a, b, c, d] => func
+                .typed::<(i32, i32, i32, i32), R>(&*store)?
+                .call(store, (a, b, c, d)),
+            [a, b, c, d, e] => func
+                .typed::<(i32, i32, i32, i32, i32), R>(&*store)?
+                .call(store, (a, b, c, d, e)),
+            _ => Err(anyhow::format_err!(
+                "Error: `{}` called with {} params, more than any proxy-wasm callback takes",
+                name,
+                params.len()
+            )),
+        }
+    }
+}
+
+macro_rules! define_wasmtime_host_functions {
+    ($($name:ident($($param:ident: $ty:ty),*);)*) => {
+        fn define_wasmtime_host_function(
+            linker: &mut Linker<HostState>,
+            module: &str,
+            name: &str,
+        ) -> Result<bool> {
+            match name {
+                $(stringify!($name) => {
+                    linker.func_wrap(
+                        module,
+                        name,
+                        |mut caller: Caller<'_, HostState>, $($param: $ty),*| -> i32 {
+                            hostcalls::$name(&mut caller, $($param),*)
+                        },
+                    )?;
+                })*
+                _ => return Ok(false),
+            }
+            Ok(true)
+        }
+    };
+}
+
+for_each_host_function!(define_wasmtime_host_functions);
+
+impl WasmRuntime for WasmtimeRuntime {
+    fn define_host_function(&mut self, module: &str, name: &str) -> Result<bool> {
+        define_wasmtime_host_function(&mut self.linker, module, name)
+    }
+
+    fn instantiate(&mut self) -> Result<()> {
+        self.instance = Some(self.linker.instantiate(&mut self.store, &self.module)?);
+        Ok(())
+    }
+
+    fn get_export_results(&mut self, name: &str) -> Option<usize> {
+        let func = self.get_func(name).ok()?;
+        Some(func.ty(&self.store).results().len())
+    }
+
+    fn call_export(&mut self, name: &str, params: &[Val]) -> Result<Vec<Val>> {
+        let export = self.get_func(name)?;
+        let mut results = vec![Val::I32(0); export.ty(&self.store).results().len()];
+        export.call(&mut self.store, params, &mut results)?;
+        Ok(results)
+    }
+
+    fn call_void(&mut self, name: &str, params: &[i32]) -> Result<()> {
+        self.call_typed::<()>(name, params)
+    }
+
+    fn call_i32(&mut self, name: &str, params: &[i32]) -> Result<i32> {
+        self.call_typed::<i32>(name, params)
+    }
+}
+
+impl Guest for Caller<'_, HostState> {
+    fn state(&self) -> &HostState {
+        self.data()
+    }
+
+    fn memory(&mut self) -> Option<GuestMemory> {
+        match self.get_export("memory") {
+            Some(Extern::Memory(mem)) => Some(GuestMemory::new(
+                mem.data_ptr(&*self),
+                mem.data_size(&*self),
+            )),
+            _ => None,
+        }
+    }
+
+    fn has_allocator(&mut self) -> bool {
+        get_allocator(self).is_some()
+    }
+
+    fn call_allocator(&mut self, size: i32) -> Result<i32> {
+        match get_allocator(self) {
+            Some(allocate) => allocate.call(self, size),
+            None => Err(anyhow::format_err!("module exports no allocator")),
+        }
+    }
+}
+
+// proxy_on_memory_allocate, or malloc for modules that do not export it
+fn get_allocator(caller: &mut Caller<'_, HostState>) -> Option<TypedFunc<i32, i32>> {
+    let func = match caller
+        .get_export("proxy_on_memory_allocate")
+        .or_else(|| caller.get_export("malloc"))
+    {
+        Some(Extern::Func(func)) => func,
+        _ => return None,
+    };
+    func.typed::<i32, i32>(&*caller).ok()
+}
@@ -0,0 +1,519 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Generation of skeleton tests from a run of a proxy-wasm module in permissive recording mode
+
+use crate::hostcalls::serial_utils::deserialize_map;
+use crate::tester::{mock, MockSettings};
+use crate::types::*;
+
+use anyhow::Result;
+use std::time::Duration;
+use wasmtime::Val;
+
+// Runs the module against a sample http request and returns the source of a test that replays the
+// same calls, expecting every hostcall the module made along with the values it returned
+pub fn scaffold_http_request(
+    mock_settings: MockSettings,
+    request_headers: Vec<(&str, &str)>,
+    request_body: Option<&str>,
+) -> Result<String> {
+    let wasm_path = mock_settings.wasm_path.clone();
+    let mut mock_settings = mock_settings;
+    mock_settings.allow_unexpected = true;
+    let mut tester = mock(mock_settings)?;
+
+    let root_context = 1;
+    let http_context = 2;
+    tester
+        .call_start()
+        .call_proxy_on_context_create(root_context, 0)
+        .call_proxy_on_vm_start(root_context, 0)
+        .call_proxy_on_configure(root_context, 0)
+        .call_proxy_on_context_create(http_context, root_context)
+        .set_default_header_map_pairs(MapType::HttpRequestHeaders)
+        .returning(request_headers.clone())
+        .call_proxy_on_request_headers(
+            http_context,
+            request_headers.len() as i32,
+            request_body.is_none(),
+        );
+    if let Some(body) = request_body {
+        tester
+            .set_default_buffer_bytes(BufferType::HttpRequestBody)
+            .returning(body)
+            .call_proxy_on_request_body(http_context, body.len() as i32, true);
+    }
+
+    let tester_name = "module_test";
+    let mut source = String::new();
+    source += &format!(
+        "// Generated from a recorded run of {}, review the expectations before relying on them\n\n",
+        wasm_path
+    );
+    source += "use anyhow::Result;\n";
+    source += "use proxy_wasm_test_framework::tester;\n";
+    source += "use proxy_wasm_test_framework::types::*;\n";
+    source += "use structopt::StructOpt;\n\n";
+    source += "fn main() -> Result<()> {\n";
+    source += "    let args = tester::MockSettings::from_args();\n";
+    source += &format!("    let mut {} = tester::mock(args)?;\n\n", tester_name);
+    source += &format!(
+        "    {}\n        .set_default_header_map_pairs(MapType::HttpRequestHeaders)\n        .returning(vec!{:?});\n",
+        tester_name, request_headers
+    );
+    if let Some(body) = request_body {
+        source += &format!(
+            "    {}\n        .set_default_buffer_bytes(BufferType::HttpRequestBody)\n        .returning({:?});\n",
+            tester_name, body
+        );
+    }
+    while tester.pending_calls() > 0 {
+        source += "\n";
+        source += &tester.record_next(tester_name)?;
+    }
+    source += "\n    return Ok(());\n}\n";
+    Ok(source)
+}
+
+// Renders the staged call from the Debug representation of a FunctionCall, e.g.
+// "ProxyOnRequestHeaders(2, 5, false)" becomes ".call_proxy_on_request_headers(2, 5, false)"
+pub(crate) fn render_call(function_call: &str) -> String {
+    let (name, args) = match function_call.find('(') {
+        Some(index) => function_call.split_at(index),
+        None => (function_call, "()"),
+    };
+    let mut method = String::new();
+    let mut previous: Option<char> = None;
+    for c in name.chars() {
+        let is_boundary = c.is_ascii_uppercase()
+            || (c.is_ascii_digit() && !previous.map_or(false, |p| p.is_ascii_digit()));
+        if is_boundary && previous.is_some() {
+            method.push('_');
+        }
+        method.push(c.to_ascii_lowercase());
+        previous = Some(c);
+    }
+    format!(".call_{}{}", method, args)
+}
+
+// Renders a staged call_export, e.g. ".call_export("init", &[Val::I32(1)])"
+pub(crate) fn render_call_export(name: &str, params: &[Val]) -> String {
+    let params: Vec<String> = params
+        .iter()
+        .map(|param| format!("Val::{:?}", param))
+        .collect();
+    format!(".call_export({:?}, &[{}])", name, params.join(", "))
+}
+
+fn log_level_arg(log_level: i32) -> String {
+    let name = match log_level {
+        0 => "Trace",
+        1 => "Debug",
+        2 => "Info",
+        3 => "Warn",
+        4 => "Error",
+        5 => "Critical",
+        _ => return "None".to_string(),
+    };
+    format!("Some(LogLevel::{})", name)
+}
+
+fn buffer_type_arg(buffer_type: i32) -> String {
+    let name = match buffer_type {
+        0 => "HttpRequestBody",
+        1 => "HttpResponseBody",
+        2 => "DownstreamData",
+        3 => "UpstreamData",
+        4 => "HttpCallResponseBody",
+        5 => "GrpcReceiveBuffer",
+        6 => "VmConfiguration",
+        7 => "PluginConfiguration",
+        _ => return "None".to_string(),
+    };
+    format!("Some(BufferType::{})", name)
+}
+
+fn map_type_arg(map_type: i32) -> String {
+    let name = match map_type {
+        0 => "HttpRequestHeaders",
+        1 => "HttpRequestTrailers",
+        2 => "HttpResponseHeaders",
+        3 => "HttpResponseTrailers",
+        6 => "HttpCallResponseHeaders",
+        7 => "HttpCallResponseTrailers",
+        _ => return "None".to_string(),
+    };
+    format!("Some(MapType::{})", name)
+}
+
+fn header_map_arg(header_map: &[u8]) -> String {
+    format!("Some(vec!{:?})", deserialize_map(header_map))
+}
+
+/* ---------------------------------- Recorded Expectations ---------------------------------- */
+
+pub(crate) fn expect_log(log_level: i32, log_string: &str) -> String {
+    format!(
+        ".expect_log({}, Some({:?}))",
+        log_level_arg(log_level),
+        log_string
+    )
+}
+
+pub(crate) fn expect_get_status() -> String {
+    ".expect_get_status().returning(None, None)".to_string()
+}
+
+pub(crate) fn expect_set_tick_period(tick_period: Duration) -> String {
+    format!(
+        ".expect_set_tick_period_millis(Some({}))",
+        tick_period.as_millis()
+    )
+}
+
+pub(crate) fn expect_get_current_time() -> String {
+    ".expect_get_current_time_nanos().returning(None)".to_string()
+}
+
+pub(crate) fn expect_get_buffer_bytes(buffer_type: i32) -> String {
+    format!(
+        ".expect_get_buffer_bytes({}).returning(None)",
+        buffer_type_arg(buffer_type)
+    )
+}
+
+pub(crate) fn expect_set_buffer_bytes(buffer_type: i32, buffer_data: &[u8]) -> String {
+    match std::str::from_utf8(buffer_data) {
+        Ok(buffer_data) => format!(
+            ".expect_set_buffer_bytes({}, Some({:?}))",
+            buffer_type_arg(buffer_type),
+            buffer_data
+        ),
+        Err(_) => format!(
+            ".expect_set_buffer_data({}, Some(&{:?}))",
+            buffer_type_arg(buffer_type),
+            buffer_data
+        ),
+    }
+}
+
+pub(crate) fn expect_get_header_map_pairs(map_type: i32) -> String {
+    format!(
+        ".expect_get_header_map_pairs({}).returning(None)",
+        map_type_arg(map_type)
+    )
+}
+
+pub(crate) fn expect_set_header_map_pairs(map_type: i32, header_map_pairs: &[u8]) -> String {
+    format!(
+        ".expect_set_header_map_pairs({}, {})",
+        map_type_arg(map_type),
+        header_map_arg(header_map_pairs)
+    )
+}
+
+pub(crate) fn expect_get_header_map_value(map_type: i32, header_map_key: &str) -> String {
+    format!(
+        ".expect_get_header_map_value({}, Some({:?})).returning(None)",
+        map_type_arg(map_type),
+        header_map_key
+    )
+}
+
+pub(crate) fn expect_replace_header_map_value(
+    map_type: i32,
+    header_map_key: &str,
+    header_map_value: &[u8],
+) -> String {
+    match std::str::from_utf8(header_map_value) {
+        Ok(header_map_value) => format!(
+            ".expect_replace_header_map_value({}, Some({:?}), Some({:?}))",
+            map_type_arg(map_type),
+            header_map_key,
+            header_map_value
+        ),
+        Err(_) => format!(
+            ".expect_replace_header_map_value_bytes({}, Some({:?}), Some(&{:?}[..]))",
+            map_type_arg(map_type),
+            header_map_key,
+            header_map_value
+        ),
+    }
+}
+
+pub(crate) fn expect_remove_header_map_value(map_type: i32, header_map_key: &str) -> String {
+    format!(
+        ".expect_remove_header_map_value({}, Some({:?}))",
+        map_type_arg(map_type),
+        header_map_key
+    )
+}
+
+pub(crate) fn expect_add_header_map_value(
+    map_type: i32,
+    header_map_key: &str,
+    header_map_value: &[u8],
+) -> String {
+    match std::str::from_utf8(header_map_value) {
+        Ok(header_map_value) => format!(
+            ".expect_add_header_map_value({}, Some({:?}), Some({:?}))",
+            map_type_arg(map_type),
+            header_map_key,
+            header_map_value
+        ),
+        Err(_) => format!(
+            ".expect_add_header_map_value_bytes({}, Some({:?}), Some(&{:?}[..]))",
+            map_type_arg(map_type),
+            header_map_key,
+            header_map_value
+        ),
+    }
+}
+
+pub(crate) fn expect_send_local_response(
+    status_code: i32,
+    body: Option<&str>,
+    headers: &[u8],
+    grpc_status: i32,
+) -> String {
+    format!(
+        ".expect_send_local_response(Some({}), {:?}, {}, Some({}))",
+        status_code,
+        body,
+        header_map_arg(headers),
+        grpc_status
+    )
+}
+
+pub(crate) fn expect_http_call(
+    upstream: &str,
+    headers: &[u8],
+    body: Option<&str>,
+    trailers: &[u8],
+    timeout: Duration,
+) -> String {
+    format!(
+        ".expect_http_call(Some({:?}), {}, {:?}, {}, Some({})).returning(None)",
+        upstream,
+        header_map_arg(headers),
+        body,
+        header_map_arg(trailers),
+        timeout.as_millis()
+    )
+}
+
+pub(crate) fn expect_grpc_call(
+    upstream: &str,
+    service: &str,
+    method: &str,
+    initial_metadata: &[u8],
+    message: &[u8],
+    timeout: Duration,
+) -> String {
+    format!(
+        ".expect_grpc_call(Some({:?}), Some({:?}), Some({:?}), {}, Some(&{:?}[..]), Some({})).returning(None)",
+        upstream,
+        service,
+        method,
+        header_map_arg(initial_metadata),
+        message,
+        timeout.as_millis()
+    )
+}
+
+pub(crate) fn expect_grpc_stream(
+    upstream: &str,
+    service: &str,
+    method: &str,
+    initial_metadata: &[u8],
+) -> String {
+    format!(
+        ".expect_grpc_stream(Some({:?}), Some({:?}), Some({:?}), {}).returning(None)",
+        upstream,
+        service,
+        method,
+        header_map_arg(initial_metadata)
+    )
+}
+
+pub(crate) fn expect_grpc_send(token_id: u32, message: &[u8], end_of_stream: bool) -> String {
+    format!(
+        ".expect_grpc_send(Some({}), Some(&{:?}[..]), Some({}))",
+        token_id, message, end_of_stream
+    )
+}
+
+pub(crate) fn expect_grpc_cancel(token_id: u32) -> String {
+    format!(".expect_grpc_cancel(Some({}))", token_id)
+}
+
+pub(crate) fn expect_grpc_close(token_id: u32) -> String {
+    format!(".expect_grpc_close(Some({}))", token_id)
+}
+
+pub(crate) fn expect_set_effective_context(context_id: i32) -> String {
+    format!(".expect_set_effective_context(Some({}))", context_id)
+}
+
+pub(crate) fn expect_call_foreign_function(name: &str, args: &[u8]) -> String {
+    format!(
+        ".expect_call_foreign_function(Some({:?}), Some(&{:?}[..])).returning(None)",
+        name, args
+    )
+}
+
+pub(crate) fn expect_proxy_done(context_id: i32) -> String {
+    format!(".expect_proxy_done(Some({}))", context_id)
+}
+
+pub(crate) fn expect_clear_route_cache() -> String {
+    ".expect_clear_route_cache()".to_string()
+}
+
+pub(crate) fn expect_continue_request() -> String {
+    ".expect_continue_request()".to_string()
+}
+
+pub(crate) fn expect_continue_response() -> String {
+    ".expect_continue_response()".to_string()
+}
+
+pub(crate) fn expect_resume_downstream() -> String {
+    ".expect_resume_downstream()".to_string()
+}
+
+pub(crate) fn expect_resume_upstream() -> String {
+    ".expect_resume_upstream()".to_string()
+}
+
+fn stream_type_arg(stream_type: i32) -> String {
+    let name = match stream_type {
+        0 => "HttpRequest",
+        1 => "HttpResponse",
+        2 => "Downstream",
+        3 => "Upstream",
+        _ => return "None".to_string(),
+    };
+    format!("Some(StreamType::{})", name)
+}
+
+pub(crate) fn expect_continue_stream(stream_type: i32) -> String {
+    format!(".expect_continue_stream({})", stream_type_arg(stream_type))
+}
+
+pub(crate) fn expect_close_stream(stream_type: i32) -> String {
+    format!(".expect_close_stream({})", stream_type_arg(stream_type))
+}
+
+pub(crate) fn expect_register_shared_queue(name: &str) -> String {
+    format!(".expect_register_shared_queue(Some({:?}))", name)
+}
+
+pub(crate) fn expect_resolve_shared_queue(vm_id: &str, name: &str) -> String {
+    format!(
+        ".expect_resolve_shared_queue(Some({:?}), Some({:?})).returning(None)",
+        vm_id, name
+    )
+}
+
+pub(crate) fn expect_enqueue_shared_queue(queue_id: u32, data: &[u8]) -> String {
+    format!(
+        ".expect_enqueue_shared_queue(Some({}), Some({:?}))",
+        queue_id,
+        String::from_utf8_lossy(data)
+    )
+}
+
+pub(crate) fn expect_dequeue_shared_queue(queue_id: u32) -> String {
+    format!(
+        ".expect_dequeue_shared_queue(Some({})).returning(None)",
+        queue_id
+    )
+}
+
+fn property_path_arg(path: &[u8]) -> String {
+    let parts: Vec<String> = String::from_utf8_lossy(path)
+        .split('\0')
+        .map(|part| part.to_string())
+        .collect();
+    format!("Some(vec!{:?})", parts)
+}
+
+pub(crate) fn expect_get_property(path: &[u8]) -> String {
+    format!(
+        ".expect_get_property({}).returning(None)",
+        property_path_arg(path)
+    )
+}
+
+pub(crate) fn expect_set_property(path: &[u8], value: &[u8]) -> String {
+    format!(
+        ".expect_set_property({}, Some(&{:?}[..]))",
+        property_path_arg(path),
+        value
+    )
+}
+
+fn metric_type_arg(metric_type: i32) -> String {
+    let name = match metric_type {
+        0 => "Counter",
+        1 => "Gauge",
+        2 => "Histogram",
+        _ => return "None".to_string(),
+    };
+    format!("Some(MetricType::{})", name)
+}
+
+pub(crate) fn expect_define_metric(metric_type: i32, name: &str) -> String {
+    format!(
+        ".expect_define_metric({}, Some({:?})).returning(None)",
+        metric_type_arg(metric_type),
+        name
+    )
+}
+
+pub(crate) fn expect_increment_metric(metric_id: u32, offset: i64) -> String {
+    format!(
+        ".expect_increment_metric(Some({}), Some({}))",
+        metric_id, offset
+    )
+}
+
+pub(crate) fn expect_record_metric(metric_id: u32, value: u64) -> String {
+    format!(
+        ".expect_record_metric(Some({}), Some({}))",
+        metric_id, value
+    )
+}
+
+pub(crate) fn expect_get_metric(metric_id: u32) -> String {
+    format!(".expect_get_metric(Some({})).returning(None)", metric_id)
+}
+
+pub(crate) fn expect_get_shared_data(key: &str) -> String {
+    format!(
+        ".expect_get_shared_data(Some({:?})).returning(None, None)",
+        key
+    )
+}
+
+pub(crate) fn expect_set_shared_data(key: &str, value: &[u8], cas: u32) -> String {
+    format!(
+        ".expect_set_shared_data(Some({:?}), Some({:?}), Some({}))",
+        key,
+        String::from_utf8_lossy(value),
+        cas
+    )
+}
@@ -34,6 +34,14 @@ impl<'a> DefaultBufferBytes<'a> {
             .set_buffer_bytes(self.buffer_type, buffer_data);
         self.tester
     }
+
+    pub fn returning_bytes(&mut self, buffer_data: &[u8]) -> &mut Tester {
+        self.tester
+            .get_settings_handle()
+            .staged
+            .set_buffer_data(self.buffer_type, buffer_data);
+        self.tester
+    }
 }
 
 pub struct DefaultHeaderMapPairs<'a> {
@@ -56,4 +64,13 @@ impl<'a> DefaultHeaderMapPairs<'a> {
             .set_header_map_pairs(self.map_type, header_map_pairs);
         self.tester
     }
+
+    // Values may be any bytes, e.g. vec![("grpc-status-details-bin", &details[..])]
+    pub fn returning_bytes(&mut self, header_map_pairs: Vec<(&str, &[u8])>) -> &mut Tester {
+        self.tester
+            .get_settings_handle()
+            .staged
+            .set_header_map_data(self.map_type, header_map_pairs);
+        self.tester
+    }
 }
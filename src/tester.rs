@@ -12,15 +12,32 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::context_manager::ContextManager;
 use crate::expect_interface::*;
-use crate::expectations::ExpectHandle;
-use crate::host_settings::HostHandle;
-use crate::hostcalls::{generate_import_list, get_abi_version};
+use crate::expectations::{Capture, ExpectHandle, StringMatch};
+use crate::host_settings::{envoy_properties, HostHandle};
+use crate::hostcalls::{
+    check_imports, create_host_state, create_worker_host_states, detect_abi_version,
+    get_abi_mismatches, get_abi_version, get_missing_exports, link_host_functions,
+    serial_utils::{serialize_grpc_frame, serialize_property_path},
+    HostState,
+};
+use crate::presets::HostPreset;
+use crate::runtime::{load_module, load_precompiled, WasmRuntime, WasmtimeRuntime};
+use crate::scaffold;
 use crate::settings_interface::*;
 use crate::types::*;
+#[cfg(feature = "wasmer")]
+use crate::wasmer_runtime::WasmerRuntime;
+use crate::workers::Workers;
 
 use anyhow::Result;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::hash::{Hash, Hasher};
 use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use structopt::StructOpt;
 use wasmtime::*;
 
@@ -36,34 +53,243 @@ pub struct MockSettings {
     pub quiet: bool,
     #[structopt(short = "a", long)]
     pub allow_unexpected: bool,
+    #[structopt(short = "w", long)]
+    pub watch: bool,
 }
 
 pub fn mock(mock_settings: MockSettings) -> Result<Tester> {
     // initialize wasm engine and shared cache
-    let store = Store::default();
-    let module = Module::from_file(store.engine(), &mock_settings.wasm_path)?;
+    let engine = Engine::default();
+    let module = load_module(&engine, &mock_settings.wasm_path)?;
+    let abi_version = get_abi_version(&module);
+    instantiate(&engine, module, abi_version, mock_settings)
+}
 
-    // generate and link host function implementations
+// Same as mock() with an engine configured by the test, e.g. to enable wasm features (SIMD, bulk
+// memory, multi-value), pick the cranelift optimization level or generate debug info
+pub fn mock_with_config(mock_settings: MockSettings, config: &Config) -> Result<Tester> {
+    let engine = Engine::new(config)?;
+    let module = load_module(&engine, &mock_settings.wasm_path)?;
+    let abi_version = get_abi_version(&module);
+    instantiate(&engine, module, abi_version, mock_settings)
+}
+
+// Same as mock() for a wasm_path pointing to an artifact of runtime::precompile, see
+// runtime::load_precompiled for why this is unsafe
+pub unsafe fn mock_precompiled(mock_settings: MockSettings) -> Result<Tester> {
+    let engine = Engine::default();
+    let module = load_precompiled(&engine, &mock_settings.wasm_path)?;
     let abi_version = get_abi_version(&module);
-    let imports: Arc<Mutex<Vec<Extern>>> = Arc::new(Mutex::new(Vec::new()));
-    let (host_settings, expectations): (Arc<Mutex<HostHandle>>, Arc<Mutex<ExpectHandle>>) =
-        generate_import_list(&store, &module, imports.clone());
-    let instance = Instance::new(&store, &module, &(*imports).lock().unwrap()[..])?;
+    instantiate(&engine, module, abi_version, mock_settings)
+}
+
+// Interprets the module as the given ABI version instead of the one detected from its
+// proxy_abi_version_* export, for modules exporting an ambiguous or custom version marker
+pub fn mock_with_abi(mock_settings: MockSettings, abi_version: AbiVersion) -> Result<Tester> {
+    assert_ne!(
+        abi_version,
+        AbiVersion::UnknownAbiVersion,
+        "Error: cannot force an unknown abi version"
+    );
+    let engine = Engine::default();
+    let module = load_module(&engine, &mock_settings.wasm_path)?;
+    let detected = detect_abi_version(&module);
+    if detected != abi_version {
+        println!(
+            "Warning: module declares {:?} but is tested as {:?}",
+            detected, abi_version
+        );
+    }
+    instantiate(&engine, module, abi_version, mock_settings)
+}
+
+pub fn test_with_abi(wasm_path: &str, abi_version: AbiVersion) -> Result<Tester> {
+    let mock_settings = MockSettings {
+        wasm_path: wasm_path.to_string(),
+        quiet: false,
+        allow_unexpected: false,
+        watch: false,
+    };
+    mock_with_abi(mock_settings, abi_version)
+}
+
+// Fails on modules missing callbacks the host cannot run without, before any of them is called
+fn check_required_exports(module: &Module, abi_version: AbiVersion) -> Result<()> {
+    let missing_exports = get_missing_exports(module);
+    if missing_exports.is_empty() {
+        return Ok(());
+    }
+    Err(anyhow::format_err!(
+        "Error: proxy-wasm module of {:?} does not export {}",
+        abi_version,
+        missing_exports.join(", ")
+    ))
+}
+
+fn instantiate(
+    engine: &Engine,
+    module: Module,
+    abi_version: AbiVersion,
+    mock_settings: MockSettings,
+) -> Result<Tester> {
+    check_required_exports(&module, abi_version)?;
+    let state = create_host_state(&module, abi_version);
+    let runtime = WasmtimeRuntime::new(engine, &module, state.clone());
+    instantiate_in(Box::new(runtime), module, abi_version, mock_settings, state)
+}
+
+fn instantiate_in(
+    mut runtime: Box<dyn WasmRuntime>,
+    module: Module,
+    abi_version: AbiVersion,
+    mock_settings: MockSettings,
+    state: HostState,
+) -> Result<Tester> {
+    // generate and link host function implementations
+    link_host_functions(&module, runtime.as_mut());
+    runtime.instantiate()?;
 
     // create mock test proxy-wasm object
     let tester = Tester::new(
         abi_version,
         mock_settings,
-        instance,
-        host_settings,
-        expectations,
+        module,
+        runtime,
+        state.host,
+        state.expect,
     );
     return Ok(tester);
 }
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+// Same as mock() running the module with wasmer, while wasmtime still loads it to detect its ABI
+// version and check its exports
+#[cfg(feature = "wasmer")]
+pub fn mock_wasmer(mock_settings: MockSettings) -> Result<Tester> {
+    let engine = Engine::default();
+    let module = load_module(&engine, &mock_settings.wasm_path)?;
+    let abi_version = get_abi_version(&module);
+    check_required_exports(&module, abi_version)?;
+    let state = create_host_state(&module, abi_version);
+    let runtime = WasmerRuntime::new(&mock_settings.wasm_path, state.clone())?;
+    instantiate_in(Box::new(runtime), module, abi_version, mock_settings, state)
+}
+
+// Reports exports and imports of a module that belong to a different ABI version than the declared
+// one, failing on modules missing the callbacks every ABI version requires
+pub fn check_abi_version(wasm_path: &str) -> Result<Vec<AbiMismatch>> {
+    let engine = Engine::default();
+    let module = load_module(&engine, wasm_path)?;
+    check_required_exports(&module, detect_abi_version(&module))?;
+    Ok(get_abi_mismatches(&module))
+}
+
+// Explains why a module cannot be tested: load errors (including required wasm features), missing
+// exports, imports the framework does not provide or provides with another signature, and mixed ABIs
+pub fn diagnose(wasm_path: &str) -> Diagnosis {
+    let engine = Engine::default();
+    let module = match load_module(&engine, wasm_path) {
+        Ok(module) => module,
+        Err(error) => {
+            return Diagnosis {
+                load_error: Some(format!("{:#}", error)),
+                abi_version: AbiVersion::UnknownAbiVersion,
+                missing_exports: Vec::new(),
+                unsupported_imports: Vec::new(),
+                signature_mismatches: Vec::new(),
+                abi_mismatches: Vec::new(),
+            }
+        }
+    };
+
+    let abi_version = detect_abi_version(&module);
+    let missing_exports = get_missing_exports(&module);
+    let (unsupported_imports, signature_mismatches) = check_imports(&engine, &module);
+    Diagnosis {
+        load_error: None,
+        abi_version: abi_version,
+        missing_exports: missing_exports,
+        unsupported_imports: unsupported_imports,
+        signature_mismatches: signature_mismatches,
+        abi_mismatches: get_abi_mismatches(&module),
+    }
+}
+
+pub fn mock_workers(mock_settings: MockSettings, num_workers: usize) -> Result<Workers> {
+    // initialize wasm engine and shared cache
+    let engine = Engine::default();
+    let module = load_module(&engine, &mock_settings.wasm_path)?;
+
+    // give every worker instance host settings and expectations of its own, backed by the same
+    // host stores (shared data, queues, metrics)
+    let abi_version = get_abi_version(&module);
+    check_required_exports(&module, abi_version)?;
+    let states = create_worker_host_states(&module, abi_version, num_workers);
+
+    // create one mock test proxy-wasm object per worker instance, each in a runtime of its own
+    let mut workers = Vec::new();
+    for state in states {
+        let runtime = WasmtimeRuntime::new(&engine, &module, state.clone());
+        workers.push(instantiate_in(
+            Box::new(runtime),
+            module.clone(),
+            abi_version,
+            mock_settings.clone(),
+            state,
+        )?);
+    }
+    return Ok(Workers::new(workers));
+}
+
+// Engine shared by the testers of a suite, along with the modules it compiled keyed by the hash of
+// their contents, so that a plugin is only compiled once however many tests instantiate it
+pub struct TestContext {
+    engine: Engine,
+    modules: Mutex<HashMap<u64, Module>>,
+}
+
+impl TestContext {
+    pub fn new() -> TestContext {
+        TestContext {
+            engine: Engine::default(),
+            modules: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_config(config: &Config) -> Result<TestContext> {
+        Ok(TestContext {
+            engine: Engine::new(config)?,
+            modules: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    pub fn load_module(&self, wasm_path: &str) -> Result<Module> {
+        let mut hasher = DefaultHasher::new();
+        std::fs::read(wasm_path)?.hash(&mut hasher);
+        let key = hasher.finish();
+        if let Some(module) = self.modules.lock().unwrap().get(&key) {
+            return Ok(module.clone());
+        }
+        let module = load_module(&self.engine, wasm_path)?;
+        self.modules.lock().unwrap().insert(key, module.clone());
+        Ok(module)
+    }
+
+    // Same as mock() with the engine and compiled modules of the context
+    pub fn mock(&self, mock_settings: MockSettings) -> Result<Tester> {
+        let module = self.load_module(&mock_settings.wasm_path)?;
+        let abi_version = get_abi_version(&module);
+        instantiate(&self.engine, module, abi_version, mock_settings)
+    }
+}
+
+#[derive(Debug, Clone)]
 enum FunctionCall {
     Start(),
+    CallExport(String, Vec<Val>),
     ProxyOnVmStart(i32, i32),
     ProxyValidateConfiguration(i32, i32),
     ProxyOnConfigure(i32, i32),
@@ -84,6 +310,7 @@ enum FunctionCall {
     ProxyOnResponseBody(i32, i32, bool),
     ProxyOnResponseTrailers(i32, i32),
     ProxyOnResponseMetadata(i32, i32),
+    ProxyOnResponse1xx(i32, i32),
     ProxyOnHttpCallResponse(i32, i32, i32, i32, i32),
     ProxyOnGrpcReceiveInitialMetadata(i32, i32, i32),
     ProxyOnGrpcReceiveTrailingMetadata(i32, i32, i32),
@@ -94,39 +321,111 @@ enum FunctionCall {
     ProxyOnDelete(i32),
 }
 
+impl FunctionCall {
+    fn get_context_id(&self) -> Option<i32> {
+        match *self {
+            FunctionCall::Start() | FunctionCall::CallExport(..) => None,
+            FunctionCall::ProxyOnVmStart(context_id, _)
+            | FunctionCall::ProxyValidateConfiguration(context_id, _)
+            | FunctionCall::ProxyOnConfigure(context_id, _)
+            | FunctionCall::ProxyOnTick(context_id)
+            | FunctionCall::ProxyOnForeignFunction(context_id, _, _)
+            | FunctionCall::ProxyOnQueueReady(context_id, _)
+            | FunctionCall::ProxyOnContextCreate(context_id, _)
+            | FunctionCall::ProxyOnNewConnection(context_id)
+            | FunctionCall::ProxyOnDownstreamData(context_id, _, _)
+            | FunctionCall::ProxyOnDownstreamConnectionClose(context_id, _)
+            | FunctionCall::ProxyOnUpstreamData(context_id, _, _)
+            | FunctionCall::ProxyOnUpstreamConnectionClose(context_id, _)
+            | FunctionCall::ProxyOnRequestHeaders(context_id, _, _)
+            | FunctionCall::ProxyOnRequestBody(context_id, _, _)
+            | FunctionCall::ProxyOnRequestTrailers(context_id, _)
+            | FunctionCall::ProxyOnRequestMetadata(context_id, _)
+            | FunctionCall::ProxyOnResponseHeaders(context_id, _, _)
+            | FunctionCall::ProxyOnResponseBody(context_id, _, _)
+            | FunctionCall::ProxyOnResponseTrailers(context_id, _)
+            | FunctionCall::ProxyOnResponseMetadata(context_id, _)
+            | FunctionCall::ProxyOnResponse1xx(context_id, _)
+            | FunctionCall::ProxyOnHttpCallResponse(context_id, _, _, _, _)
+            | FunctionCall::ProxyOnGrpcReceiveInitialMetadata(context_id, _, _)
+            | FunctionCall::ProxyOnGrpcReceiveTrailingMetadata(context_id, _, _)
+            | FunctionCall::ProxyOnGrpcReceive(context_id, _, _)
+            | FunctionCall::ProxyOnGrpcClose(context_id, _, _)
+            | FunctionCall::ProxyOnDone(context_id)
+            | FunctionCall::ProxyOnLog(context_id)
+            | FunctionCall::ProxyOnDelete(context_id) => Some(context_id),
+        }
+    }
+
+    fn is_http_stream_callback(&self) -> bool {
+        match *self {
+            FunctionCall::ProxyOnRequestHeaders(..)
+            | FunctionCall::ProxyOnRequestBody(..)
+            | FunctionCall::ProxyOnRequestTrailers(..)
+            | FunctionCall::ProxyOnRequestMetadata(..)
+            | FunctionCall::ProxyOnResponseHeaders(..)
+            | FunctionCall::ProxyOnResponseBody(..)
+            | FunctionCall::ProxyOnResponseTrailers(..)
+            | FunctionCall::ProxyOnResponseMetadata(..)
+            | FunctionCall::ProxyOnResponse1xx(..) => true,
+            _ => false,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 enum FunctionType {
     ReturnVoid,
     ReturnBool,
     ReturnAction,
+    ReturnValue,
 }
 
 pub struct Tester {
     abi_version: AbiVersion,
     mock_settings: MockSettings,
-    instance: Instance,
+    module: Module,
+    runtime: Box<dyn WasmRuntime>,
     defaults: Arc<Mutex<HostHandle>>,
     expect: Arc<Mutex<ExpectHandle>>,
     function_call: Vec<FunctionCall>,
     function_type: Vec<FunctionType>,
+    held_body: Vec<(i32, StreamType, Bytes, bool)>,
+    skip_after_local_response: bool,
+    restore_skip_after_local_response: Option<bool>,
+    assert_no_data_after_local_response: bool,
+    half_closed: Vec<(i32, StreamType)>,
+    contexts: ContextManager,
+    validate_lifecycle: bool,
+    check_leaks_on_drop: bool,
 }
 
 impl Tester {
     fn new(
         abi_version: AbiVersion,
         mock_settings: MockSettings,
-        instance: Instance,
+        module: Module,
+        runtime: Box<dyn WasmRuntime>,
         host_settings: Arc<Mutex<HostHandle>>,
         expect: Arc<Mutex<ExpectHandle>>,
     ) -> Tester {
         let mut tester = Tester {
             abi_version: abi_version,
             mock_settings: mock_settings,
-            instance: instance,
+            module: module,
+            runtime: runtime,
             defaults: host_settings,
             expect: expect,
             function_call: vec![],
             function_type: vec![],
+            held_body: vec![],
+            skip_after_local_response: false,
+            restore_skip_after_local_response: None,
+            assert_no_data_after_local_response: false,
+            half_closed: vec![],
+            contexts: ContextManager::new(),
+            validate_lifecycle: false,
+            check_leaks_on_drop: false,
         };
         tester.update_expect_stage();
         tester.reset_host_settings();
@@ -135,6 +434,7 @@ impl Tester {
 
     /* ------------------------------------- Low-level Expectation Setting ------------------------------------- */
 
+    #[track_caller]
     pub fn expect_log(&mut self, log_level: Option<LogLevel>, log_msg: Option<&str>) -> &mut Self {
         self.get_expect_handle()
             .staged
@@ -142,203 +442,1402 @@ impl Tester {
         self
     }
 
+    // Log expectations for messages embedding values that change between runs (timestamps,
+    // durations, addresses...), matched against a regex or a substring rather than exactly
+    #[track_caller]
+    pub fn expect_log_matches(&mut self, log_level: Option<LogLevel>, regex: &str) -> &mut Self {
+        self.get_expect_handle().staged.set_expect_log_match(
+            log_level.map(|data| data as i32),
+            Some(StringMatch::Regex(regex.to_string(), None)),
+        );
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_log_contains(
+        &mut self,
+        log_level: Option<LogLevel>,
+        substring: &str,
+    ) -> &mut Self {
+        self.get_expect_handle().staged.set_expect_log_match(
+            log_level.map(|data| data as i32),
+            Some(StringMatch::Contains(substring.to_string())),
+        );
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_log_with<F>(&mut self, log_level: Option<LogLevel>, predicate: F) -> &mut Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.get_expect_handle().staged.set_expect_log_match(
+            log_level.map(|data| data as i32),
+            Some(StringMatch::Predicate(Arc::new(predicate))),
+        );
+        self
+    }
+
+    #[track_caller]
     pub fn expect_set_tick_period_millis(&mut self, tick_period_millis: Option<u64>) -> &mut Self {
+        self.expect_set_tick_period(tick_period_millis.map(Duration::from_millis))
+    }
+
+    #[track_caller]
+    pub fn expect_set_tick_period(&mut self, tick_period: Option<Duration>) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_expect_set_tick_period(tick_period);
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_get_status(&mut self) -> ExpectGetStatus {
+        ExpectGetStatus::expecting(self)
+    }
+
+    #[track_caller]
+    pub fn expect_get_current_time_nanos(&mut self) -> ExpectGetCurrentTimeNanos {
+        ExpectGetCurrentTimeNanos::expecting(self)
+    }
+
+    #[track_caller]
+    pub fn expect_get_buffer_bytes(
+        &mut self,
+        buffer_type: Option<BufferType>,
+    ) -> ExpectGetBufferBytes {
+        ExpectGetBufferBytes::expecting(self, buffer_type.map(|data| data as i32))
+    }
+
+    #[track_caller]
+    pub fn expect_set_buffer_bytes(
+        &mut self,
+        buffer_type: Option<BufferType>,
+        buffer_data: Option<&str>,
+    ) -> &mut Self {
+        self.get_expect_handle().staged.set_expect_set_buffer_bytes(
+            buffer_type.map(|data| data as i32),
+            buffer_data.map(|data| data.as_bytes()),
+        );
+        self
+    }
+
+    // Same as expect_set_buffer_bytes for binary payloads the plugin writes
+    #[track_caller]
+    pub fn expect_set_buffer_data(
+        &mut self,
+        buffer_type: Option<BufferType>,
+        buffer_data: Option<&[u8]>,
+    ) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_expect_set_buffer_bytes(buffer_type.map(|data| data as i32), buffer_data);
+        self
+    }
+
+    // Stages an expectation on any data written to the buffer and returns a handle on it, to be
+    // inspected once the plugin has run
+    #[track_caller]
+    pub fn capture_set_buffer_bytes(&mut self, buffer_type: BufferType) -> Capture {
+        let capture = Capture::new();
+        self.get_expect_handle()
+            .staged
+            .set_capture_set_buffer_bytes(Some(buffer_type as i32), capture.clone());
+        capture
+    }
+
+    #[track_caller]
+    pub fn expect_get_header_map_pairs(
+        &mut self,
+        map_type: Option<MapType>,
+    ) -> ExpectGetHeaderMapPairs {
+        ExpectGetHeaderMapPairs::expecting(self, map_type.map(|data| data as i32))
+    }
+
+    #[track_caller]
+    pub fn expect_set_header_map_pairs(
+        &mut self,
+        map_type: Option<MapType>,
+        header_map_pairs: Option<Vec<(&str, &str)>>,
+    ) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_expect_set_header_map_pairs(map_type.map(|data| data as i32), header_map_pairs);
+        self
+    }
+
+    // Same as expect_set_header_map_pairs for maps with values that are not UTF-8
+    #[track_caller]
+    pub fn expect_set_header_map_data(
+        &mut self,
+        map_type: Option<MapType>,
+        header_map_pairs: Option<Vec<(&str, &[u8])>>,
+    ) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_expect_set_header_map_data(map_type.map(|data| data as i32), header_map_pairs);
+        self
+    }
+
+    // Follows expect_set_header_map_pairs to only require the given pairs to be present, so that
+    // headers the SDK adds on its own do not break the expectation
+    pub fn containing(&mut self, header_map_pairs: Vec<(&str, &str)>) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_containing(header_map_pairs);
+        self
+    }
+
+    // Same as capture_set_buffer_bytes, with the pairs read back through Capture::get_pairs
+    #[track_caller]
+    pub fn capture_set_header_map_pairs(&mut self, map_type: MapType) -> Capture {
+        let capture = Capture::new();
+        self.get_expect_handle()
+            .staged
+            .set_capture_set_header_map_pairs(Some(map_type as i32), capture.clone());
+        capture
+    }
+
+    #[track_caller]
+    pub fn expect_get_header_map_value(
+        &mut self,
+        map_type: Option<MapType>,
+        header_map_key: Option<&str>,
+    ) -> ExpectGetHeaderMapValue {
+        ExpectGetHeaderMapValue::expecting(self, map_type.map(|data| data as i32), header_map_key)
+    }
+
+    #[track_caller]
+    pub fn expect_replace_header_map_value(
+        &mut self,
+        map_type: Option<MapType>,
+        header_map_key: Option<&str>,
+        header_map_value: Option<&str>,
+    ) -> &mut Self {
+        self.expect_replace_header_map_value_bytes(
+            map_type,
+            header_map_key,
+            header_map_value.map(|value| value.as_bytes()),
+        )
+    }
+
+    #[track_caller]
+    pub fn expect_replace_header_map_value_bytes(
+        &mut self,
+        map_type: Option<MapType>,
+        header_map_key: Option<&str>,
+        header_map_value: Option<&[u8]>,
+    ) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_expect_replace_header_map_value(
+                map_type.map(|data| data as i32),
+                header_map_key,
+                header_map_value,
+            );
+        self
+    }
+
+    // Checks the value with a predicate instead, e.g. |value| value.len() == 36 for a UUID
+    #[track_caller]
+    pub fn expect_replace_header_map_value_with<F>(
+        &mut self,
+        map_type: Option<MapType>,
+        header_map_key: Option<&str>,
+        predicate: F,
+    ) -> &mut Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.get_expect_handle()
+            .staged
+            .set_expect_replace_header_map_value_match(
+                map_type.map(|data| data as i32),
+                header_map_key,
+                Some(StringMatch::Predicate(Arc::new(predicate))),
+            );
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_remove_header_map_value(
+        &mut self,
+        map_type: Option<MapType>,
+        header_map_key: Option<&str>,
+    ) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_expect_remove_header_map_value(map_type.map(|data| data as i32), header_map_key);
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_add_header_map_value(
+        &mut self,
+        map_type: Option<MapType>,
+        header_map_key: Option<&str>,
+        header_map_value: Option<&str>,
+    ) -> &mut Self {
+        self.expect_add_header_map_value_bytes(
+            map_type,
+            header_map_key,
+            header_map_value.map(|value| value.as_bytes()),
+        )
+    }
+
+    #[track_caller]
+    pub fn expect_add_header_map_value_bytes(
+        &mut self,
+        map_type: Option<MapType>,
+        header_map_key: Option<&str>,
+        header_map_value: Option<&[u8]>,
+    ) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_expect_add_header_map_value(
+                map_type.map(|data| data as i32),
+                header_map_key,
+                header_map_value,
+            );
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_add_header_map_value_with<F>(
+        &mut self,
+        map_type: Option<MapType>,
+        header_map_key: Option<&str>,
+        predicate: F,
+    ) -> &mut Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.get_expect_handle()
+            .staged
+            .set_expect_add_header_map_value_match(
+                map_type.map(|data| data as i32),
+                header_map_key,
+                Some(StringMatch::Predicate(Arc::new(predicate))),
+            );
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_send_local_response(
+        &mut self,
+        status_code: Option<i32>,
+        body: Option<&str>,
+        headers: Option<Vec<(&str, &str)>>,
+        grpc_status: Option<i32>,
+    ) -> &mut Self {
         self.get_expect_handle()
             .staged
-            .set_expect_set_tick_period_millis(tick_period_millis);
+            .set_expect_send_local_response(status_code, body, headers, grpc_status);
         self
     }
 
-    pub fn expect_get_current_time_nanos(&mut self) -> ExpectGetCurrentTimeNanos {
-        ExpectGetCurrentTimeNanos::expecting(self)
+    // Accepts whatever local response the module sends, see get_local_response to assert on it
+    pub fn capture_send_local_response(&mut self) -> &mut Self {
+        self.expect_send_local_response(None, None, None, None)
+    }
+
+    #[track_caller]
+    pub fn expect_http_call(
+        &mut self,
+        upstream: Option<&str>,
+        headers: Option<Vec<(&str, &str)>>,
+        body: Option<&str>,
+        trailers: Option<Vec<(&str, &str)>>,
+        timeout: Option<u64>,
+    ) -> ExpectHttpCall {
+        ExpectHttpCall::expecting(self, upstream, headers, body, trailers, timeout)
+    }
+
+    #[track_caller]
+    pub fn expect_grpc_call(
+        &mut self,
+        upstream: Option<&str>,
+        service: Option<&str>,
+        method: Option<&str>,
+        initial_metadata: Option<Vec<(&str, &str)>>,
+        message: Option<&[u8]>,
+        timeout: Option<u64>,
+    ) -> ExpectGrpcCall {
+        ExpectGrpcCall::expecting(
+            self,
+            upstream,
+            service,
+            method,
+            initial_metadata,
+            message,
+            timeout,
+        )
+    }
+
+    #[track_caller]
+    pub fn expect_grpc_stream(
+        &mut self,
+        upstream: Option<&str>,
+        service: Option<&str>,
+        method: Option<&str>,
+        initial_metadata: Option<Vec<(&str, &str)>>,
+    ) -> ExpectGrpcStream {
+        ExpectGrpcStream::expecting(self, upstream, service, method, initial_metadata)
+    }
+
+    #[track_caller]
+    pub fn expect_grpc_send(
+        &mut self,
+        token_id: Option<u32>,
+        message: Option<&[u8]>,
+        end_of_stream: Option<bool>,
+    ) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_expect_grpc_send(token_id, message, end_of_stream);
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_grpc_cancel(&mut self, token_id: Option<u32>) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_expect_grpc_cancel(token_id);
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_grpc_close(&mut self, token_id: Option<u32>) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_expect_grpc_close(token_id);
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_call_foreign_function(
+        &mut self,
+        name: Option<&str>,
+        args: Option<&[u8]>,
+    ) -> ExpectCallForeignFunction {
+        ExpectCallForeignFunction::expecting(self, name, args)
+    }
+
+    #[track_caller]
+    pub fn expect_set_effective_context(&mut self, context_id: Option<i32>) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_expect_set_effective_context(context_id);
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_proxy_done(&mut self, context_id: Option<i32>) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_expect_proxy_done(context_id);
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_clear_route_cache(&mut self) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_expect_clear_route_cache();
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_continue_request(&mut self) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_expect_continue_request();
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_continue_response(&mut self) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_expect_continue_response();
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_resume_downstream(&mut self) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_expect_resume_downstream();
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_resume_upstream(&mut self) -> &mut Self {
+        self.get_expect_handle().staged.set_expect_resume_upstream();
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_continue_stream(&mut self, stream_type: Option<StreamType>) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_expect_continue_stream(stream_type.map(|data| data as i32));
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_close_stream(&mut self, stream_type: Option<StreamType>) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_expect_close_stream(stream_type.map(|data| data as i32));
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_register_shared_queue(&mut self, name: Option<&str>) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_expect_register_shared_queue(name);
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_resolve_shared_queue(
+        &mut self,
+        vm_id: Option<&str>,
+        name: Option<&str>,
+    ) -> ExpectResolveSharedQueue {
+        ExpectResolveSharedQueue::expecting(self, vm_id, name)
+    }
+
+    #[track_caller]
+    pub fn expect_enqueue_shared_queue(
+        &mut self,
+        queue_id: Option<u32>,
+        data: Option<&str>,
+    ) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_expect_enqueue_shared_queue(queue_id, data);
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_dequeue_shared_queue(
+        &mut self,
+        queue_id: Option<u32>,
+    ) -> ExpectDequeueSharedQueue {
+        ExpectDequeueSharedQueue::expecting(self, queue_id)
+    }
+
+    #[track_caller]
+    pub fn expect_get_property(&mut self, path: Option<Vec<&str>>) -> ExpectGetProperty {
+        ExpectGetProperty::expecting(self, path)
+    }
+
+    #[track_caller]
+    pub fn expect_set_property(
+        &mut self,
+        path: Option<Vec<&str>>,
+        value: Option<&[u8]>,
+    ) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_expect_set_property(path, value);
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_set_property_value<T: Into<PropertyValue>>(
+        &mut self,
+        path: Option<Vec<&str>>,
+        value: T,
+    ) -> &mut Self {
+        let value = value.into().encode();
+        self.expect_set_property(path, Some(&value))
+    }
+
+    #[track_caller]
+    pub fn expect_define_metric(
+        &mut self,
+        metric_type: Option<MetricType>,
+        name: Option<&str>,
+    ) -> ExpectDefineMetric {
+        ExpectDefineMetric::expecting(self, metric_type.map(|data| data as i32), name)
+    }
+
+    #[track_caller]
+    pub fn expect_increment_metric(
+        &mut self,
+        metric_id: Option<u32>,
+        offset: Option<i64>,
+    ) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_expect_increment_metric(metric_id, offset);
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_record_metric(
+        &mut self,
+        metric_id: Option<u32>,
+        value: Option<u64>,
+    ) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_expect_record_metric(metric_id, value);
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_get_metric(&mut self, metric_id: Option<u32>) -> ExpectGetMetric {
+        ExpectGetMetric::expecting(self, metric_id)
+    }
+
+    #[track_caller]
+    pub fn expect_get_shared_data(&mut self, key: Option<&str>) -> ExpectGetSharedData {
+        ExpectGetSharedData::expecting(self, key)
+    }
+
+    #[track_caller]
+    pub fn expect_set_shared_data(
+        &mut self,
+        key: Option<&str>,
+        value: Option<&str>,
+        cas: Option<u32>,
+    ) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_expect_set_shared_data(key, value, cas);
+        self
+    }
+
+    // Cardinality of the expectation staged last, e.g. expect_log(..).times(3) or
+    // expect_get_header_map_value(..).returning(..).at_least(1)
+    pub fn times(&mut self, calls: u32) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_cardinality(calls, Some(calls));
+        self
+    }
+
+    pub fn at_least(&mut self, calls: u32) -> &mut Self {
+        self.get_expect_handle().staged.set_cardinality(calls, None);
+        self
+    }
+
+    pub fn at_most(&mut self, calls: u32) -> &mut Self {
+        self.get_expect_handle()
+            .staged
+            .set_cardinality(0, Some(calls));
+        self
+    }
+
+    // Names the expectation staged last in failure output next to the test line staging it, e.g.
+    // expect_add_header_map_value(..).named("auth header added")
+    pub fn named(&mut self, name: &str) -> &mut Self {
+        self.get_expect_handle().staged.set_name(name);
+        self
+    }
+
+    // Keeps the expectation staged last across executions as a fallback for any number of calls,
+    // e.g. a default get_property answer or an always-allowed log
+    pub fn persistent(&mut self) -> &mut Self {
+        self.get_expect_handle().staged.set_persistent();
+        self
+    }
+
+    // Fails the next execution if the plugin calls the host function at all, e.g. to check that an
+    // allowed request passes through untouched
+    pub fn expect_no_call(&mut self, kind: HostcallKind) -> &mut Self {
+        self.get_expect_handle().staged.set_forbidden(kind);
+        self
+    }
+
+    pub fn expect_no_http_call(&mut self) -> &mut Self {
+        self.expect_no_call(HostcallKind::HttpCall)
+    }
+
+    pub fn expect_no_grpc_call(&mut self) -> &mut Self {
+        self.expect_no_call(HostcallKind::GrpcCall)
+    }
+
+    pub fn expect_no_send_local_response(&mut self) -> &mut Self {
+        self.expect_no_call(HostcallKind::SendLocalResponse)
+    }
+
+    /* ------------------------------------- High-level Expectation Setting ------------------------------------- */
+
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.mock_settings.quiet = quiet;
+        self.get_settings_handle().staged.set_quiet_mode(quiet);
+    }
+
+    pub fn reset_default_tick_period_millis(&mut self) -> &mut Self {
+        self.get_settings_handle().staged.reset_tick_period();
+        self
+    }
+
+    pub fn set_default_tick_period_millis(&mut self, tick_period_millis: u64) -> &mut Self {
+        self.set_default_tick_period(Duration::from_millis(tick_period_millis))
+    }
+
+    pub fn set_default_tick_period(&mut self, tick_period: Duration) -> &mut Self {
+        self.get_settings_handle()
+            .staged
+            .set_tick_period(tick_period);
+        self
+    }
+
+    pub fn get_default_tick_period(&self) -> Duration {
+        self.get_settings_handle().staged.get_tick_period()
+    }
+
+    pub fn reset_default_buffer_bytes(&mut self) -> &mut Self {
+        self.get_settings_handle().staged.reset_buffer_bytes();
+        self
+    }
+
+    pub fn set_default_buffer_bytes(&mut self, buffer_type: BufferType) -> DefaultBufferBytes {
+        DefaultBufferBytes::expecting(self, buffer_type as i32)
+    }
+
+    pub fn reset_default_header_map_pairs(&mut self) -> &mut Self {
+        self.get_settings_handle().staged.reset_header_map_pairs();
+        self
+    }
+
+    pub fn set_default_header_map_pairs(&mut self, map_type: MapType) -> DefaultHeaderMapPairs {
+        DefaultHeaderMapPairs::expecting(self, map_type as i32)
+    }
+
+    // Delivers the response through proxy_on_http_call_response once the module makes the callout
+    // given token_id, with its headers, body and trailers backing the HttpCallResponse* reads
+    pub fn set_http_callout_response(
+        &mut self,
+        token_id: u32,
+        response: HttpCalloutResponse,
+    ) -> &mut Self {
+        self.get_settings_handle()
+            .staged
+            .attach_http_call_response(token_id, response);
+        self
+    }
+
+    pub fn set_default_map_serialization(
+        &mut self,
+        map_serialization: MapSerialization,
+    ) -> &mut Self {
+        self.get_settings_handle()
+            .staged
+            .set_map_serialization(map_serialization);
+        self
+    }
+
+    // Keeps the content-length header of the http request/response headers in sync with the body
+    // set by the test or the module
+    pub fn set_default_content_length_sync(&mut self, sync: bool) -> &mut Self {
+        self.get_settings_handle()
+            .staged
+            .set_sync_content_length(sync);
+        self
+    }
+
+    pub fn set_default_queue_delivery(&mut self, queue_delivery: QueueDelivery) -> &mut Self {
+        self.get_settings_handle()
+            .staged
+            .set_queue_delivery(queue_delivery);
+        self
+    }
+
+    pub fn flush_shared_queues(&mut self) -> Result<&mut Self> {
+        self.deliver_queue_ready(true)?;
+        self.assert_expect_stage();
+        self.update_expect_stage();
+        Ok(self)
+    }
+
+    pub fn set_default_upstream_cluster_header(&mut self, header_map_key: &str) -> &mut Self {
+        self.get_settings_handle()
+            .staged
+            .set_upstream_cluster_header(header_map_key);
+        self
+    }
+
+    // Seeds the shared data store as if the value had been set by another plugin
+    pub fn set_default_shared_data(&mut self, key: &str, value: &str) -> &mut Self {
+        self.get_settings_handle()
+            .staged
+            .set_shared_data(key, value.as_bytes(), 0);
+        self
+    }
+
+    pub fn set_default_property(&mut self, path: &[&str], value: &[u8]) -> &mut Self {
+        self.get_settings_handle()
+            .staged
+            .set_property(&serialize_property_path(path.to_vec()), value);
+        self
+    }
+
+    pub fn set_default_property_value<T: Into<PropertyValue>>(
+        &mut self,
+        path: &[&str],
+        value: T,
+    ) -> &mut Self {
+        let value = value.into().encode();
+        self.set_default_property(path, &value)
+    }
+
+    // Installs the message wrapped in its grpc frame as the GrpcReceiveBuffer
+    pub fn set_default_grpc_message(&mut self, message: &[u8]) -> &mut Self {
+        self.get_settings_handle().staged.set_buffer_data(
+            BufferType::GrpcReceiveBuffer as i32,
+            &serialize_grpc_frame(message, false),
+        );
+        self
+    }
+
+    // Installs the header maps and properties of a preset such as HostPreset::envoy_http1() as
+    // host defaults
+    pub fn set_default_preset(&mut self, preset: &HostPreset) -> &mut Self {
+        for (map_type, header_map_pairs) in &preset.header_map_pairs {
+            self.get_settings_handle().staged.set_header_map_pairs(
+                *map_type as i32,
+                header_map_pairs
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value.as_str()))
+                    .collect(),
+            );
+        }
+        for (path, value) in &preset.properties {
+            let path: Vec<&str> = path.iter().map(String::as_str).collect();
+            self.set_default_property(&path, &value.encode());
+        }
+        self
+    }
+
+    // Populates the property store with typical Envoy attributes (request.*, source.*, node.*, ...)
+    pub fn set_default_envoy_properties(&mut self) -> &mut Self {
+        self.get_settings_handle()
+            .staged
+            .set_properties(envoy_properties());
+        self
+    }
+
+    pub fn set_default_status(&mut self, status_code: u32, message: &str) -> &mut Self {
+        self.get_settings_handle()
+            .staged
+            .set_status(status_code, message);
+        self
+    }
+
+    // Level reported by proxy_get_log_level, Trace unless set otherwise
+    pub fn set_default_log_level(&mut self, log_level: LogLevel) -> &mut Self {
+        self.get_settings_handle()
+            .staged
+            .set_log_level(log_level as i32);
+        self
+    }
+
+    // Freezes the host clock at the given number of nanoseconds since the epoch, every subsequent
+    // proxy_get_current_time_nanoseconds reads it until the clock is set or advanced again
+    pub fn set_time(&mut self, time_nanos: u64) -> &mut Self {
+        self.set_system_time(UNIX_EPOCH + Duration::from_nanos(time_nanos))
+    }
+
+    pub fn set_system_time(&mut self, current_time: SystemTime) -> &mut Self {
+        self.get_settings_handle()
+            .staged
+            .set_current_time(current_time);
+        self
+    }
+
+    pub fn advance_time(&mut self, duration: Duration) -> &mut Self {
+        self.get_settings_handle()
+            .staged
+            .advance_current_time(duration);
+        self
+    }
+
+    // Advances the host clock by time_step after every proxy_get_current_time_nanoseconds served
+    // without an expectation, None stops the automatic steps
+    pub fn set_default_time_step(&mut self, time_step: Option<Duration>) -> &mut Self {
+        self.get_settings_handle().staged.set_time_step(time_step);
+        self
+    }
+
+    pub fn get_time(&self) -> SystemTime {
+        self.get_settings_handle().staged.get_current_time()
+    }
+
+    // Advances the host clock by duration, calling proxy_on_tick on each root context at every
+    // tick it scheduled through proxy_set_tick_period_milliseconds in the meantime
+    pub fn advance_time_and_fire_ticks(&mut self, duration: Duration) -> Result<&mut Self> {
+        assert_eq!(
+            self.function_call.len(),
+            0,
+            "Error: ticks cannot be fired while other calls are staged"
+        );
+        let deadline = self.get_time() + duration;
+        loop {
+            let next_tick = self.get_settings_handle().staged.take_next_tick(deadline);
+            match next_tick {
+                Some((context_id, tick_time)) => {
+                    self.set_system_time(tick_time)
+                        .call_proxy_on_tick(context_id)
+                        .execute_and_expect(ReturnType::None)?;
+                }
+                None => break,
+            }
+        }
+        self.set_system_time(deadline);
+        Ok(self)
+    }
+
+    // Served by proxy_get_configuration and get_buffer_bytes(PluginConfiguration), and whose size
+    // call_proxy_on_configure_with_plugin_configuration passes to the module
+    pub fn set_plugin_configuration(&mut self, plugin_configuration: &[u8]) -> &mut Self {
+        self.get_settings_handle()
+            .staged
+            .set_plugin_configuration(plugin_configuration);
+        self
+    }
+
+    pub fn get_plugin_configuration(&self) -> Bytes {
+        self.get_settings_handle().staged.get_plugin_configuration()
+    }
+
+    // Served by get_buffer_bytes(VmConfiguration) and proxy_get_configuration during
+    // proxy_on_vm_start, and whose size call_proxy_on_vm_start_with_vm_configuration passes
+    pub fn set_vm_configuration(&mut self, vm_configuration: &[u8]) -> &mut Self {
+        self.get_settings_handle()
+            .staged
+            .set_vm_configuration(vm_configuration);
+        self
+    }
+
+    pub fn get_vm_configuration(&self) -> Bytes {
+        self.get_settings_handle().staged.get_vm_configuration()
+    }
+
+    pub fn set_default_vm_id(&mut self, vm_id: &str) -> &mut Self {
+        self.get_settings_handle().staged.set_vm_id(vm_id);
+        self
+    }
+
+    pub fn set_default_root_id(&mut self, root_id: &str) -> &mut Self {
+        self.get_settings_handle().staged.set_root_id(root_id);
+        self
+    }
+
+    // Treats every allocation the host makes in the module's memory as failed, as under OOM, so
+    // hostcalls returning data report InternalFailure until the failure injection is disabled
+    pub fn set_default_allocation_failure(&mut self, allocation_failure: bool) -> &mut Self {
+        self.get_settings_handle()
+            .staged
+            .set_allocation_failure(allocation_failure);
+        self
+    }
+
+    /* ------------------------------------- Utility Functions ------------------------------------- */
+
+    // ABI version the module is tested as, detected from its exports unless forced by mock_with_abi
+    pub fn abi_version(&self) -> AbiVersion {
+        self.abi_version
+    }
+
+    pub fn exports(&self) -> Vec<String> {
+        self.module
+            .exports()
+            .map(|export| export.name().to_string())
+            .collect()
+    }
+
+    // Names of the host functions the module imports, e.g. "proxy_http_call"
+    pub fn imports(&self) -> Vec<String> {
+        self.module
+            .imports()
+            .map(|import| import.name().to_string())
+            .collect()
+    }
+
+    // Calls the allocator the host copies data into the module with (proxy_on_memory_allocate, or
+    // malloc for modules that do not export it) and returns the address it handed out, or a null
+    // address without calling into the module while allocation failures are injected
+    pub fn allocate(&mut self, size: usize) -> Result<u32> {
+        let allocator = *["proxy_on_memory_allocate", "malloc"]
+            .iter()
+            .find(|name| self.runtime.get_export_results(name).is_some())
+            .ok_or(anyhow::format_err!(
+                "Error: failed to find `proxy_on_memory_allocate` or `malloc` function export"
+            ))?;
+        if self.get_settings_handle().staged.get_allocation_failure() {
+            println!(
+                "[host] proxy_on_memory_allocate(size={}) failed: allocation failure injected",
+                size
+            );
+            return Ok(0);
+        }
+        println!("[host->vm] proxy_on_memory_allocate(size={})", size);
+        let address = self.runtime.call_i32(allocator, &[abi_size(size)])? as u32;
+        println!(
+            "[host<-vm] proxy_on_memory_allocate return: address={}",
+            address
+        );
+        Ok(address)
+    }
+
+    pub fn get_expect_handle(&self) -> MutexGuard<ExpectHandle> {
+        self.expect.lock().unwrap()
+    }
+
+    pub fn print_expectations(&self) {
+        self.expect.lock().unwrap().print_staged();
+    }
+
+    pub fn export_expectations(&self) -> Result<String> {
+        self.expect.lock().unwrap().export_stage()
+    }
+
+    pub fn import_expectations(&mut self, serialized_expectations: &str) -> Result<&mut Self> {
+        self.expect
+            .lock()
+            .unwrap()
+            .import_stage(serialized_expectations)?;
+        Ok(self)
+    }
+
+    pub fn save_expectations(&self, path: &str) -> Result<()> {
+        std::fs::write(path, self.export_expectations()?)?;
+        Ok(())
+    }
+
+    pub fn load_expectations(&mut self, path: &str) -> Result<&mut Self> {
+        let serialized_expectations = std::fs::read_to_string(path)?;
+        self.import_expectations(&serialized_expectations)
+    }
+
+    pub(crate) fn update_expect_stage(&mut self) {
+        self.expect
+            .lock()
+            .unwrap()
+            .update_stage(self.mock_settings.allow_unexpected);
+    }
+
+    pub(crate) fn assert_expect_stage(&mut self) {
+        self.expect.lock().unwrap().assert_stage();
+    }
+
+    // Checks the current stage after the fact: every staged expectation was satisfied, every host
+    // call was accounted for (in strict mode) and every staged call was executed, reporting each
+    // unmet expectation with the line of the test that staged it
+    pub fn verify(&mut self) {
+        let mut failures: Vec<String> = Vec::new();
+        {
+            let expect_handle = self.get_expect_handle();
+            for unmet in expect_handle.staged.get_unmet_expectations() {
+                failures.push(format!("  - unmet expectation {}", unmet));
+            }
+            if !expect_handle.staged.get_allow_unexpected() {
+                for call in expect_handle.staged.get_unexpected_calls() {
+                    failures.push(format!("  + unexpected host call, matched by {}", call));
+                }
+            }
+        }
+        for function_call in &self.function_call {
+            failures.push(format!(
+                "  - staged call never executed {:?}",
+                function_call
+            ));
+        }
+        if !failures.is_empty() {
+            panic!(
+                "Error: verification failed with {} problem(s)\n{}",
+                failures.len(),
+                failures.join("\n")
+            );
+        }
+    }
+
+    pub fn get_settings_handle(&self) -> MutexGuard<HostHandle> {
+        self.defaults.lock().unwrap()
+    }
+
+    pub fn print_host_settings(&self) {
+        self.defaults.lock().unwrap().print_staged();
+    }
+
+    pub fn reset_host_settings(&mut self) {
+        self.defaults
+            .lock()
+            .unwrap()
+            .reset(self.abi_version, self.mock_settings.quiet);
+    }
+
+    pub fn is_pending_done(&self, context_id: i32) -> bool {
+        self.get_settings_handle()
+            .staged
+            .is_pending_done(context_id)
+    }
+
+    pub fn skip_after_local_response(&mut self, skip: bool) -> &mut Self {
+        self.skip_after_local_response = skip;
+        self.restore_skip_after_local_response = None;
+        self
+    }
+
+    // Applies skip_after_local_response to the calls staged so far only, restoring the previous
+    // value once they are executed
+    fn skip_after_local_response_for_staged(&mut self, skip: bool) {
+        if self.function_call.is_empty() {
+            return;
+        }
+        if self.restore_skip_after_local_response.is_none() {
+            self.restore_skip_after_local_response = Some(self.skip_after_local_response);
+        }
+        self.skip_after_local_response = skip;
+    }
+
+    fn restore_skip_after_staged(&mut self) {
+        if !self.function_call.is_empty() {
+            return;
+        }
+        if let Some(skip) = self.restore_skip_after_local_response.take() {
+            self.skip_after_local_response = skip;
+        }
+    }
+
+    pub fn assert_no_data_after_local_response(&mut self, assert: bool) -> &mut Self {
+        self.assert_no_data_after_local_response = assert;
+        self
+    }
+
+    // Fails the test when callbacks are driven on contexts that were never created or out of the
+    // created -> active -> done -> logged -> deleted order
+    pub fn validate_lifecycle(&mut self, validate: bool) -> &mut Self {
+        self.validate_lifecycle = validate;
+        self
+    }
+
+    pub fn get_route_cache_clears(&self, context_id: i32) -> usize {
+        self.get_settings_handle()
+            .staged
+            .get_route_cache_clears(context_id)
+    }
+
+    pub fn selected_upstream(&self) -> SelectedUpstream {
+        self.get_settings_handle().staged.get_selected_upstream()
+    }
+
+    // Enqueues data as if it had been produced by another plugin, the queue must be registered.
+    // Unless queue delivery is manual, proxy_on_queue_ready follows the next executed call.
+    pub fn enqueue_shared_queue(&mut self, queue_id: u32, data: &str) -> &mut Self {
+        let status = self
+            .get_settings_handle()
+            .staged
+            .enqueue_shared_queue(queue_id, data.as_bytes());
+        assert_eq!(
+            status as u32,
+            Status::Ok as u32,
+            "Error: shared queue {} is not registered",
+            queue_id
+        );
+        self
+    }
+
+    pub fn get_shared_queue_id(&self, vm_id: &str, name: &str) -> Option<u32> {
+        self.get_settings_handle()
+            .staged
+            .resolve_shared_queue(vm_id, name)
+    }
+
+    pub fn get_shared_queue_len(&self, queue_id: u32) -> usize {
+        self.get_settings_handle()
+            .staged
+            .get_shared_queue_len(queue_id)
+    }
+
+    pub fn get_shared_data(&self, key: &str) -> Option<(Bytes, u32)> {
+        self.get_settings_handle().staged.get_shared_data(key)
+    }
+
+    pub fn get_shared_data_keys(&self) -> Vec<String> {
+        self.get_settings_handle().staged.get_shared_data_keys()
+    }
+
+    pub fn assert_shared_data(&self, key: &str, value: Option<&str>) {
+        let stored_value = self
+            .get_shared_data(key)
+            .map(|(stored_value, _)| stored_value);
+        assert_eq!(
+            stored_value,
+            value.map(|data| data.as_bytes().to_vec()),
+            "Error: unexpected shared data for key {:?}",
+            key
+        );
+    }
+
+    // Context the module is acting on, either the one last called into or the one it switched to
+    pub fn get_effective_context_id(&self) -> i32 {
+        self.get_settings_handle().staged.get_effective_context_id()
+    }
+
+    pub fn get_context_switches(&self) -> Vec<i32> {
+        self.get_settings_handle().staged.get_context_switches()
+    }
+
+    pub fn assert_effective_context(&self, context_id: i32) {
+        assert_eq!(
+            self.get_effective_context_id(),
+            context_id,
+            "Error: unexpected effective context (switches: {:?})",
+            self.get_context_switches()
+        );
+    }
+
+    // Buffer as left by the module after the proxy_set_buffer_bytes calls it made
+    pub fn get_buffer(&self, buffer_type: BufferType) -> Bytes {
+        self.get_settings_handle()
+            .staged
+            .get_buffer_bytes(buffer_type as i32)
+    }
+
+    pub fn assert_buffer(&self, buffer_type: BufferType, buffer_data: &str) {
+        let buffer_name = format!("{:?}", buffer_type);
+        let buffer = self.get_buffer(buffer_type);
+        assert_eq!(
+            String::from_utf8_lossy(&buffer),
+            buffer_data,
+            "Error: unexpected contents of buffer {}",
+            buffer_name
+        );
+    }
+
+    pub fn assert_buffer_data(&self, buffer_type: BufferType, buffer_data: &[u8]) {
+        let buffer_name = format!("{:?}", buffer_type);
+        assert_eq!(
+            &self.get_buffer(buffer_type)[..],
+            buffer_data,
+            "Error: unexpected contents of buffer {}",
+            buffer_name
+        );
     }
 
-    pub fn expect_get_buffer_bytes(
-        &mut self,
-        buffer_type: Option<BufferType>,
-    ) -> ExpectGetBufferBytes {
-        ExpectGetBufferBytes::expecting(self, buffer_type.map(|data| data as i32))
+    // Header map as left by the module after the add/replace/remove hostcalls it made
+    pub fn get_header_map(&self, map_type: MapType) -> Vec<(String, String)> {
+        self.get_settings_handle()
+            .staged
+            .get_header_map(map_type as i32)
     }
 
-    pub fn expect_set_buffer_bytes(
-        &mut self,
-        buffer_type: Option<BufferType>,
-        buffer_data: Option<&str>,
-    ) -> &mut Self {
-        self.get_expect_handle()
+    pub fn get_header_map_data(&self, map_type: MapType) -> Vec<(String, Bytes)> {
+        self.get_settings_handle()
             .staged
-            .set_expect_set_buffer_bytes(buffer_type.map(|data| data as i32), buffer_data);
-        self
+            .get_header_map_data(map_type as i32)
     }
 
-    pub fn expect_get_header_map_pairs(
-        &mut self,
-        map_type: Option<MapType>,
-    ) -> ExpectGetHeaderMapPairs {
-        ExpectGetHeaderMapPairs::expecting(self, map_type.map(|data| data as i32))
+    pub fn assert_header_map(&self, map_type: MapType, header_map_pairs: Vec<(&str, &str)>) {
+        let map_name = format!("{:?}", map_type);
+        let header_map_pairs: Vec<(String, String)> = header_map_pairs
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        assert_eq!(
+            self.get_header_map(map_type),
+            header_map_pairs,
+            "Error: unexpected contents of header map {}",
+            map_name
+        );
     }
 
-    pub fn expect_set_header_map_pairs(
-        &mut self,
-        map_type: Option<MapType>,
-        header_map_pairs: Option<Vec<(&str, &str)>>,
-    ) -> &mut Self {
-        self.get_expect_handle()
-            .staged
-            .set_expect_set_header_map_pairs(map_type.map(|data| data as i32), header_map_pairs);
-        self
+    pub fn assert_header_map_value(&self, map_type: MapType, key: &str, value: Option<&str>) {
+        let map_name = format!("{:?}", map_type);
+        assert_eq!(
+            self.get_settings_handle()
+                .staged
+                .get_header_map_value(map_type as i32, key),
+            value.map(|data| data.as_bytes().to_vec()),
+            "Error: unexpected value for header {:?} in header map {}",
+            key,
+            map_name
+        );
     }
 
-    pub fn expect_get_header_map_value(
-        &mut self,
-        map_type: Option<MapType>,
-        header_map_key: Option<&'static str>,
-    ) -> ExpectGetHeaderMapValue {
-        ExpectGetHeaderMapValue::expecting(self, map_type.map(|data| data as i32), header_map_key)
+    // Most recent local response sent by the module
+    pub fn get_local_response(&self) -> Option<LocalResponse> {
+        self.get_local_responses().pop()
     }
 
-    pub fn expect_replace_header_map_value(
-        &mut self,
-        map_type: Option<MapType>,
-        header_map_key: Option<&str>,
-        header_map_value: Option<&str>,
-    ) -> &mut Self {
-        self.get_expect_handle()
-            .staged
-            .set_expect_replace_header_map_value(
-                map_type.map(|data| data as i32),
-                header_map_key,
-                header_map_value,
-            );
-        self
+    pub fn get_local_responses(&self) -> Vec<LocalResponse> {
+        self.get_settings_handle().staged.get_local_responses()
     }
 
-    pub fn expect_remove_header_map_value(
-        &mut self,
-        map_type: Option<MapType>,
-        header_map_key: Option<&str>,
-    ) -> &mut Self {
-        self.get_expect_handle()
+    pub fn is_grpc_call_open(&self, token_id: u32) -> bool {
+        self.get_settings_handle()
             .staged
-            .set_expect_remove_header_map_value(map_type.map(|data| data as i32), header_map_key);
-        self
+            .is_grpc_call_open(token_id)
     }
 
-    pub fn expect_add_header_map_value(
-        &mut self,
-        map_type: Option<MapType>,
-        header_map_key: Option<&str>,
-        header_map_value: Option<&str>,
-    ) -> &mut Self {
-        self.get_expect_handle()
+    // Messages the module sent on the grpc stream identified by token_id, in order
+    pub fn get_grpc_messages(&self, token_id: u32) -> Vec<Bytes> {
+        self.get_settings_handle()
             .staged
-            .set_expect_add_header_map_value(
-                map_type.map(|data| data as i32),
-                header_map_key,
-                header_map_value,
-            );
-        self
+            .get_grpc_messages(token_id)
     }
 
-    pub fn expect_send_local_response(
-        &mut self,
-        status_code: Option<i32>,
-        body: Option<&str>,
-        headers: Option<Vec<(&str, &str)>>,
-        grpc_status: Option<i32>,
-    ) -> &mut Self {
-        self.get_expect_handle()
+    pub fn get_property(&self, path: &[&str]) -> Option<Bytes> {
+        self.get_settings_handle()
             .staged
-            .set_expect_send_local_response(status_code, body, headers, grpc_status);
-        self
+            .get_property(&serialize_property_path(path.to_vec()))
     }
 
-    pub fn expect_http_call(
-        &mut self,
-        upstream: Option<&'static str>,
-        headers: Option<Vec<(&'static str, &'static str)>>,
-        body: Option<&'static str>,
-        trailers: Option<Vec<(&'static str, &'static str)>>,
-        timeout: Option<u64>,
-    ) -> ExpectHttpCall {
-        ExpectHttpCall::expecting(self, upstream, headers, body, trailers, timeout)
+    pub fn assert_property(&self, path: &[&str], value: Option<&[u8]>) {
+        assert_eq!(
+            self.get_property(path),
+            value.map(|data| data.to_vec()),
+            "Error: unexpected value for property {:?}",
+            path.join(".")
+        );
     }
 
-    /* ------------------------------------- High-level Expectation Setting ------------------------------------- */
+    pub fn assert_property_value<T: Into<PropertyValue>>(&self, path: &[&str], value: T) {
+        let value = value.into().encode();
+        self.assert_property(path, Some(&value));
+    }
 
-    pub fn set_quiet(&mut self, quiet: bool) {
-        self.mock_settings.quiet = quiet;
-        self.get_settings_handle().staged.set_quiet_mode(quiet);
+    pub fn get_metric(&self, name: &str) -> Option<i64> {
+        self.get_settings_handle().staged.get_metric_by_name(name)
     }
 
-    pub fn reset_default_tick_period_millis(&mut self) -> &mut Self {
-        self.get_settings_handle().staged.reset_tick_period_millis();
-        self
+    pub fn assert_metric(&self, name: &str, value: i64) {
+        assert_eq!(
+            self.get_metric(name),
+            Some(value),
+            "Error: unexpected value for metric {:?}",
+            name
+        );
     }
 
-    pub fn set_default_tick_period_millis(&mut self, tick_period_millis: u64) -> &mut Self {
-        self.get_settings_handle()
+    pub fn get_histogram_samples(&self, name: &str) -> Vec<u64> {
+        match self
+            .get_settings_handle()
             .staged
-            .set_tick_period_millis(tick_period_millis);
-        self
+            .get_histogram_samples(name)
+        {
+            Some(samples) => samples,
+            None => panic!("Error: no histogram metric defined with name {:?}", name),
+        }
     }
 
-    pub fn reset_default_buffer_bytes(&mut self) -> &mut Self {
-        self.get_settings_handle().staged.reset_buffer_bytes();
-        self
+    pub fn assert_histogram_count(&self, name: &str, count: usize) {
+        assert_eq!(
+            self.get_histogram_samples(name).len(),
+            count,
+            "Error: unexpected sample count for histogram {:?}",
+            name
+        );
     }
 
-    pub fn set_default_buffer_bytes(&mut self, buffer_type: BufferType) -> DefaultBufferBytes {
-        DefaultBufferBytes::expecting(self, buffer_type as i32)
+    pub fn assert_histogram_sum(&self, name: &str, sum: u64) {
+        assert_eq!(
+            self.get_histogram_samples(name).iter().sum::<u64>(),
+            sum,
+            "Error: unexpected sample sum for histogram {:?}",
+            name
+        );
     }
 
-    pub fn reset_default_header_map_pairs(&mut self) -> &mut Self {
-        self.get_settings_handle().staged.reset_header_map_pairs();
-        self
+    // Nearest-rank percentile over the recorded samples, e.g. percentile 50.0 for the median
+    pub fn get_histogram_percentile(&self, name: &str, percentile: f64) -> Option<u64> {
+        assert!(
+            percentile > 0.0 && percentile <= 100.0,
+            "Error: percentile must be within (0, 100]"
+        );
+        let mut samples = self.get_histogram_samples(name);
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_unstable();
+        let rank = (percentile / 100.0 * samples.len() as f64).ceil() as usize;
+        Some(samples[rank.max(1) - 1])
     }
 
-    pub fn set_default_header_map_pairs(&mut self, map_type: MapType) -> DefaultHeaderMapPairs {
-        DefaultHeaderMapPairs::expecting(self, map_type as i32)
+    pub fn assert_histogram_percentile(&self, name: &str, percentile: f64, value: u64) {
+        assert_eq!(
+            self.get_histogram_percentile(name, percentile),
+            Some(value),
+            "Error: unexpected p{} for histogram {:?}",
+            percentile,
+            name
+        );
     }
 
-    /* ------------------------------------- Utility Functions ------------------------------------- */
-
-    pub fn get_expect_handle(&self) -> MutexGuard<ExpectHandle> {
-        self.expect.lock().unwrap()
+    pub fn get_vm_id(&self) -> String {
+        self.get_settings_handle().staged.get_vm_id().to_string()
     }
 
-    pub fn print_expectations(&self) {
-        self.expect.lock().unwrap().print_staged();
+    pub fn get_root_id(&self) -> String {
+        self.get_settings_handle().staged.get_root_id().to_string()
     }
 
-    fn update_expect_stage(&mut self) {
-        self.expect
-            .lock()
-            .unwrap()
-            .update_stage(self.mock_settings.allow_unexpected);
+    // Applies to the current stage, keeping what is staged so far, and to every following one
+    pub fn set_expect_mode(&mut self, mode: ExpectMode) -> &mut Self {
+        let allow_unexpected = mode == ExpectMode::Lenient;
+        self.mock_settings.allow_unexpected = allow_unexpected;
+        self.get_expect_handle()
+            .staged
+            .set_allow_unexpected(allow_unexpected);
+        self
     }
 
-    fn assert_expect_stage(&mut self) {
-        self.expect.lock().unwrap().assert_stage();
+    pub fn get_expect_mode(&self) -> ExpectMode {
+        if self.mock_settings.allow_unexpected {
+            ExpectMode::Lenient
+        } else {
+            ExpectMode::Strict
+        }
     }
 
-    pub fn get_settings_handle(&self) -> MutexGuard<HostHandle> {
-        self.defaults.lock().unwrap()
+    // Lets calls of a noisy host function (trace logs, time, properties...) through without
+    // expectations, in this stage and every following one
+    pub fn ignore(&mut self, kind: HostcallKind) -> &mut Self {
+        let mut ignored = self.get_expect_handle().staged.get_ignored().to_vec();
+        if !ignored.contains(&kind) {
+            ignored.push(kind);
+        }
+        self.get_expect_handle().staged.set_ignored(ignored);
+        self
     }
 
-    pub fn print_host_settings(&self) {
-        self.defaults.lock().unwrap().print_staged();
+    pub fn clear_ignored(&mut self) -> &mut Self {
+        self.get_expect_handle().staged.set_ignored(Vec::new());
+        self
     }
 
-    pub fn reset_host_settings(&mut self) {
-        self.defaults
-            .lock()
-            .unwrap()
-            .reset(self.abi_version, self.mock_settings.quiet);
+    // Colors the expected and received sides of the diffs printed on mismatching host calls
+    pub fn colored_diffs(&mut self, on: bool) -> &mut Self {
+        self.get_expect_handle().staged.set_colored_diffs(on);
+        self
     }
 
     pub fn toggle_strict_mode(&mut self, on: bool) {
-        self.expect.lock().unwrap().update_stage(!on);
+        self.set_expect_mode(if on {
+            ExpectMode::Strict
+        } else {
+            ExpectMode::Lenient
+        });
+    }
+
+    // Host calls let through without an expectation in lenient mode, over all finished stages and
+    // the current one
+    pub fn get_warnings(&self) -> Vec<String> {
+        let expect_handle = self.get_expect_handle();
+        let mut warnings = expect_handle.get_warnings().to_vec();
+        if expect_handle.staged.get_allow_unexpected() {
+            warnings.extend_from_slice(expect_handle.staged.get_unexpected_calls());
+        }
+        warnings
     }
 
     /* ------------------------------------- Wasm Function Executation ------------------------------------- */
@@ -355,50 +1854,344 @@ impl Tester {
     }
 
     pub fn execute_and_expect(&mut self, expect_wasm: ReturnType) -> Result<()> {
+        self.execute_next_and_expect(expect_wasm)?;
+        self.deliver_held_body()?;
+        self.deliver_queue_ready(false)?;
+        self.deliver_http_call_responses()?;
+
+        if self.function_call.len() == 0 {
+            self.assert_expect_stage();
+            self.update_expect_stage();
+        }
+
+        println!("\n");
+        Ok(())
+    }
+
+    // Calls proxy_on_request_body/proxy_on_response_body (proxy_on_downstream/upstream_data) with the
+    // chunks held for streams the plugin resumed (proxy_continue_*, proxy_resume_*), until the plugin
+    // pauses again
+    fn deliver_held_body(&mut self) -> Result<()> {
+        loop {
+            let resumed_streams = self.get_settings_handle().staged.take_resumed_streams();
+            if resumed_streams.is_empty() {
+                return Ok(());
+            }
+            for (context_id, stream_type) in resumed_streams {
+                while let Some(index) = self
+                    .held_body
+                    .iter()
+                    .position(|(id, stream, _, _)| *id == context_id && *stream == stream_type)
+                {
+                    let (_, _, body_chunk, end_of_stream) = self.held_body.remove(index);
+                    let body_size = abi_size(body_chunk.len());
+                    let buffer_type = get_buffer_type(stream_type);
+                    let function_call = match stream_type {
+                        StreamType::HttpRequest => {
+                            FunctionCall::ProxyOnRequestBody(context_id, body_size, end_of_stream)
+                        }
+                        StreamType::HttpResponse => {
+                            FunctionCall::ProxyOnResponseBody(context_id, body_size, end_of_stream)
+                        }
+                        StreamType::Downstream => FunctionCall::ProxyOnDownstreamData(
+                            context_id,
+                            body_size,
+                            end_of_stream,
+                        ),
+                        StreamType::Upstream => {
+                            FunctionCall::ProxyOnUpstreamData(context_id, body_size, end_of_stream)
+                        }
+                    };
+                    self.get_settings_handle()
+                        .staged
+                        .set_buffer_data(buffer_type as i32, &body_chunk);
+                    self.function_call.insert(0, function_call);
+                    if self.execute_next()? == Some(Action::Pause as i64) {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    // Calls proxy_on_queue_ready for undelivered shared queue notifications according to the
+    // queue delivery mode (held notifications are only delivered when flushing)
+    fn deliver_queue_ready(&mut self, flush: bool) -> Result<()> {
+        let queue_delivery = self.get_settings_handle().staged.get_queue_delivery();
+        if !flush
+            && (queue_delivery == QueueDelivery::Manual || queue_delivery == QueueDelivery::Flush)
+        {
+            return Ok(());
+        }
+        loop {
+            let queue_ready = self.get_settings_handle().staged.take_queue_ready();
+            if queue_ready.is_empty() {
+                return Ok(());
+            }
+            for (context_id, queue_id) in queue_ready {
+                self.function_call.insert(
+                    0,
+                    FunctionCall::ProxyOnQueueReady(context_id, queue_id as i32),
+                );
+                self.function_type.insert(0, FunctionType::ReturnVoid);
+                self.execute_next_and_expect(ReturnType::None)?;
+            }
+        }
+    }
+
+    // Calls proxy_on_http_call_response for the callouts whose expectation was given a response
+    // with returning_response, with its headers (and :status) and body installed
+    fn deliver_http_call_responses(&mut self) -> Result<()> {
+        loop {
+            let responses = self.get_settings_handle().staged.take_http_call_responses();
+            if responses.is_empty() {
+                return Ok(());
+            }
+            for (context_id, token_id, response) in responses {
+                let status_code = response.status.to_string();
+                let mut header_map_pairs = vec![(":status", status_code.as_str())];
+                header_map_pairs.extend(
+                    response
+                        .headers
+                        .iter()
+                        .map(|(key, value)| (key.as_str(), value.as_str())),
+                );
+                let num_headers = header_map_pairs.len() as i32;
+                self.set_default_header_map_pairs(MapType::HttpCallResponseHeaders)
+                    .returning(header_map_pairs);
+                self.set_default_header_map_pairs(MapType::HttpCallResponseTrailers)
+                    .returning(
+                        response
+                            .trailers
+                            .iter()
+                            .map(|(key, value)| (key.as_str(), value.as_str()))
+                            .collect(),
+                    );
+                self.get_settings_handle()
+                    .staged
+                    .set_buffer_data(BufferType::HttpCallResponseBody as i32, &response.body);
+                self.function_call.insert(
+                    0,
+                    FunctionCall::ProxyOnHttpCallResponse(
+                        context_id,
+                        token_id as i32,
+                        num_headers,
+                        response.body.len() as i32,
+                        response.trailers.len() as i32,
+                    ),
+                );
+                self.function_type.insert(0, FunctionType::ReturnVoid);
+                self.execute_next_and_expect(ReturnType::None)?;
+            }
+        }
+    }
+
+    pub(crate) fn pending_calls(&self) -> usize {
+        self.function_call.len()
+    }
+
+    // Executes the next staged call without asserting on the expectation stage
+    pub(crate) fn execute_next_and_expect(&mut self, expect_wasm: ReturnType) -> Result<()> {
+        if self.is_short_circuited(self.function_call[0].clone()) {
+            let function_call = self.function_call.remove(0);
+            self.function_type.remove(0);
+            println!(
+                "[host] skipping {:?} after proxy_send_local_response",
+                function_call
+            );
+            self.restore_skip_after_staged();
+            return Ok(());
+        }
+        let return_wasm = self.execute_next()?;
+        match expect_wasm {
+            // results of arbitrary exports are not asserted on with ReturnType::None
+            ReturnType::None if self.function_type[0] == FunctionType::ReturnValue => {
+                self.function_type.remove(0);
+            }
+            ReturnType::None => {
+                assert_eq!(self.function_type.remove(0), FunctionType::ReturnVoid);
+                assert_eq!(return_wasm.is_none(), true);
+            }
+            ReturnType::Bool(expect_bool) => {
+                assert_eq!(self.function_type.remove(0), FunctionType::ReturnBool);
+                assert_eq!(expect_bool as i64, return_wasm.unwrap_or(-1));
+            }
+            ReturnType::Action(expect_action) => {
+                assert_eq!(self.function_type.remove(0), FunctionType::ReturnAction);
+                match return_wasm.and_then(Action::from_wasm) {
+                    Some(action) => assert_eq!(
+                        action as i64, expect_action as i64,
+                        "Error: expected Action::{:?} but wasm returned Action::{:?}",
+                        expect_action, action
+                    ),
+                    None => panic!(
+                        "Error: expected Action::{:?} but wasm returned {:?}, which is not a known action",
+                        expect_action, return_wasm
+                    ),
+                }
+            }
+            ReturnType::I32(expect_value) => {
+                assert_ne!(self.function_type.remove(0), FunctionType::ReturnVoid);
+                assert_eq!(Some(expect_value as i64), return_wasm);
+            }
+            ReturnType::U64(expect_value) => {
+                assert_ne!(self.function_type.remove(0), FunctionType::ReturnVoid);
+                assert_eq!(Some(expect_value), return_wasm.map(|value| value as u64));
+            }
+            ReturnType::AnyBool => {
+                assert_eq!(self.function_type.remove(0), FunctionType::ReturnBool);
+                assert!(
+                    return_wasm == Some(0) || return_wasm == Some(1),
+                    "Error: expected a bool return but wasm returned {:?}",
+                    return_wasm
+                );
+            }
+            ReturnType::AnyAction => {
+                assert_eq!(self.function_type.remove(0), FunctionType::ReturnAction);
+                assert!(
+                    return_wasm.and_then(Action::from_wasm).is_some(),
+                    "Error: expected an action return but wasm returned {:?}",
+                    return_wasm
+                );
+            }
+        }
+        self.restore_skip_after_staged();
+        Ok(())
+    }
+
+    // Executes the next staged call without asserting its return value, and renders it along with
+    // the hostcalls it made and the value it returned as a statement of a generated test
+    pub(crate) fn record_next(&mut self, tester_name: &str) -> Result<String> {
+        let function_call = self.function_call[0].clone();
+        self.get_expect_handle().staged.start_recording();
+        let return_wasm = self.execute_next();
+        let expectations = self.get_expect_handle().staged.stop_recording();
+        let return_wasm = return_wasm?;
+        let expect_wasm = match self.function_type.remove(0) {
+            FunctionType::ReturnVoid | FunctionType::ReturnValue => "ReturnType::None".to_string(),
+            FunctionType::ReturnBool => {
+                format!("ReturnType::Bool({})", return_wasm.unwrap_or(0) != 0)
+            }
+            FunctionType::ReturnAction => match return_wasm {
+                Some(action) if action == Action::Pause as i64 => {
+                    "ReturnType::Action(Action::Pause)".to_string()
+                }
+                _ => "ReturnType::Action(Action::Continue)".to_string(),
+            },
+        };
+
+        let call = match &function_call {
+            FunctionCall::CallExport(name, params) => scaffold::render_call_export(name, params),
+            function_call => scaffold::render_call(&format!("{:?}", function_call)),
+        };
+        let mut statement = format!("    {}\n        {}\n", tester_name, call);
+        for expectation in expectations {
+            statement += &format!("        {}\n", expectation);
+        }
+        statement += &format!("        .execute_and_expect({})?;\n", expect_wasm);
+        Ok(statement)
+    }
+
+    // Once a context has sent a local response, its remaining request and response callbacks are
+    // never delivered by the host (they are skipped, or fail the test when asserting on them)
+    fn is_short_circuited(&self, function_call: FunctionCall) -> bool {
+        let context_id = match function_call.get_context_id() {
+            Some(context_id) if function_call.is_http_stream_callback() => context_id,
+            _ => return false,
+        };
+        if !self
+            .get_settings_handle()
+            .staged
+            .has_sent_local_response(context_id)
+        {
+            return false;
+        }
+        if self.assert_no_data_after_local_response {
+            panic!(
+                "Error: {:?} called after context {} sent a local response",
+                function_call, context_id
+            );
+        }
+        self.skip_after_local_response
+    }
+
+    // Moves the context of a callback along created -> active -> done -> logged -> deleted, failing
+    // on callbacks driven out of that order when lifecycle validation is enabled
+    fn track_lifecycle(&mut self, function_call: &FunctionCall, context_id: i32) {
+        let next = match function_call {
+            FunctionCall::ProxyOnContextCreate(..) => return,
+            FunctionCall::ProxyOnDone(..) => ContextState::Done,
+            FunctionCall::ProxyOnLog(..) => ContextState::Logged,
+            FunctionCall::ProxyOnDelete(..) => ContextState::Deleted,
+            _ => ContextState::Active,
+        };
+        if self.validate_lifecycle && !self.contexts.contains(context_id) {
+            panic!(
+                "Error: {:?} called on context {} which was never created",
+                function_call, context_id
+            );
+        }
+        if let Err(state) = self.contexts.transition(context_id, next) {
+            if self.validate_lifecycle {
+                panic!(
+                    "Error: {:?} driven out of order, context {} is {:?} and cannot become {:?}",
+                    function_call, context_id, state, next
+                );
+            }
+        }
+    }
+
+    // Executes the next staged call and returns the raw value returned by the wasm function
+    fn execute_next(&mut self) -> Result<Option<i64>> {
         let mut return_wasm: Option<i32> = None;
-        match self.function_call.remove(0) {
+        let function_call = self.function_call.remove(0);
+        if let Some(context_id) = function_call.get_context_id() {
+            self.get_settings_handle()
+                .staged
+                .set_effective_context_id(context_id);
+            self.track_lifecycle(&function_call, context_id);
+        }
+        match function_call {
             FunctionCall::Start() => {
-                let _start = self
-                    .instance
-                    .get_func("_start")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find `_start` function export"
-                    ))?
-                    .get0::<()>()?;
                 println!("[host->vm] _start()");
-                _start()?;
+                self.runtime.call_void("_start", &[])?;
+            }
+
+            FunctionCall::CallExport(name, params) => {
+                println!("[host->vm] {}(params={:?})", name, params);
+                let results = self.runtime.call_export(&name, &params)?;
+                println!("[host<-vm] {} return: results={:?}", name, results);
+                return match results.first() {
+                    Some(Val::I32(result)) => Ok(Some(*result as i64)),
+                    Some(Val::I64(result)) => Ok(Some(*result)),
+                    _ => Ok(None),
+                };
             }
 
             FunctionCall::ProxyOnVmStart(context_id, vm_configuration_size) => {
-                let proxy_on_vm_start = self
-                    .instance
-                    .get_func("proxy_on_vm_start")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find `proxy_on_vm_start` function export"
-                    ))?
-                    .get2::<i32, i32, i32>()?;
                 println!(
                     "[host->vm] proxy_on_vm_start(context_id={}, vm_configuration_size={})",
                     context_id, vm_configuration_size
                 );
-                let success = proxy_on_vm_start(context_id, vm_configuration_size)?;
+                self.get_settings_handle().staged.set_vm_starting(true);
+                let success = self
+                    .runtime
+                    .call_i32("proxy_on_vm_start", &[context_id, vm_configuration_size]);
+                self.get_settings_handle().staged.set_vm_starting(false);
+                let success = success?;
                 println!("[host<-vm] proxy_on_vm_start return: success={}", success);
                 return_wasm = Some(success);
             }
 
             FunctionCall::ProxyValidateConfiguration(root_context_id, configuration_size) => {
-                let proxy_validate_configuration = self
-                    .instance
-                    .get_func("proxy_validate_configuration")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find `proxy_validate_configuration` function export"
-                    ))?
-                    .get2::<i32, i32, i32>()?;
                 println!(
                     "[host->vm] proxy_validate_configuration(root_context_id={}, configuration_size={})",
                     root_context_id, configuration_size
                 );
-                let success = proxy_validate_configuration(root_context_id, configuration_size)?;
+                let success = self.runtime.call_i32(
+                    "proxy_validate_configuration",
+                    &[root_context_id, configuration_size],
+                )?;
                 println!(
                     "[host<-vm] proxy_validate_configuration return: success={}",
                     success
@@ -407,46 +2200,31 @@ impl Tester {
             }
 
             FunctionCall::ProxyOnConfigure(context_id, plugin_configuration_size) => {
-                let proxy_on_configure = self
-                    .instance
-                    .get_func("proxy_on_configure")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find 'proxy_on_configure' function export"
-                    ))?
-                    .get2::<i32, i32, i32>()?;
                 println!(
                     "[host->vm] proxy_on_configure(context_id={}, plugin_configuration_size={})",
                     context_id, plugin_configuration_size
                 );
-                let success = proxy_on_configure(context_id, plugin_configuration_size)?;
+                let success = self.runtime.call_i32(
+                    "proxy_on_configure",
+                    &[context_id, plugin_configuration_size],
+                )?;
                 println!("[host<-vm] proxy_on_configure return: success={}", success);
                 return_wasm = Some(success);
             }
 
             FunctionCall::ProxyOnTick(context_id) => {
-                let proxy_on_tick = self
-                    .instance
-                    .get_func("proxy_on_tick")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find `proxy_on_tick` function export"
-                    ))?
-                    .get1::<i32, ()>()?;
                 println!("[host->vm] proxy_on_tick(context_id={})", context_id);
-                proxy_on_tick(context_id)?;
+                self.runtime.call_void("proxy_on_tick", &[context_id])?;
             }
 
             FunctionCall::ProxyOnForeignFunction(root_context_id, function_id, data_size) => {
                 assert_eq!(self.abi_version, AbiVersion::ProxyAbiVersion0_2_0);
-                let proxy_on_foreign_function = self
-                    .instance
-                    .get_func("proxy_on_foreign_function")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find 'proxy_on_foreign_function' function export"
-                    ))?
-                    .get3::<i32, i32, i32, i32>()?;
                 println!("[host->vm] proxy_on_foreign_function(root_context_id={}, function_id={}, data_size={})", 
                     root_context_id, function_id, data_size);
-                let action = proxy_on_foreign_function(root_context_id, function_id, data_size)?;
+                let action = self.runtime.call_i32(
+                    "proxy_on_foreign_function",
+                    &[root_context_id, function_id, data_size],
+                )?;
                 println!(
                     "[host<-vm] proxy_on_foreign_function return: action={}",
                     action
@@ -455,49 +2233,38 @@ impl Tester {
             }
 
             FunctionCall::ProxyOnQueueReady(context_id, queue_id) => {
-                let proxy_on_queue_ready = self
-                    .instance
-                    .get_func("proxy_on_queue_ready")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find 'proxy_on_queue_ready' function export"
-                    ))?
-                    .get2::<i32, i32, ()>()?;
                 println!(
                     "[host->vm] proxy_on_queue_ready(context_id={}, queue_id={})",
                     context_id, queue_id
                 );
-                proxy_on_queue_ready(context_id, queue_id)?;
+                self.runtime
+                    .call_void("proxy_on_queue_ready", &[context_id, queue_id])?;
             }
 
             // Stream calls
             FunctionCall::ProxyOnContextCreate(root_context_id, parent_context_id) => {
-                let proxy_on_context_create = self
-                    .instance
-                    .get_func("proxy_on_context_create")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find `proxy_on_context_create` function export"
-                    ))?
-                    .get2::<i32, i32, ()>()?;
                 println!(
                     "[host->vm] proxy_on_context_create(root_context_id={}, parent_context_id={})",
                     root_context_id, parent_context_id
                 );
-                proxy_on_context_create(root_context_id, parent_context_id)?;
+                self.get_settings_handle()
+                    .staged
+                    .create_context(root_context_id);
+                self.contexts.register(root_context_id, parent_context_id);
+                self.runtime.call_void(
+                    "proxy_on_context_create",
+                    &[root_context_id, parent_context_id],
+                )?;
             }
 
             FunctionCall::ProxyOnNewConnection(context_id) => {
-                let proxy_on_new_connection = self
-                    .instance
-                    .get_func("proxy_on_new_connection")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find 'proxy_on_new_connection' function export"
-                    ))?
-                    .get1::<i32, i32>()?;
                 println!(
                     "[host->vm] proxy_on_new_connection(context_id={})",
                     context_id
                 );
-                let action = proxy_on_new_connection(context_id)?;
+                let action = self
+                    .runtime
+                    .call_i32("proxy_on_new_connection", &[context_id])?;
                 println!(
                     "[host<-vm] proxy_on_new_connection return: action={}",
                     action
@@ -506,18 +2273,14 @@ impl Tester {
             }
 
             FunctionCall::ProxyOnDownstreamData(context_id, data_size, end_of_stream) => {
-                let proxy_on_downstream_data = self
-                    .instance
-                    .get_func("proxy_on_downstream_data")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find 'proxy_on_downstream_data' function export"
-                    ))?
-                    .get3::<i32, i32, i32, i32>()?;
                 println!(
                         "[host->vm] proxy_on_downstream_data(context_id={}, data_size={}, end_of_stream={})",
                         context_id, data_size, end_of_stream
                     );
-                let action = proxy_on_downstream_data(context_id, data_size, end_of_stream as i32)?;
+                let action = self.runtime.call_i32(
+                    "proxy_on_downstream_data",
+                    &[context_id, data_size, end_of_stream as i32],
+                )?;
                 println!(
                     "[host<-vm] proxy_on_downstream_data return: action={}",
                     action
@@ -526,33 +2289,25 @@ impl Tester {
             }
 
             FunctionCall::ProxyOnDownstreamConnectionClose(context_id, peer_type) => {
-                let proxy_on_downstream_connection_close = self
-                    .instance
-                    .get_func("proxy_on_downstream_connection_close")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find 'proxy_on_downstream_connection_close' function export"
-                    ))?
-                    .get2::<i32, i32, ()>()?;
                 println!(
                     "[host->vm] proxy_on_downstream_connection_close(context_id={}, peer_data={})",
                     context_id, peer_type as i32
                 );
-                proxy_on_downstream_connection_close(context_id, peer_type)?;
+                self.runtime.call_void(
+                    "proxy_on_downstream_connection_close",
+                    &[context_id, peer_type],
+                )?;
             }
 
             FunctionCall::ProxyOnUpstreamData(context_id, data_size, end_of_stream) => {
-                let proxy_on_upstream_data = self
-                    .instance
-                    .get_func("proxy_on_upstream_data")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find 'proxy_on_upstream_data' function export"
-                    ))?
-                    .get3::<i32, i32, i32, i32>()?;
                 println!(
                         "[host->vm] proxy_on_upstream_data(context_id={}, data_size={}, end_of_stream={})",
                         context_id, data_size, end_of_stream
                     );
-                let action = proxy_on_upstream_data(context_id, data_size, end_of_stream as i32)?;
+                let action = self.runtime.call_i32(
+                    "proxy_on_upstream_data",
+                    &[context_id, data_size, end_of_stream as i32],
+                )?;
                 println!(
                     "[host<-vm] proxy_on_upstream_data return: action={}",
                     action
@@ -561,40 +2316,28 @@ impl Tester {
             }
 
             FunctionCall::ProxyOnUpstreamConnectionClose(context_id, peer_type) => {
-                let proxy_on_upstream_connection_close = self
-                    .instance
-                    .get_func("proxy_on_upstream_connection_close")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find 'proxy_on_upstream_connection_close' function export"
-                    ))?
-                    .get2::<i32, i32, ()>()?;
                 println!(
                     "[host->vm] proxy_on_upstream_connection_close(context_id={}, peer_data={})",
                     context_id, peer_type as i32
                 );
-                proxy_on_upstream_connection_close(context_id, peer_type)?;
+                self.runtime.call_void(
+                    "proxy_on_upstream_connection_close",
+                    &[context_id, peer_type],
+                )?;
             }
 
             FunctionCall::ProxyOnRequestHeaders(context_id, num_headers, end_of_stream) => {
-                let proxy_on_request_headers = self
-                    .instance
-                    .get_func("proxy_on_request_headers")
-                    .ok_or(anyhow::format_err!(
-                    "Error: failed to find `proxy_on_request_headers` function export"
-                ))?;
                 println!(
                     "[host->vm] proxy_on_request_headers(context_id={}, num_headers={}, end_of_stream={})",
                     context_id, num_headers, end_of_stream
                 );
                 let action = match self.abi_version {
-                    AbiVersion::ProxyAbiVersion0_1_0 => {
-                        proxy_on_request_headers.get2::<i32, i32, i32>()?(context_id, num_headers)?
-                    }
-                    AbiVersion::ProxyAbiVersion0_2_0 => proxy_on_request_headers
-                        .get3::<i32, i32, i32, i32>()?(
-                        context_id,
-                        num_headers,
-                        end_of_stream as i32,
+                    AbiVersion::ProxyAbiVersion0_1_0 => self
+                        .runtime
+                        .call_i32("proxy_on_request_headers", &[context_id, num_headers])?,
+                    AbiVersion::ProxyAbiVersion0_2_0 => self.runtime.call_i32(
+                        "proxy_on_request_headers",
+                        &[context_id, num_headers, end_of_stream as i32],
                     )?,
                     _ => panic!(
                         "Error: proxy_on_request_headers not supported for {:?}",
@@ -610,35 +2353,26 @@ impl Tester {
             }
 
             FunctionCall::ProxyOnRequestBody(context_id, body_size, end_of_stream) => {
-                let proxy_on_request_body = self
-                    .instance
-                    .get_func("proxy_on_request_body")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find 'proxy_on_request_body' function export"
-                    ))?
-                    .get3::<i32, i32, i32, i32>()?;
                 println!(
                         "[host->vm] proxy_on_request_body(context_id={}, body_size={}, end_of_stream={})",
                         context_id, body_size, end_of_stream
                     );
-                let action = proxy_on_request_body(context_id, body_size, end_of_stream as i32)?;
+                let action = self.runtime.call_i32(
+                    "proxy_on_request_body",
+                    &[context_id, body_size, end_of_stream as i32],
+                )?;
                 println!("[host<-vm] proxy_on_request_body return: action={}", action);
                 return_wasm = Some(action);
             }
 
             FunctionCall::ProxyOnRequestTrailers(context_id, num_trailers) => {
-                let proxy_on_request_trailers = self
-                    .instance
-                    .get_func("proxy_on_request_trailers")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find `proxy_on_request_trailers` function export"
-                    ))?
-                    .get2::<i32, i32, i32>()?;
                 println!(
                     "[host->vm] proxy_on_request_trailers(context_id={}, num_trailers={})",
                     context_id, num_trailers
                 );
-                let action = proxy_on_request_trailers(context_id, num_trailers)?;
+                let action = self
+                    .runtime
+                    .call_i32("proxy_on_request_trailers", &[context_id, num_trailers])?;
                 println!(
                     "[host<-vm] proxy_on_request_trailers return: action={}",
                     action
@@ -647,18 +2381,13 @@ impl Tester {
             }
 
             FunctionCall::ProxyOnRequestMetadata(context_id, nelements) => {
-                let proxy_on_request_metadata = self
-                    .instance
-                    .get_func("proxy_on_request_metadata")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find `proxy_on_request_metadata` function export"
-                    ))?
-                    .get2::<i32, i32, i32>()?;
                 println!(
                     "[host->vm] proxy_on_request_metadata(context_id={}, nelements={})",
                     context_id, nelements
                 );
-                let action = proxy_on_request_metadata(context_id, nelements)?;
+                let action = self
+                    .runtime
+                    .call_i32("proxy_on_request_metadata", &[context_id, nelements])?;
                 println!(
                     "[host<-vm] proxy_on_request_metadata return: action={}",
                     action
@@ -667,25 +2396,17 @@ impl Tester {
             }
 
             FunctionCall::ProxyOnResponseHeaders(context_id, num_headers, end_of_stream) => {
-                let proxy_on_response_headers = self
-                    .instance
-                    .get_func("proxy_on_response_headers")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find `proxy_on_response_headers` function export"
-                    ))?;
                 println!(
                         "[host->vm] proxy_on_response_headers(context_id={}, num_headers={}, end_of_stream={})",
                         context_id, num_headers, end_of_stream
                     );
                 let action = match self.abi_version {
-                    AbiVersion::ProxyAbiVersion0_1_0 => {
-                        proxy_on_response_headers.get2::<i32, i32, i32>()?(context_id, num_headers)?
-                    }
-                    AbiVersion::ProxyAbiVersion0_2_0 => proxy_on_response_headers
-                        .get3::<i32, i32, i32, i32>()?(
-                        context_id,
-                        num_headers,
-                        end_of_stream as i32,
+                    AbiVersion::ProxyAbiVersion0_1_0 => self
+                        .runtime
+                        .call_i32("proxy_on_response_headers", &[context_id, num_headers])?,
+                    AbiVersion::ProxyAbiVersion0_2_0 => self.runtime.call_i32(
+                        "proxy_on_response_headers",
+                        &[context_id, num_headers, end_of_stream as i32],
                     )?,
                     _ => panic!(
                         "Error: proxy_on_response_headers not supported for {:?}",
@@ -700,35 +2421,26 @@ impl Tester {
             }
 
             FunctionCall::ProxyOnResponseBody(context_id, body_size, end_of_stream) => {
-                let proxy_on_response_body = self
-                    .instance
-                    .get_func("proxy_on_response_body")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find 'proxy_on_response_body' function export"
-                    ))?
-                    .get3::<i32, i32, i32, i32>()?;
                 println!(
                         "[host->vm] proxy_on_response_body(context_id={}, body_size={}, end_of_stream={})",
                         context_id, body_size, end_of_stream
                     );
-                let action = proxy_on_response_body(context_id, body_size, end_of_stream as i32)?;
+                let action = self.runtime.call_i32(
+                    "proxy_on_response_body",
+                    &[context_id, body_size, end_of_stream as i32],
+                )?;
                 println!("[host<-vm] function return: action -> {}", action);
                 return_wasm = Some(action);
             }
 
             FunctionCall::ProxyOnResponseTrailers(context_id, num_trailers) => {
-                let proxy_on_response_trailers = self
-                    .instance
-                    .get_func("proxy_on_response_trailers")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find `proxy_on_response_trailers` function export"
-                    ))?
-                    .get2::<i32, i32, i32>()?;
                 println!(
                     "[host->vm] proxy_on_response_trailers(context_id={}, num_trailers={})",
                     context_id, num_trailers
                 );
-                let action = proxy_on_response_trailers(context_id, num_trailers)?;
+                let action = self
+                    .runtime
+                    .call_i32("proxy_on_response_trailers", &[context_id, num_trailers])?;
                 println!(
                     "[host<-vm] proxy_on_response_body return: action={}",
                     action
@@ -737,18 +2449,13 @@ impl Tester {
             }
 
             FunctionCall::ProxyOnResponseMetadata(context_id, nelements) => {
-                let proxy_on_response_metadata = self
-                    .instance
-                    .get_func("proxy_on_response_metadata")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find `proxy_on_response_metadata` function export"
-                    ))?
-                    .get2::<i32, i32, i32>()?;
                 println!(
                     "[host->vm] call_proxy_on_response_metadata(context_id={}, nelements={})",
                     context_id, nelements
                 );
-                let action = proxy_on_response_metadata(context_id, nelements)?;
+                let action = self
+                    .runtime
+                    .call_i32("proxy_on_response_metadata", &[context_id, nelements])?;
                 println!(
                     "[host<-vm] proxy_on_response_metadata return: action={}",
                     action
@@ -756,6 +2463,28 @@ impl Tester {
                 return_wasm = Some(action);
             }
 
+            FunctionCall::ProxyOnResponse1xx(context_id, num_headers) => {
+                if self
+                    .runtime
+                    .get_export_results("proxy_on_response_1xx")
+                    .is_none()
+                {
+                    return Err(anyhow::format_err!(
+                        "Error: failed to find `proxy_on_response_1xx` function export \
+                        (informational responses are not part of this module's ABI)"
+                    ));
+                }
+                println!(
+                    "[host->vm] proxy_on_response_1xx(context_id={}, num_headers={})",
+                    context_id, num_headers
+                );
+                let action = self
+                    .runtime
+                    .call_i32("proxy_on_response_1xx", &[context_id, num_headers])?;
+                println!("[host<-vm] proxy_on_response_1xx return: action={}", action);
+                return_wasm = Some(action);
+            }
+
             // HTTP/gRPC
             FunctionCall::ProxyOnHttpCallResponse(
                 context_id,
@@ -764,13 +2493,6 @@ impl Tester {
                 body_size,
                 num_trailers,
             ) => {
-                let proxy_on_http_call_response = self
-                    .instance
-                    .get_func("proxy_on_http_call_response")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find `proxy_on_http_call_response` function export"
-                    ))?
-                    .get5::<i32, i32, i32, i32, i32, ()>()?;
                 println!(
                         "[host->vm] proxy_on_http_call_response(context_id={}, callout_id={}, num_headers={}",
                         context_id, callout_id, num_headers
@@ -779,134 +2501,88 @@ impl Tester {
                     "                                       body_size={}, num_trailers={})",
                     body_size, num_trailers
                 );
-                proxy_on_http_call_response(
-                    context_id,
-                    callout_id,
-                    num_headers,
-                    body_size,
-                    num_trailers,
+                self.runtime.call_void(
+                    "proxy_on_http_call_response",
+                    &[context_id, callout_id, num_headers, body_size, num_trailers],
                 )?;
             }
 
             FunctionCall::ProxyOnGrpcReceiveInitialMetadata(context_id, token, headers) => {
-                let proxy_on_grpc_receive_initial_metadata = self
-                    .instance
-                    .get_func("proxy_on_grpc_receive_initial_metadata")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find 'proxy_on_grpc_receive_initial_metadata' function export"
-                    ))?
-                    .get3::<i32, i32, i32, ()>()?;
                 println!("[host->vm] proxy_on_grpc_receive_initial_metadata(context_id={}, token={}, headers={})", context_id, token, headers);
-                proxy_on_grpc_receive_initial_metadata(context_id, token, headers)?;
+                self.runtime.call_void(
+                    "proxy_on_grpc_receive_initial_metadata",
+                    &[context_id, token, headers],
+                )?;
             }
 
             FunctionCall::ProxyOnGrpcReceiveTrailingMetadata(context_id, token, trailers) => {
-                let proxy_on_grpc_trailing_metadata = self
-                    .instance
-                    .get_func("proxy_on_grpc_receive_trailing_metadata")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find 'proxy_on_grpc_trailing_metadata' function export"
-                    ))?
-                    .get3::<i32, i32, i32, ()>()?;
                 println!(
                         "[host->vm] proxy_on_grpc_receive_trailing_metadata(context_id={}, token={}, trailers={})",
                         context_id, token, trailers
                     );
-                proxy_on_grpc_trailing_metadata(context_id, token, trailers)?;
+                self.runtime.call_void(
+                    "proxy_on_grpc_receive_trailing_metadata",
+                    &[context_id, token, trailers],
+                )?;
             }
 
             FunctionCall::ProxyOnGrpcReceive(context_id, token, response_size) => {
-                let proxy_on_grpc_receive = self
-                    .instance
-                    .get_func("proxy_on_grpc_receive")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find 'proxy_on_grpc_receive' function export"
-                    ))?
-                    .get3::<i32, i32, i32, ()>()?;
                 println!(
                     "[host->vm] proxy_on_grpc_receive(context_id={}, token={}, response_size={})",
                     context_id, token, response_size
                 );
-                proxy_on_grpc_receive(context_id, token, response_size)?;
+                self.runtime
+                    .call_void("proxy_on_grpc_receive", &[context_id, token, response_size])?;
             }
 
             FunctionCall::ProxyOnGrpcClose(context_id, token, status_code) => {
-                let proxy_on_grpc_close = self
-                    .instance
-                    .get_func("proxy_on_grpc_close")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find 'proxy_on_grpc_close' function export"
-                    ))?
-                    .get3::<i32, i32, i32, ()>()?;
                 println!(
                     "[host->vm] proxy_on_grpc_close(context_id={}, token={}, status_code={})",
                     context_id, token, status_code
                 );
-                proxy_on_grpc_close(context_id, token, status_code)?;
+                // the remote end closed the call or stream, so the token is no longer outstanding
+                self.get_settings_handle()
+                    .staged
+                    .close_grpc_call(token as u32);
+                self.runtime
+                    .call_void("proxy_on_grpc_close", &[context_id, token, status_code])?;
             }
 
             // The stream/vm has completed
             FunctionCall::ProxyOnDone(context_id) => {
-                let proxy_on_done = self
-                    .instance
-                    .get_func("proxy_on_done")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find 'proxy_on_done' function export"
-                    ))?
-                    .get1::<i32, i32>()?;
                 println!("[host->vm] proxy_on_done(context_id={})", context_id);
-                let is_done = proxy_on_done(context_id)?;
+                let is_done = self.runtime.call_i32("proxy_on_done", &[context_id])?;
                 println!("[host<-vm] proxy_on_done return: is_done={}", is_done);
+                if is_done == 0 {
+                    // deletion is deferred until the plugin calls proxy_done for this context
+                    self.get_settings_handle()
+                        .staged
+                        .set_pending_done(context_id);
+                }
                 return_wasm = Some(is_done);
             }
 
             FunctionCall::ProxyOnLog(context_id) => {
-                let proxy_on_log = self
-                    .instance
-                    .get_func("proxy_on_log")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find `proxy_on_log` function export"
-                    ))?
-                    .get1::<i32, ()>()?;
                 println!("[host->vm] proxy_on_log(context_id={})", context_id);
-                proxy_on_log(context_id)?;
+                self.runtime.call_void("proxy_on_log", &[context_id])?;
             }
 
             FunctionCall::ProxyOnDelete(context_id) => {
-                let proxy_on_delete = self
-                    .instance
-                    .get_func("proxy_on_delete")
-                    .ok_or(anyhow::format_err!(
-                        "Error: failed to find 'proxy_on_delete' function export"
-                    ))?
-                    .get1::<i32, ()>()?;
+                if self
+                    .get_settings_handle()
+                    .staged
+                    .is_pending_done(context_id)
+                {
+                    panic!(
+                        "Error: proxy_on_delete(context_id={}) called before the plugin signalled completion with proxy_done",
+                        context_id
+                    );
+                }
                 println!("[host->vm] proxy_on_delete(context_id={})", context_id);
-                proxy_on_delete(context_id)?;
-            }
-        }
-
-        match expect_wasm {
-            ReturnType::None => {
-                assert_eq!(self.function_type.remove(0), FunctionType::ReturnVoid);
-                assert_eq!(return_wasm.is_none(), true);
-            }
-            ReturnType::Bool(expect_bool) => {
-                assert_eq!(self.function_type.remove(0), FunctionType::ReturnBool);
-                assert_eq!(expect_bool as i32, return_wasm.unwrap_or(-1));
+                self.runtime.call_void("proxy_on_delete", &[context_id])?;
             }
-            ReturnType::Action(expect_action) => {
-                assert_eq!(self.function_type.remove(0), FunctionType::ReturnAction);
-                assert_eq!(expect_action as i32, return_wasm.unwrap_or(-1));
-            }
-        }
-
-        if self.function_call.len() == 0 {
-            self.assert_expect_stage();
-            self.update_expect_stage();
         }
-
-        println!("\n");
-        Ok(())
+        Ok(return_wasm.map(|value| value as i64))
     }
 
     /* ------------------------------------- Calls in setting ------------------------------------- */
@@ -917,6 +2593,20 @@ impl Tester {
         self
     }
 
+    // Calls an export outside of the proxy-wasm ABI (test hooks, initialization helpers), whose
+    // results are ignored by execute_and_expect(ReturnType::None)
+    pub fn call_export(&mut self, name: &str, params: &[Val]) -> &mut Self {
+        let function_type = match self.runtime.get_export_results(name) {
+            Some(0) => FunctionType::ReturnVoid,
+            Some(_) => FunctionType::ReturnValue,
+            None => panic!("Error: failed to find `{}` function export", name),
+        };
+        self.function_call
+            .push(FunctionCall::CallExport(name.to_string(), params.to_vec()));
+        self.function_type.push(function_type);
+        self
+    }
+
     pub fn call_proxy_on_vm_start(
         &mut self,
         context_id: i32,
@@ -944,6 +2634,16 @@ impl Tester {
         self
     }
 
+    // Serves the configuration as the plugin configuration and passes its size to the module
+    pub fn call_proxy_validate_configuration_with(
+        &mut self,
+        root_context_id: i32,
+        configuration: &str,
+    ) -> &mut Self {
+        self.set_plugin_configuration(configuration.as_bytes());
+        self.call_proxy_validate_configuration(root_context_id, configuration.len() as i32)
+    }
+
     pub fn call_proxy_on_configure(
         &mut self,
         context_id: i32,
@@ -957,6 +2657,29 @@ impl Tester {
         self
     }
 
+    // Passes the size of the configuration given to set_vm_configuration
+    pub fn call_proxy_on_vm_start_with_vm_configuration(&mut self, context_id: i32) -> &mut Self {
+        let vm_configuration_size = self
+            .get_settings_handle()
+            .staged
+            .get_vm_configuration()
+            .len();
+        self.call_proxy_on_vm_start(context_id, abi_size(vm_configuration_size))
+    }
+
+    // Passes the size of the configuration given to set_plugin_configuration
+    pub fn call_proxy_on_configure_with_plugin_configuration(
+        &mut self,
+        context_id: i32,
+    ) -> &mut Self {
+        let plugin_configuration_size = self
+            .get_settings_handle()
+            .staged
+            .get_plugin_configuration()
+            .len();
+        self.call_proxy_on_configure(context_id, abi_size(plugin_configuration_size))
+    }
+
     pub fn call_proxy_on_tick(&mut self, context_id: i32) -> &mut Self {
         self.function_call
             .push(FunctionCall::ProxyOnTick(context_id));
@@ -1173,6 +2896,14 @@ impl Tester {
         self
     }
 
+    // Informational (1xx) response headers, e.g. 100-continue
+    pub fn call_proxy_on_response_1xx(&mut self, context_id: i32, num_headers: i32) -> &mut Self {
+        self.function_call
+            .push(FunctionCall::ProxyOnResponse1xx(context_id, num_headers));
+        self.function_type.push(FunctionType::ReturnAction);
+        self
+    }
+
     // HTTP/gRPC
     pub fn call_proxy_on_http_call_response(
         &mut self,
@@ -1237,16 +2968,29 @@ impl Tester {
         self
     }
 
-    pub fn proxy_on_grpc_close(
+    // Frames and installs the message as the GrpcReceiveBuffer before delivering it, so the module
+    // reads back exactly the response_size it is told about
+    pub fn call_proxy_on_grpc_receive_message(
+        &mut self,
+        context_id: i32,
+        token: i32,
+        message: &[u8],
+    ) -> &mut Self {
+        let response_size = serialize_grpc_frame(message, false).len() as i32;
+        self.set_default_grpc_message(message)
+            .call_proxy_on_grpc_receive(context_id, token, response_size)
+    }
+
+    pub fn call_proxy_on_grpc_close(
         &mut self,
         context_id: i32,
         token: i32,
-        status_code: i32,
+        status_code: GrpcStatus,
     ) -> &mut Self {
         self.function_call.push(FunctionCall::ProxyOnGrpcClose(
             context_id,
             token,
-            status_code,
+            status_code as i32,
         ));
         self.function_type.push(FunctionType::ReturnVoid);
         self
@@ -1274,7 +3018,285 @@ impl Tester {
         self
     }
 
+    // Holds body chunks for a paused stream, delivered once the plugin resumes the request
+    pub fn hold_request_body(&mut self, context_id: i32, body_chunks: Vec<&[u8]>) -> &mut Self {
+        self.hold_body(context_id, StreamType::HttpRequest, body_chunks);
+        self
+    }
+
+    // Holds body chunks for a paused stream, delivered once the plugin resumes the response
+    pub fn hold_response_body(&mut self, context_id: i32, body_chunks: Vec<&[u8]>) -> &mut Self {
+        self.hold_body(context_id, StreamType::HttpResponse, body_chunks);
+        self
+    }
+
+    // Holds data chunks for a paused connection, delivered once the plugin resumes the downstream
+    pub fn hold_downstream_data(&mut self, context_id: i32, data_chunks: Vec<&[u8]>) -> &mut Self {
+        self.hold_body(context_id, StreamType::Downstream, data_chunks);
+        self
+    }
+
+    // Holds data chunks for a paused connection, delivered once the plugin resumes the upstream
+    pub fn hold_upstream_data(&mut self, context_id: i32, data_chunks: Vec<&[u8]>) -> &mut Self {
+        self.hold_body(context_id, StreamType::Upstream, data_chunks);
+        self
+    }
+
+    fn hold_body(&mut self, context_id: i32, stream_type: StreamType, body_chunks: Vec<&[u8]>) {
+        let num_chunks = body_chunks.len();
+        for (index, body_chunk) in body_chunks.into_iter().enumerate() {
+            self.held_body.push((
+                context_id,
+                stream_type,
+                body_chunk.to_vec(),
+                index + 1 == num_chunks,
+            ));
+        }
+    }
+
+    /* ----------------------------------- Size-Safe Calls ----------------------------------- */
+    // Variants of the calls above taking host sizes, converted to the u32 sizes of the ABI so that
+    // declared sizes above 2GiB reach the plugin as they would from a real host
+    pub fn call_proxy_on_vm_start_with_size(
+        &mut self,
+        context_id: i32,
+        vm_configuration_size: usize,
+    ) -> &mut Self {
+        self.call_proxy_on_vm_start(context_id, abi_size(vm_configuration_size))
+    }
+
+    pub fn call_proxy_on_configure_with_size(
+        &mut self,
+        context_id: i32,
+        plugin_configuration_size: usize,
+    ) -> &mut Self {
+        self.call_proxy_on_configure(context_id, abi_size(plugin_configuration_size))
+    }
+
+    pub fn call_proxy_on_downstream_data_with_size(
+        &mut self,
+        context_id: i32,
+        data_size: usize,
+        end_of_stream: bool,
+    ) -> &mut Self {
+        self.call_proxy_on_downstream_data(context_id, abi_size(data_size), end_of_stream)
+    }
+
+    pub fn call_proxy_on_upstream_data_with_size(
+        &mut self,
+        context_id: i32,
+        data_size: usize,
+        end_of_stream: bool,
+    ) -> &mut Self {
+        self.call_proxy_on_upstream_data(context_id, abi_size(data_size), end_of_stream)
+    }
+
+    pub fn call_proxy_on_request_body_with_size(
+        &mut self,
+        context_id: i32,
+        body_size: usize,
+        end_of_stream: bool,
+    ) -> &mut Self {
+        self.call_proxy_on_request_body(context_id, abi_size(body_size), end_of_stream)
+    }
+
+    pub fn call_proxy_on_response_body_with_size(
+        &mut self,
+        context_id: i32,
+        body_size: usize,
+        end_of_stream: bool,
+    ) -> &mut Self {
+        self.call_proxy_on_response_body(context_id, abi_size(body_size), end_of_stream)
+    }
+
+    // Install the data as the buffer of the direction and pass its size, so that the size given to
+    // the plugin always matches the buffer it reads (the buffer is replaced when staging the call)
+    pub fn call_proxy_on_request_body_with_data(
+        &mut self,
+        context_id: i32,
+        body_data: &[u8],
+        end_of_stream: bool,
+    ) -> &mut Self {
+        self.set_default_buffer_bytes(BufferType::HttpRequestBody)
+            .returning_bytes(body_data);
+        self.call_proxy_on_request_body_with_size(context_id, body_data.len(), end_of_stream)
+    }
+
+    pub fn call_proxy_on_response_body_with_data(
+        &mut self,
+        context_id: i32,
+        body_data: &[u8],
+        end_of_stream: bool,
+    ) -> &mut Self {
+        self.set_default_buffer_bytes(BufferType::HttpResponseBody)
+            .returning_bytes(body_data);
+        self.call_proxy_on_response_body_with_size(context_id, body_data.len(), end_of_stream)
+    }
+
+    pub fn call_proxy_on_downstream_data_with_data(
+        &mut self,
+        context_id: i32,
+        data: &[u8],
+        end_of_stream: bool,
+    ) -> &mut Self {
+        self.set_default_buffer_bytes(BufferType::DownstreamData)
+            .returning_bytes(data);
+        self.call_proxy_on_downstream_data_with_size(context_id, data.len(), end_of_stream)
+    }
+
+    pub fn call_proxy_on_upstream_data_with_data(
+        &mut self,
+        context_id: i32,
+        data: &[u8],
+        end_of_stream: bool,
+    ) -> &mut Self {
+        self.set_default_buffer_bytes(BufferType::UpstreamData)
+            .returning_bytes(data);
+        self.call_proxy_on_upstream_data_with_size(context_id, data.len(), end_of_stream)
+    }
+
+    // Variants deriving the number of pairs or the body size from the header map or buffer the host
+    // currently serves for the callback, as it stands when the call is staged
+    pub fn call_proxy_on_request_headers_auto(
+        &mut self,
+        context_id: i32,
+        end_of_stream: bool,
+    ) -> &mut Self {
+        let num_headers = self.get_header_map(MapType::HttpRequestHeaders).len() as i32;
+        self.call_proxy_on_request_headers(context_id, num_headers, end_of_stream)
+    }
+
+    pub fn call_proxy_on_request_body_auto(
+        &mut self,
+        context_id: i32,
+        end_of_stream: bool,
+    ) -> &mut Self {
+        let body_size = self.get_buffer(BufferType::HttpRequestBody).len();
+        self.call_proxy_on_request_body_with_size(context_id, body_size, end_of_stream)
+    }
+
+    pub fn call_proxy_on_request_trailers_auto(&mut self, context_id: i32) -> &mut Self {
+        let num_trailers = self.get_header_map(MapType::HttpRequestTrailers).len() as i32;
+        self.call_proxy_on_request_trailers(context_id, num_trailers)
+    }
+
+    pub fn call_proxy_on_response_headers_auto(
+        &mut self,
+        context_id: i32,
+        end_of_stream: bool,
+    ) -> &mut Self {
+        let num_headers = self.get_header_map(MapType::HttpResponseHeaders).len() as i32;
+        self.call_proxy_on_response_headers(context_id, num_headers, end_of_stream)
+    }
+
+    pub fn call_proxy_on_response_body_auto(
+        &mut self,
+        context_id: i32,
+        end_of_stream: bool,
+    ) -> &mut Self {
+        let body_size = self.get_buffer(BufferType::HttpResponseBody).len();
+        self.call_proxy_on_response_body_with_size(context_id, body_size, end_of_stream)
+    }
+
+    pub fn call_proxy_on_response_trailers_auto(&mut self, context_id: i32) -> &mut Self {
+        let num_trailers = self.get_header_map(MapType::HttpResponseTrailers).len() as i32;
+        self.call_proxy_on_response_trailers(context_id, num_trailers)
+    }
+
     /* ---------------------------------- Combination Calls ---------------------------------- */
+    // Delivers data chunks to context_id in the order of the schedule, where each entry gives the
+    // direction of the chunk, its data, whether it ends that direction and the expected action
+    pub fn stream_data(
+        &mut self,
+        context_id: i32,
+        schedule: Vec<(StreamType, &[u8], bool, Action)>,
+    ) -> Result<&mut Self> {
+        assert_eq!(
+            self.function_call.len(),
+            0,
+            "Error: stream_data cannot be combined with other staged calls"
+        );
+        let mut half_closed = self.half_closed.clone();
+        for (stream_type, data, end_of_stream, _) in &schedule {
+            if half_closed.contains(&(context_id, *stream_type)) {
+                panic!(
+                    "Error: {:?} data for context {} scheduled after its end_of_stream",
+                    stream_type, context_id
+                );
+            }
+            if *end_of_stream {
+                half_closed.push((context_id, *stream_type));
+            }
+            let data_size = abi_size(data.len());
+            let function_call = match stream_type {
+                StreamType::HttpRequest => {
+                    FunctionCall::ProxyOnRequestBody(context_id, data_size, *end_of_stream)
+                }
+                StreamType::HttpResponse => {
+                    FunctionCall::ProxyOnResponseBody(context_id, data_size, *end_of_stream)
+                }
+                StreamType::Downstream => {
+                    FunctionCall::ProxyOnDownstreamData(context_id, data_size, *end_of_stream)
+                }
+                StreamType::Upstream => {
+                    FunctionCall::ProxyOnUpstreamData(context_id, data_size, *end_of_stream)
+                }
+            };
+            self.function_call.push(function_call);
+            self.function_type.push(FunctionType::ReturnAction);
+        }
+
+        // each chunk replaces the buffer of its direction right before it is delivered
+        for (stream_type, data, end_of_stream, action) in schedule {
+            self.set_default_buffer_bytes(get_buffer_type(stream_type))
+                .returning_bytes(data);
+            self.execute_and_expect(ReturnType::Action(action))?;
+            if end_of_stream {
+                self.half_closed.push((context_id, stream_type));
+            }
+        }
+        Ok(self)
+    }
+
+    // Ends a single direction of the stream with an empty chunk while the other stays open
+    pub fn half_close(
+        &mut self,
+        context_id: i32,
+        stream_type: StreamType,
+        action: Action,
+    ) -> Result<&mut Self> {
+        self.stream_data(context_id, vec![(stream_type, &[][..], true, action)])
+    }
+
+    pub fn is_half_closed(&self, context_id: i32, stream_type: StreamType) -> bool {
+        self.half_closed.contains(&(context_id, stream_type))
+    }
+
+    // Whether the module closed the stream itself through proxy_close_stream
+    pub fn is_stream_closed(&self, context_id: i32, stream_type: StreamType) -> bool {
+        self.get_settings_handle()
+            .staged
+            .is_stream_closed(context_id, stream_type)
+    }
+
+    // Signals an upstream failure to a network filter: the upstream close is reported first and
+    // the proxy then closes the downstream connection itself, as a TCP proxy would
+    pub fn simulate_upstream_failure(
+        &mut self,
+        context_id: i32,
+        upstream_failure: UpstreamFailure,
+    ) -> Result<&mut Self> {
+        let upstream_peer_type = match upstream_failure {
+            UpstreamFailure::ConnectFailure => PeerType::Unknown,
+            UpstreamFailure::Reset => PeerType::Remote,
+        };
+        println!("[host] simulating upstream {:?}", upstream_failure);
+        self.call_proxy_on_upstream_connection_close(context_id, upstream_peer_type)
+            .call_proxy_on_downstream_connection_close(context_id, PeerType::Local)
+            .execute_and_expect_n(vec![ReturnType::None, ReturnType::None])?;
+        Ok(self)
+    }
+
     pub fn http_request(
         &mut self,
         headers: Option<(MapType, Vec<(&str, &str)>)>,
@@ -1314,6 +3336,343 @@ impl Tester {
                 .call_proxy_on_request_trailers(http_context, num_trailers);
         }
 
+        // skip the request callbacks staged above after a local response, until they are executed
+        self.skip_after_local_response_for_staged(true);
+        Ok(self)
+    }
+
+    // Creates a root context with the next unused ID and returns it
+    pub fn new_root_context(&mut self) -> Result<i32> {
+        self.new_context(0)
+    }
+
+    // Creates an http context of root_context_id with the next unused ID and returns it
+    pub fn new_http_context(&mut self, root_context_id: i32) -> Result<i32> {
+        assert!(
+            self.contexts.is_root(root_context_id),
+            "Error: http context created with parent {} which is not a root context",
+            root_context_id
+        );
+        self.new_context(root_context_id)
+    }
+
+    // Creates a stream context of a network filter under root_context_id and returns its ID
+    pub fn new_stream_context(&mut self, root_context_id: i32) -> Result<i32> {
+        assert!(
+            self.contexts.is_root(root_context_id),
+            "Error: stream context created with parent {} which is not a root context",
+            root_context_id
+        );
+        self.new_context(root_context_id)
+    }
+
+    fn new_context(&mut self, parent_context_id: i32) -> Result<i32> {
+        assert_eq!(
+            self.function_call.len(),
+            0,
+            "Error: contexts cannot be created while other calls are staged"
+        );
+        let context_id = self.contexts.allocate();
+        self.call_proxy_on_context_create(context_id, parent_context_id)
+            .execute_and_expect(ReturnType::None)?;
+        Ok(context_id)
+    }
+
+    pub fn get_context_manager(&self) -> &ContextManager {
+        &self.contexts
+    }
+
+    pub fn get_context_state(&self, context_id: i32) -> Option<ContextState> {
+        self.contexts.get_state(context_id)
+    }
+
+    pub fn assert_no_leaked_contexts(&self) {
+        let leaked_contexts = self.contexts.get_leaked_contexts();
+        let leaks: Vec<String> = leaked_contexts
+            .iter()
+            .map(|context_id| {
+                format!(
+                    "{} ({:?}, parent {})",
+                    context_id,
+                    self.contexts.get_state(*context_id).unwrap(),
+                    self.contexts.get_parent(*context_id).unwrap()
+                )
+            })
+            .collect();
+        assert!(
+            leaks.is_empty(),
+            "Error: contexts created but never deleted with proxy_on_delete: {}",
+            leaks.join(", ")
+        );
+    }
+
+    // Runs assert_no_leaked_contexts when the tester is dropped at the end of the scenario
+    pub fn check_leaks_on_drop(&mut self, check: bool) -> &mut Self {
+        self.check_leaks_on_drop = check;
+        self
+    }
+
+    // Drives a whole http stream on context_id: its creation, the request and response callbacks
+    // with the header maps and bodies of both messages installed, then proxy_on_done, proxy_on_log
+    // and proxy_on_delete (left to the test when the module defers completion with proxy_done).
+    // The response is not delivered once the module sent a local response, and the response the
+    // client receives (the local response, or the response as modified by the module) is asserted
+    // to be expected_response.
+    pub fn simulate_http_request(
+        &mut self,
+        root_context_id: i32,
+        context_id: i32,
+        request: HttpMessage,
+        response: HttpMessage,
+        expected_response: HttpMessage,
+    ) -> Result<&mut Self> {
+        assert_eq!(
+            self.function_call.len(),
+            0,
+            "Error: simulate_http_request cannot be combined with other staged calls"
+        );
+        self.call_proxy_on_context_create(context_id, root_context_id)
+            .execute_and_expect(ReturnType::None)?;
+
+        let skip_after_local_response = self.skip_after_local_response;
+        self.skip_after_local_response = true;
+        let delivered = self.deliver_http_messages(context_id, request, response);
+        self.skip_after_local_response = skip_after_local_response;
+        let received_response = delivered?;
+        assert_eq!(
+            received_response, expected_response,
+            "Error: unexpected response received by the client of context {}",
+            context_id
+        );
+
+        self.call_proxy_on_done(context_id)
+            .execute_and_expect(ReturnType::AnyBool)?;
+        self.call_proxy_on_log(context_id)
+            .execute_and_expect(ReturnType::None)?;
+        if !self.is_pending_done(context_id) {
+            self.call_proxy_on_delete(context_id)
+                .execute_and_expect(ReturnType::None)?;
+        }
+        Ok(self)
+    }
+
+    // Delivers the request then, unless the module sent a local response, the response, returning
+    // the response the client receives
+    fn deliver_http_messages(
+        &mut self,
+        context_id: i32,
+        request: HttpMessage,
+        response: HttpMessage,
+    ) -> Result<HttpMessage> {
+        let has_body = response.body.is_some();
+        let has_trailers = !response.trailers.is_empty();
+        self.deliver_http_message(context_id, StreamType::HttpRequest, request)?;
+        if !self
+            .get_settings_handle()
+            .staged
+            .has_sent_local_response(context_id)
+        {
+            self.deliver_http_message(context_id, StreamType::HttpResponse, response)?;
+        }
+
+        if let Some(local_response) = self
+            .get_local_responses()
+            .into_iter()
+            .rev()
+            .find(|local_response| local_response.context_id == context_id)
+        {
+            let mut headers = vec![(
+                ":status".to_string(),
+                local_response.status_code.to_string(),
+            )];
+            headers.extend(local_response.headers.clone());
+            return Ok(HttpMessage {
+                headers: headers,
+                body: if local_response.body.is_empty() {
+                    None
+                } else {
+                    Some(local_response.body_str().to_string())
+                },
+                trailers: Vec::new(),
+            });
+        }
+        let body = self.get_buffer(BufferType::HttpResponseBody);
+        Ok(HttpMessage {
+            headers: self.get_header_map(MapType::HttpResponseHeaders),
+            body: if has_body {
+                Some(String::from_utf8_lossy(&body).to_string())
+            } else {
+                None
+            },
+            trailers: if has_trailers {
+                self.get_header_map(MapType::HttpResponseTrailers)
+            } else {
+                Vec::new()
+            },
+        })
+    }
+
+    // Delivers the headers, body and trailers of a message with end_of_stream set on its last part
+    fn deliver_http_message(
+        &mut self,
+        context_id: i32,
+        stream_type: StreamType,
+        message: HttpMessage,
+    ) -> Result<()> {
+        let (headers_type, trailers_type) = match stream_type {
+            StreamType::HttpRequest => (MapType::HttpRequestHeaders, MapType::HttpRequestTrailers),
+            _ => (MapType::HttpResponseHeaders, MapType::HttpResponseTrailers),
+        };
+        let has_trailers = !message.trailers.is_empty();
+        let headers_end_of_stream = message.body.is_none() && !has_trailers;
+
+        let headers = message
+            .headers
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect::<Vec<(&str, &str)>>();
+        let num_headers = headers.len() as i32;
+        self.set_default_header_map_pairs(headers_type)
+            .returning(headers);
+        match stream_type {
+            StreamType::HttpRequest => {
+                self.call_proxy_on_request_headers(context_id, num_headers, headers_end_of_stream)
+            }
+            _ => {
+                self.call_proxy_on_response_headers(context_id, num_headers, headers_end_of_stream)
+            }
+        };
+        self.execute_and_expect(ReturnType::AnyAction)?;
+
+        if let Some(body) = &message.body {
+            self.set_default_buffer_bytes(get_buffer_type(stream_type))
+                .returning(body);
+            let body_size = body.len() as i32;
+            match stream_type {
+                StreamType::HttpRequest => {
+                    self.call_proxy_on_request_body(context_id, body_size, !has_trailers)
+                }
+                _ => self.call_proxy_on_response_body(context_id, body_size, !has_trailers),
+            };
+            self.execute_and_expect(ReturnType::AnyAction)?;
+        }
+
+        if has_trailers {
+            let trailers = message
+                .trailers
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str()))
+                .collect::<Vec<(&str, &str)>>();
+            let num_trailers = trailers.len() as i32;
+            self.set_default_header_map_pairs(trailers_type)
+                .returning(trailers);
+            match stream_type {
+                StreamType::HttpRequest => {
+                    self.call_proxy_on_request_trailers(context_id, num_trailers)
+                }
+                _ => self.call_proxy_on_response_trailers(context_id, num_trailers),
+            };
+            self.execute_and_expect(ReturnType::AnyAction)?;
+        }
+        Ok(())
+    }
+
+    // Drives a whole connection of a network filter on context_id: its creation and
+    // proxy_on_new_connection, the downstream and upstream chunks in order with the data of each
+    // installed as its buffer and end_of_stream set on the last chunk of each direction, the closes
+    // of the downstream (by the peer) and upstream connections, then proxy_on_done, proxy_on_log and
+    // proxy_on_delete (left to the test when the module defers completion with proxy_done)
+    pub fn simulate_tcp_session(
+        &mut self,
+        root_context_id: i32,
+        context_id: i32,
+        chunks: Vec<(StreamType, &[u8])>,
+    ) -> Result<&mut Self> {
+        assert_eq!(
+            self.function_call.len(),
+            0,
+            "Error: simulate_tcp_session cannot be combined with other staged calls"
+        );
+        self.call_proxy_on_context_create(context_id, root_context_id)
+            .execute_and_expect(ReturnType::None)?;
+        self.call_proxy_on_new_connection(context_id)
+            .execute_and_expect(ReturnType::AnyAction)?;
+
+        for (index, (stream_type, data)) in chunks.iter().enumerate() {
+            let (stream_type, data) = (*stream_type, *data);
+            let end_of_stream = !chunks[index + 1..]
+                .iter()
+                .any(|(next_stream_type, _)| *next_stream_type == stream_type);
+            let data_size = abi_size(data.len());
+            self.set_default_buffer_bytes(get_buffer_type(stream_type))
+                .returning_bytes(data);
+            match stream_type {
+                StreamType::Downstream => {
+                    self.call_proxy_on_downstream_data(context_id, data_size, end_of_stream)
+                }
+                StreamType::Upstream => {
+                    self.call_proxy_on_upstream_data(context_id, data_size, end_of_stream)
+                }
+                _ => panic!(
+                    "Error: simulate_tcp_session cannot deliver {:?} data",
+                    stream_type
+                ),
+            };
+            self.execute_and_expect(ReturnType::AnyAction)?;
+        }
+
+        self.call_proxy_on_downstream_connection_close(context_id, PeerType::Remote)
+            .execute_and_expect(ReturnType::None)?;
+        self.call_proxy_on_upstream_connection_close(context_id, PeerType::Local)
+            .execute_and_expect(ReturnType::None)?;
+        self.call_proxy_on_done(context_id)
+            .execute_and_expect(ReturnType::AnyBool)?;
+        self.call_proxy_on_log(context_id)
+            .execute_and_expect(ReturnType::None)?;
+        if !self.is_pending_done(context_id) {
+            self.call_proxy_on_delete(context_id)
+                .execute_and_expect(ReturnType::None)?;
+        }
         Ok(self)
     }
 }
+
+impl Drop for Tester {
+    fn drop(&mut self) {
+        // a failing test already reports its own panic
+        if std::thread::panicking() {
+            return;
+        }
+        // expectations still staged were never checked, e.g. the test returned before executing
+        for unmet in self.get_expect_handle().staged.get_unmet_expectations() {
+            println!("Warning: expectation never asserted, {}", unmet);
+        }
+        for function_call in &self.function_call {
+            println!("Warning: staged call never executed {:?}", function_call);
+        }
+        if self.check_leaks_on_drop {
+            self.assert_no_leaked_contexts();
+        }
+    }
+}
+
+fn get_buffer_type(stream_type: StreamType) -> BufferType {
+    match stream_type {
+        StreamType::HttpRequest => BufferType::HttpRequestBody,
+        StreamType::HttpResponse => BufferType::HttpResponseBody,
+        StreamType::Downstream => BufferType::DownstreamData,
+        StreamType::Upstream => BufferType::UpstreamData,
+    }
+}
+
+// Reinterprets a host size as the i32 carrying a u32 size across the ABI, so sizes between 2GiB
+// and 4GiB are passed through as negative i32 values rather than rejected
+pub fn abi_size(size: usize) -> i32 {
+    match u32::try_from(size) {
+        Ok(size) => size as i32,
+        Err(_) => panic!(
+            "Error: size {} does not fit in the 32-bit sizes of the proxy-wasm ABI",
+            size
+        ),
+    }
+}
@@ -30,6 +30,17 @@ pub enum Action {
     Pause = 1,
 }
 
+impl Action {
+    // Action a callback returned, or None for values outside of the ABI
+    pub fn from_wasm(value: i64) -> Option<Action> {
+        match value {
+            0 => Some(Action::Continue),
+            1 => Some(Action::Pause),
+            _ => None,
+        }
+    }
+}
+
 #[repr(i32)]
 #[derive(Debug, Clone, Copy)]
 pub enum GrpcStatus {
@@ -88,10 +99,13 @@ pub enum BufferType {
     DownstreamData = 2,
     UpstreamData = 3,
     HttpCallResponseBody = 4,
+    GrpcReceiveBuffer = 5,
+    VmConfiguration = 6,
+    PluginConfiguration = 7,
 }
 
 #[repr(u32)]
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum MapType {
     HttpRequestHeaders = 0,
     HttpRequestTrailers = 1,
@@ -101,6 +115,15 @@ pub enum MapType {
     HttpCallResponseTrailers = 7,
 }
 
+#[repr(u32)]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum StreamType {
+    HttpRequest = 0,
+    HttpResponse = 1,
+    Downstream = 2,
+    Upstream = 3,
+}
+
 #[repr(u32)]
 #[derive(Debug, Clone, Copy)]
 pub enum PeerType {
@@ -109,11 +132,51 @@ pub enum PeerType {
     Remote = 2,
 }
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MapSerialization {
+    Framework, // empty maps are served as a 4-byte zero count
+    CppHost,   // empty maps are served as a null pointer and zero size, as the Envoy C++ host does
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum QueueDelivery {
+    Manual,    // proxy_on_queue_ready is only ever called explicitly by the test
+    Immediate, // every enqueue is delivered once the current callback returns
+    Flush,     // enqueues are held until the test flushes the shared queues
+    Coalesced, // enqueues to the same queue are delivered once the current callback returns
+}
+
 #[derive(Debug)]
 pub enum ReturnType {
     None,
     Bool(bool),
     Action(Action),
+    I32(i32),  // raw return of a callback or an export from call_export
+    U64(u64),  // return of an export from call_export returning an i64
+    AnyBool,   // any bool, without asserting which one
+    AnyAction, // any valid action, without asserting which one
+}
+
+// Lifecycle of a context as driven by the host
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ContextState {
+    Created, // proxy_on_context_create
+    Active,  // any other callback of the context
+    Done,    // proxy_on_done
+    Logged,  // proxy_on_log
+    Deleted, // proxy_on_delete
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum UpstreamFailure {
+    ConnectFailure, // the upstream connection could not be established
+    Reset,          // the established upstream connection was reset by the peer
+}
+
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct SelectedUpstream {
+    pub cluster: Option<String>,
+    pub host: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -123,6 +186,286 @@ pub enum AbiVersion {
     ProxyAbiVersion0_2_0,
 }
 
+// Export or import of a module whose signature belongs to another ABI version than the declared one
+#[derive(Debug, PartialEq, Clone)]
+pub struct AbiMismatch {
+    pub name: String,
+    pub is_export: bool,
+    pub num_params: usize,
+    pub declared: AbiVersion,
+    pub matching: AbiVersion, // UnknownAbiVersion if no supported ABI version has this signature
+}
+
+// Explanation of why a proxy-wasm module cannot be tested, as returned by tester::diagnose()
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnosis {
+    pub load_error: Option<String>, // includes wasm features the engine does not enable
+    pub abi_version: AbiVersion,
+    pub missing_exports: Vec<String>,
+    pub unsupported_imports: Vec<String>,
+    pub signature_mismatches: Vec<String>,
+    pub abi_mismatches: Vec<AbiMismatch>,
+}
+
+impl Diagnosis {
+    pub fn is_ok(&self) -> bool {
+        self.load_error.is_none()
+            && self.abi_version != AbiVersion::UnknownAbiVersion
+            && self.missing_exports.is_empty()
+            && self.unsupported_imports.is_empty()
+            && self.signature_mismatches.is_empty()
+            && self.abi_mismatches.is_empty()
+    }
+}
+
+impl std::fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_ok() {
+            return write!(f, "module can be tested with {:?}", self.abi_version);
+        }
+        if let Some(load_error) = &self.load_error {
+            writeln!(f, "module failed to load: {}", load_error)?;
+        }
+        if self.abi_version == AbiVersion::UnknownAbiVersion && self.load_error.is_none() {
+            writeln!(
+                f,
+                "module does not export a supported proxy_abi_version_* function"
+            )?;
+        }
+        for name in &self.missing_exports {
+            writeln!(f, "missing export \"{}\"", name)?;
+        }
+        for name in &self.unsupported_imports {
+            writeln!(
+                f,
+                "import \"{}\" is not provided by the test-framework",
+                name
+            )?;
+        }
+        for mismatch in &self.signature_mismatches {
+            writeln!(f, "signature mismatch: {}", mismatch)?;
+        }
+        for mismatch in &self.abi_mismatches {
+            writeln!(
+                f,
+                "{} \"{}\" with {} params matches {:?} instead of {:?}",
+                if mismatch.is_export {
+                    "export"
+                } else {
+                    "import"
+                },
+                mismatch.name,
+                mismatch.num_params,
+                mismatch.matching,
+                mismatch.declared
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Local response sent by the module through proxy_send_local_response
+#[derive(Debug, PartialEq, Clone)]
+pub struct LocalResponse {
+    pub context_id: i32,
+    pub status_code: u32,
+    pub status_code_details: String,
+    pub body: Bytes,
+    pub headers: Vec<(String, String)>,
+    pub grpc_status: i32,
+}
+
+impl LocalResponse {
+    pub fn body_str(&self) -> &str {
+        std::str::from_utf8(&self.body).unwrap_or("")
+    }
+
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// Headers, body and trailers of a request or response delivered, or expected to be received by the
+// client, in Tester::simulate_http_request
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct HttpMessage {
+    pub headers: Vec<(String, String)>,
+    pub body: Option<String>,
+    pub trailers: Vec<(String, String)>,
+}
+
+impl HttpMessage {
+    pub fn new(headers: Vec<(&str, &str)>) -> HttpMessage {
+        HttpMessage {
+            headers: headers
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            body: None,
+            trailers: Vec::new(),
+        }
+    }
+
+    pub fn with_body(mut self, body: &str) -> HttpMessage {
+        self.body = Some(body.to_string());
+        self
+    }
+
+    pub fn with_trailers(mut self, trailers: Vec<(&str, &str)>) -> HttpMessage {
+        self.trailers = trailers
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        self
+    }
+}
+
+// Response to an http callout of the module, served to proxy_on_http_call_response through the
+// HttpCallResponseHeaders/Body/Trailers maps and buffer
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct HttpCalloutResponse {
+    pub status: u32,
+    pub headers: Vec<(String, String)>,
+    pub body: Bytes,
+    pub trailers: Vec<(String, String)>,
+}
+
+impl HttpCalloutResponse {
+    pub fn new(status: u32) -> HttpCalloutResponse {
+        HttpCalloutResponse {
+            status: status,
+            ..HttpCalloutResponse::default()
+        }
+    }
+
+    pub fn with_headers(mut self, headers: Vec<(&str, &str)>) -> HttpCalloutResponse {
+        self.headers = headers
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        self
+    }
+
+    pub fn with_body(mut self, body: &[u8]) -> HttpCalloutResponse {
+        self.body = body.to_vec();
+        self
+    }
+
+    pub fn with_trailers(mut self, trailers: Vec<(&str, &str)>) -> HttpCalloutResponse {
+        self.trailers = trailers
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        self
+    }
+}
+
+// Policy on host calls without a staged expectation: Strict fails the stage, Lenient falls back to
+// the host defaults and records a warning
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ExpectMode {
+    Strict,
+    Lenient,
+}
+
+// Host functions as far as expectations are concerned, e.g. to ignore the noisy ones
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum HostcallKind {
+    Log,
+    GetStatus,
+    SetTickPeriod,
+    GetCurrentTime,
+    GetBufferBytes,
+    SetBufferBytes,
+    GetHeaderMapPairs,
+    SetHeaderMapPairs,
+    GetHeaderMapValue,
+    ReplaceHeaderMapValue,
+    RemoveHeaderMapValue,
+    AddHeaderMapValue,
+    SendLocalResponse,
+    HttpCall,
+    GrpcCall,
+    GrpcStream,
+    GrpcSend,
+    GrpcCancel,
+    GrpcClose,
+    SetEffectiveContext,
+    CallForeignFunction,
+    Done,
+    ClearRouteCache,
+    ContinueRequest,
+    ContinueResponse,
+    ResumeDownstream,
+    ResumeUpstream,
+    ContinueStream,
+    CloseStream,
+    GetSharedData,
+    SetSharedData,
+    RegisterSharedQueue,
+    ResolveSharedQueue,
+    EnqueueSharedQueue,
+    DequeueSharedQueue,
+    DefineMetric,
+    IncrementMetric,
+    RecordMetric,
+    GetMetric,
+    GetProperty,
+    SetProperty,
+}
+
+impl HostcallKind {
+    // Name of the staged expectations of this kind, see expectations.rs
+    pub(crate) fn staged_name(&self) -> &'static str {
+        match self {
+            HostcallKind::Log => "log_message",
+            HostcallKind::GetStatus => "get_status",
+            HostcallKind::SetTickPeriod => "tick_period",
+            HostcallKind::GetCurrentTime => "current_time",
+            HostcallKind::GetBufferBytes => "get_buffer_bytes",
+            HostcallKind::SetBufferBytes => "set_buffer_bytes",
+            HostcallKind::GetHeaderMapPairs => "get_header_map_pairs",
+            HostcallKind::SetHeaderMapPairs => "set_header_map_pairs",
+            HostcallKind::GetHeaderMapValue => "get_header_map_value",
+            HostcallKind::ReplaceHeaderMapValue => "replace_header_map_value",
+            HostcallKind::RemoveHeaderMapValue => "remove_header_map_value",
+            HostcallKind::AddHeaderMapValue => "add_header_map_value",
+            HostcallKind::SendLocalResponse => "send_local_response",
+            HostcallKind::HttpCall => "http_call",
+            HostcallKind::GrpcCall => "grpc_call",
+            HostcallKind::GrpcStream => "grpc_stream",
+            HostcallKind::GrpcSend => "grpc_send",
+            HostcallKind::GrpcCancel => "grpc_cancel",
+            HostcallKind::GrpcClose => "grpc_close",
+            HostcallKind::SetEffectiveContext => "set_effective_context",
+            HostcallKind::CallForeignFunction => "call_foreign_function",
+            HostcallKind::Done => "proxy_done",
+            HostcallKind::ClearRouteCache => "clear_route_cache",
+            HostcallKind::ContinueRequest => "continue_request",
+            HostcallKind::ContinueResponse => "continue_response",
+            HostcallKind::ResumeDownstream => "resume_downstream",
+            HostcallKind::ResumeUpstream => "resume_upstream",
+            HostcallKind::ContinueStream => "continue_stream",
+            HostcallKind::CloseStream => "close_stream",
+            HostcallKind::GetSharedData => "get_shared_data",
+            HostcallKind::SetSharedData => "set_shared_data",
+            HostcallKind::RegisterSharedQueue => "register_shared_queue",
+            HostcallKind::ResolveSharedQueue => "resolve_shared_queue",
+            HostcallKind::EnqueueSharedQueue => "enqueue_shared_queue",
+            HostcallKind::DequeueSharedQueue => "dequeue_shared_queue",
+            HostcallKind::DefineMetric => "define_metric",
+            HostcallKind::IncrementMetric => "increment_metric",
+            HostcallKind::RecordMetric => "record_metric",
+            HostcallKind::GetMetric => "get_metric",
+            HostcallKind::GetProperty => "get_property",
+            HostcallKind::SetProperty => "set_property",
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum ExpectStatus {
     Expected,
@@ -131,3 +474,89 @@ pub enum ExpectStatus {
 }
 
 pub type Bytes = Vec<u8>;
+
+// Property values as Envoy's attribute layer serializes them: integers, timestamps and durations as
+// little-endian i64 (timestamps and durations in nanoseconds), booleans as a single byte, strings as
+// raw UTF-8 and messages as their serialized protobuf bytes
+#[derive(Debug, Clone)]
+pub enum PropertyValue {
+    Int(i64),
+    Uint(u64),
+    Double(f64),
+    Bool(bool),
+    String(String),
+    Bytes(Bytes),
+    Timestamp(std::time::SystemTime),
+    Duration(std::time::Duration),
+    Proto(Bytes),
+}
+
+impl PropertyValue {
+    pub fn encode(&self) -> Bytes {
+        match self {
+            PropertyValue::Int(value) => value.to_le_bytes().to_vec(),
+            PropertyValue::Uint(value) => value.to_le_bytes().to_vec(),
+            PropertyValue::Double(value) => value.to_le_bytes().to_vec(),
+            PropertyValue::Bool(value) => vec![*value as u8],
+            PropertyValue::String(value) => value.as_bytes().to_vec(),
+            PropertyValue::Bytes(value) => value.clone(),
+            PropertyValue::Timestamp(value) => {
+                let nanos = match value.duration_since(std::time::UNIX_EPOCH) {
+                    Ok(duration) => duration.as_nanos() as i64,
+                    Err(error) => -(error.duration().as_nanos() as i64),
+                };
+                nanos.to_le_bytes().to_vec()
+            }
+            PropertyValue::Duration(value) => (value.as_nanos() as i64).to_le_bytes().to_vec(),
+            PropertyValue::Proto(value) => value.clone(),
+        }
+    }
+}
+
+impl From<i64> for PropertyValue {
+    fn from(value: i64) -> Self {
+        PropertyValue::Int(value)
+    }
+}
+
+impl From<u64> for PropertyValue {
+    fn from(value: u64) -> Self {
+        PropertyValue::Uint(value)
+    }
+}
+
+impl From<f64> for PropertyValue {
+    fn from(value: f64) -> Self {
+        PropertyValue::Double(value)
+    }
+}
+
+impl From<bool> for PropertyValue {
+    fn from(value: bool) -> Self {
+        PropertyValue::Bool(value)
+    }
+}
+
+impl From<&str> for PropertyValue {
+    fn from(value: &str) -> Self {
+        PropertyValue::String(value.to_string())
+    }
+}
+
+impl From<String> for PropertyValue {
+    fn from(value: String) -> Self {
+        PropertyValue::String(value)
+    }
+}
+
+impl From<std::time::SystemTime> for PropertyValue {
+    fn from(value: std::time::SystemTime) -> Self {
+        PropertyValue::Timestamp(value)
+    }
+}
+
+impl From<std::time::Duration> for PropertyValue {
+    fn from(value: std::time::Duration) -> Self {
+        PropertyValue::Duration(value)
+    }
+}
@@ -12,12 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::runtime::load_module;
+
 use anyhow::Result;
 use wasmtime::*;
 
 pub fn print_boundary(wasm_file: &str) -> Result<()> {
-    let store = Store::default();
-    let module = Module::from_file(store.engine(), wasm_file)?;
+    let engine = Engine::default();
+    let module = load_module(&engine, wasm_file)?;
     print_imports(&module);
     print_exports(&module);
     return Ok(());
@@ -0,0 +1,224 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::hostcalls::{self, for_each_host_function, HostState};
+use crate::runtime::{Guest, GuestMemory, WasmRuntime};
+
+use anyhow::{format_err, Result};
+use wasmer::{
+    Function, FunctionEnv, FunctionEnvMut, Imports, Instance, Memory, Module, Store, TypedFunction,
+    Value, WasmTypeList,
+};
+use wasmtime::Val;
+
+// Data of the host functions, completed with the memory and allocator of the module once it is
+// instantiated
+struct HostEnv {
+    state: HostState,
+    memory: Option<Memory>,
+    allocator: Option<TypedFunction<i32, i32>>,
+}
+
+// Runs modules with wasmer, e.g. to check a plugin behaves the same in both runtimes
+pub struct WasmerRuntime {
+    store: Store,
+    module: Module,
+    env: FunctionEnv<HostEnv>,
+    imports: Imports,
+    instance: Option<Instance>,
+}
+
+impl WasmerRuntime {
+    pub fn new(wasm_path: &str, state: HostState) -> Result<WasmerRuntime> {
+        let binary = if wasm_path.ends_with(".wat") {
+            wat::parse_file(wasm_path)?
+        } else {
+            std::fs::read(wasm_path)?
+        };
+        let mut store = Store::default();
+        let module = Module::new(&store, &binary)?;
+        let env = FunctionEnv::new(
+            &mut store,
+            HostEnv {
+                state: state,
+                memory: None,
+                allocator: None,
+            },
+        );
+        Ok(WasmerRuntime {
+            store: store,
+            module: module,
+            env: env,
+            imports: Imports::new(),
+            instance: None,
+        })
+    }
+
+    fn get_function(&self, name: &str) -> Result<Function> {
+        self.instance
+            .as_ref()
+            .and_then(|instance| instance.exports.get_function(name).ok())
+            .cloned()
+            .ok_or(format_err!(
+                "Error: failed to find `{}` function export",
+                name
+            ))
+    }
+
+    fn call_typed<R: WasmTypeList>(&mut self, name: &str, params: &[i32]) -> Result<R> {
+        let func = self.get_function(name)?;
+        let store = &mut self.store;
+        Ok(match *params {
+            [] => func.typed::<(), R>(&*store)?.call(store)?,
+            [a] => func.typed::<i32, R>(&*store)?.call(store, a)?,
+            [a, b] => func.typed::<(i32, i32), R>(&*store)?.call(store, a, b)?,
+            [a, b, c] => func
+                .typed::<(i32, i32, i32), R>(&*store)?
+                .call(store, a, b, c)?,
+            [a, b, c, d] => func
+                .typed::<(i32, i32, i32, i32), R>(&*store)?
+                .call(store, a, b, c, d)?,
+            [a, b, c, d, e] => func
+                .typed::<(i32, i32, i32, i32, i32), R>(&*store)?
+                .call(store, a, b, c, d, e)?,
+            _ => {
+                return Err(format_err!(
+                    "Error: `{}` called with {} params, more than any proxy-wasm callback takes",
+                    name,
+                    params.len()
+                ))
+            }
+        })
+    }
+}
+
+macro_rules! define_wasmer_host_functions {
+    ($($name:ident($($param:ident: $ty:ty),*);)*) => {
+        fn new_wasmer_host_function(
+            store: &mut Store,
+            env: &FunctionEnv<HostEnv>,
+            name: &str,
+        ) -> Option<Function> {
+            match name {
+                $(stringify!($name) => Some(Function::new_typed_with_env(
+                    store,
+                    env,
+                    |mut env: FunctionEnvMut<HostEnv>, $($param: $ty),*| -> i32 {
+                        hostcalls::$name(&mut env, $($param),*)
+                    },
+                )),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+for_each_host_function!(define_wasmer_host_functions);
+
+impl WasmRuntime for WasmerRuntime {
+    fn define_host_function(&mut self, module: &str, name: &str) -> Result<bool> {
+        match new_wasmer_host_function(&mut self.store, &self.env, name) {
+            Some(function) => {
+                self.imports.define(module, name, function);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn instantiate(&mut self) -> Result<()> {
+        let instance = Instance::new(&mut self.store, &self.module, &self.imports)?;
+        let memory = instance.exports.get_memory("memory").ok().cloned();
+        let allocator = instance
+            .exports
+            .get_typed_function::<i32, i32>(&self.store, "proxy_on_memory_allocate")
+            .or_else(|_| {
+                instance
+                    .exports
+                    .get_typed_function::<i32, i32>(&self.store, "malloc")
+            })
+            .ok();
+        let env = self.env.as_mut(&mut self.store);
+        env.memory = memory;
+        env.allocator = allocator;
+        self.instance = Some(instance);
+        Ok(())
+    }
+
+    fn get_export_results(&mut self, name: &str) -> Option<usize> {
+        let func = self.get_function(name).ok()?;
+        Some(func.ty(&self.store).results().len())
+    }
+
+    fn call_export(&mut self, name: &str, params: &[Val]) -> Result<Vec<Val>> {
+        let func = self.get_function(name)?;
+        let params = params.iter().map(to_value).collect::<Result<Vec<_>>>()?;
+        let results = func.call(&mut self.store, &params)?;
+        results.iter().map(from_value).collect()
+    }
+
+    fn call_void(&mut self, name: &str, params: &[i32]) -> Result<()> {
+        self.call_typed::<()>(name, params)
+    }
+
+    fn call_i32(&mut self, name: &str, params: &[i32]) -> Result<i32> {
+        self.call_typed::<i32>(name, params)
+    }
+}
+
+impl Guest for FunctionEnvMut<'_, HostEnv> {
+    fn state(&self) -> &HostState {
+        &self.data().state
+    }
+
+    fn memory(&mut self) -> Option<GuestMemory> {
+        let (env, store) = self.data_and_store_mut();
+        let view = env.memory.as_ref()?.view(&store);
+        Some(GuestMemory::new(view.data_ptr(), view.data_size() as usize))
+    }
+
+    fn has_allocator(&mut self) -> bool {
+        self.data().allocator.is_some()
+    }
+
+    fn call_allocator(&mut self, size: i32) -> Result<i32> {
+        let allocate = self
+            .data()
+            .allocator
+            .clone()
+            .ok_or(format_err!("module exports no allocator"))?;
+        Ok(allocate.call(self, size)?)
+    }
+}
+
+// Params and results of call_export, which the tester passes as wasmtime values
+fn to_value(val: &Val) -> Result<Value> {
+    match *val {
+        Val::I32(value) => Ok(Value::I32(value)),
+        Val::I64(value) => Ok(Value::I64(value)),
+        Val::F32(bits) => Ok(Value::F32(f32::from_bits(bits))),
+        Val::F64(bits) => Ok(Value::F64(f64::from_bits(bits))),
+        _ => Err(format_err!("Error: cannot pass {:?} to wasmer", val)),
+    }
+}
+
+fn from_value(value: &Value) -> Result<Val> {
+    match *value {
+        Value::I32(value) => Ok(Val::I32(value)),
+        Value::I64(value) => Ok(Val::I64(value)),
+        Value::F32(value) => Ok(Val::F32(value.to_bits())),
+        Value::F64(value) => Ok(Val::F64(value.to_bits())),
+        _ => Err(format_err!("Error: cannot return {:?} from wasmer", value)),
+    }
+}
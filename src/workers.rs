@@ -0,0 +1,97 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::tester::Tester;
+use crate::types::*;
+
+use anyhow::Result;
+
+// Set of logical workers, each an instance of the same proxy-wasm module with host settings and
+// expectations of its own, that share the stores of one simulated host (shared data, shared queues
+// and metrics)
+pub struct Workers {
+    workers: Vec<Tester>,
+}
+
+impl Workers {
+    pub fn new(workers: Vec<Tester>) -> Workers {
+        Workers { workers: workers }
+    }
+
+    pub fn len(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.workers.is_empty()
+    }
+
+    pub fn worker(&mut self, worker_id: usize) -> &mut Tester {
+        assert!(
+            worker_id < self.workers.len(),
+            "Error: worker {} does not exist, only {} workers were created",
+            worker_id,
+            self.workers.len()
+        );
+        &mut self.workers[worker_id]
+    }
+
+    // Executes the calls staged on each worker in the order given by the schedule, where each
+    // entry names the worker whose next staged call is executed and its expected return value.
+    // The expectations of every worker are only asserted once every worker is drained.
+    pub fn execute_interleaved(&mut self, schedule: Vec<(usize, ReturnType)>) -> Result<()> {
+        for (worker_id, expect_wasm) in schedule {
+            assert_ne!(
+                self.worker(worker_id).pending_calls(),
+                0,
+                "Error: worker {} has no staged calls left to execute",
+                worker_id
+            );
+            println!("[worker {}]", worker_id);
+            self.worker(worker_id)
+                .execute_next_and_expect(expect_wasm)?;
+        }
+
+        for (worker_id, worker) in self.workers.iter().enumerate() {
+            assert_eq!(
+                worker.pending_calls(),
+                0,
+                "Error: worker {} has staged calls that were not scheduled",
+                worker_id
+            );
+        }
+        for worker in self.workers.iter_mut() {
+            worker.assert_expect_stage();
+            worker.update_expect_stage();
+        }
+
+        println!("\n");
+        Ok(())
+    }
+
+    // Executes the calls staged on every worker, one call per worker at a time
+    pub fn execute_round_robin(&mut self, expect_wasm: Vec<Vec<ReturnType>>) -> Result<()> {
+        assert_eq!(expect_wasm.len(), self.workers.len());
+        let mut schedule = Vec::new();
+        let mut expect_wasm: Vec<Vec<ReturnType>> = expect_wasm;
+        while expect_wasm.iter().any(|returns| !returns.is_empty()) {
+            for (worker_id, returns) in expect_wasm.iter_mut().enumerate() {
+                if !returns.is_empty() {
+                    schedule.push((worker_id, returns.remove(0)));
+                }
+            }
+        }
+        self.execute_interleaved(schedule)
+    }
+}
@@ -0,0 +1,29 @@
+;; Http filter denying every request with a local response from proxy_on_request_headers, and
+;; trapping if the host still delivers its response headers
+(module
+  (import "env" "proxy_send_local_response"
+    (func $proxy_send_local_response (param i32 i32 i32 i32 i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (global $heap (mut i32) (i32.const 1024))
+
+  (func (export "proxy_abi_version_0_1_0"))
+
+  (func (export "proxy_on_memory_allocate") (param $size i32) (result i32)
+    (local $address i32)
+    (local.set $address (global.get $heap))
+    (global.set $heap (i32.add (global.get $heap) (local.get $size)))
+    (local.get $address))
+
+  (func (export "proxy_on_context_create") (param $context_id i32) (param $root_context_id i32))
+
+  (func (export "proxy_on_request_headers") (param $context_id i32) (param $num_headers i32)
+    (result i32)
+    (drop (call $proxy_send_local_response
+      (i32.const 403) (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0)
+      (i32.const 0) (i32.const 0) (i32.const -1)))
+    ;; Action::Pause
+    (i32.const 1))
+
+  (func (export "proxy_on_response_headers") (param $context_id i32) (param $num_headers i32)
+    (result i32)
+    (unreachable)))
@@ -0,0 +1,53 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::Result;
+use proxy_wasm_test_framework::prelude::*;
+
+fn mock_fixture() -> Result<Tester> {
+    mock(MockSettings {
+        wasm_path: concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/local_response.wat"
+        )
+        .to_string(),
+        quiet: true,
+        allow_unexpected: false,
+        watch: false,
+    })
+}
+
+#[test]
+fn response_headers_skipped_after_local_response() -> Result<()> {
+    let mut local_response_test = mock_fixture()?;
+    let root_context = 1;
+    let http_context = 2;
+    local_response_test
+        .call_proxy_on_context_create(root_context, 0)
+        .call_proxy_on_context_create(http_context, root_context)
+        .execute_and_expect_n(vec![ReturnType::None, ReturnType::None])?;
+
+    local_response_test
+        .skip_after_local_response(true)
+        .call_proxy_on_request_headers(http_context, 0, false)
+        .expect_send_local_response(Some(403), None, None, Some(-1))
+        .execute_and_expect(ReturnType::Action(Action::Pause))?;
+
+    // the fixture traps if proxy_on_response_headers is delivered
+    local_response_test
+        .call_proxy_on_response_headers(http_context, 0, false)
+        .execute_and_expect(ReturnType::Action(Action::Continue))?;
+    assert_eq!(local_response_test.get_local_responses().len(), 1);
+    Ok(())
+}